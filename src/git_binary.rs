@@ -1,18 +1,39 @@
 //! See [`GitBinary`] for the primary entry point.
 
-use anyhow::{bail, Result};
-use std::{borrow::Cow, collections::HashSet, ffi::OsStr, path::Path, process::Command};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    env,
+    ffi::OsStr,
+    fmt, fs,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    rc::Rc,
+    sync::OnceLock,
+    time::Duration,
+};
+use tempfile::TempDir;
 
 use crate::{
-    git_ref::GitRef,
+    git_ref::{GitRef, ListedRef},
     renderer::Renderer,
-    snapshot::{PruneFrom, Snapshot},
-    types::{Branch, Host, NomadRef, Remote, User},
-    verbosity::{is_output_allowed, output_stdout, run_notable, run_trivial, Verbosity},
+    snapshot::{PruneFrom, Snapshot, SnapshotDiff},
+    types::{Branch, Host, Interner, NomadRef, Remote, User},
+    verbosity::{
+        is_output_allowed, output_stdout, report_dry_run, retry_transient, run_notable,
+        run_notable_concurrently, run_trivial, with_stdin, with_timeout, CommandFailure, Retries,
+        TimedOut, Verbosity,
+    },
 };
 
 /// Run the git binary inheriting the same environment that this git-nomad
 /// binary is running under.
+///
+/// Nothing is added or removed, so that global config like `credential.helper` or
+/// `core.sshCommand` applies exactly as it would to a plain `git push`. The only environment
+/// nomad sets itself is in [`GitBinary::remote_command`], which leaves credential helpers alone.
 #[cfg(not(test))]
 pub fn git_command(name: impl AsRef<OsStr>) -> Command {
     Command::new(name)
@@ -20,6 +41,8 @@ pub fn git_command(name: impl AsRef<OsStr>) -> Command {
 
 /// Constructs a standalone git invocation that works in test environments without any ambient
 /// configuration.
+///
+/// Never compiled into release builds, see the non-test version above.
 #[cfg(test)]
 pub fn git_command(name: impl AsRef<OsStr>) -> Command {
     let mut command = Command::new(name);
@@ -37,26 +60,155 @@ pub fn git_command(name: impl AsRef<OsStr>) -> Command {
     command
 }
 
+/// The version reported by `git --version` for the git binary called `name`, like `2.39.2`.
+pub fn git_version(
+    renderer: &mut impl Renderer,
+    verbosity: Option<Verbosity>,
+    name: &str,
+) -> Result<String> {
+    let output = run_trivial(
+        renderer,
+        verbosity,
+        "Checking git version",
+        git_command(name).arg("--version"),
+    )
+    .and_then(output_stdout)
+    .map(LineArity::from)
+    .and_then(LineArity::one)?;
+
+    match output.strip_prefix("git version ") {
+        Some(version) => Ok(version.to_string()),
+        None => bail!("Unexpected `{} --version` output: {:?}", name, output),
+    }
+}
+
+/// A `major.minor.patch` git version, as parsed from [`git_version`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct GitVersion(pub u64, pub u64, pub u64);
+
+impl GitVersion {
+    /// Parse the version part of `git --version`, ignoring any vendor suffix like the
+    /// `.windows.1` in `2.39.2.windows.1` or the ` (Apple Git-137.1)` in `2.37.1 (Apple Git-137.1)`.
+    pub fn parse(version: &str) -> Option<Self> {
+        let mut parts = version.splitn(3, '.');
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts.next()?.parse().ok()?;
+        let patch = parts.next()?;
+        let end = patch
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(patch.len());
+        Some(Self(major, minor, patch[..end].parse().ok()?))
+    }
+}
+
+impl fmt::Display for GitVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.0, self.1, self.2)
+    }
+}
+
+/// Things nomad can do that need a newer git than the rest, see [`GitBinary::require`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GitFeature {
+    /// `git fetch --filter=blob:none`, see [`FetchOptions::partial`].
+    PartialFetch,
+    /// `git rev-list --disk-usage`, see [`GitBinary::disk_usage`].
+    DiskUsage,
+}
+
+impl GitFeature {
+    /// The oldest git that understands the feature.
+    pub fn min_version(self) -> GitVersion {
+        match self {
+            Self::PartialFetch => GitVersion(2, 19, 0),
+            Self::DiskUsage => GitVersion(2, 31, 0),
+        }
+    }
+
+    fn describe(self) -> &'static str {
+        match self {
+            Self::PartialFetch => "Fetching partially",
+            Self::DiskUsage => "Estimating disk usage",
+        }
+    }
+
+    /// Error out if `version` is too old for the feature.
+    ///
+    /// A version that could not be parsed is assumed to be new enough, so that unusual builds
+    /// of git are not locked out of anything.
+    fn check(self, version: Option<GitVersion>) -> Result<()> {
+        match version {
+            Some(version) if version < self.min_version() => bail!(
+                "{} needs git >= {}, you have {}",
+                self.describe(),
+                self.min_version(),
+                version,
+            ),
+            _ => Ok(()),
+        }
+    }
+}
+
 /// Containerizes all the naming schemes used by nomad from the wild west of all other git tools,
 /// both built-in and third party.
 mod namespace {
     use crate::{
         git_ref::GitRef,
-        types::{Branch, Host, NomadRef, User},
+        types::{Branch, Host, Interner, NomadRef, Remote, User},
     };
 
     /// The main name that we declare to be ours and nobody elses. This lays claim to the section
-    /// in `git config` and the `refs/{PREFIX}` hierarchy in all git repos!
+    /// in `git config` and, unless overridden by [`crate::git_binary::GitBinary::ref_prefix`], the
+    /// `refs/{PREFIX}` hierarchy in all git repos!
     pub const PREFIX: &str = "nomad";
 
+    /// The standard `refs/{hierarchy}`s that git and users keep real refs under, which a ref
+    /// prefix must never name or pushes would overwrite them, see [`super::check_ref_prefix`].
+    pub const RESERVED_PREFIXES: [&str; 4] = ["heads", "tags", "remotes", "notes"];
+
     /// Where information is stored for `git config`.
     pub fn config_key(key: &str) -> String {
         format!("{}.{}", PREFIX, key)
     }
 
+    /// The key (relative to [`config_key`]) that remembers when nomad last pushed to `remote`.
+    pub fn last_push_key(remote: &Remote) -> String {
+        format!("{}.lastPush", remote.0)
+    }
+
+    /// The key (relative to [`config_key`]) that remembers when nomad last synced successfully.
+    pub const LAST_SYNC_KEY: &str = "last-sync";
+
     /// The refspec to list remote nomad managed refs.
-    pub fn list_refspec(user: &User) -> String {
-        format!("refs/{prefix}/{user}/*", prefix = PREFIX, user = user.0)
+    pub fn list_refspec(prefix: &str, user: &User) -> String {
+        format!("refs/{prefix}/{user}/*", prefix = prefix, user = user.0)
+    }
+
+    /// The refspec to list remote nomad managed refs of every user.
+    pub fn list_all_users_refspec(prefix: &str) -> String {
+        format!("refs/{prefix}/*", prefix = prefix)
+    }
+
+    /// The refspec to list refs that could be nomad managed refs under any prefix, see
+    /// [`detected_prefix`].
+    pub const ANY_PREFIX_REFSPEC: &str = "refs/*/*/*/*";
+
+    /// The prefix of `ref_name` if it is shaped like a nomad managed ref in a remote.
+    ///
+    /// `refs/team-a/rraval/apollo/master` has the prefix `team-a`, while the standard
+    /// `refs/heads`, `refs/tags`, and `refs/remotes` hierarchies never count.
+    pub fn detected_prefix(ref_name: &str) -> Option<&str> {
+        let mut parts = ref_name.splitn(5, '/');
+        let (refs, prefix) = (parts.next()?, parts.next()?);
+        if refs != "refs" || RESERVED_PREFIXES.contains(&prefix) {
+            return None;
+        }
+        let rest = parts.collect::<Vec<_>>();
+        if rest.len() == 3 && rest.iter().all(|part| !part.is_empty()) && !prefix.is_empty() {
+            Some(prefix)
+        } else {
+            None
+        }
     }
 
     /// The refspec to fetch remote nomad managed refs as local refs.
@@ -64,40 +216,139 @@ mod namespace {
     /// `refs/nomad/rraval/apollo/master` becomes `refs/nomad/apollo/master`.
     ///
     /// `refs/nomad/rraval/boreas/feature` becomes `refs/nomad/boreas/feature`.
-    pub fn fetch_refspec(user: &User) -> String {
+    pub fn fetch_refspec(prefix: &str, user: &User) -> String {
         format!(
             "+{remote_pattern}:refs/{prefix}/*",
-            remote_pattern = list_refspec(user),
-            prefix = PREFIX,
+            remote_pattern = list_refspec(prefix, user),
+            prefix = prefix,
         )
     }
 
-    /// The refspec to push local branches as nomad managed refs in the remote.
+    /// The refspec to push local refs under `refs/{hierarchy}` as nomad managed refs in the
+    /// remote, see [`super::Tracked::hierarchy`].
     ///
     /// When run on host `boreas` that has a branch named `feature`:
     /// `refs/heads/feature` becomes `refs/nomad/rraval/boreas/feature`.
-    pub fn push_refspec(user: &User, host: &Host) -> String {
+    ///
+    /// # Panics
+    ///
+    /// If `prefix` is one of [`RESERVED_PREFIXES`], which would overwrite real branches.
+    pub fn push_refspec(prefix: &str, hierarchy: &str, user: &User, host: &Host) -> String {
+        assert_not_reserved(prefix);
+        format!(
+            "+refs/{hierarchy}/*:refs/{prefix}/{user}/{host}/*",
+            hierarchy = hierarchy,
+            prefix = prefix,
+            user = user.0,
+            host = host.0,
+        )
+    }
+
+    /// Like [`push_refspec`], but for a single named branch.
+    pub fn push_branch_refspec(
+        prefix: &str,
+        hierarchy: &str,
+        user: &User,
+        host: &Host,
+        branch: &Branch,
+    ) -> String {
+        assert_not_reserved(prefix);
         format!(
-            "+refs/heads/*:refs/{prefix}/{user}/{host}/*",
-            prefix = PREFIX,
+            "+refs/{hierarchy}/{branch}:refs/{prefix}/{user}/{host}/{branch}",
+            hierarchy = hierarchy,
+            prefix = prefix,
             user = user.0,
             host = host.0,
+            branch = branch.0,
+        )
+    }
+
+    /// Where [`crate::git_binary::GitBinary::update_tracking_refs`] copies the local nomad ref for
+    /// `host` and `branch`, so that history tools treat it like a remote-tracking branch.
+    ///
+    /// `refs/nomad/boreas/feature` is copied to `refs/remotes/nomad/boreas/feature`.
+    pub fn tracking_ref(prefix: &str, host: &Host, branch: &Branch) -> String {
+        format!("{}{}/{}", tracking_refs_prefix(prefix), host.0, branch.0)
+    }
+
+    /// The hierarchy that every [`tracking_ref`] lives under.
+    pub fn tracking_refs_prefix(prefix: &str) -> String {
+        format!("refs/remotes/{}/", prefix)
+    }
+
+    /// The local branch that [`crate::git_binary::GitBinary::checkout_nomad_ref`] creates to
+    /// inspect `branch` from `host`.
+    ///
+    /// The branch `feature` from host `boreas` is checked out as `boreas/feature`. Naming it
+    /// `nomad/boreas/feature` instead would be ambiguous with `refs/nomad/boreas/feature`, which
+    /// git resolves first.
+    pub fn checkout_branch(host: &Host, branch: &Branch) -> Branch<'static> {
+        Branch::from(format!("{}/{}", host.0, branch.0))
+    }
+
+    /// The last line of defence behind [`super::check_ref_prefix`], since a push to a reserved
+    /// hierarchy force-overwrites real refs.
+    fn assert_not_reserved(prefix: &str) {
+        assert!(
+            !RESERVED_PREFIXES.contains(&prefix),
+            "refusing to push nomad refs into refs/{}",
+            prefix
+        );
+    }
+
+    /// The prefix that local tags are tracked under, kept apart from `prefix` since a tag and a
+    /// branch can share a name.
+    ///
+    /// `nomad` becomes `nomad-tags`, so the tag `v1` on host `boreas` is pushed to
+    /// `refs/nomad-tags/rraval/boreas/v1`.
+    pub fn tags_prefix(prefix: &str) -> String {
+        format!("{}-tags", prefix)
+    }
+
+    /// The synthetic host that mirrors the branches of `remote` itself, so that the canonical
+    /// state of the remote shows up like any other host.
+    ///
+    /// `origin` becomes `remote-origin`.
+    pub fn mirror_host(remote: &Remote) -> Host<'static> {
+        Host::from(format!("remote-{}", remote.0))
+    }
+
+    /// The refspec to fetch a remote's branches as local refs for its [`mirror_host`].
+    ///
+    /// `refs/heads/master` on the `origin` remote becomes `refs/nomad/remote-origin/master`.
+    pub fn mirror_fetch_refspec(prefix: &str, mirror_host: &Host) -> String {
+        format!(
+            "+refs/heads/*:refs/{prefix}/{host}/*",
+            prefix = prefix,
+            host = mirror_host.0,
+        )
+    }
+
+    /// The refspec to push the local refs for a [`mirror_host`] as nomad managed refs in the
+    /// remote.
+    ///
+    /// `refs/nomad/remote-origin/master` becomes `refs/nomad/rraval/remote-origin/master`.
+    pub fn mirror_push_refspec(prefix: &str, user: &User, mirror_host: &Host) -> String {
+        format!(
+            "+refs/{prefix}/{host}/*:refs/{prefix}/{user}/{host}/*",
+            prefix = prefix,
+            user = user.0,
+            host = mirror_host.0,
         )
     }
 
     impl<Ref> NomadRef<'_, Ref> {
         /// A nomad ref in the local clone, which elides the user name for convenience.
-        #[cfg(test)]
-        pub fn to_git_local_ref(&self) -> String {
-            format!("refs/{}/{}/{}", PREFIX, self.host.0, self.branch.0)
+        pub fn to_git_local_ref(&self, prefix: &str) -> String {
+            format!("refs/{}/{}/{}", prefix, self.host.0, self.branch.0)
         }
 
         /// A nomad ref in the remote. The remote may have many users that all use `git-nomad` and
         /// so shouldn't step on each others toes.
-        pub fn to_git_remote_ref(&self) -> String {
+        pub fn to_git_remote_ref(&self, prefix: &str) -> String {
             format!(
                 "refs/{}/{}/{}/{}",
-                PREFIX, self.user.0, self.host.0, self.branch.0
+                prefix, self.user.0, self.host.0, self.branch.0
             )
         }
     }
@@ -105,20 +356,24 @@ mod namespace {
     impl NomadRef<'_, GitRef> {
         /// Constructs a [`NomadRef`] from a git ref in the local clone, which elides the user name
         /// for convenience.
+        ///
+        /// The host name is shared with every other ref parsed through `interner`.
         pub fn from_git_local_ref<'a>(
+            prefix: &str,
             user: &'a User,
+            interner: &mut Interner,
             git_ref: GitRef,
         ) -> Result<NomadRef<'a, GitRef>, GitRef> {
             let parts = git_ref.name.split('/').collect::<Vec<_>>();
             match parts.as_slice() {
-                ["refs", prefix, host, branch_segments @ ..] => {
-                    if prefix != &PREFIX {
+                ["refs", parsed_prefix, host, branch_segments @ ..] => {
+                    if parsed_prefix != &prefix {
                         return Err(git_ref);
                     }
 
                     Ok(NomadRef {
                         user: user.always_borrow(),
-                        host: Host::from(host.to_string()),
+                        host: Host::interned(interner, host),
                         branch: Branch::from(branch_segments.join("/")),
                         ref_: git_ref,
                     })
@@ -129,18 +384,24 @@ mod namespace {
 
         /// Constructs a [`NomadRef`] from a git ref in the remote, which includes the user as part
         /// of the ref name.
-        pub fn from_git_remote_ref(git_ref: GitRef) -> Result<NomadRef<'static, GitRef>, GitRef> {
+        ///
+        /// The user and host names are shared with every other ref parsed through `interner`.
+        pub fn from_git_remote_ref(
+            prefix: &str,
+            interner: &mut Interner,
+            git_ref: GitRef,
+        ) -> Result<NomadRef<'static, GitRef>, GitRef> {
             let parts = git_ref.name.split('/').collect::<Vec<_>>();
             match parts.as_slice() {
-                ["refs", prefix, user, host, branch_name] => {
-                    if prefix != &PREFIX {
+                ["refs", parsed_prefix, user, host, branch_segments @ ..] => {
+                    if parsed_prefix != &prefix || branch_segments.is_empty() {
                         return Err(git_ref);
                     }
 
                     Ok(NomadRef {
-                        user: User::from(user.to_string()),
-                        host: Host::from(host.to_string()),
-                        branch: Branch::from(branch_name.to_string()),
+                        user: User::interned(interner, user),
+                        host: Host::interned(interner, host),
+                        branch: Branch::from(branch_segments.join("/")),
                         ref_: git_ref,
                     })
                 }
@@ -153,42 +414,85 @@ mod namespace {
     mod tests {
         use crate::{
             git_ref::GitRef,
-            types::{Branch, Host, NomadRef, User},
+            types::{Branch, Host, Interner, NomadRef, User},
         };
 
+        use super::{detected_prefix, push_refspec, PREFIX};
+
         const USER: &str = "user0";
         const HOST: &str = "host0";
         const BRANCH: &str = "branch0";
 
+        /// Ref prefixes to exercise, including one with a dash.
+        const PREFIXES: &[&str] = &[PREFIX, "team-a"];
+
+        /// Only refs with a prefix, user, host, and branch count towards a prefix.
+        #[test]
+        fn test_detected_prefix() {
+            assert_eq!(
+                detected_prefix("refs/team-a/user0/host0/master"),
+                Some("team-a")
+            );
+            assert_eq!(
+                detected_prefix("refs/team-a/user0/host0/feature/x"),
+                Some("team-a")
+            );
+            for ref_name in [
+                "refs/team-a/user0/master",
+                "refs/heads/user0/host0/master",
+                "refs/tags/user0/host0/v1",
+                "refs/remotes/origin/feature/x",
+                "refs/notes/user0/host0/commits",
+                "refs//user0/host0/master",
+            ] {
+                assert_eq!(detected_prefix(ref_name), None, "{}", ref_name);
+            }
+        }
+
+        /// Push refspecs must never be built for a reserved prefix.
+        #[test]
+        #[should_panic(expected = "refusing to push nomad refs into refs/heads")]
+        fn test_push_refspec_reserved() {
+            push_refspec("heads", "heads", &User::from(USER), &Host::from(HOST));
+        }
+
         /// [`NomadRef::from_git_local_ref`] should be able to parse ref names produced by
         /// [`NomadRef::to_git_local_ref`] (they are duals).
         #[test]
         fn test_to_and_from_local_ref() {
-            let local_ref_name = NomadRef {
-                user: User::from(USER),
-                host: Host::from(HOST),
-                branch: Branch::from(BRANCH),
-                ref_: (),
-            }
-            .to_git_local_ref();
+            for prefix in PREFIXES {
+                let local_ref_name = NomadRef {
+                    user: User::from(USER),
+                    host: Host::from(HOST),
+                    branch: Branch::from(BRANCH),
+                    ref_: (),
+                }
+                .to_git_local_ref(prefix);
 
-            let local_git_ref = GitRef {
-                commit_id: "some_commit_id".to_string(),
-                name: local_ref_name,
-            };
+                let local_git_ref = GitRef {
+                    commit_id: "some_commit_id".to_string(),
+                    name: local_ref_name,
+                };
 
-            let user = &User::from(USER);
-            let nomad_ref = NomadRef::<GitRef>::from_git_local_ref(user, local_git_ref).unwrap();
+                let user = &User::from(USER);
+                let nomad_ref = NomadRef::<GitRef>::from_git_local_ref(
+                    prefix,
+                    user,
+                    &mut Interner::default(),
+                    local_git_ref,
+                )
+                .unwrap();
 
-            assert_eq!(&nomad_ref.user.0, USER);
-            assert_eq!(&nomad_ref.host.0, HOST);
-            assert_eq!(&nomad_ref.branch.0, BRANCH);
+                assert_eq!(&nomad_ref.user.0, USER);
+                assert_eq!(&nomad_ref.host.0, HOST);
+                assert_eq!(&nomad_ref.branch.0, BRANCH);
+            }
         }
 
         #[test]
         fn test_from_local_ref_with_slashes() {
             for segment_count in 1..3 {
-                let segments: Vec<_> = std::iter::repeat(BRANCH).take(segment_count).collect();
+                let segments: Vec<_> = std::iter::repeat_n(BRANCH, segment_count).collect();
                 let branch = segments.join("/");
 
                 let local_ref_name = NomadRef {
@@ -197,7 +501,7 @@ mod namespace {
                     branch: Branch::from(branch.clone()),
                     ref_: (),
                 }
-                .to_git_local_ref();
+                .to_git_local_ref(PREFIX);
 
                 let local_git_ref = GitRef {
                     commit_id: "some_commit_id".to_string(),
@@ -205,8 +509,13 @@ mod namespace {
                 };
 
                 let user = &User::from(USER);
-                let nomad_ref =
-                    NomadRef::<GitRef>::from_git_local_ref(user, local_git_ref).unwrap();
+                let nomad_ref = NomadRef::<GitRef>::from_git_local_ref(
+                    PREFIX,
+                    user,
+                    &mut Interner::default(),
+                    local_git_ref,
+                )
+                .unwrap();
 
                 assert_eq!(&nomad_ref.user.0, USER);
                 assert_eq!(&nomad_ref.host.0, HOST);
@@ -218,7 +527,9 @@ mod namespace {
         fn test_from_local_ref_err() {
             let user = &User::from(USER);
             let nomad_ref = NomadRef::<GitRef>::from_git_local_ref(
+                PREFIX,
                 user,
+                &mut Interner::default(),
                 GitRef {
                     commit_id: "some_commit_id".to_string(),
                     name: "refs/not_a_nomad_ref".to_string(),
@@ -234,44 +545,459 @@ mod namespace {
             );
         }
 
+        /// [`NomadRef::from_git_local_ref`] should refuse to parse refs with a different prefix.
+        #[test]
+        fn test_from_local_ref_wrong_prefix() {
+            let user = &User::from(USER);
+            let local_git_ref = GitRef {
+                commit_id: "some_commit_id".to_string(),
+                name: "refs/team-a/host/branch".to_string(),
+            };
+
+            let parsed = NomadRef::<GitRef>::from_git_local_ref(
+                PREFIX,
+                user,
+                &mut Interner::default(),
+                local_git_ref,
+            );
+            assert!(parsed.is_err());
+        }
+
         /// [`NomadRef::from_git_remote_ref`] should be able to parse ref names produced by
         /// [`NomadRef::to_git_local_ref`] (they are duals).
         #[test]
         fn test_to_and_from_remote_ref() {
-            let remote_ref_name = NomadRef {
-                user: User::from(USER),
-                host: Host::from(HOST),
-                branch: Branch::from(BRANCH),
-                ref_: (),
+            for prefix in PREFIXES {
+                let remote_ref_name = NomadRef {
+                    user: User::from(USER),
+                    host: Host::from(HOST),
+                    branch: Branch::from(BRANCH),
+                    ref_: (),
+                }
+                .to_git_remote_ref(prefix);
+
+                let remote_git_ref = GitRef {
+                    commit_id: "some_commit_id".to_string(),
+                    name: remote_ref_name,
+                };
+
+                let nomad_ref = NomadRef::<GitRef>::from_git_remote_ref(
+                    prefix,
+                    &mut Interner::default(),
+                    remote_git_ref,
+                )
+                .unwrap();
+
+                assert_eq!(&nomad_ref.user.0, USER);
+                assert_eq!(&nomad_ref.host.0, HOST);
+                assert_eq!(&nomad_ref.branch.0, BRANCH);
             }
-            .to_git_remote_ref();
+        }
 
-            let remote_git_ref = GitRef {
-                commit_id: "some_commit_id".to_string(),
-                name: remote_ref_name,
-            };
+        #[test]
+        fn test_from_remote_ref_with_slashes() {
+            for segment_count in 1..3 {
+                let segments: Vec<_> = std::iter::repeat_n(BRANCH, segment_count).collect();
+                let branch = segments.join("/");
+
+                let remote_ref_name = NomadRef {
+                    user: User::from(USER),
+                    host: Host::from(HOST),
+                    branch: Branch::from(branch.clone()),
+                    ref_: (),
+                }
+                .to_git_remote_ref(PREFIX);
+
+                let remote_git_ref = GitRef {
+                    commit_id: "some_commit_id".to_string(),
+                    name: remote_ref_name,
+                };
 
-            let nomad_ref = NomadRef::<GitRef>::from_git_remote_ref(remote_git_ref).unwrap();
+                let nomad_ref = NomadRef::<GitRef>::from_git_remote_ref(
+                    PREFIX,
+                    &mut Interner::default(),
+                    remote_git_ref,
+                )
+                .unwrap();
 
-            assert_eq!(&nomad_ref.user.0, USER);
-            assert_eq!(&nomad_ref.host.0, HOST);
-            assert_eq!(&nomad_ref.branch.0, BRANCH);
+                assert_eq!(&nomad_ref.user.0, USER);
+                assert_eq!(&nomad_ref.host.0, HOST);
+                assert_eq!(nomad_ref.branch.0, std::borrow::Cow::from(branch));
+            }
         }
 
         /// [`NomadRef::from_git_remote_ref`] should refuse to parse refs with a different prefix.
         #[test]
         fn test_from_remote_ref_wrong_prefix() {
-            let remote_git_ref = GitRef {
-                commit_id: "some_commit_id".to_string(),
-                name: "refs/something/user/host/branch".to_string(),
-            };
+            for prefix in PREFIXES {
+                let remote_git_ref = GitRef {
+                    commit_id: "some_commit_id".to_string(),
+                    name: "refs/something/user/host/branch".to_string(),
+                };
 
-            let parsed = NomadRef::<GitRef>::from_git_remote_ref(remote_git_ref);
-            assert!(parsed.is_err());
+                let parsed = NomadRef::<GitRef>::from_git_remote_ref(
+                    prefix,
+                    &mut Interner::default(),
+                    remote_git_ref,
+                );
+                assert!(parsed.is_err());
+            }
+        }
+
+        /// Parsing many refs from a handful of hosts should produce the right names while
+        /// sharing the storage for repeated users and hosts.
+        #[test]
+        fn test_from_remote_ref_interns_repeated_names() {
+            let hosts = ["host0", "host1", "host2"];
+            let mut interner = Interner::default();
+            let parsed = (0..300)
+                .map(|i| {
+                    let host = hosts[i % hosts.len()];
+                    let remote_git_ref = GitRef {
+                        commit_id: "some_commit_id".to_string(),
+                        name: format!("refs/{PREFIX}/{USER}/{host}/branch{i}"),
+                    };
+                    NomadRef::<GitRef>::from_git_remote_ref(PREFIX, &mut interner, remote_git_ref)
+                        .unwrap()
+                })
+                .collect::<Vec<_>>();
+
+            for (i, nomad_ref) in parsed.iter().enumerate() {
+                assert_eq!(&nomad_ref.user.0, USER);
+                assert_eq!(&nomad_ref.host.0, hosts[i % hosts.len()]);
+                assert_eq!(nomad_ref.branch.0, format!("branch{i}"));
+
+                let first = &parsed[i % hosts.len()];
+                assert_eq!(nomad_ref.user.0.as_ptr(), first.user.0.as_ptr());
+                assert_eq!(nomad_ref.host.0.as_ptr(), first.host.0.as_ptr());
+            }
+        }
+    }
+}
+
+/// The prefix for nomad managed refs when the user has not picked one, see
+/// [`GitBinary::ref_prefix`].
+pub const DEFAULT_REF_PREFIX: &str = namespace::PREFIX;
+
+/// Reject a [`GitBinary::ref_prefix`] that names one of git's own hierarchies, like `heads`,
+/// since nomad force-pushes into `refs/{prefix}` and would clobber real branches.
+pub fn check_ref_prefix(prefix: &str) -> std::result::Result<(), String> {
+    if namespace::RESERVED_PREFIXES.contains(&prefix) {
+        return Err(format!(
+            "refs/{} is reserved for git itself, pushing nomad refs there would overwrite them",
+            prefix
+        ));
+    }
+    Ok(())
+}
+
+/// Which local refs a [`GitBinary`] pushes as nomad managed refs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Tracked {
+    #[default]
+    Branches,
+    /// Tags, under their own ref prefix, see [`GitBinary::for_tags`].
+    Tags,
+}
+
+impl Tracked {
+    /// The `refs/{hierarchy}` that the local refs live under.
+    pub fn hierarchy(self) -> &'static str {
+        match self {
+            Self::Branches => "heads",
+            Self::Tags => "tags",
+        }
+    }
+
+    /// What to call the local refs in progress messages.
+    fn noun(self) -> &'static str {
+        match self {
+            Self::Branches => "branches",
+            Self::Tags => "tags",
+        }
+    }
+}
+
+/// How much [`GitBinary::push_nomad_refs`] pushed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PushCount {
+    /// Refspecs given to `git push`, where the wildcard refspec for every branch counts once.
+    pub refspecs: usize,
+    /// Refs at the remote that were created, updated, or deleted, leaving out those that were
+    /// already up to date.
+    pub updated: usize,
+}
+
+/// How many refs the output of `git push --porcelain` reports as changed.
+///
+/// Each ref gets a `<flag>\t<from>:<to>\t<summary>` line, where `=` flags refs that were already
+/// up to date and `!` flags rejected refs.
+fn count_updated_refs(porcelain: &str) -> usize {
+    porcelain
+        .lines()
+        .filter(|line| {
+            line.split_once('\t')
+                .is_some_and(|(flag, _)| [" ", "+", "-", "*"].contains(&flag))
+        })
+        .count()
+}
+
+/// Whether git would read `remote` as a URL or path rather than the name of a configured remote,
+/// like `https://example.com/repo.git`, `git@example.com:repo.git`, or `../repo.git`.
+///
+/// Relative paths are resolved against the current directory, just like git does.
+fn is_url_or_path(remote: &str) -> bool {
+    remote.contains(':') || remote.starts_with(['/', '.', '~']) || Path::new(remote).exists()
+}
+
+/// Render a committer like `Name <email>`, dropping whichever half is empty, or `None` when the
+/// commit has neither.
+fn format_committer(name: &str, email: &str) -> Option<String> {
+    match (name.is_empty(), email.is_empty()) {
+        (false, false) => Some(format!("{} <{}>", name, email)),
+        (false, true) => Some(name.to_owned()),
+        (true, false) => Some(format!("<{}>", email)),
+        (true, true) => None,
+    }
+}
+
+/// The branch named by the contents of a `HEAD` file, or `None` when it is anything other than a
+/// symbolic ref to a branch.
+///
+/// The reftable backend writes `ref: refs/heads/.invalid` since the real `HEAD` lives in the
+/// table, which can never be a valid branch name.
+fn parse_head_branch(head: &str) -> Option<&str> {
+    head.strip_prefix("ref: refs/heads/")
+        .map(|branch| branch.trim_end_matches('\n'))
+        .filter(|branch| !branch.is_empty() && *branch != ".invalid")
+}
+
+/// Knobs to tweak how refs are fetched from a remote.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FetchOptions {
+    /// Fetch commits and trees but not blobs (`--filter=blob:none`), deferring their download
+    /// until something actually needs them.
+    ///
+    /// Requires the remote to support partial clone, and marks it as a promisor remote in the
+    /// local repository.
+    pub partial: bool,
+    /// Only fetch this many commits of history for each ref (`--depth`).
+    ///
+    /// Only allowed in a clone that is already shallow, see [`GitBinary::is_shallow`], since git
+    /// would otherwise make a full clone shallow and truncate the history of its local branches.
+    pub depth: Option<u32>,
+    /// Record the nomad refs changed by the fetch, see [`GitBinary::write_fetch_summary`].
+    pub write_summary: bool,
+}
+
+/// Config passed to every `git push`, so that the user's `push.*` settings cannot add to (like
+/// `push.followTags`) or otherwise alter nomad's explicit refspecs. Hooks are skipped separately
+/// with `--no-verify`.
+const PUSH_CONFIG_OVERRIDES: [&str; 8] = [
+    "-c",
+    "push.default=nothing",
+    "-c",
+    "push.followTags=false",
+    "-c",
+    "push.recurseSubmodules=no",
+    "-c",
+    "push.gpgSign=false",
+];
+
+/// How many hex digits git abbreviates commit IDs to when `core.abbrev` is not set.
+const DEFAULT_ABBREV: usize = 7;
+
+/// The shortest abbreviation git allows for `core.abbrev`.
+const MIN_ABBREV: usize = 4;
+
+/// How long [`GitBinary::check_remote`] waits for the remote to respond.
+const CHECK_REMOTE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Why a remote could not be used, as classified from git's error output by
+/// [`RemoteProblem::classify`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RemoteProblem {
+    /// The host could not be reached at all, like a mistyped SSH alias or a network outage.
+    Unreachable,
+    /// The host was reached, but has no repository at that path.
+    NotFound,
+    /// The host refused the credentials, or none were available.
+    Authentication,
+    /// Something else went wrong.
+    Unknown,
+}
+
+impl RemoteProblem {
+    /// Classify the `stderr` of a failed `git ls-remote`.
+    ///
+    /// Authentication is checked first since SSH permission errors are usually followed by a
+    /// generic "Could not read from remote repository".
+    pub fn classify(stderr: &str) -> Self {
+        const AUTHENTICATION: &[&str] = &[
+            "Permission denied",
+            "Authentication failed",
+            "could not read Username",
+            "could not read Password",
+            "terminal prompts disabled",
+            "Host key verification failed",
+            "returned error: 403",
+        ];
+        const UNREACHABLE: &[&str] = &[
+            "Could not resolve hostname",
+            "Could not resolve host",
+            "Connection refused",
+            "Connection timed out",
+            "Network is unreachable",
+            "No route to host",
+            "Failed to connect",
+            "Operation timed out",
+        ];
+        const NOT_FOUND: &[&str] = &[
+            "does not appear to be a git repository",
+            "Repository not found",
+            "The project you were looking for could not be found",
+            "returned error: 404",
+        ];
+
+        let matches_any = |patterns: &[&str]| patterns.iter().any(|p| stderr.contains(p));
+
+        if matches_any(AUTHENTICATION) {
+            Self::Authentication
+        } else if matches_any(UNREACHABLE) {
+            Self::Unreachable
+        } else if matches_any(NOT_FOUND) {
+            Self::NotFound
+        } else {
+            Self::Unknown
+        }
+    }
+
+    fn describe(self, remote: &Remote) -> String {
+        match self {
+            Self::Unreachable => format!("Remote {} is unreachable", remote.0),
+            Self::NotFound => format!("Remote {} has no git repository", remote.0),
+            Self::Authentication => format!("Authentication failed for remote {}", remote.0),
+            Self::Unknown => format!("Remote {} could not be checked", remote.0),
+        }
+    }
+}
+
+/// Which `git config` file to write to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigScope {
+    /// The repository's own `.git/config`.
+    Local,
+    /// The user's `~/.gitconfig`, shared by all repositories.
+    Global,
+}
+
+impl ConfigScope {
+    fn flag(self) -> &'static str {
+        match self {
+            Self::Local => "--local",
+            Self::Global => "--global",
+        }
+    }
+}
+
+/// Makes every git command that talks to a remote over SSH share a single connection per host via
+/// OpenSSH's `ControlMaster`, instead of paying for a new handshake each time.
+///
+/// The control sockets live in a temporary directory, and any master connections still lingering
+/// are closed and the directory removed when this is dropped.
+pub struct SshMultiplex {
+    control_dir: TempDir,
+    /// The SSH command being multiplexed, used again to close the master connections.
+    base_command: String,
+    /// The value for `GIT_SSH_COMMAND`.
+    ssh_command: String,
+}
+
+impl SshMultiplex {
+    /// How long a master connection stays open after the last command using it exits.
+    const PERSIST_SECONDS: u32 = 60;
+
+    /// The longest control directory that still fits socket paths in `sun_path`, which is only
+    /// 104 bytes on macOS. Each socket adds a `/`, the 40 hex digits of `%C`, and the 17 byte
+    /// random suffix of the temporary name SSH binds before renaming, plus a trailing NUL.
+    const MAX_CONTROL_DIR_LEN: usize = 104 - 1 - 40 - 17 - 1;
+
+    /// Multiplex connections made by `base_command`, see [`GitBinary::ssh_command`].
+    pub fn new(base_command: &str) -> Result<Self> {
+        Self::new_in(base_command, std::env::temp_dir())
+    }
+
+    /// Like [`Self::new`], but with the control directory under `base_dir`.
+    ///
+    /// `TMPDIR` is a long per-user path on macOS, so this falls back to `/tmp` when `base_dir`
+    /// leaves no room for the socket names.
+    fn new_in(base_command: &str, mut base_dir: PathBuf) -> Result<Self> {
+        // Account for the random suffix `tempfile` appends to the prefix.
+        if base_dir.join("git-nomad-ssh-XXXXXX").as_os_str().len() > Self::MAX_CONTROL_DIR_LEN {
+            base_dir = PathBuf::from("/tmp");
+        }
+        let control_dir = tempfile::Builder::new()
+            .prefix("git-nomad-ssh-")
+            .tempdir_in(base_dir)
+            .context("creating a directory for SSH control sockets")?;
+
+        // `%C` hashes the connection details into a fixed length socket name. The command is run
+        // by a shell, so the path is quoted.
+        let ssh_command = format!(
+            "{} -o ControlMaster=auto -o ControlPersist={} -o 'ControlPath={}/%C'",
+            base_command,
+            Self::PERSIST_SECONDS,
+            control_dir.path().display(),
+        );
+
+        Ok(Self {
+            control_dir,
+            base_command: base_command.to_string(),
+            ssh_command,
+        })
+    }
+}
+
+impl Drop for SshMultiplex {
+    fn drop(&mut self) {
+        let Ok(sockets) = fs::read_dir(self.control_dir.path()) else {
+            return;
+        };
+
+        for socket in sockets.flatten() {
+            // Run through a shell like git does with `GIT_SSH_COMMAND`. With an explicit
+            // `ControlPath`, the destination is only a placeholder.
+            let _ = Command::new("sh")
+                .arg("-c")
+                .arg(format!(
+                    "{} -o 'ControlPath={}' -O exit git-nomad",
+                    self.base_command,
+                    socket.path().display()
+                ))
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status();
         }
     }
 }
 
+impl PartialEq for SshMultiplex {
+    fn eq(&self, other: &Self) -> bool {
+        self.ssh_command == other.ssh_command
+    }
+}
+
+impl Eq for SshMultiplex {}
+
+/// How many refs [`GitBinary::prune_remote_nomad_refs`] deletes per `git push`.
+const PRUNE_BATCH_SIZE: usize = 100;
+
+/// How many changed refs [`GitBinary::fetch_changed_nomad_refs`] fetches by name before falling
+/// back to fetching everything.
+const SELECTIVE_FETCH_LIMIT: usize = 20;
+
 /// Implements repository manipulations by delegating to some ambient `git` binary that exists
 /// somewhere on the system.
 #[derive(PartialEq, Eq)]
@@ -285,6 +1011,34 @@ pub struct GitBinary<'name> {
 
     /// The absolute path to the `.git` directory of the repository.
     git_dir: String,
+
+    /// The `refs/{ref_prefix}` hierarchy that nomad managed refs live under, both locally and in
+    /// the remote. Teams sharing a remote can pick different prefixes to avoid colliding.
+    pub ref_prefix: String,
+    /// Which local refs are pushed under [`Self::ref_prefix`], and thus which local refs count as
+    /// the "branches" of a [`Snapshot`].
+    pub tracked: Tracked,
+
+    /// Whether commands that talk to a remote may prompt for credentials.
+    ///
+    /// When unset, missing credentials fail fast instead of hanging a non-interactive run.
+    pub allow_prompt: bool,
+    /// Shares SSH connections between commands that talk to a remote, see [`SshMultiplex`].
+    pub ssh_multiplex: Option<Rc<SshMultiplex>>,
+    /// Scopes every ref on the remote under `refs/namespaces/{namespace}`, see `gitnamespaces(7)`.
+    ///
+    /// Only takes effect when the server side `git-upload-pack` and `git-receive-pack` see
+    /// `GIT_NAMESPACE`, which local path remotes always do.
+    pub git_namespace: Option<String>,
+    /// Whether [`GitBinary::snapshot`] should warn about nomad refs whose commit is missing from
+    /// the local object database, as happens with corruption or an interrupted partial fetch.
+    pub verify_objects: bool,
+    /// How often fetches, pushes, and listing refs at a remote are retried when they fail for
+    /// transient network reasons, see [`retry_transient`].
+    pub retries: Retries,
+    /// The version of git, detected by [`GitBinary::version`] the first time a [`GitFeature`]
+    /// needs it, so that runs that do not use any pay nothing for it.
+    version: OnceLock<Option<GitVersion>>,
 }
 
 impl<'name> GitBinary<'name> {
@@ -308,23 +1062,289 @@ impl<'name> GitBinary<'name> {
         .map(LineArity::from)
         .and_then(LineArity::one)?;
 
-        Ok(GitBinary {
-            verbosity,
-            name,
-            git_dir,
-        })
+        Ok(Self::with_resolved_git_dir(verbosity, name, git_dir))
     }
-}
 
-impl GitBinary<'_> {
-    /// Invoke a git sub-command with an explicit `--git-dir` to make it independent of the working
-    /// directory it is invoked from.
-    pub fn command(&self) -> Command {
-        let mut command = git_command(self.name.as_ref());
+    /// Like [`GitBinary::new`], but trusts that `git_dir` is the `.git` directory instead of
+    /// asking git, which saves spawning a process on every run.
+    ///
+    /// Only checks that `git_dir` looks like a git directory at all, so pointing this at the wrong
+    /// repository is on the caller.
+    pub fn with_git_dir(
+        verbosity: Option<Verbosity>,
+        name: Cow<'name, str>,
+        git_dir: &Path,
+    ) -> Result<Self> {
+        if !git_dir.join("HEAD").is_file() {
+            bail!("{} is not a .git directory", git_dir.display());
+        }
+
+        let git_dir = git_dir
+            .canonicalize()
+            .with_context(|| format!("resolving {}", git_dir.display()))?
+            .to_str()
+            .with_context(|| format!("{} is not valid UTF-8", git_dir.display()))?
+            .to_string();
+
+        Ok(Self::with_resolved_git_dir(verbosity, name, git_dir))
+    }
+
+    fn with_resolved_git_dir(
+        verbosity: Option<Verbosity>,
+        name: Cow<'name, str>,
+        git_dir: String,
+    ) -> Self {
+        GitBinary {
+            verbosity,
+            name,
+            git_dir,
+            ref_prefix: namespace::PREFIX.to_string(),
+            tracked: Tracked::Branches,
+            allow_prompt: false,
+            ssh_multiplex: None,
+            git_namespace: None,
+            verify_objects: false,
+            retries: Retries::NONE,
+            version: OnceLock::new(),
+        }
+    }
+
+    /// The same repository and settings, but tracking local tags under
+    /// [`namespace::tags_prefix`] instead of branches.
+    ///
+    /// Every method works the same on the result, with tags standing in for branches.
+    pub fn for_tags(&self) -> GitBinary<'name> {
+        GitBinary {
+            verbosity: self.verbosity,
+            name: self.name.clone(),
+            git_dir: self.git_dir.clone(),
+            ref_prefix: namespace::tags_prefix(&self.ref_prefix),
+            tracked: Tracked::Tags,
+            allow_prompt: self.allow_prompt,
+            ssh_multiplex: self.ssh_multiplex.clone(),
+            git_namespace: self.git_namespace.clone(),
+            verify_objects: self.verify_objects,
+            retries: self.retries,
+            version: self.version.clone(),
+        }
+    }
+}
+
+impl GitBinary<'_> {
+    /// The version of git, or `None` if `git --version` could not be understood.
+    ///
+    /// Only asks git once, later calls reuse the answer.
+    pub fn version(&self, renderer: &mut impl Renderer) -> Result<Option<GitVersion>> {
+        if let Some(version) = self.version.get() {
+            return Ok(*version);
+        }
+
+        let reported = git_version(renderer, self.verbosity, &self.name)?;
+        Ok(*self.version.get_or_init(|| GitVersion::parse(&reported)))
+    }
+
+    /// Error out with the version that `feature` needs if git is too old for it, see
+    /// [`GitFeature::min_version`].
+    pub fn require(&self, renderer: &mut impl Renderer, feature: GitFeature) -> Result<()> {
+        feature.check(self.version(renderer)?)
+    }
+
+    /// Invoke a git sub-command with an explicit `--git-dir` to make it independent of the working
+    /// directory it is invoked from.
+    pub fn command(&self) -> Command {
+        let mut command = git_command(self.name.as_ref());
         command.args(["--git-dir", &self.git_dir]);
         command
     }
 
+    /// Like [`GitBinary::command`], but for sub-commands that talk to a remote and might need
+    /// credentials.
+    ///
+    /// Unless [`GitBinary::allow_prompt`] is set, git is prevented from prompting on the terminal
+    /// or through any askpass helper (an empty `GIT_ASKPASS` takes precedence over
+    /// `core.askPass` and `SSH_ASKPASS`).
+    fn remote_command(&self) -> Command {
+        let mut command = self.command();
+        if !self.allow_prompt {
+            command
+                .env("GIT_TERMINAL_PROMPT", "0")
+                .env("GIT_ASKPASS", "");
+        }
+        if let Some(multiplex) = &self.ssh_multiplex {
+            command.env("GIT_SSH_COMMAND", &multiplex.ssh_command);
+        }
+        if let Some(namespace) = &self.git_namespace {
+            command.env("GIT_NAMESPACE", namespace);
+        }
+        command
+    }
+
+    /// The SSH command git would use on its own, so that [`SshMultiplex`] can add to it rather
+    /// than replace it.
+    ///
+    /// Follows git's precedence of `GIT_SSH_COMMAND` over `core.sshCommand`. `GIT_SSH` is not
+    /// considered since it names a program that may not understand OpenSSH options.
+    pub fn ssh_command(&self, renderer: &mut impl Renderer) -> Result<String> {
+        if let Some(command) = env::var("GIT_SSH_COMMAND").ok().filter(|c| !c.is_empty()) {
+            return Ok(command);
+        }
+        Ok(self
+            .get_git_config(renderer, "core.sshCommand")?
+            .unwrap_or_else(|| "ssh".to_string()))
+    }
+
+    /// Probe that `remote` is usable before doing any real work, with a short timeout so that a
+    /// wrong SSH alias does not hang.
+    ///
+    /// Failures are explained via [`RemoteProblem`].
+    pub fn check_remote(&self, renderer: &mut impl Renderer, remote: &Remote) -> Result<()> {
+        let result = with_timeout(CHECK_REMOTE_TIMEOUT, || {
+            run_notable(
+                renderer,
+                self.verbosity,
+                format!("Checking remote {}", remote.0),
+                self.remote_command().args(["ls-remote", &remote.0, "HEAD"]),
+            )
+        });
+
+        let Err(error) = result else {
+            return Ok(());
+        };
+
+        let problem = if error.downcast_ref::<TimedOut>().is_some() {
+            RemoteProblem::Unreachable
+        } else if let Some(failure) = error.downcast_ref::<CommandFailure>() {
+            RemoteProblem::classify(&failure.stderr())
+        } else {
+            return Err(error);
+        };
+
+        Err(error.context(problem.describe(remote)))
+    }
+
+    /// Replace the obscure git error produced when [`GitBinary::remote_command`] prevents a
+    /// credential prompt with one that explains what happened.
+    fn explain_prompt_failure(&self, remote: &Remote, error: anyhow::Error) -> anyhow::Error {
+        let prompt_disabled = !self.allow_prompt
+            && error
+                .downcast_ref::<CommandFailure>()
+                .is_some_and(|failure| failure.stderr().contains("terminal prompts disabled"));
+
+        if prompt_disabled {
+            error.context(format!(
+                "Authentication required for {}, pass --allow-prompt to enter credentials interactively",
+                remote.0
+            ))
+        } else {
+            error
+        }
+    }
+
+    /// The absolute path to the `.git` directory this binary operates on.
+    pub fn git_dir(&self) -> &Path {
+        Path::new(&self.git_dir)
+    }
+
+    /// The root of the working tree for the `.git` directory, if it has one.
+    ///
+    /// Worked out from the layout on disk rather than by asking git, since an explicit `--git-dir`
+    /// makes git treat the current directory as the working tree. Bare repositories and unusual
+    /// layouts like `core.worktree` have none.
+    pub fn work_tree(&self) -> Option<PathBuf> {
+        let git_dir = self.git_dir();
+        if git_dir.file_name() == Some(OsStr::new(".git")) {
+            return git_dir.parent().map(Path::to_path_buf);
+        }
+
+        // Linked worktrees record where their `.git` file is.
+        let gitdir_file = fs::read_to_string(git_dir.join("gitdir")).ok()?;
+        Path::new(gitdir_file.trim())
+            .parent()
+            .map(Path::to_path_buf)
+    }
+
+    /// Use the bare repository at `path` as a remote, initializing it first if it does not exist.
+    ///
+    /// Git accepts a path anywhere it expects a remote name, so the returned [`Remote`] is just the
+    /// absolute path, which keeps working no matter where git is run from.
+    pub fn local_remote(
+        &self,
+        renderer: &mut impl Renderer,
+        path: &Path,
+    ) -> Result<Remote<'static>> {
+        if !path.exists() {
+            run_notable(
+                renderer,
+                self.verbosity,
+                format!("Creating local remote at {}", path.display()),
+                git_command(self.name.as_ref())
+                    .args(["init", "--bare"])
+                    .arg(path),
+            )?;
+        } else if !path.join("HEAD").is_file() {
+            bail!("{} exists but is not a bare git repository", path.display());
+        }
+
+        let path = path
+            .canonicalize()
+            .with_context(|| format!("resolving {}", path.display()))?;
+        let path = path
+            .to_str()
+            .with_context(|| format!("{} is not valid UTF-8", path.display()))?;
+        Ok(Remote::from(path.to_string()))
+    }
+
+    /// The URL that `remote` is configured to fetch from, which fails if there is no such remote.
+    pub fn remote_url(&self, renderer: &mut impl Renderer, remote: &Remote) -> Result<String> {
+        run_trivial(
+            renderer,
+            self.verbosity,
+            format!("Resolving remote {}", remote.0),
+            self.command().args(["remote", "get-url", &remote.0]),
+        )
+        .and_then(output_stdout)
+        .map(LineArity::from)
+        .and_then(LineArity::one)
+    }
+
+    /// Error with the configured remotes when `remote` names none of them, instead of letting a
+    /// typo surface as whatever git fails with halfway through a command.
+    ///
+    /// Git accepts a URL or path anywhere it expects a remote name, so those are left for git to
+    /// resolve, see [`is_url_or_path`].
+    fn require_remote(&self, renderer: &mut impl Renderer, remote: &Remote) -> Result<()> {
+        if is_url_or_path(&remote.0) || self.remote_url(renderer, remote).is_ok() {
+            return Ok(());
+        }
+
+        let known = self
+            .list_remotes(renderer)?
+            .into_iter()
+            .map(|remote| remote.0.into_owned())
+            .collect::<Vec<_>>();
+        let known = if known.is_empty() {
+            "none".to_string()
+        } else {
+            known.join(", ")
+        };
+        bail!("Unknown remote '{}'; known remotes: {}", remote.0, known)
+    }
+
+    /// Names of every remote configured in the repository, via `git remote`.
+    pub fn list_remotes(&self, renderer: &mut impl Renderer) -> Result<Vec<Remote<'static>>> {
+        let output = run_trivial(
+            renderer,
+            self.verbosity,
+            "Listing remotes",
+            self.command().arg("remote"),
+        )
+        .and_then(output_stdout)?;
+        Ok(output
+            .lines()
+            .map(|name| Remote::from(name.to_string()))
+            .collect())
+    }
+
     /// Wraps `git config` to read a single namespaced value.
     pub fn get_config(&self, renderer: &mut impl Renderer, key: &str) -> Result<Option<String>> {
         self.get_config_with_env(renderer, key, [] as [(&str, &str); 0])
@@ -335,11 +1355,30 @@ impl GitBinary<'_> {
         renderer: &mut impl Renderer,
         key: &str,
         vars: impl IntoIterator<Item = (impl AsRef<OsStr>, impl AsRef<OsStr>)>,
+    ) -> Result<Option<String>> {
+        self.read_config(renderer, &namespace::config_key(key), vars)
+    }
+
+    /// Wraps `git config` to read a single value that git itself (or some other tool) owns, like
+    /// `core.abbrev`.
+    pub fn get_git_config(
+        &self,
+        renderer: &mut impl Renderer,
+        full_key: &str,
+    ) -> Result<Option<String>> {
+        self.read_config(renderer, full_key, [] as [(&str, &str); 0])
+    }
+
+    fn read_config(
+        &self,
+        renderer: &mut impl Renderer,
+        full_key: &str,
+        vars: impl IntoIterator<Item = (impl AsRef<OsStr>, impl AsRef<OsStr>)>,
     ) -> Result<Option<String>> {
         run_trivial(
             renderer,
             self.verbosity,
-            format!("Get config {}", key),
+            format!("Get config {}", full_key),
             self.command().envs(vars).args([
                 "config",
                 // Use a default to prevent git from returning a non-zero exit code when the value does
@@ -347,7 +1386,7 @@ impl GitBinary<'_> {
                 "--default",
                 "",
                 "--get",
-                &namespace::config_key(key),
+                full_key,
             ]),
         )
         .and_then(output_stdout)
@@ -356,15 +1395,123 @@ impl GitBinary<'_> {
     }
 
     /// Wraps `git config` to write a single namespaced value.
-    #[cfg(test)]
+    ///
+    /// Also records the running [`crate::version`] under [`crate::CONFIG_VERSION`], so that future
+    /// versions know which format the config was written in.
     pub fn set_config(&self, renderer: &mut impl Renderer, key: &str, value: &str) -> Result<()> {
+        self.write_config(renderer, ConfigScope::Local, key, value)?;
+        self.record_version(renderer, ConfigScope::Local)
+    }
+
+    /// Wraps `git config` to remove a single namespaced value, so that it falls back to its
+    /// default.
+    ///
+    /// Removing a value that is not set is not an error. Like [`Self::set_config`], this records
+    /// the running [`crate::version`], but only in the repository: unsetting a global value should
+    /// not leave a version behind in the user's global config.
+    pub fn unset_config(
+        &self,
+        renderer: &mut impl Renderer,
+        scope: ConfigScope,
+        key: &str,
+    ) -> Result<()> {
+        let result = run_trivial(
+            renderer,
+            self.verbosity,
+            format!("Unset config {}", key),
+            self.command().args([
+                "config",
+                scope.flag(),
+                "--unset-all",
+                &namespace::config_key(key),
+            ]),
+        );
+
+        if let Err(error) = result {
+            // `git config` exits with 5 when there is nothing to unset.
+            let is_missing = error
+                .downcast_ref::<CommandFailure>()
+                .is_some_and(|failure| failure.code() == Some(5));
+            if !is_missing {
+                return Err(error);
+            }
+        }
+
+        match scope {
+            ConfigScope::Local => self.record_version(renderer, scope),
+            ConfigScope::Global => Ok(()),
+        }
+    }
+
+    /// When nomad last pushed to `remote`, in seconds since the epoch, as remembered by
+    /// [`Self::record_push`].
+    pub fn last_push(&self, renderer: &mut impl Renderer, remote: &Remote) -> Result<Option<u64>> {
+        let key = namespace::last_push_key(remote);
+        self.get_config(renderer, &key)?
+            .map(|value| {
+                value
+                    .parse()
+                    .with_context(|| format!("parsing {} = {:?}", key, value))
+            })
+            .transpose()
+    }
+
+    /// Remember that nomad pushed to `remote` at `timestamp` (in seconds since the epoch), in the
+    /// config of the local repository.
+    ///
+    /// Like [`Self::set_config`], this records the running [`crate::version`].
+    pub fn record_push(
+        &self,
+        renderer: &mut impl Renderer,
+        remote: &Remote,
+        timestamp: u64,
+    ) -> Result<()> {
+        self.write_config(
+            renderer,
+            ConfigScope::Local,
+            &namespace::last_push_key(remote),
+            &timestamp.to_string(),
+        )?;
+        self.record_version(renderer, ConfigScope::Local)
+    }
+
+    /// When nomad last synced successfully, in seconds since the epoch, as remembered by
+    /// [`Self::record_sync`].
+    pub fn last_sync(&self, renderer: &mut impl Renderer) -> Result<Option<u64>> {
+        self.get_config(renderer, namespace::LAST_SYNC_KEY)?
+            .map(|value| {
+                value
+                    .parse()
+                    .with_context(|| format!("parsing {} = {:?}", namespace::LAST_SYNC_KEY, value))
+            })
+            .transpose()
+    }
+
+    /// Remember that nomad synced successfully at `timestamp` (in seconds since the epoch), see
+    /// [`Self::set_config`].
+    pub fn record_sync(&self, renderer: &mut impl Renderer, timestamp: u64) -> Result<()> {
+        self.set_config(renderer, namespace::LAST_SYNC_KEY, &timestamp.to_string())
+    }
+
+    /// Record the running [`crate::version`] as the last one to write config in `scope`.
+    fn record_version(&self, renderer: &mut impl Renderer, scope: ConfigScope) -> Result<()> {
+        self.write_config(renderer, scope, crate::CONFIG_VERSION, crate::version())
+    }
+
+    fn write_config(
+        &self,
+        renderer: &mut impl Renderer,
+        scope: ConfigScope,
+        key: &str,
+        value: &str,
+    ) -> Result<()> {
         run_trivial(
             renderer,
             self.verbosity,
             format!("Set config {} = {}", key, value),
             self.command().args([
                 "config",
-                "--local",
+                scope.flag(),
                 "--replace-all",
                 &namespace::config_key(key),
                 value,
@@ -385,22 +1532,110 @@ impl GitBinary<'_> {
         description: Description,
         remote: &Remote,
         refspecs: &[RefSpec],
+        options: FetchOptions,
     ) -> Result<()>
     where
         Description: AsRef<str>,
         RefSpec: AsRef<OsStr>,
     {
-        assert!(!refspecs.is_empty());
-        run_notable(
+        if options.depth.is_some() && !self.is_shallow(renderer)? {
+            bail!(
+                "Refusing to fetch with --depth into a full clone, which would make it shallow \
+                 and truncate the history of local branches"
+            );
+        }
+        let options = self.supported_fetch_options(renderer, options)?;
+        retry_transient(renderer, self.verbosity, self.retries, |renderer| {
+            run_notable(
+                renderer,
+                self.verbosity,
+                description.as_ref(),
+                &mut self.fetch_command(remote, refspecs, options),
+            )
+        })
+        .map_err(|e| self.explain_prompt_failure(remote, e))?;
+        Ok(())
+    }
+
+    /// Whether the repository is a shallow clone, via `git rev-parse --is-shallow-repository`.
+    pub fn is_shallow(&self, renderer: &mut impl Renderer) -> Result<bool> {
+        let output = run_trivial(
             renderer,
             self.verbosity,
-            description,
-            self.command().args(["fetch", &remote.0]).args(refspecs),
-        )?;
-        Ok(())
+            "Checking for a shallow clone",
+            self.command()
+                .args(["rev-parse", "--is-shallow-repository"]),
+        )
+        .and_then(output_stdout)
+        .map(LineArity::from)
+        .and_then(LineArity::one)?;
+        Ok(output == "true")
+    }
+
+    /// Turn off the parts of `options` that git is too old for, with a warning, since fetching
+    /// everything is slower but otherwise just as good.
+    fn supported_fetch_options(
+        &self,
+        renderer: &mut impl Renderer,
+        options: FetchOptions,
+    ) -> Result<FetchOptions> {
+        if !options.partial {
+            return Ok(options);
+        }
+
+        match self.require(renderer, GitFeature::PartialFetch) {
+            Ok(()) => Ok(options),
+            Err(e) => {
+                if self.is_output_allowed() {
+                    renderer.writer(|w| {
+                        writeln!(w, "Warning: {}, fetching everything instead", e)?;
+                        Ok(())
+                    })?;
+                }
+                Ok(FetchOptions {
+                    partial: false,
+                    ..options
+                })
+            }
+        }
+    }
+
+    /// The `git fetch` command that [`GitBinary::fetch_refspecs`] runs.
+    ///
+    /// Building it does not need the renderer, so it can be run from any thread.
+    ///
+    /// # Panics
+    ///
+    /// If `refspecs` is empty, see [`GitBinary::fetch_refspecs`].
+    fn fetch_command<RefSpec>(
+        &self,
+        remote: &Remote,
+        refspecs: &[RefSpec],
+        options: FetchOptions,
+    ) -> Command
+    where
+        RefSpec: AsRef<OsStr>,
+    {
+        assert!(!refspecs.is_empty());
+        let mut command = self.remote_command();
+        command.arg("fetch");
+
+        if options.partial {
+            command.arg("--filter=blob:none");
+        }
+
+        if let Some(depth) = options.depth {
+            command.arg(format!("--depth={}", depth));
+        }
+
+        command.arg(remote.0.as_ref()).args(refspecs);
+        command
     }
 
-    /// Wraps `git push` to push refs from the local repository into the given remote.
+    /// Wraps `git push` to push refs from the local repository into the given remote, returning
+    /// how many refs at the remote were actually updated, see [`count_updated_refs`].
+    ///
+    /// With `dry_run`, the push is only reported, see [`report_dry_run`], and nothing is updated.
     ///
     /// # Panics
     ///
@@ -412,72 +1647,286 @@ impl GitBinary<'_> {
         description: Description,
         remote: &Remote,
         refspecs: &[RefSpec],
-    ) -> Result<()>
+        leases: &[String],
+        dry_run: bool,
+    ) -> Result<usize>
     where
         Description: AsRef<str>,
         RefSpec: AsRef<OsStr>,
     {
         assert!(!refspecs.is_empty());
-        run_notable(
+        let mut command = self.remote_command();
+        command
+            .args(PUSH_CONFIG_OVERRIDES)
+            .args(["push", "--no-verify"])
+            .args(leases);
+
+        if dry_run {
+            command.arg(remote.0.as_ref()).args(refspecs);
+            report_dry_run(renderer, self.verbosity, description, &command)?;
+            return Ok(0);
+        }
+
+        // Only the real push needs to be parsed, dry runs report the command as a user would type
+        // it.
+        command
+            .arg("--porcelain")
+            .arg(remote.0.as_ref())
+            .args(refspecs);
+
+        let output = retry_transient(renderer, self.verbosity, self.retries, |renderer| {
+            run_notable(renderer, self.verbosity, description.as_ref(), &mut command)
+        })
+        .map_err(|e| self.explain_prompt_failure(remote, e))
+        .and_then(output_stdout)?;
+        Ok(count_updated_refs(&output))
+    }
+
+    /// Whether `ancestor` is reachable from `descendant`, as in `descendant` has no commits beyond
+    /// `ancestor`. Every commit is its own ancestor.
+    ///
+    /// Fails if either commit cannot be found locally.
+    pub fn is_ancestor(
+        &self,
+        renderer: &mut impl Renderer,
+        ancestor: &str,
+        descendant: &str,
+    ) -> Result<bool> {
+        let result = run_trivial(
             renderer,
             self.verbosity,
-            description,
+            format!("Checking whether {} contains {}", descendant, ancestor),
             self.command()
-                .args(["push", "--no-verify", &remote.0])
-                .args(refspecs),
-        )?;
-        Ok(())
+                .args(["merge-base", "--is-ancestor", ancestor, descendant]),
+        );
+
+        match result {
+            Ok(_) => Ok(true),
+            Err(error) => {
+                // `git merge-base --is-ancestor` exits with 1 when the answer is no, anything else
+                // is a real failure.
+                let is_not_ancestor = error
+                    .downcast_ref::<CommandFailure>()
+                    .is_some_and(|failure| failure.code() == Some(1));
+                if is_not_ancestor {
+                    Ok(false)
+                } else {
+                    Err(error)
+                }
+            }
+        }
     }
 
-    /// Extract a single `GitRef` for a given `ref_name`.
-    #[cfg(test)]
-    pub fn get_ref<Description, RefName>(
+    /// How many commits `other` is `(ahead, behind)` of `local`, as in commits only reachable
+    /// from `other` and commits only reachable from `local`.
+    ///
+    /// Unrelated histories are fine, every commit just counts towards one side.
+    pub fn ahead_behind(
         &self,
         renderer: &mut impl Renderer,
-        description: Description,
-        ref_name: RefName,
-    ) -> Result<GitRef>
-    where
-        Description: AsRef<str>,
-        RefName: AsRef<str>,
-    {
-        run_trivial(
+        local: &str,
+        other: &str,
+    ) -> Result<(usize, usize)> {
+        let line = run_trivial(
             renderer,
             self.verbosity,
-            description,
-            self.command()
-                .args(["show-ref", "--verify", ref_name.as_ref()]),
+            format!("Comparing {} to {}", other, local),
+            self.command().args([
+                "rev-list",
+                "--left-right",
+                "--count",
+                &format!("{}...{}", local, other),
+                "--",
+            ]),
         )
         .and_then(output_stdout)
         .map(LineArity::from)
-        .and_then(LineArity::one)
-        .and_then(|line| GitRef::parse_show_ref_line(&line).map_err(Into::into))
+        .and_then(LineArity::one)?;
+
+        let parse = |count: Option<&str>| -> Result<usize> {
+            count
+                .and_then(|count| count.parse().ok())
+                .ok_or_else(|| anyhow::anyhow!("Unexpected rev-list output: {:?}", line))
+        };
+        let mut counts = line.split_whitespace();
+        let behind = parse(counts.next())?;
+        let ahead = parse(counts.next())?;
+        Ok((ahead, behind))
     }
 
-    /// List all the non-HEAD refs in the repository as `GitRef`s.
-    pub fn list_refs<Description>(
+    /// The committer timestamp (in seconds since the epoch) of each of `commit_ids`, looked up
+    /// with a single `git log`.
+    ///
+    /// Commits that are missing from the local object database are left out rather than failing
+    /// the whole lookup.
+    pub fn commit_timestamps<'c>(
         &self,
         renderer: &mut impl Renderer,
-        description: Description,
-    ) -> Result<Vec<GitRef>>
-    where
+        commit_ids: impl Iterator<Item = &'c String>,
+    ) -> Result<HashMap<String, u64>> {
+        self.log_commits(renderer, commit_ids, "%ct", |timestamp| {
+            timestamp.parse().ok()
+        })
+    }
+
+    /// When each of `commit_ids` was committed relative to now, like `3 days ago`, looked up with
+    /// a single `git log`.
+    ///
+    /// Commits that are missing from the local object database are left out rather than failing
+    /// the whole lookup.
+    pub fn commit_dates<'c>(
+        &self,
+        renderer: &mut impl Renderer,
+        commit_ids: impl Iterator<Item = &'c String>,
+    ) -> Result<HashMap<String, String>> {
+        self.log_commits(renderer, commit_ids, "%cr", |date| Some(date.to_owned()))
+    }
+
+    /// Read `format` (a `git log` placeholder like `%ct`) for each of `commit_ids` with a single
+    /// `git log`, converting each value with `parse`.
+    ///
+    /// Commits that are missing from the local object database are left out.
+    fn log_commits<'c, T>(
+        &self,
+        renderer: &mut impl Renderer,
+        commit_ids: impl Iterator<Item = &'c String>,
+        format: &str,
+        parse: impl Fn(&str) -> Option<T>,
+    ) -> Result<HashMap<String, T>> {
+        let input = commit_ids
+            .map(|commit_id| format!("{}\n", commit_id))
+            .collect::<String>();
+        // `git log` with no commits at all would fall back to `HEAD`.
+        if input.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let output = with_stdin(input.into_bytes(), || {
+            run_trivial(
+                renderer,
+                self.verbosity,
+                "Reading commit dates",
+                self.command().args([
+                    "log",
+                    "--no-walk=unsorted",
+                    "--ignore-missing",
+                    "--stdin",
+                    &format!("--format=%H {}", format),
+                ]),
+            )
+        })
+        .and_then(output_stdout)?;
+
+        output
+            .lines()
+            .map(|line| {
+                line.split_once(' ')
+                    .and_then(|(commit_id, value)| Some((commit_id.to_owned(), parse(value)?)))
+                    .ok_or_else(|| anyhow::anyhow!("Unexpected log output: {:?}", line))
+            })
+            .collect()
+    }
+
+    /// Look up who committed the commit each of `ref_names` points to, in one `git for-each-ref`.
+    ///
+    /// Refs that do not exist locally are left out, as are commits without any committer name or
+    /// email. Fails if any ref points to a missing object.
+    pub fn ref_committers<'r>(
+        &self,
+        renderer: &mut impl Renderer,
+        ref_names: impl Iterator<Item = &'r String>,
+    ) -> Result<HashMap<String, String>> {
+        let ref_names = ref_names.collect::<Vec<_>>();
+        // `git for-each-ref` with no patterns at all would list every ref.
+        if ref_names.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let output = run_trivial(
+            renderer,
+            self.verbosity,
+            "Reading committers",
+            self.command()
+                .args([
+                    "for-each-ref",
+                    "--format=%(refname)%00%(committername)%00%(committeremail:trim)",
+                ])
+                .args(ref_names),
+        )
+        .and_then(output_stdout)?;
+
+        output
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split('\0');
+                match (fields.next(), fields.next(), fields.next(), fields.next()) {
+                    (Some(ref_name), Some(name), Some(email), None) => {
+                        format_committer(name, email)
+                            .map(|committer| Ok((ref_name.to_owned(), committer)))
+                    }
+                    _ => Some(Err(anyhow::anyhow!(
+                        "Unexpected for-each-ref output: {:?}",
+                        line
+                    ))),
+                }
+            })
+            .collect()
+    }
+
+    /// Extract a single `GitRef` for a given `ref_name`.
+    pub fn get_ref<Description, RefName>(
+        &self,
+        renderer: &mut impl Renderer,
+        description: Description,
+        ref_name: RefName,
+    ) -> Result<GitRef>
+    where
+        Description: AsRef<str>,
+        RefName: AsRef<str>,
+    {
+        run_trivial(
+            renderer,
+            self.verbosity,
+            description,
+            self.command()
+                .args(["show-ref", "--verify", ref_name.as_ref()]),
+        )
+        .and_then(output_stdout)
+        .map(LineArity::from)
+        .and_then(LineArity::one)
+        .and_then(|line| GitRef::parse_show_ref_line(&line).map_err(Into::into))
+    }
+
+    /// List all the non-HEAD refs in the repository as [`ListedRef`]s, which distinguishes
+    /// symbolic refs from the normal ones.
+    pub fn list_refs<Description>(
+        &self,
+        renderer: &mut impl Renderer,
+        description: Description,
+    ) -> Result<Vec<ListedRef>>
+    where
         Description: AsRef<str>,
     {
         let output = run_trivial(
             renderer,
             self.verbosity,
             description,
-            self.command().arg("show-ref"),
+            self.command().args([
+                "for-each-ref",
+                &format!("--format={}", ListedRef::FOR_EACH_REF_FORMAT),
+            ]),
         )
         .and_then(output_stdout)?;
         output
             .lines()
-            .map(|line| GitRef::parse_show_ref_line(line).map_err(Into::into))
+            .map(|line| ListedRef::parse_for_each_ref_line(line).map_err(Into::into))
             .collect()
     }
 
     /// Wraps `git ls-remote` to query a remote for all refs that match the given `refspecs`.
     ///
+    /// The refs are parsed lazily as the returned iterator is consumed, see [`LsRemoteOutput`].
+    ///
     /// # Panics
     ///
     /// If `refspecs` is empty, which means git will list all refs, which is never what we want.
@@ -487,28 +1936,114 @@ impl GitBinary<'_> {
         description: Description,
         remote: &Remote,
         refspecs: &[RefSpec],
-    ) -> Result<Vec<GitRef>>
+    ) -> Result<LsRemoteOutput>
     where
         Description: AsRef<str>,
         RefSpec: AsRef<OsStr>,
     {
-        assert!(!refspecs.is_empty());
-        let output = run_notable(
-            renderer,
-            self.verbosity,
-            description,
-            self.command()
-                .arg("ls-remote")
-                .arg(remote.0.as_ref())
-                .args(refspecs),
-        )
+        let output = retry_transient(renderer, self.verbosity, self.retries, |renderer| {
+            run_notable(
+                renderer,
+                self.verbosity,
+                description.as_ref(),
+                &mut self.ls_remote_command(remote, refspecs),
+            )
+        })
+        .map_err(|e| self.explain_prompt_failure(remote, e))
         .and_then(output_stdout)?;
-        output
-            .lines()
-            .map(|line| GitRef::parse_ls_remote_line(line).map_err(Into::into))
+        Ok(LsRemoteOutput::from(output))
+    }
+
+    /// Count how many refs at `remote` look like nomad managed refs under each prefix, most common
+    /// first, to discover which [`GitBinary::ref_prefix`] a shared remote already uses.
+    pub fn detect_ref_prefixes(
+        &self,
+        renderer: &mut impl Renderer,
+        remote: &Remote,
+    ) -> Result<Vec<(String, usize)>> {
+        let mut counts = HashMap::<String, usize>::new();
+        for ref_ in self.list_remote_refs(
+            renderer,
+            format!("Listing refs at {}", remote.0),
+            remote,
+            &[namespace::ANY_PREFIX_REFSPEC],
+        )? {
+            let ref_ = ref_?;
+            if let Some(prefix) = namespace::detected_prefix(&ref_.name) {
+                *counts.entry(prefix.to_string()).or_default() += 1;
+            }
+        }
+
+        let mut counts = counts.into_iter().collect::<Vec<_>>();
+        counts.sort_by(|(a_prefix, a_count), (b_prefix, b_count)| {
+            b_count.cmp(a_count).then_with(|| a_prefix.cmp(b_prefix))
+        });
+        Ok(counts)
+    }
+
+    /// Like [`GitBinary::list_remote_refs`], but queries all of `remotes` at once, returning the
+    /// refs of each remote in the order of `remotes`.
+    ///
+    /// The `git ls-remote` processes run concurrently, see [`run_notable_concurrently`].
+    ///
+    /// # Panics
+    ///
+    /// If `refspecs` is empty, see [`GitBinary::list_remote_refs`].
+    fn list_remote_refs_many<Description, RefSpec>(
+        &self,
+        renderer: &mut impl Renderer,
+        description: Description,
+        remotes: &[Remote],
+        refspecs: &[RefSpec],
+    ) -> Result<Vec<Vec<GitRef>>>
+    where
+        Description: AsRef<str>,
+        RefSpec: AsRef<OsStr>,
+    {
+        let commands = remotes
+            .iter()
+            .map(|remote| {
+                (
+                    format!("Listing refs at {}", remote.0),
+                    self.ls_remote_command(remote, refspecs),
+                )
+            })
+            .collect();
+
+        let outputs = run_notable_concurrently(renderer, self.verbosity, description, commands)?;
+
+        remotes
+            .iter()
+            .zip(outputs)
+            .map(|(remote, output)| {
+                let output = output
+                    .map_err(|e| self.explain_prompt_failure(remote, e))
+                    .and_then(output_stdout)?;
+                LsRemoteOutput::from(output).collect()
+            })
             .collect()
     }
 
+    /// The `git ls-remote` command that [`GitBinary::list_remote_refs`] runs.
+    ///
+    /// Building it does not need the renderer, so it can be run from any thread.
+    ///
+    /// # Panics
+    ///
+    /// If `refspecs` is empty, see [`GitBinary::list_remote_refs`].
+    fn ls_remote_command<RefSpec>(&self, remote: &Remote, refspecs: &[RefSpec]) -> Command
+    where
+        RefSpec: AsRef<OsStr>,
+    {
+        assert!(!refspecs.is_empty());
+        let mut command = self.remote_command();
+        command
+            .arg("ls-remote")
+            .arg(remote.0.as_ref())
+            .args(refspecs);
+        command
+    }
+
     /// Delete a ref from the repository.
     ///
     /// Note that deleting refs on a remote is done via [`GitBinary::push_refspecs`].
@@ -517,16 +2052,123 @@ impl GitBinary<'_> {
         renderer: &mut impl Renderer,
         description: Description,
         git_ref: &GitRef,
+        dry_run: bool,
     ) -> Result<()>
     where
         Description: AsRef<str>,
     {
         let mut command = self.command();
         command.args(["update-ref", "-d", &git_ref.name, &git_ref.commit_id]);
+
+        if dry_run {
+            return report_dry_run(renderer, self.verbosity, description, &command);
+        }
+
         run_notable(renderer, self.verbosity, description, &mut command)?;
         Ok(())
     }
 
+    /// Make the [`namespace::tracking_ref`]s match the local nomad refs of every host except
+    /// `host`, creating, moving, and deleting them in a single `git update-ref --stdin`.
+    ///
+    /// The nomad refs stay authoritative, the copies only exist so that `git log --all`, `gitk`,
+    /// and `git branch --remotes` show other hosts' branches as `nomad/{host}/{branch}`.
+    pub fn update_tracking_refs(
+        &self,
+        renderer: &mut impl Renderer,
+        user: &User,
+        host: &Host,
+    ) -> Result<()> {
+        let tracking_prefix = namespace::tracking_refs_prefix(&self.ref_prefix);
+        let mut existing = self
+            .list_refs(renderer, "Listing tracking refs")?
+            .into_iter()
+            .filter_map(ListedRef::direct)
+            .filter(|git_ref| git_ref.name.starts_with(&tracking_prefix))
+            .map(|git_ref| (git_ref.name, git_ref.commit_id))
+            .collect::<HashMap<_, _>>();
+
+        let mut input = String::new();
+        for nomad_ref in self.snapshot(renderer, user)?.nomad_refs {
+            if nomad_ref.host == *host {
+                continue;
+            }
+
+            let name =
+                namespace::tracking_ref(&self.ref_prefix, &nomad_ref.host, &nomad_ref.branch);
+            let commit_id = nomad_ref.ref_.commit_id;
+            if existing.remove(&name).as_ref() != Some(&commit_id) {
+                input.push_str(&format!("update {} {}\n", name, commit_id));
+            }
+        }
+        for (name, commit_id) in existing {
+            input.push_str(&format!("delete {} {}\n", name, commit_id));
+        }
+
+        if input.is_empty() {
+            return Ok(());
+        }
+
+        with_stdin(input.into_bytes(), || {
+            run_notable(
+                renderer,
+                self.verbosity,
+                "Updating tracking refs",
+                self.command().args(["update-ref", "--stdin"]),
+            )
+        })?;
+        Ok(())
+    }
+
+    /// Delete all of `git_refs` in a single transaction, as long as each one still points at the
+    /// commit it was read with.
+    fn delete_refs<Description>(
+        &self,
+        renderer: &mut impl Renderer,
+        description: Description,
+        git_refs: &[GitRef],
+    ) -> Result<()>
+    where
+        Description: AsRef<str>,
+    {
+        let input = git_refs
+            .iter()
+            .map(|git_ref| format!("delete {} {}\n", git_ref.name, git_ref.commit_id))
+            .collect::<String>();
+
+        with_stdin(input.into_bytes(), || {
+            run_notable(
+                renderer,
+                self.verbosity,
+                description,
+                self.command().args(["update-ref", "--stdin"]),
+            )
+        })?;
+        Ok(())
+    }
+
+    /// How many hex digits to abbreviate commit IDs to, following the repository's `core.abbrev`
+    /// like the rest of git does.
+    ///
+    /// Falls back to [`DEFAULT_ABBREV`] when unset or `auto` (git's own length for `auto` grows
+    /// with the number of objects, which is not worth replicating). `None` means `no`, as in full
+    /// commit IDs.
+    pub fn abbrev_length(&self, renderer: &mut impl Renderer) -> Result<Option<usize>> {
+        let configured = self.get_git_config(renderer, "core.abbrev")?;
+        match configured
+            .as_deref()
+            .map(str::to_ascii_lowercase)
+            .as_deref()
+        {
+            None | Some("auto") => Ok(Some(DEFAULT_ABBREV)),
+            Some("no" | "false" | "off") => Ok(None),
+            Some(length) => length
+                .parse::<usize>()
+                .map(|length| Some(length.max(MIN_ABBREV)))
+                .with_context(|| format!("parsing core.abbrev: {:?}", length)),
+        }
+    }
+
     /// Get the current branch, which may fail if the work tree is in a detached HEAD state.
     pub fn current_branch(&self, renderer: &mut impl Renderer) -> Result<Branch<'static>> {
         let mut command = self.command();
@@ -543,6 +2185,39 @@ impl GitBinary<'_> {
         .map(Branch::from)
     }
 
+    /// Like [`Self::current_branch`], but reads the `HEAD` file directly instead of spawning git,
+    /// for latency sensitive callers like the `prompt` command.
+    ///
+    /// Falls back to [`Self::current_branch`] whenever `HEAD` is anything other than a plain
+    /// `ref: refs/heads/<branch>`, like a detached HEAD or the placeholder that the reftable
+    /// backend leaves behind. Linked worktrees need no special casing since their git dir has its
+    /// own `HEAD`.
+    pub fn current_branch_fast(&self, renderer: &mut impl Renderer) -> Result<Branch<'static>> {
+        let head = fs::read_to_string(self.git_dir().join("HEAD")).ok();
+        match head.as_deref().and_then(parse_head_branch) {
+            Some(branch) => Ok(Branch::from(branch.to_string())),
+            None => self.current_branch(renderer),
+        }
+    }
+
+    /// Switch the work tree to a new local branch at the commit of `nomad_ref`, like `git
+    /// checkout -b`, returning the name of the branch, see [`namespace::checkout_branch`].
+    pub fn checkout_nomad_ref(
+        &self,
+        renderer: &mut impl Renderer,
+        nomad_ref: &NomadRef<GitRef>,
+    ) -> Result<Branch<'static>> {
+        let branch = namespace::checkout_branch(&nomad_ref.host, &nomad_ref.branch);
+        run_notable(
+            renderer,
+            self.verbosity,
+            format!("Checking out {}", branch.0),
+            self.command()
+                .args(["checkout", "-b", &branch.0, &nomad_ref.ref_.commit_id]),
+        )?;
+        Ok(branch)
+    }
+
     /// Create a git branch named `branch_name`.
     #[cfg(test)]
     pub fn create_branch(
@@ -587,18 +2262,95 @@ impl GitBinary<'_> {
 
         let mut local_branches = HashSet::<Branch>::new();
         let mut nomad_refs = Vec::<NomadRef<'a, GitRef>>::new();
-
-        for r in refs {
-            if let Some(name) = r.name.strip_prefix("refs/heads/") {
+        let mut interner = Interner::default();
+
+        // Symbolic refs are not something nomad manages, and pruning one would delete its target.
+        for r in refs.into_iter().filter_map(ListedRef::direct) {
+            let local_name = r
+                .name
+                .strip_prefix("refs/")
+                .and_then(|name| name.strip_prefix(self.tracked.hierarchy()))
+                .and_then(|name| name.strip_prefix('/'));
+            if let Some(name) = local_name {
                 local_branches.insert(Branch::from(name.to_string()));
             }
 
-            if let Ok(nomad_ref) = NomadRef::<GitRef>::from_git_local_ref(user, r) {
+            if let Ok(nomad_ref) =
+                NomadRef::<GitRef>::from_git_local_ref(&self.ref_prefix, user, &mut interner, r)
+            {
                 nomad_refs.push(nomad_ref);
             }
         }
 
-        Ok(Snapshot::new(user, local_branches, nomad_refs))
+        if self.verify_objects {
+            let missing = self.missing_commits(
+                renderer,
+                nomad_refs.iter().map(|nomad_ref| &nomad_ref.ref_.commit_id),
+            )?;
+            if !missing.is_empty() && self.is_output_allowed() {
+                renderer.writer(|w| {
+                    for nomad_ref in &nomad_refs {
+                        if missing.contains(&nomad_ref.ref_.commit_id) {
+                            writeln!(
+                                w,
+                                "Warning: {} points at missing commit {}, the repository may be corrupt or partially fetched",
+                                nomad_ref.ref_.name, nomad_ref.ref_.commit_id,
+                            )?;
+                        }
+                    }
+                    Ok(())
+                })?;
+            }
+        }
+
+        let mut snapshot = Snapshot::new(user, local_branches, nomad_refs);
+        let duplicates = snapshot
+            .dedup_hosts_and_branches(|r| r.ref_.name == r.to_git_local_ref(&self.ref_prefix));
+        if !duplicates.is_empty() && self.is_output_allowed() {
+            renderer.writer(|w| {
+                for duplicate in &duplicates {
+                    writeln!(
+                        w,
+                        "Warning: ignoring {} since it duplicates the nomad ref for {} on {}",
+                        duplicate.ref_.name, duplicate.branch.0, duplicate.host.0,
+                    )?;
+                }
+                Ok(())
+            })?;
+        }
+
+        Ok(snapshot)
+    }
+
+    /// The subset of `commit_ids` that do not exist in the local object database, checked with a
+    /// single `git cat-file --batch-check`.
+    fn missing_commits<'c>(
+        &self,
+        renderer: &mut impl Renderer,
+        commit_ids: impl Iterator<Item = &'c String>,
+    ) -> Result<HashSet<String>> {
+        let input = commit_ids
+            .map(|commit_id| format!("{}\n", commit_id))
+            .collect::<String>();
+        if input.is_empty() {
+            return Ok(HashSet::new());
+        }
+
+        let output = with_stdin(input.into_bytes(), || {
+            run_trivial(
+                renderer,
+                self.verbosity,
+                "Verifying nomad ref objects",
+                self.command().args(["cat-file", "--batch-check"]),
+            )
+        })
+        .and_then(output_stdout)?;
+
+        Ok(output
+            .lines()
+            .filter_map(|line| line.strip_suffix(" missing"))
+            .map(String::from)
+            .collect())
     }
 
     /// Fetch all nomad managed refs from a given remote.
@@ -607,25 +2359,182 @@ impl GitBinary<'_> {
         renderer: &mut impl Renderer,
         user: &User,
         remote: &Remote,
+        options: FetchOptions,
     ) -> Result<()> {
-        self.fetch_refspecs(
+        self.require_remote(renderer, remote)?;
+        self.fetch_nomad_refspecs(
             renderer,
-            format!("Fetching branches from {}", remote.0),
+            user,
             remote,
-            &[&namespace::fetch_refspec(user)],
+            &[namespace::fetch_refspec(&self.ref_prefix, user)],
+            options,
         )
     }
 
+    /// Like [`Self::fetch_nomad_refs`], but only fetches the refs in `listed` (as returned by
+    /// [`Self::list_nomad_refs`]) whose commit differs from the local nomad ref, and does not
+    /// talk to `remote` at all if none do.
+    ///
+    /// The local nomad refs are the record of what was fetched last, so nothing else needs to be
+    /// stored. Past [`SELECTIVE_FETCH_LIMIT`] changed refs, a single wildcard fetch is used
+    /// instead of a long list of refspecs.
+    pub fn fetch_changed_nomad_refs(
+        &self,
+        renderer: &mut impl Renderer,
+        user: &User,
+        remote: &Remote,
+        listed: &[NomadRef<GitRef>],
+        options: FetchOptions,
+    ) -> Result<()> {
+        let local = self
+            .snapshot(renderer, user)?
+            .nomad_refs
+            .into_iter()
+            .map(|nomad_ref| (nomad_ref.ref_.name, nomad_ref.ref_.commit_id))
+            .collect::<HashMap<_, _>>();
+
+        let changed = listed
+            .iter()
+            .filter(|nomad_ref| {
+                local.get(&nomad_ref.to_git_local_ref(&self.ref_prefix))
+                    != Some(&nomad_ref.ref_.commit_id)
+            })
+            .collect::<Vec<_>>();
+
+        if changed.is_empty() {
+            return Ok(());
+        }
+
+        let refspecs = if changed.len() > SELECTIVE_FETCH_LIMIT {
+            vec![namespace::fetch_refspec(&self.ref_prefix, user)]
+        } else {
+            changed
+                .iter()
+                .map(|nomad_ref| {
+                    format!(
+                        "+{}:{}",
+                        nomad_ref.to_git_remote_ref(&self.ref_prefix),
+                        nomad_ref.to_git_local_ref(&self.ref_prefix)
+                    )
+                })
+                .collect()
+        };
+
+        self.fetch_nomad_refspecs(renderer, user, remote, &refspecs, options)
+    }
+
+    fn fetch_nomad_refspecs(
+        &self,
+        renderer: &mut impl Renderer,
+        user: &User,
+        remote: &Remote,
+        refspecs: &[String],
+        options: FetchOptions,
+    ) -> Result<()> {
+        let before = if options.write_summary {
+            Some(self.snapshot(renderer, user)?)
+        } else {
+            None
+        };
+
+        self.fetch_refspecs(
+            renderer,
+            format!("Fetching {} from {}", self.tracked.noun(), remote.0),
+            remote,
+            refspecs,
+            options,
+        )?;
+
+        if let Some(before) = before {
+            let after = self.snapshot(renderer, user)?;
+            self.write_fetch_summary(&before.diff(&after))?;
+        }
+
+        Ok(())
+    }
+
+    /// Where [`Self::write_fetch_summary`] writes to.
+    pub fn fetch_summary_path(&self) -> PathBuf {
+        self.git_dir().join(namespace::PREFIX).join("last-fetch")
+    }
+
+    /// Write the nomad refs changed by a fetch as JSON lines, like:
+    ///
+    /// ```json
+    /// {"host":"apollo","branch":"master","old":"abc...","new":"def..."}
+    /// ```
+    ///
+    /// `old` is `null` for new refs and `new` is `null` for deleted ones. The file is overwritten
+    /// on every fetch, so tooling can watch it for changes instead of parsing nomad's output.
+    fn write_fetch_summary(&self, diff: &SnapshotDiff<GitRef>) -> Result<()> {
+        #[derive(Serialize)]
+        struct Line<'a> {
+            host: &'a str,
+            branch: &'a str,
+            old: Option<&'a str>,
+            new: Option<&'a str>,
+        }
+
+        fn line<'a>(
+            nomad_ref: &'a NomadRef<GitRef>,
+            old: Option<&'a str>,
+            new: Option<&'a str>,
+        ) -> Line<'a> {
+            Line {
+                host: &nomad_ref.host.0,
+                branch: &nomad_ref.branch.0,
+                old,
+                new,
+            }
+        }
+
+        let mut lines = diff
+            .added
+            .iter()
+            .map(|r| line(r, None, Some(r.ref_.commit_id.as_str())))
+            .chain(
+                diff.removed
+                    .iter()
+                    .map(|r| line(r, Some(r.ref_.commit_id.as_str()), None)),
+            )
+            .chain(diff.changed.iter().map(|(older, newer)| {
+                line(
+                    newer,
+                    Some(older.ref_.commit_id.as_str()),
+                    Some(newer.ref_.commit_id.as_str()),
+                )
+            }))
+            .collect::<Vec<_>>();
+        lines.sort_by(|a, b| (a.host, a.branch).cmp(&(b.host, b.branch)));
+
+        let mut output = String::new();
+        for line in lines {
+            output.push_str(&serde_json::to_string(&line)?);
+            output.push('\n');
+        }
+
+        let path = self.fetch_summary_path();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).with_context(|| format!("creating {}", parent.display()))?;
+        }
+        fs::write(&path, output).with_context(|| format!("writing {}", path.display()))
+    }
+
     /// List all nomad managed refs from a given remote.
     ///
     /// Separated from [`Self::fetch_nomad_refs`] because not all callers want to pay the overhead
     /// of actually listing the fetched refs.
+    ///
+    /// Refs are yielded lazily so that callers can build their own data structures without an
+    /// intermediate collection.
     pub fn list_nomad_refs(
         &self,
         renderer: &mut impl Renderer,
         user: &User,
         remote: &Remote,
-    ) -> Result<impl Iterator<Item = NomadRef<GitRef>>> {
+    ) -> Result<impl Iterator<Item = Result<NomadRef<'static, GitRef>>>> {
+        self.require_remote(renderer, remote)?;
+
         // In an ideal world, we would be able to get the list of refs fetched directly from `git`.
         //
         // However, `git fetch` is a porcelain command and we don't want to get into parsing its
@@ -635,81 +2544,740 @@ impl GitBinary<'_> {
             renderer,
             format!("Listing branches at {}", remote.0),
             remote,
-            &[&namespace::list_refspec(user)],
+            &[&namespace::list_refspec(&self.ref_prefix, user)],
         )?;
 
-        Ok(remote_refs
-            .into_iter()
-            .filter_map(|ref_| NomadRef::<GitRef>::from_git_remote_ref(ref_).ok()))
+        let prefix = self.ref_prefix.clone();
+        let mut interner = Interner::default();
+        Ok(remote_refs.filter_map(move |ref_| match ref_ {
+            Ok(ref_) => NomadRef::<GitRef>::from_git_remote_ref(&prefix, &mut interner, ref_)
+                .ok()
+                .map(Ok),
+            Err(e) => Some(Err(e)),
+        }))
     }
 
-    /// Push local branches to nomad managed refs in the remote.
-    pub fn push_nomad_refs(
+    /// Like [`Self::list_nomad_refs`], but for each of `remotes` at once, returning the refs of
+    /// each remote in the order of `remotes`, see [`Self::list_remote_refs_many`].
+    pub fn list_nomad_refs_many(
         &self,
         renderer: &mut impl Renderer,
         user: &User,
-        host: &Host,
-        remote: &Remote,
-    ) -> Result<()> {
-        self.push_refspecs(
+        remotes: &[Remote],
+    ) -> Result<Vec<Vec<NomadRef<'static, GitRef>>>> {
+        for remote in remotes {
+            self.require_remote(renderer, remote)?;
+        }
+
+        let listings = self.list_remote_refs_many(
             renderer,
-            format!("Pushing local branches to {}", remote.0),
+            "Listing branches",
+            remotes,
+            &[&namespace::list_refspec(&self.ref_prefix, user)],
+        )?;
+        let mut interner = Interner::default();
+        Ok(listings
+            .into_iter()
+            .map(|refs| {
+                refs.into_iter()
+                    .filter_map(|ref_| {
+                        NomadRef::<GitRef>::from_git_remote_ref(
+                            &self.ref_prefix,
+                            &mut interner,
+                            ref_,
+                        )
+                        .ok()
+                    })
+                    .collect()
+            })
+            .collect())
+    }
+
+    /// Like [`Self::list_nomad_refs`], but for each of `users` at once, or for every user on the
+    /// remote if `users` is `None`.
+    pub fn list_users_nomad_refs(
+        &self,
+        renderer: &mut impl Renderer,
+        remote: &Remote,
+        users: Option<&[User]>,
+    ) -> Result<Vec<NomadRef<'static, GitRef>>> {
+        let refspecs = match users {
+            Some(users) => users
+                .iter()
+                .map(|user| namespace::list_refspec(&self.ref_prefix, user))
+                .collect(),
+            None => vec![namespace::list_all_users_refspec(&self.ref_prefix)],
+        };
+        if refspecs.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut interner = Interner::default();
+        self.list_remote_refs(
+            renderer,
+            format!("Listing other users' branches at {}", remote.0),
             remote,
-            &[&namespace::push_refspec(user, host)],
-        )
+            &refspecs,
+        )?
+        .filter_map(|ref_| match ref_ {
+            Ok(ref_) => {
+                NomadRef::<GitRef>::from_git_remote_ref(&self.ref_prefix, &mut interner, ref_)
+                    .ok()
+                    .map(Ok)
+            }
+            Err(e) => Some(Err(e)),
+        })
+        .collect()
     }
 
-    /// Delete the given nomad managed refs.
-    pub fn prune_nomad_refs<'a>(
+    /// The refspecs that a sync would use, labelled by the kind of git command that uses them.
+    ///
+    /// Computed without running anything, which makes this useful for debugging where refs end
+    /// up.
+    pub fn sync_refspecs(
+        &self,
+        user: &User,
+        host: &Host,
+        remote: &Remote,
+        branches: Option<&[Branch]>,
+        mirror_remote_branches: bool,
+    ) -> Vec<(&'static str, String)> {
+        let prefix = &self.ref_prefix;
+        let mut refspecs = Vec::new();
+
+        if mirror_remote_branches {
+            let mirror_host = namespace::mirror_host(remote);
+            refspecs.push((
+                "mirror fetch",
+                namespace::mirror_fetch_refspec(prefix, &mirror_host),
+            ));
+            refspecs.push((
+                "mirror push",
+                namespace::mirror_push_refspec(prefix, user, &mirror_host),
+            ));
+        }
+
+        refspecs.extend(self.nomad_refspecs(user, host, branches));
+        refspecs
+    }
+
+    /// The refspecs to push, fetch, and list the nomad refs of `user` on `host`, labelled like
+    /// [`Self::sync_refspecs`] but independent of any remote.
+    ///
+    /// Pushes are limited to `branches` like [`Self::push_nomad_refs`].
+    pub fn nomad_refspecs(
+        &self,
+        user: &User,
+        host: &Host,
+        branches: Option<&[Branch]>,
+    ) -> Vec<(&'static str, String)> {
+        let prefix = &self.ref_prefix;
+        let mut refspecs = self
+            .push_nomad_refspecs(user, host, branches)
+            .into_iter()
+            .map(|refspec| ("push", refspec))
+            .collect::<Vec<_>>();
+        refspecs.push(("fetch", namespace::fetch_refspec(prefix, user)));
+        refspecs.push(("list", namespace::list_refspec(prefix, user)));
+        refspecs
+    }
+
+    /// The host and branch of the nomad ref of `user` named `name`, which may be written like it is
+    /// in the remote (`refs/nomad/rraval/apollo/master`) or in the local clone
+    /// (`refs/nomad/apollo/master`).
+    ///
+    /// Only names in the remote form for `user` are read as such, since a local name can have the
+    /// same shape when the branch contains a slash.
+    pub fn parse_nomad_ref_name(
+        &self,
+        user: &User,
+        name: &str,
+    ) -> Result<(Host<'static>, Branch<'static>)> {
+        let git_ref = || GitRef {
+            commit_id: String::new(),
+            name: name.to_string(),
+        };
+        let mut interner = Interner::default();
+
+        if let Ok(nomad_ref) =
+            NomadRef::from_git_remote_ref(&self.ref_prefix, &mut interner, git_ref())
+        {
+            if nomad_ref.user == *user {
+                return Ok((nomad_ref.host, nomad_ref.branch));
+            }
+        }
+
+        match NomadRef::from_git_local_ref(&self.ref_prefix, user, &mut interner, git_ref()) {
+            Ok(nomad_ref) if !nomad_ref.branch.0.is_empty() => Ok((
+                nomad_ref.host.possibly_clone(),
+                nomad_ref.branch.possibly_clone(),
+            )),
+            _ => bail!(
+                "{} is not a nomad ref, expected refs/{prefix}/{user}/<host>/<branch> or refs/{prefix}/<host>/<branch>",
+                name,
+                prefix = self.ref_prefix,
+                user = user.0,
+            ),
+        }
+    }
+
+    /// Push local branches to nomad managed refs in the remote.
+    ///
+    /// Pushes every local branch unless limited to `branches`, which must exist locally. An empty
+    /// list pushes nothing at all.
+    pub fn push_nomad_refs(
+        &self,
+        renderer: &mut impl Renderer,
+        user: &User,
+        host: &Host,
+        remote: &Remote,
+        branches: Option<&[Branch]>,
+        dry_run: bool,
+    ) -> Result<PushCount> {
+        let refspecs = self.push_nomad_refspecs(user, host, branches);
+        if refspecs.is_empty() {
+            return Ok(PushCount::default());
+        }
+        self.require_remote(renderer, remote)?;
+
+        let updated = self.push_refspecs(
+            renderer,
+            format!("Pushing local {} to {}", self.tracked.noun(), remote.0),
+            remote,
+            &refspecs,
+            &[],
+            dry_run,
+        )?;
+        Ok(PushCount {
+            refspecs: refspecs.len(),
+            updated,
+        })
+    }
+
+    /// Like [`Self::push_nomad_refs`], but with `git push --force-with-lease` so that refs at
+    /// `remote` are only overwritten if they still point where this clone last fetched them.
+    ///
+    /// Guards against two clones that share a host name clobbering each other when they sync at
+    /// the same time. Refs that were never fetched are expected to not exist at the remote yet.
+    pub fn push_nomad_refs_with_lease(
         &self,
         renderer: &mut impl Renderer,
+        user: &User,
+        host: &Host,
         remote: &Remote,
-        prune: impl Iterator<Item = PruneFrom<'a, GitRef>>,
-    ) -> Result<()> {
-        let mut refspecs = Vec::<String>::new();
-        let mut refs = Vec::<GitRef>::new();
+        branches: Option<&[Branch]>,
+        dry_run: bool,
+    ) -> Result<PushCount> {
+        // A leading `+` forces the update regardless of the lease, while a lease that holds forces
+        // the update by itself.
+        let refspecs = self
+            .push_nomad_refspecs(user, host, branches)
+            .into_iter()
+            .map(|refspec| match refspec.strip_prefix('+') {
+                Some(unforced) => unforced.to_string(),
+                None => refspec,
+            })
+            .collect::<Vec<_>>();
+        if refspecs.is_empty() {
+            return Ok(PushCount::default());
+        }
+
+        let (leases, local_updates) = self.push_leases(renderer, user, host, branches)?;
+        let updated = self
+            .push_refspecs(
+                renderer,
+                format!("Pushing local {} to {}", self.tracked.noun(), remote.0),
+                remote,
+                &refspecs,
+                &leases,
+                dry_run,
+            )
+            .map_err(|error| {
+                // Only a lease rejection warrants `--force always`, not a network or auth problem.
+                let stale = error
+                    .downcast_ref::<CommandFailure>()
+                    .is_some_and(|failure| {
+                        failure.stdout().contains("stale info")
+                            || failure.stderr().contains("stale info")
+                    });
+                if stale {
+                    error.context(format!(
+                        "pushing to {} with lease, a rejected ref changed since it was last \
+                         fetched: inspect it with `git nomad sync --fetch-only` or overwrite it \
+                         with `--force always`",
+                        remote.0
+                    ))
+                } else {
+                    error
+                }
+            })?;
+
+        // A push-only sync never fetches, so the next lease would otherwise still expect the
+        // commit from before this push.
+        if !dry_run && !local_updates.is_empty() {
+            with_stdin(local_updates.into_bytes(), || {
+                run_notable(
+                    renderer,
+                    self.verbosity,
+                    "Updating local nomad refs",
+                    self.command().args(["update-ref", "--stdin"]),
+                )
+            })?;
+        }
+
+        Ok(PushCount {
+            refspecs: refspecs.len(),
+            updated,
+        })
+    }
+
+    /// One `--force-with-lease=<ref>:<commit>` per pushed branch, expecting the commit of the
+    /// local copy of this host's nomad ref, or no ref at all when there is no local copy.
+    ///
+    /// The branches are listed explicitly since leases cannot apply to wildcard refspecs.
+    ///
+    /// Also returns `update-ref --stdin` input that points the local copies at the pushed commits,
+    /// to be applied once the push succeeds.
+    fn push_leases(
+        &self,
+        renderer: &mut impl Renderer,
+        user: &User,
+        host: &Host,
+        branches: Option<&[Branch]>,
+    ) -> Result<(Vec<String>, String)> {
+        let mut local_branches = Vec::new();
+        let mut local_commits = HashMap::new();
+        let mut fetched = HashMap::new();
+        let mut interner = Interner::default();
+        for git_ref in self
+            .list_refs(renderer, "Reading last fetched refs")?
+            .into_iter()
+            .filter_map(ListedRef::direct)
+        {
+            let local_name = git_ref
+                .name
+                .strip_prefix("refs/")
+                .and_then(|name| name.strip_prefix(self.tracked.hierarchy()))
+                .and_then(|name| name.strip_prefix('/'));
+            if let Some(name) = local_name {
+                local_commits.insert(Branch::from(name.to_string()), git_ref.commit_id.clone());
+                local_branches.push(Branch::from(name.to_string()));
+            }
 
-        for prune_from in prune {
-            if let PruneFrom::LocalAndRemote(ref nomad_ref) = prune_from {
-                refspecs.push(format!(":{}", nomad_ref.to_git_remote_ref()));
+            if let Ok(nomad_ref) = NomadRef::<GitRef>::from_git_local_ref(
+                &self.ref_prefix,
+                user,
+                &mut interner,
+                git_ref,
+            ) {
+                if &nomad_ref.host == host {
+                    fetched.insert(nomad_ref.branch, nomad_ref.ref_.commit_id);
+                }
             }
+        }
 
-            refs.push(
-                match prune_from {
-                    PruneFrom::LocalOnly(nomad_ref) | PruneFrom::LocalAndRemote(nomad_ref) => {
-                        nomad_ref
-                    }
+        let mut leases = Vec::new();
+        let mut local_updates = String::new();
+        for branch in branches.unwrap_or(&local_branches) {
+            let nomad_ref = NomadRef {
+                user: user.always_borrow(),
+                host: host.always_borrow(),
+                branch: branch.always_borrow(),
+                ref_: (),
+            };
+            let expected = fetched.get(branch).map_or("", String::as_str);
+            leases.push(format!(
+                "--force-with-lease={}:{}",
+                nomad_ref.to_git_remote_ref(&self.ref_prefix),
+                expected
+            ));
+
+            if let Some(commit_id) = local_commits.get(branch) {
+                if commit_id != expected {
+                    local_updates.push_str(&format!(
+                        "update {} {}\n",
+                        nomad_ref.to_git_local_ref(&self.ref_prefix),
+                        commit_id
+                    ));
                 }
-                .ref_,
-            );
+            }
+        }
+
+        Ok((leases, local_updates))
+    }
+
+    /// A wildcard refspec for all local branches, or one refspec per branch in `branches`.
+    fn push_nomad_refspecs(
+        &self,
+        user: &User,
+        host: &Host,
+        branches: Option<&[Branch]>,
+    ) -> Vec<String> {
+        let hierarchy = self.tracked.hierarchy();
+        match branches {
+            None => vec![namespace::push_refspec(
+                &self.ref_prefix,
+                hierarchy,
+                user,
+                host,
+            )],
+            Some(branches) => branches
+                .iter()
+                .map(|branch| {
+                    namespace::push_branch_refspec(&self.ref_prefix, hierarchy, user, host, branch)
+                })
+                .collect(),
+        }
+    }
+
+    /// Record the branches of `remote` itself as nomad managed refs under the
+    /// [`namespace::mirror_host`] for that remote.
+    ///
+    /// Branches deleted from the remote are pruned from the mirror as well.
+    ///
+    /// With `dry_run`, the mirror is still updated locally but the push is only reported.
+    pub fn mirror_remote_branches(
+        &self,
+        renderer: &mut impl Renderer,
+        user: &User,
+        remote: &Remote,
+        dry_run: bool,
+    ) -> Result<()> {
+        let mirror_host = namespace::mirror_host(remote);
+
+        let fetch_refspec = namespace::mirror_fetch_refspec(&self.ref_prefix, &mirror_host);
+        retry_transient(renderer, self.verbosity, self.retries, |renderer| {
+            run_notable(
+                renderer,
+                self.verbosity,
+                format!("Fetching branches to mirror from {}", remote.0),
+                self.remote_command()
+                    .args(["fetch", "--prune", &remote.0, &fetch_refspec]),
+            )
+        })
+        .map_err(|e| self.explain_prompt_failure(remote, e))?;
+
+        let description = format!("Pushing mirrored branches to {}", remote.0);
+        let mut command = self.remote_command();
+        command.args(PUSH_CONFIG_OVERRIDES).args([
+            "push",
+            "--no-verify",
+            "--prune",
+            &remote.0,
+            &namespace::mirror_push_refspec(&self.ref_prefix, user, &mirror_host),
+        ]);
+
+        if dry_run {
+            return report_dry_run(renderer, self.verbosity, description, &command);
+        }
+
+        retry_transient(renderer, self.verbosity, self.retries, |renderer| {
+            run_notable(renderer, self.verbosity, &description, &mut command)
+        })
+        .map_err(|e| self.explain_prompt_failure(remote, e))?;
+
+        Ok(())
+    }
+
+    /// List refs in `remote` that match `pattern` (as understood by `git ls-remote`) and are not
+    /// already nomad managed.
+    pub fn list_adoptable_refs(
+        &self,
+        renderer: &mut impl Renderer,
+        remote: &Remote,
+        pattern: &str,
+    ) -> Result<Vec<GitRef>> {
+        let nomad_prefix = format!("refs/{}/", self.ref_prefix);
+        self.list_remote_refs(
+            renderer,
+            format!("Listing {} at {}", pattern, remote.0),
+            remote,
+            &[pattern],
+        )?
+        .filter(|ref_| {
+            ref_.as_ref()
+                .map_or(true, |ref_| !ref_.name.starts_with(&nomad_prefix))
+        })
+        .collect()
+    }
+
+    /// Push each of `adopt` to the nomad managed ref it is paired with, optionally deleting the
+    /// original refs in the same push.
+    ///
+    /// The commits are fetched first since they may not exist locally. With `dry_run`, nothing is
+    /// fetched and the push is only reported.
+    pub fn adopt_refs(
+        &self,
+        renderer: &mut impl Renderer,
+        remote: &Remote,
+        adopt: &[(GitRef, NomadRef<()>)],
+        delete_originals: bool,
+        dry_run: bool,
+    ) -> Result<()> {
+        if adopt.is_empty() {
+            return Ok(());
         }
 
-        // Delete from the remote first
-        if !refspecs.is_empty() {
-            self.push_refspecs(
+        if !dry_run {
+            let names = adopt
+                .iter()
+                .map(|(original, _)| original.name.as_str())
+                .collect::<Vec<_>>();
+            self.fetch_refspecs(
                 renderer,
-                format!("Pruning branches at {}", remote.0),
+                format!("Fetching refs to adopt from {}", remote.0),
                 remote,
-                &refspecs,
+                &names,
+                FetchOptions::default(),
             )?;
         }
 
-        // ... then delete locally. This order means that interruptions leave the local ref around
-        // to be picked up and pruned again.
+        let mut refspecs = Vec::new();
+        for (original, nomad_ref) in adopt {
+            refspecs.push(format!(
+                "+{}:{}",
+                original.commit_id,
+                nomad_ref.to_git_remote_ref(&self.ref_prefix)
+            ));
+            if delete_originals {
+                refspecs.push(format!(":{}", original.name));
+            }
+        }
+
+        self.push_refspecs(
+            renderer,
+            format!("Adopting refs at {}", remote.0),
+            remote,
+            &refspecs,
+            &[],
+            dry_run,
+        )?;
+        Ok(())
+    }
+
+    /// Estimate the on-disk size of objects reachable from `refs` but not from the branches of
+    /// `remote`, via `git rev-list --disk-usage`.
+    ///
+    /// This is roughly the storage cost that those refs add on top of the shared history.
+    pub fn disk_usage<'r, Description>(
+        &self,
+        renderer: &mut impl Renderer,
+        description: Description,
+        remote: &Remote,
+        refs: impl IntoIterator<Item = &'r GitRef>,
+    ) -> Result<u64>
+    where
+        Description: AsRef<str>,
+    {
+        let commit_ids = refs
+            .into_iter()
+            .map(|r| r.commit_id.as_str())
+            .collect::<Vec<_>>();
+        if commit_ids.is_empty() {
+            return Ok(0);
+        }
+        self.require(renderer, GitFeature::DiskUsage)?;
+
+        let bytes = run_notable(
+            renderer,
+            self.verbosity,
+            description,
+            self.command()
+                .args(["rev-list", "--objects", "--disk-usage"])
+                .args(commit_ids)
+                .args(["--not", &format!("--remotes={}", remote.0)]),
+        )
+        .and_then(output_stdout)
+        .map(LineArity::from)
+        .and_then(LineArity::one)?;
+
+        bytes
+            .parse()
+            .with_context(|| format!("parsing disk usage: {:?}", bytes))
+    }
+
+    /// Delete the given nomad managed refs, returning the local refs that were deleted.
+    ///
+    /// With `dry_run`, the deletions are only reported and the returned refs are the ones that
+    /// would have been deleted. See [`Self::prune_local_nomad_refs`] for `fast`.
+    pub fn prune_nomad_refs<'a>(
+        &self,
+        renderer: &mut impl Renderer,
+        remote: &Remote,
+        prune: impl Iterator<Item = PruneFrom<'a, GitRef>>,
+        fast: bool,
+        dry_run: bool,
+    ) -> Result<Vec<GitRef>> {
+        let prune = prune.collect::<Vec<_>>();
+
+        // Delete from the remote first, then locally. This order means that interruptions leave
+        // the local ref around to be picked up and pruned again.
         //
         // In practice, we do a fetch from the remote first anyways, which would recreate the local
         // ref if this code deleted local refs first and then was interrupted.
         //
         // But that is non-local reasoning and this ordering is theoretically correct.
-        for r in refs {
+        let on_remote = prune.iter().filter_map(|prune_from| match prune_from {
+            PruneFrom::LocalAndRemote(nomad_ref) => Some(nomad_ref),
+            PruneFrom::LocalOnly(_) => None,
+        });
+        self.prune_remote_nomad_refs(renderer, remote, on_remote, dry_run)?;
+
+        self.prune_local_nomad_refs(renderer, prune.into_iter(), fast, dry_run)
+    }
+
+    /// Delete the given nomad managed refs from `remote` only, see [`Self::prune_nomad_refs`].
+    pub fn prune_remote_nomad_refs<'a, 'b: 'a>(
+        &self,
+        renderer: &mut impl Renderer,
+        remote: &Remote,
+        nomad_refs: impl Iterator<Item = &'a NomadRef<'b, GitRef>>,
+        dry_run: bool,
+    ) -> Result<()> {
+        let refspecs = nomad_refs
+            .map(|nomad_ref| format!(":{}", nomad_ref.to_git_remote_ref(&self.ref_prefix)))
+            .collect::<Vec<_>>();
+
+        // Split across several pushes so that pruning hundreds of branches does not exceed the
+        // operating system's limit on command line length.
+        let batches = refspecs.chunks(PRUNE_BATCH_SIZE).collect::<Vec<_>>();
+        for (index, batch) in batches.iter().enumerate() {
+            let description = if batches.len() == 1 {
+                format!("Pruning branches at {}", remote.0)
+            } else {
+                format!(
+                    "Pruning branches at {} ({}/{})",
+                    remote.0,
+                    index + 1,
+                    batches.len()
+                )
+            };
+            self.push_refspecs(renderer, description, remote, batch, &[], dry_run)?;
+        }
+
+        Ok(())
+    }
+
+    /// Delete the local refs backing `prune`, regardless of where else they should be pruned
+    /// from, returning the refs that were deleted.
+    ///
+    /// Meant to run after [`Self::prune_remote_nomad_refs`], see [`Self::prune_nomad_refs`].
+    ///
+    /// With `fast`, all the refs are deleted by a single `git update-ref --stdin` instead of one
+    /// process per ref. That transaction is all or nothing, so a single ref that changed in the
+    /// meantime leaves every ref in place, and only the batch as a whole is reported.
+    pub fn prune_local_nomad_refs<'a>(
+        &self,
+        renderer: &mut impl Renderer,
+        prune: impl Iterator<Item = PruneFrom<'a, GitRef>>,
+        fast: bool,
+        dry_run: bool,
+    ) -> Result<Vec<GitRef>> {
+        let refs = prune
+            .map(|prune_from| match prune_from {
+                PruneFrom::LocalOnly(nomad_ref) | PruneFrom::LocalAndRemote(nomad_ref) => {
+                    nomad_ref.ref_
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // A dry run does not spawn anything, so report every ref like the slow path does.
+        if fast && !dry_run && !refs.is_empty() {
+            self.delete_refs(renderer, format!("  Delete {} refs", refs.len()), &refs)?;
+            return Ok(refs);
+        }
+
+        for r in &refs {
             self.delete_ref(
                 renderer,
                 format!("  Delete {} (was {})", r.name, r.commit_id),
-                &r,
+                r,
+                dry_run,
             )?;
         }
 
-        Ok(())
+        Ok(refs)
+    }
+}
+
+/// Parses the output of `git ls-remote` as [`GitRef`]s, one line at a time.
+///
+/// Owns the output so that the parsed refs can be yielded lazily instead of being collected into
+/// an intermediate `Vec`, which matters for remotes with enormous ref counts.
+struct LsRemoteOutput {
+    output: String,
+    offset: usize,
+}
+
+impl From<String> for LsRemoteOutput {
+    fn from(output: String) -> Self {
+        Self { output, offset: 0 }
+    }
+}
+
+impl Iterator for LsRemoteOutput {
+    type Item = Result<GitRef>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = &self.output[self.offset..];
+        if rest.is_empty() {
+            return None;
+        }
+
+        let (line, consumed) = match rest.find('\n') {
+            Some(index) => (&rest[..index], index + 1),
+            None => (rest, rest.len()),
+        };
+        self.offset += consumed;
+
+        Some(GitRef::parse_ls_remote_line(line).map_err(Into::into))
+    }
+}
+
+#[cfg(test)]
+mod test_ls_remote_output {
+    use crate::git_ref::GitRef;
+
+    use super::LsRemoteOutput;
+
+    /// Every line should be parsed as a [`GitRef`].
+    #[test]
+    fn test_parse() {
+        let refs = LsRemoteOutput::from("id0\trefs/heads/a\nid1\trefs/heads/b\n".to_string())
+            .collect::<anyhow::Result<Vec<_>>>()
+            .unwrap();
+
+        assert_eq!(
+            refs,
+            vec![
+                GitRef {
+                    commit_id: "id0".to_string(),
+                    name: "refs/heads/a".to_string(),
+                },
+                GitRef {
+                    commit_id: "id1".to_string(),
+                    name: "refs/heads/b".to_string(),
+                },
+            ]
+        );
+    }
+
+    /// Lines should only be parsed as they are consumed, so a malformed line is not observed until
+    /// the iterator reaches it.
+    #[test]
+    fn test_parse_is_lazy() {
+        let mut refs =
+            LsRemoteOutput::from("id0\trefs/heads/a\nnot\ta\tref\nid1\trefs/heads/b".to_string());
+
+        assert!(refs.next().unwrap().is_ok());
+        assert!(refs.next().unwrap().is_err());
+        assert!(refs.next().unwrap().is_ok());
+        assert!(refs.next().is_none());
+    }
+
+    #[test]
+    fn test_parse_empty() {
+        assert_eq!(LsRemoteOutput::from(String::new()).count(), 0);
     }
 }
 
@@ -770,27 +3338,153 @@ impl LineArity {
 }
 
 #[cfg(test)]
-mod test_line_arity {
-    use super::LineArity;
+mod test_count_updated_refs {
+    use super::count_updated_refs;
 
-    /// No lines counts as zero.
+    /// Created, updated, forced, and deleted refs count, up to date and rejected refs do not.
     #[test]
-    fn test_empty() {
-        let arity = || LineArity::from("".to_string());
-        assert!(arity().one().is_err());
-        assert_eq!(arity().zero_or_one().unwrap(), None);
+    fn flags() {
+        let porcelain = "To /tmp/remote\n\
+            *\trefs/heads/a:refs/nomad/u/h/a\t[new reference]\n\
+            \x20\trefs/heads/b:refs/nomad/u/h/b\t1234567..89abcde\n\
+            +\trefs/heads/c:refs/nomad/u/h/c\t1234567...89abcde (forced update)\n\
+            -\t:refs/nomad/u/h/d\t[deleted]\n\
+            =\trefs/heads/e:refs/nomad/u/h/e\t[up to date]\n\
+            !\trefs/heads/f:refs/nomad/u/h/f\t[rejected] (fetch first)\n\
+            Done\n";
+        assert_eq!(count_updated_refs(porcelain), 4);
     }
+}
 
-    /// An empty line counts as zero.
+#[cfg(test)]
+mod test_is_url_or_path {
+    use super::is_url_or_path;
+
+    /// Only plain names are checked against the configured remotes.
     #[test]
-    fn test_newline() {
-        let arity = || LineArity::from("\n".to_string());
-        assert!(arity().one().is_err());
-        assert_eq!(arity().zero_or_one().unwrap(), None);
+    fn remotes() {
+        for remote in [
+            "https://example.com/repo.git",
+            "git@example.com:repo.git",
+            "/srv/repo.git",
+            "../repo.git",
+            "~/repo.git",
+        ] {
+            assert!(is_url_or_path(remote), "{}", remote);
+        }
+
+        for remote in ["origin", "orgin", "team/backup"] {
+            assert!(!is_url_or_path(remote), "{}", remote);
+        }
     }
+}
 
-    /// A line without a trailing newline counts as one.
-    #[test]
+#[cfg(test)]
+mod test_format_committer {
+    use super::format_committer;
+
+    /// Missing names or emails should be left out rather than rendered as empty.
+    #[test]
+    fn partial() {
+        assert_eq!(
+            format_committer("Ada", "ada@example.com").as_deref(),
+            Some("Ada <ada@example.com>")
+        );
+        assert_eq!(format_committer("Ada", "").as_deref(), Some("Ada"));
+        assert_eq!(
+            format_committer("", "ada@example.com").as_deref(),
+            Some("<ada@example.com>")
+        );
+        assert_eq!(format_committer("", ""), None);
+    }
+}
+
+#[cfg(test)]
+mod test_parse_head_branch {
+    use super::parse_head_branch;
+
+    /// Only symbolic refs to real branches are parsed, everything else defers to git.
+    #[test]
+    fn parse() {
+        assert_eq!(
+            parse_head_branch("ref: refs/heads/master\n"),
+            Some("master")
+        );
+        assert_eq!(
+            parse_head_branch("ref: refs/heads/feature/wip\n"),
+            Some("feature/wip")
+        );
+        assert_eq!(parse_head_branch("ref: refs/heads/.invalid\n"), None);
+        assert_eq!(parse_head_branch("ref: refs/remotes/origin/master\n"), None);
+        assert_eq!(
+            parse_head_branch("267719fb8448cc1cbef2c35a638610573779f2ac\n"),
+            None
+        );
+        assert_eq!(parse_head_branch(""), None);
+    }
+}
+
+#[cfg(test)]
+mod test_git_version {
+    use super::{GitFeature, GitVersion};
+
+    /// The version should be found regardless of vendor suffixes, and anything else is `None`.
+    #[test]
+    fn parse() {
+        for (reported, expected) in [
+            ("2.13.0", Some(GitVersion(2, 13, 0))),
+            ("2.39.2.windows.1", Some(GitVersion(2, 39, 2))),
+            ("2.37.1 (Apple Git-137.1)", Some(GitVersion(2, 37, 1))),
+            ("2.45.0-rc1", Some(GitVersion(2, 45, 0))),
+            ("2.39", None),
+            ("unknown", None),
+        ] {
+            assert_eq!(GitVersion::parse(reported), expected, "{}", reported);
+        }
+    }
+
+    /// Features should be allowed from their minimum version on, and for unknown versions.
+    #[test]
+    fn gate() {
+        let feature = GitFeature::PartialFetch;
+        assert!(feature.check(Some(GitVersion(2, 19, 0))).is_ok());
+        assert!(feature.check(Some(GitVersion(3, 0, 0))).is_ok());
+        assert!(feature.check(None).is_ok());
+        assert_eq!(
+            feature
+                .check(Some(GitVersion(2, 18, 5)))
+                .unwrap_err()
+                .to_string(),
+            "Fetching partially needs git >= 2.19.0, you have 2.18.5",
+        );
+        assert!(GitFeature::DiskUsage
+            .check(Some(GitVersion(2, 30, 9)))
+            .is_err());
+    }
+}
+
+#[cfg(test)]
+mod test_line_arity {
+    use super::LineArity;
+
+    /// No lines counts as zero.
+    #[test]
+    fn test_empty() {
+        let arity = || LineArity::from("".to_string());
+        assert!(arity().one().is_err());
+        assert_eq!(arity().zero_or_one().unwrap(), None);
+    }
+
+    /// An empty line counts as zero.
+    #[test]
+    fn test_newline() {
+        let arity = || LineArity::from("\n".to_string());
+        assert!(arity().one().is_err());
+        assert_eq!(arity().zero_or_one().unwrap(), None);
+    }
+
+    /// A line without a trailing newline counts as one.
+    #[test]
     fn test_one_line_without_newline() {
         let arity = || LineArity::from("line".to_string());
         assert_eq!(arity().one().unwrap(), "line".to_string());
@@ -814,19 +3508,88 @@ mod test_line_arity {
     }
 }
 
+#[cfg(test)]
+mod test_remote_problem {
+    use super::RemoteProblem;
+
+    #[test]
+    fn classify() {
+        for (stderr, expected) in [
+            (
+                "ssh: Could not resolve hostname nomad-alias: Name or service not known\n\
+                 fatal: Could not read from remote repository.",
+                RemoteProblem::Unreachable,
+            ),
+            (
+                "ssh: connect to host example.com port 22: Connection refused",
+                RemoteProblem::Unreachable,
+            ),
+            (
+                "fatal: unable to access 'https://example.com/repo.git/': Failed to connect to \
+                 example.com port 443",
+                RemoteProblem::Unreachable,
+            ),
+            (
+                "fatal: 'origin' does not appear to be a git repository\n\
+                 fatal: Could not read from remote repository.",
+                RemoteProblem::NotFound,
+            ),
+            (
+                "ERROR: Repository not found.\nfatal: Could not read from remote repository.",
+                RemoteProblem::NotFound,
+            ),
+            (
+                "remote: Repository not found.\n\
+                 fatal: repository 'https://example.com/repo.git/' not found",
+                RemoteProblem::NotFound,
+            ),
+            (
+                "remote: The project you were looking for could not be found or you don't have \
+                 permission to view it.\n\
+                 fatal: repository 'https://example.com/repo.git/' not found",
+                RemoteProblem::NotFound,
+            ),
+            (
+                "fatal: unable to access 'https://example.com/repo.git/': The requested URL \
+                 returned error: 404",
+                RemoteProblem::NotFound,
+            ),
+            (
+                "sh: 1: git-upload-pack: not found\n\
+                 fatal: Could not read from remote repository.",
+                RemoteProblem::Unknown,
+            ),
+            (
+                "git@example.com: Permission denied (publickey).\n\
+                 fatal: Could not read from remote repository.",
+                RemoteProblem::Authentication,
+            ),
+            (
+                "fatal: could not read Username for 'https://example.com': terminal prompts \
+                 disabled",
+                RemoteProblem::Authentication,
+            ),
+            ("fatal: something unexpected", RemoteProblem::Unknown),
+        ] {
+            assert_eq!(RemoteProblem::classify(stderr), expected, "{}", stderr);
+        }
+    }
+}
+
 #[cfg(test)]
 mod test_impl {
-    use std::{borrow::Cow, fs};
+    use std::{borrow::Cow, collections::HashMap, fs};
 
     use tempfile::{tempdir, TempDir};
 
     use crate::{
+        git_ref::{GitRef, ListedRef},
         renderer::test::NoRenderer,
-        types::Branch,
+        types::{Branch, User},
         verbosity::{run_notable, Verbosity},
     };
 
-    use super::{git_command, GitBinary};
+    use super::{git_command, ConfigScope, GitBinary, DEFAULT_ABBREV, MIN_ABBREV};
     use anyhow::Result;
 
     const INITIAL_BRANCH: &str = "branch0";
@@ -906,6 +3669,208 @@ mod test_impl {
         Ok(())
     }
 
+    /// Unsetting config should remove the value, and not mind if it was never set.
+    #[test]
+    fn unset_config() -> Result<()> {
+        let (name, tmpdir) = git_init()?;
+        let git = GitBinary::new(&mut NoRenderer, None, name, tmpdir.path())?;
+
+        git.unset_config(&mut NoRenderer, ConfigScope::Local, "key")?;
+        assert_eq!(
+            git.get_config(&mut NoRenderer, crate::CONFIG_VERSION)?,
+            Some(crate::version().to_string())
+        );
+
+        git.set_config(&mut NoRenderer, "key", "testvalue")?;
+        git.unset_config(&mut NoRenderer, ConfigScope::Local, "key")?;
+        assert_eq!(git.get_config(&mut NoRenderer, "key")?, None);
+
+        git.unset_config(&mut NoRenderer, ConfigScope::Local, "key")?;
+        assert_eq!(git.get_config(&mut NoRenderer, "key")?, None);
+
+        Ok(())
+    }
+
+    /// Unsetting global config should not record the version in the global config.
+    #[test]
+    fn unset_global_config() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let (_, tmpdir) = git_init()?;
+        let config_dir = tempdir()?;
+        let global_config = config_dir.path().join("gitconfig");
+        fs::write(&global_config, "[nomad]\n\tkey = testvalue\n")?;
+        let fake_git = config_dir.path().join("git");
+        fs::write(
+            &fake_git,
+            format!(
+                "#!/bin/sh\n\
+                 GIT_CONFIG_GLOBAL='{}' exec git \"$@\"\n",
+                global_config.display()
+            ),
+        )?;
+        fs::set_permissions(&fake_git, fs::Permissions::from_mode(0o755))?;
+        let git = GitBinary::new(
+            &mut NoRenderer,
+            None,
+            Cow::Owned(fake_git.display().to_string()),
+            tmpdir.path(),
+        )?;
+
+        git.unset_config(&mut NoRenderer, ConfigScope::Global, "key")?;
+        assert_eq!(git.get_config(&mut NoRenderer, "key")?, None);
+        assert_eq!(
+            git.get_config(&mut NoRenderer, crate::CONFIG_VERSION)?,
+            None
+        );
+        assert!(!fs::read_to_string(&global_config)?.contains("version"));
+
+        Ok(())
+    }
+
+    /// `core.abbrev` should be followed, with git's fallbacks for special values.
+    #[test]
+    fn abbrev_length() -> Result<()> {
+        let (name, tmpdir) = git_init()?;
+        let git = GitBinary::new(&mut NoRenderer, None, name, tmpdir.path())?;
+        assert_eq!(git.abbrev_length(&mut NoRenderer)?, Some(DEFAULT_ABBREV));
+
+        for (value, expected) in [
+            ("12", Some(12)),
+            ("2", Some(MIN_ABBREV)),
+            ("auto", Some(DEFAULT_ABBREV)),
+            ("no", None),
+        ] {
+            run_notable(
+                &mut NoRenderer,
+                None,
+                "",
+                git.command().args(["config", "core.abbrev", value]),
+            )?;
+            assert_eq!(git.abbrev_length(&mut NoRenderer)?, expected, "{}", value);
+        }
+
+        Ok(())
+    }
+
+    /// Commit dates should be read for present commits, and missing commits skipped.
+    #[test]
+    fn commit_timestamps() -> Result<()> {
+        let (name, tmpdir) = git_init()?;
+        let git = GitBinary::new(&mut NoRenderer, None, name, tmpdir.path())?;
+        run_notable(
+            &mut NoRenderer,
+            None,
+            "",
+            git_command("git")
+                .current_dir(tmpdir.path())
+                .env("GIT_COMMITTER_DATE", "@1000000000 +0000")
+                .args(["commit", "--allow-empty", "-m", "old"]),
+        )?;
+        let commit_id = git
+            .get_ref(
+                &mut NoRenderer,
+                "",
+                format!("refs/heads/{}", INITIAL_BRANCH),
+            )?
+            .commit_id;
+
+        let missing = "0".repeat(40);
+        assert_eq!(
+            git.commit_timestamps(&mut NoRenderer, [&commit_id, &missing].into_iter())?,
+            HashMap::from([(commit_id, 1000000000)]),
+        );
+        assert_eq!(
+            git.commit_timestamps(&mut NoRenderer, std::iter::empty())?,
+            HashMap::new(),
+        );
+
+        Ok(())
+    }
+
+    /// Ancestry should be answered both ways, while unknown commits are an error rather than a
+    /// "no".
+    #[test]
+    fn is_ancestor() -> Result<()> {
+        let (name, tmpdir) = git_init()?;
+        let git = GitBinary::new(&mut NoRenderer, None, name, tmpdir.path())?;
+        for message in ["ancestor", "descendant"] {
+            run_notable(
+                &mut NoRenderer,
+                None,
+                "",
+                git_command("git").current_dir(tmpdir.path()).args([
+                    "commit",
+                    "--allow-empty",
+                    "-m",
+                    message,
+                ]),
+            )?;
+        }
+
+        assert!(git.is_ancestor(&mut NoRenderer, "HEAD~", "HEAD")?);
+        assert!(git.is_ancestor(&mut NoRenderer, "HEAD", "HEAD")?);
+        assert!(!git.is_ancestor(&mut NoRenderer, "HEAD", "HEAD~")?);
+        assert!(git
+            .is_ancestor(&mut NoRenderer, "does-not-exist", "HEAD")
+            .is_err());
+
+        Ok(())
+    }
+
+    /// Relative commit dates should be read in one batch, and missing commits skipped.
+    #[test]
+    fn commit_dates() -> Result<()> {
+        let (name, tmpdir) = git_init()?;
+        let git = GitBinary::new(&mut NoRenderer, None, name, tmpdir.path())?;
+        let three_days_ago = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_secs()
+            - 3 * 24 * 60 * 60;
+        run_notable(
+            &mut NoRenderer,
+            None,
+            "",
+            git_command("git")
+                .current_dir(tmpdir.path())
+                .env("GIT_COMMITTER_DATE", format!("@{} +0000", three_days_ago))
+                .args(["commit", "--allow-empty", "-m", "stale"]),
+        )?;
+        let commit_id = git
+            .get_ref(
+                &mut NoRenderer,
+                "",
+                format!("refs/heads/{}", INITIAL_BRANCH),
+            )?
+            .commit_id;
+
+        let missing = "0".repeat(40);
+        assert_eq!(
+            git.commit_dates(&mut NoRenderer, [&commit_id, &missing].into_iter())?,
+            HashMap::from([(commit_id.clone(), "3 days ago".to_string())]),
+        );
+        assert_eq!(
+            git.commit_dates(&mut NoRenderer, std::iter::empty())?,
+            HashMap::new(),
+        );
+
+        Ok(())
+    }
+
+    /// Writing config should record the version that did so.
+    #[test]
+    fn write_config_records_version() -> Result<()> {
+        let (name, tmpdir) = git_init()?;
+        let git = GitBinary::new(&mut NoRenderer, None, name, tmpdir.path())?;
+
+        git.set_config(&mut NoRenderer, "key", "testvalue")?;
+        let got = git.get_config(&mut NoRenderer, crate::CONFIG_VERSION)?;
+
+        assert_eq!(got, Some(crate::version().to_string()));
+
+        Ok(())
+    }
+
     /// Generates git config files for testing.
     mod gitconfig {
         use std::{fs, path::Path};
@@ -985,6 +3950,98 @@ mod test_impl {
         Ok(())
     }
 
+    /// Reading `HEAD` directly should agree with asking git, both on a branch and on a detached
+    /// HEAD where it falls back to git.
+    #[test]
+    fn current_branch_fast() -> Result<()> {
+        let verbosity = Some(Verbosity::max());
+
+        let (name, tmpdir) = git_init()?;
+        let git = GitBinary::new(&mut NoRenderer, verbosity, name, tmpdir.path())?;
+
+        run_notable(
+            &mut NoRenderer,
+            verbosity,
+            "Create an initial commit",
+            git.command()
+                .args(["commit", "--allow-empty", "-m", "initial commit"]),
+        )?;
+        run_notable(
+            &mut NoRenderer,
+            verbosity,
+            "Switch to a nested branch",
+            git.command().args(["checkout", "-b", "feature/wip"]),
+        )?;
+
+        assert_eq!(
+            git.current_branch_fast(&mut NoRenderer)?,
+            git.current_branch(&mut NoRenderer)?,
+        );
+        assert_eq!(
+            git.current_branch_fast(&mut NoRenderer)?,
+            Branch::from("feature/wip"),
+        );
+
+        run_notable(
+            &mut NoRenderer,
+            verbosity,
+            "Switch to detached HEAD state",
+            git.command().args(["checkout", "--detach"]),
+        )?;
+        assert!(git.current_branch_fast(&mut NoRenderer).is_err());
+
+        Ok(())
+    }
+
+    /// Symbolic refs should be classified as such, and never make it into a snapshot as a nomad
+    /// ref since pruning them would delete whatever they point to.
+    #[test]
+    fn list_symbolic_nomad_ref() -> Result<()> {
+        let verbosity = Some(Verbosity::max());
+
+        let (name, tmpdir) = git_init()?;
+        let git = GitBinary::new(&mut NoRenderer, verbosity, name, tmpdir.path())?;
+
+        run_notable(
+            &mut NoRenderer,
+            verbosity,
+            "Create an initial commit",
+            git.command()
+                .args(["commit", "--allow-empty", "-m", "initial commit"]),
+        )?;
+
+        let target = format!("refs/heads/{}", INITIAL_BRANCH);
+        run_notable(
+            &mut NoRenderer,
+            verbosity,
+            "Create a symbolic nomad ref",
+            git.command()
+                .args(["symbolic-ref", "refs/nomad/host0/alias", &target]),
+        )?;
+
+        let head = git.get_ref(&mut NoRenderer, "Get commit ID for HEAD", "HEAD")?;
+        let refs = git.list_refs(&mut NoRenderer, "List refs")?;
+        assert_eq!(
+            refs,
+            vec![
+                ListedRef::Direct(GitRef {
+                    commit_id: head.commit_id,
+                    name: target.clone(),
+                }),
+                ListedRef::Symbolic {
+                    name: "refs/nomad/host0/alias".to_string(),
+                    target,
+                },
+            ]
+        );
+
+        let user = User::from("user0");
+        let snapshot = git.snapshot(&mut NoRenderer, &user)?;
+        assert!(snapshot.nomad_refs.is_empty());
+
+        Ok(())
+    }
+
     /// Reading the current branch in a detached HEAD state should be handled as an error.
     #[test]
     fn current_branch_in_detached_head() -> Result<()> {
@@ -1019,12 +4076,21 @@ mod test_impl {
 #[cfg(test)]
 mod test_backend {
     use crate::{
-        git_testing::{GitCommitId, GitRemote, INITIAL_BRANCH},
-        verbosity::Verbosity,
+        git_testing::{GitClone, GitCommitId, GitRemote, INITIAL_BRANCH},
+        renderer::test::{MemoryRenderer, NoRenderer},
+        verbosity::{output_stdout, run_notable, with_stdin, Retries, Verbosity},
+    };
+
+    use super::{
+        git_command, FetchOptions, GitBinary, GitVersion, LineArity, PushCount, SshMultiplex,
     };
-    use std::{collections::HashSet, iter::FromIterator};
+    use std::{
+        borrow::Cow, collections::HashSet, fs, iter::FromIterator, rc::Rc, sync::OnceLock,
+        time::Duration,
+    };
+    use tempfile::tempdir;
 
-    use crate::types::NomadRef;
+    use crate::types::{Branch, NomadRef, Remote};
 
     /// Push should put local branches to remote `refs/nomad/{user}/{host}/{branch}`
     #[test]
@@ -1039,32 +4105,1022 @@ mod test_backend {
         );
     }
 
-    /// Fetch should pull refs for all hosts that have pushed under the configured user under
-    /// `refs/nomad/{host}/{branch}`
+    /// Listing several remotes at once should keep the refs of each of them apart.
     #[test]
-    fn fetch() {
-        let origin = GitRemote::init(None);
+    fn list_remote_refs_many() {
+        let origin = GitRemote::init(Some(Verbosity::max()));
+        let backup = GitRemote::init(Some(Verbosity::max()));
 
         let host0 = origin.clone("user0", "host0");
         host0.push();
+        let host1 = backup.clone("user0", "host1");
+        host1.push();
+
+        let backup_remote = host0.add_remote("backup", &backup);
+        let mut renderer = MemoryRenderer::new();
+        let refs = host0
+            .git
+            .list_remote_refs_many(
+                &mut renderer,
+                "Listing refs",
+                &[host0.remote.clone(), backup_remote],
+                &["refs/nomad/*"],
+            )
+            .unwrap();
 
-        let host1 = origin.clone("user0", "host1");
+        assert_eq!(
+            refs.into_iter()
+                .map(|refs| refs
+                    .into_iter()
+                    .map(|ref_| (ref_.name, GitCommitId(ref_.commit_id)))
+                    .collect::<Vec<_>>())
+                .collect::<Vec<_>>(),
+            vec![
+                vec![(
+                    format!("refs/nomad/user0/host0/{}", INITIAL_BRANCH),
+                    host0.current_commit(),
+                )],
+                vec![(
+                    format!("refs/nomad/user0/host1/{}", INITIAL_BRANCH),
+                    host1.current_commit(),
+                )],
+            ],
+        );
+        assert_eq!(renderer.as_str().matches("ls-remote").count(), 2);
+    }
 
-        // Before fetch, the host1 clone should have no nomad refs
-        assert_eq!(host1.nomad_refs(), HashSet::new());
+    /// Fetches that fail for transient network reasons should be retried, via a fake git that
+    /// fails the first two fetches as if DNS was down, but only as often as allowed.
+    #[test]
+    fn fetch_retries_transient_failures() {
+        use std::os::unix::fs::PermissionsExt;
 
-        // After fetch, we should see the one host0 branch
-        host1.fetch();
-        let nomad_refs = host1
-            .list()
-            .map(Into::into)
-            .collect::<HashSet<NomadRef<GitCommitId>>>();
+        let origin = GitRemote::init(Some(Verbosity::max()));
+        let mut host0 = origin.clone("user0", "host0");
+        host0.push();
+
+        let dir = tempdir().unwrap();
+        let attempts = dir.path().join("attempts");
+        let fake_git = dir.path().join("git");
+        fs::write(
+            &fake_git,
+            format!(
+                "#!/bin/sh\n\
+                 case \" $* \" in *\" fetch \"*)\n\
+                 \techo attempt >> '{attempts}'\n\
+                 \tif [ $(wc -l < '{attempts}') -le 2 ]; then\n\
+                 \t\techo \"fatal: unable to access 'https://example.invalid/': Could not resolve host: example.invalid\" >&2\n\
+                 \t\texit 128\n\
+                 \tfi;;\n\
+                 esac\n\
+                 exec git \"$@\"\n",
+                attempts = attempts.display(),
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&fake_git, fs::Permissions::from_mode(0o755)).unwrap();
+        host0.git.name = Cow::Owned(fake_git.display().to_string());
+
+        let fetch = |git: &GitBinary, renderer: &mut MemoryRenderer| {
+            git.fetch_nomad_refs(
+                renderer,
+                &host0.user,
+                &host0.remote,
+                FetchOptions::default(),
+            )
+        };
+        let backoff = Duration::from_millis(10);
+
+        host0.git.retries = Retries { count: 1, backoff };
+        assert!(fetch(&host0.git, &mut MemoryRenderer::new()).is_err());
+        fs::remove_file(&attempts).unwrap();
+
+        host0.git.retries = Retries { count: 2, backoff };
+        let mut renderer = MemoryRenderer::new();
+        fetch(&host0.git, &mut renderer).unwrap();
+        assert_eq!(fs::read_to_string(&attempts).unwrap().lines().count(), 3);
+        assert!(renderer
+            .as_str()
+            .contains("Could not resolve host: example.invalid, retrying in 0.01s (1 of 2)"));
+        assert!(renderer.as_str().contains("retrying in 0.02s (2 of 2)"));
+    }
+
+    /// The mirror fetch and push should be retried like any other network command, via a fake git
+    /// that fails the first of each as if DNS was down.
+    #[test]
+    fn mirror_retries_transient_failures() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let origin = GitRemote::init(Some(Verbosity::max()));
+        let mut host0 = origin.clone("user0", "host0");
+
+        let dir = tempdir().unwrap();
+        let fake_git = dir.path().join("git");
+        fs::write(
+            &fake_git,
+            format!(
+                "#!/bin/sh\n\
+                 for command in fetch push; do\n\
+                 \tcase \" $* \" in *\" $command \"*)\n\
+                 \t\tif [ ! -e '{dir}/'$command ]; then\n\
+                 \t\t\ttouch '{dir}/'$command\n\
+                 \t\t\techo \"fatal: unable to access 'https://example.invalid/': Could not resolve host: example.invalid\" >&2\n\
+                 \t\t\texit 128\n\
+                 \t\tfi;;\n\
+                 \tesac\n\
+                 done\n\
+                 exec git \"$@\"\n",
+                dir = dir.path().display(),
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&fake_git, fs::Permissions::from_mode(0o755)).unwrap();
+        host0.git.name = Cow::Owned(fake_git.display().to_string());
+        host0.git.retries = Retries {
+            count: 1,
+            backoff: Duration::from_millis(10),
+        };
+
+        let mut renderer = MemoryRenderer::new();
+        host0
+            .git
+            .mirror_remote_branches(&mut renderer, &host0.user, &host0.remote, false)
+            .unwrap();
         assert_eq!(
-            nomad_refs,
+            renderer
+                .as_str()
+                .matches("Could not resolve host: example.invalid, retrying in 0.01s (1 of 1)")
+                .count(),
+            2,
+        );
+    }
+
+    /// `depth` should be refused in a full clone, leaving the history of local branches intact,
+    /// and passed along to `git fetch` in a shallow one, via a fake git that records its arguments.
+    #[test]
+    fn fetch_depth() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let origin = GitRemote::init(Some(Verbosity::max()));
+        let host0 = origin.clone("user0", "host0");
+        let git = |clone: &GitClone, args: &[&str]| {
+            let output = git_command("git")
+                .current_dir(clone.working_directory())
+                .args(args)
+                .output()
+                .unwrap();
+            String::from_utf8(output.stdout).unwrap().trim().to_string()
+        };
+        host0.push();
+        let mut host1 = origin.clone("user0", "host1");
+        for message in ["second", "third"] {
+            git(&host1, &["commit", "--allow-empty", "-m", message]);
+        }
+
+        let fetch = |clone: &GitClone| {
+            clone.git.fetch_nomad_refs(
+                &mut NoRenderer,
+                &clone.user,
+                &clone.remote,
+                FetchOptions {
+                    depth: Some(1),
+                    ..FetchOptions::default()
+                },
+            )
+        };
+
+        let history = git(&host1, &["rev-list", "--count", INITIAL_BRANCH]);
+        assert_eq!(history, "3");
+        let error = fetch(&host1).unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("Refusing to fetch with --depth"));
+        assert!(!host1.git.is_shallow(&mut NoRenderer).unwrap());
+        assert_eq!(
+            git(&host1, &["rev-list", "--count", INITIAL_BRANCH]),
+            history
+        );
+
+        // Marking the current commit as a shallow boundary is what `git clone --depth 1` does.
+        let head = git(&host1, &["rev-parse", "HEAD"]);
+        fs::write(host1.git.git_dir().join("shallow"), format!("{}\n", head)).unwrap();
+        assert!(host1.git.is_shallow(&mut NoRenderer).unwrap());
+
+        let dir = tempdir().unwrap();
+        let invocations = dir.path().join("invocations");
+        let fake_git = dir.path().join("git");
+        fs::write(
+            &fake_git,
+            format!(
+                "#!/bin/sh\n\
+                 echo \"$*\" >> '{invocations}'\n\
+                 exec git \"$@\"\n",
+                invocations = invocations.display(),
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&fake_git, fs::Permissions::from_mode(0o755)).unwrap();
+        host1.git.name = Cow::Owned(fake_git.display().to_string());
+
+        fetch(&host1).unwrap();
+        let invocations = fs::read_to_string(&invocations).unwrap();
+        assert!(
+            invocations
+                .lines()
+                .any(|line| line.contains(" fetch --depth=1 origin ")),
+            "{}",
+            invocations
+        );
+        assert!(host1
+            .nomad_refs()
+            .contains(&host0.get_nomad_ref(INITIAL_BRANCH).unwrap()));
+    }
+
+    /// Every remote from `git remote` should be listed, in the order git prints them.
+    #[test]
+    fn list_remotes() {
+        let origin = GitRemote::init(Some(Verbosity::max()));
+        let backup = GitRemote::init(Some(Verbosity::max()));
+
+        let host0 = origin.clone("user0", "host0");
+        host0.add_remote("backup", &backup);
+
+        assert_eq!(
+            host0.git.list_remotes(&mut NoRenderer).unwrap(),
+            vec![Remote::from("backup"), Remote::from("origin")],
+        );
+    }
+
+    /// Refs under a custom prefix should be counted separately from the default prefix, while
+    /// branches and tags with slashes are not mistaken for nomad refs.
+    #[test]
+    fn detect_ref_prefixes() {
+        let origin = GitRemote::init(Some(Verbosity::max()));
+
+        let mut host0 = origin.clone("user0", "host0");
+        host0.git.ref_prefix = "team-a".to_string();
+        host0
+            .git
+            .create_branch(&mut NoRenderer, "", &Branch::from("feature/a/b"))
+            .unwrap();
+        host0.push();
+
+        let host1 = origin.clone("user1", "host1");
+        host1.push();
+        run_notable(
+            &mut NoRenderer,
+            None,
+            "",
+            host1
+                .git
+                .command()
+                .args(["push", "origin", "HEAD:refs/tags/v1/x/y"]),
+        )
+        .unwrap();
+
+        assert_eq!(
+            host1
+                .git
+                .detect_ref_prefixes(&mut NoRenderer, &host1.remote)
+                .unwrap(),
+            vec![("team-a".to_string(), 2), ("nomad".to_string(), 1)],
+        );
+    }
+
+    /// Committers should be keyed by ref name, skipping refs that do not exist locally.
+    #[test]
+    fn ref_committers() {
+        let origin = GitRemote::init(None);
+        let host0 = origin.clone("user0", "host0");
+        host0.push();
+        host0.fetch();
+
+        let snapshot = host0.git.snapshot(&mut NoRenderer, &host0.user).unwrap();
+        let ref_name = snapshot.nomad_refs[0].ref_.name.clone();
+        let missing = "refs/nomad/host1/missing".to_string();
+
+        assert_eq!(
+            host0
+                .git
+                .ref_committers(&mut NoRenderer, [&ref_name, &missing].into_iter())
+                .unwrap(),
+            [(ref_name, "git-nomad <git-nomad@invalid>".to_string())].into(),
+        );
+    }
+
+    /// `verify_objects` should warn about nomad refs pointing at commits that are not present
+    /// locally, and only those.
+    #[test]
+    fn snapshot_verify_objects() {
+        let origin = GitRemote::init(Some(Verbosity::default()));
+        let mut host0 = origin.clone("user0", "host0");
+        host0.push();
+        host0.fetch();
+
+        let bogus = "1111111111111111111111111111111111111111";
+        let host1_dir = host0.git.git_dir().join("refs/nomad/host1");
+        fs::create_dir_all(&host1_dir).unwrap();
+        fs::write(host1_dir.join("feature"), format!("{}\n", bogus)).unwrap();
+
+        let mut renderer = MemoryRenderer::new();
+        let snapshot = host0.git.snapshot(&mut renderer, &host0.user).unwrap();
+        assert_eq!(snapshot.nomad_refs.len(), 2);
+        assert_eq!(renderer.as_str(), "");
+
+        host0.git.verify_objects = true;
+        let mut renderer = MemoryRenderer::new();
+        let snapshot = host0.git.snapshot(&mut renderer, &host0.user).unwrap();
+        assert_eq!(snapshot.nomad_refs.len(), 2);
+        assert_eq!(
+            renderer.as_str(),
+            format!(
+                "Warning: refs/nomad/host1/feature points at missing commit {}, the repository may be corrupt or partially fetched\n",
+                bogus
+            ),
+        );
+    }
+
+    /// The working tree should be found for both regular clones and linked worktrees.
+    #[test]
+    fn work_tree() {
+        let origin = GitRemote::init(None);
+        let host0 = origin.clone("user0", "host0");
+        let work_tree = host0.git.work_tree().unwrap();
+        assert_eq!(work_tree, host0.working_directory());
+
+        let linked = work_tree.with_file_name("linked");
+        run_notable(
+            &mut NoRenderer,
+            None,
+            "",
+            host0
+                .git
+                .command()
+                .args(["worktree", "add", "-b", "linked"])
+                .arg(&linked),
+        )
+        .unwrap();
+        let git = GitBinary::new(&mut NoRenderer, None, Cow::from("git"), &linked).unwrap();
+        assert_eq!(git.work_tree(), Some(linked));
+    }
+
+    /// Refs pushed under a different prefix should only be visible under that prefix.
+    #[test]
+    fn push_and_fetch_with_ref_prefix() {
+        let origin = GitRemote::init(Some(Verbosity::max()));
+        let mut host0 = origin.clone("user0", "host0");
+        host0.git.ref_prefix = "team-a".to_string();
+        host0.push();
+
+        let host1 = origin.clone("user0", "host1");
+        host1.fetch();
+        assert_eq!(host1.list().count(), 0);
+
+        let mut host2 = origin.clone("user0", "host2");
+        host2.git.ref_prefix = "team-a".to_string();
+        host2.fetch();
+        assert_eq!(
+            host2.list().map(|r| r.ref_.name).collect::<Vec<_>>(),
+            vec![format!("refs/team-a/user0/host0/{}", INITIAL_BRANCH)],
+        );
+    }
+
+    /// Fetch should pull refs for all hosts that have pushed under the configured user under
+    /// `refs/nomad/{host}/{branch}`
+    #[test]
+    fn fetch() {
+        let origin = GitRemote::init(None);
+
+        let host0 = origin.clone("user0", "host0");
+        host0.push();
+
+        let host1 = origin.clone("user0", "host1");
+
+        // Before fetch, the host1 clone should have no nomad refs
+        assert_eq!(host1.nomad_refs(), HashSet::new());
+
+        // After fetch, we should see the one host0 branch
+        host1.fetch();
+        let nomad_refs = host1
+            .list()
+            .map(Into::into)
+            .collect::<HashSet<NomadRef<GitCommitId>>>();
+        assert_eq!(
+            nomad_refs,
+            HashSet::from_iter([host0.get_nomad_ref(INITIAL_BRANCH).unwrap()])
+        );
+    }
+
+    /// Commands that talk to the remote should not prompt for credentials unless allowed.
+    #[test]
+    fn remote_commands_disable_prompts() {
+        let origin = GitRemote::init(Some(Verbosity::max()));
+        let mut host0 = origin.clone("user0", "host0");
+
+        for (allow_prompt, expected) in [(false, true), (true, false)] {
+            host0.git.allow_prompt = allow_prompt;
+
+            let mut renderer = MemoryRenderer::new();
+            host0
+                .git
+                .push_nomad_refs(
+                    &mut renderer,
+                    &host0.user,
+                    &host0.host,
+                    &host0.remote,
+                    None,
+                    false,
+                )
+                .unwrap();
+            host0
+                .git
+                .fetch_nomad_refs(
+                    &mut renderer,
+                    &host0.user,
+                    &host0.remote,
+                    FetchOptions::default(),
+                )
+                .unwrap();
+
+            let output = renderer.as_str();
+            assert_eq!(output.matches("GIT_TERMINAL_PROMPT").count() == 2, expected);
+            assert_eq!(output.contains("GIT_ASKPASS"), expected);
+        }
+    }
+
+    /// With [`SshMultiplex`], commands that talk to the remote should be told to share SSH
+    /// connections, and only those commands.
+    #[test]
+    fn remote_commands_ssh_multiplex() {
+        let origin = GitRemote::init(Some(Verbosity::max()));
+        let mut host0 = origin.clone("user0", "host0");
+
+        for multiplex in [false, true] {
+            host0.git.ssh_multiplex = multiplex.then(|| Rc::new(SshMultiplex::new("ssh").unwrap()));
+
+            let mut renderer = MemoryRenderer::new();
+            host0
+                .git
+                .push_nomad_refs(
+                    &mut renderer,
+                    &host0.user,
+                    &host0.host,
+                    &host0.remote,
+                    None,
+                    false,
+                )
+                .unwrap();
+            host0.git.current_branch(&mut renderer).unwrap();
+
+            let output = renderer.as_str();
+            assert_eq!(
+                output.matches("GIT_SSH_COMMAND").count(),
+                multiplex as usize
+            );
+            assert_eq!(
+                output.contains("ssh -o ControlMaster=auto -o ControlPersist=60 -o 'ControlPath="),
+                multiplex
+            );
+        }
+    }
+
+    /// Only commands that talk to the remote should run in the git namespace, which moves the
+    /// nomad refs on the remote under `refs/namespaces/{namespace}`.
+    #[test]
+    fn remote_commands_git_namespace() {
+        let origin = GitRemote::init(Some(Verbosity::max()));
+        let mut host0 = origin.clone("user0", "host0");
+        host0.git.git_namespace = Some("nomad".to_string());
+
+        let mut renderer = MemoryRenderer::new();
+        host0
+            .git
+            .push_nomad_refs(
+                &mut renderer,
+                &host0.user,
+                &host0.host,
+                &host0.remote,
+                None,
+                false,
+            )
+            .unwrap();
+        host0
+            .git
+            .fetch_nomad_refs(
+                &mut renderer,
+                &host0.user,
+                &host0.remote,
+                FetchOptions::default(),
+            )
+            .unwrap();
+        host0.git.current_branch(&mut renderer).unwrap();
+        assert_eq!(renderer.as_str().matches("GIT_NAMESPACE").count(), 2);
+
+        assert_eq!(origin.nomad_refs(), HashSet::new());
+        let namespaced = output_stdout(
+            origin
+                .git
+                .command()
+                .args(["for-each-ref", "--format=%(refname)", "refs/namespaces"])
+                .output()
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            namespaced,
+            format!(
+                "refs/namespaces/nomad/refs/nomad/user0/host0/{}\n",
+                INITIAL_BRANCH
+            ),
+        );
+        assert_eq!(
+            host0.nomad_refs(),
+            HashSet::from_iter([host0.get_nomad_ref(INITIAL_BRANCH).unwrap()]),
+        );
+    }
+
+    /// The control socket directory should not outlive [`SshMultiplex`].
+    #[test]
+    fn ssh_multiplex_cleanup() {
+        let multiplex = SshMultiplex::new("ssh -i key").unwrap();
+        let control_dir = multiplex.control_dir.path().to_path_buf();
+        assert!(control_dir.is_dir());
+        assert!(multiplex
+            .ssh_command
+            .starts_with("ssh -i key -o ControlMaster=auto"));
+        assert!(multiplex
+            .ssh_command
+            .ends_with(&format!("'ControlPath={}/%C'", control_dir.display())));
+
+        drop(multiplex);
+        assert!(!control_dir.exists());
+    }
+
+    /// Socket paths should fit in `sun_path` even when the temporary directory is long, and lingering master
+    /// connections should be closed with the same SSH command that opened them.
+    #[test]
+    fn ssh_multiplex_short_control_dir() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempdir().unwrap();
+        // Never created, since it is too long to be used anyway.
+        let long_tmpdir = dir
+            .path()
+            .join("x".repeat(SshMultiplex::MAX_CONTROL_DIR_LEN));
+        let invocations = dir.path().join("invocations");
+        let fake_ssh = dir.path().join("ssh");
+        fs::write(
+            &fake_ssh,
+            format!(
+                "#!/bin/sh
+                 echo \"$*\" >> '{invocations}'\n",
+                invocations = invocations.display(),
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&fake_ssh, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let multiplex =
+            SshMultiplex::new_in(&format!("{} -i key", fake_ssh.display()), long_tmpdir).unwrap();
+
+        let control_dir = multiplex.control_dir.path().to_path_buf();
+        assert!(control_dir.starts_with("/tmp"));
+        assert!(control_dir.as_os_str().len() <= SshMultiplex::MAX_CONTROL_DIR_LEN);
+
+        let socket = control_dir.join("0".repeat(40));
+        fs::write(&socket, "").unwrap();
+        drop(multiplex);
+        assert_eq!(
+            fs::read_to_string(&invocations).unwrap(),
+            format!(
+                "-i key -o ControlPath={} -O exit git-nomad\n",
+                socket.display()
+            ),
+        );
+    }
+
+    /// Multiplexing should build on `core.sshCommand` rather than replacing it.
+    #[test]
+    fn ssh_command_from_config() {
+        let origin = GitRemote::init(None);
+        let host0 = origin.clone("user0", "host0");
+        assert_eq!(host0.git.ssh_command(&mut NoRenderer).unwrap(), "ssh");
+
+        host0
+            .git
+            .command()
+            .args(["config", "core.sshCommand", "ssh -i key"])
+            .status()
+            .unwrap();
+        assert_eq!(
+            host0.git.ssh_command(&mut NoRenderer).unwrap(),
+            "ssh -i key"
+        );
+    }
+
+    /// Globally configured credential helpers should still be consulted by commands that talk to
+    /// the remote, even with prompts disabled, so that nomad authenticates wherever a plain
+    /// `git push` does.
+    #[test]
+    fn remote_commands_use_credential_helpers() {
+        let origin = GitRemote::init(None);
+        let host0 = origin.clone("user0", "host0");
+        assert!(!host0.git.allow_prompt);
+
+        let config_dir = tempdir().unwrap();
+        let global_config = config_dir.path().join("gitconfig");
+        fs::write(
+            &global_config,
+            "[credential]\n\thelper = \"!f() { echo username=nomad; echo password=hunter2; }; f\"\n",
+        )
+        .unwrap();
+
+        let output = with_stdin(b"protocol=https\nhost=example.com\n\n".to_vec(), || {
+            run_notable(
+                &mut NoRenderer,
+                None,
+                "Fill credentials",
+                host0
+                    .git
+                    .remote_command()
+                    .env("GIT_CONFIG_GLOBAL", &global_config)
+                    .args(["credential", "fill"]),
+            )
+        })
+        .and_then(output_stdout)
+        .unwrap();
+
+        assert!(output.contains("username=nomad\n"));
+        assert!(output.contains("password=hunter2\n"));
+    }
+
+    /// Pushes should override the user's `push.*` config, even when that config would get in the
+    /// way.
+    #[test]
+    fn push_isolated_from_push_config() {
+        let origin = GitRemote::init(Some(Verbosity::default()));
+        let host0 = origin.clone("user0", "host0");
+        for (key, value) in [
+            ("push.followTags", "true"),
+            ("push.recurseSubmodules", "check"),
+            ("push.gpgSign", "true"),
+        ] {
+            host0
+                .git
+                .command()
+                .args(["config", key, value])
+                .status()
+                .unwrap();
+        }
+
+        let mut renderer = MemoryRenderer::new();
+        host0
+            .git
+            .push_nomad_refs(
+                &mut renderer,
+                &host0.user,
+                &host0.host,
+                &host0.remote,
+                None,
+                true,
+            )
+            .unwrap();
+        assert!(renderer.as_str().contains(
+            "-c push.default=nothing -c push.followTags=false -c push.recurseSubmodules=no \
+             -c push.gpgSign=false push --no-verify origin"
+        ));
+
+        // The test remote refuses signed pushes, so this only passes if it was overridden.
+        host0.push();
+        assert_eq!(
+            origin.nomad_refs(),
+            HashSet::from_iter([host0.get_nomad_ref(INITIAL_BRANCH).unwrap()]),
+        );
+
+        // The mirror push builds its own command, which needs the same overrides.
+        let mut renderer = MemoryRenderer::new();
+        host0
+            .git
+            .mirror_remote_branches(&mut renderer, &host0.user, &host0.remote, true)
+            .unwrap();
+        assert!(renderer.as_str().contains(
+            "-c push.default=nothing -c push.followTags=false -c push.recurseSubmodules=no \
+             -c push.gpgSign=false push --no-verify --prune origin"
+        ));
+        host0
+            .git
+            .mirror_remote_branches(&mut NoRenderer, &host0.user, &host0.remote, false)
+            .unwrap();
+    }
+
+    /// Pushing should count refs that changed at the remote, but not ones already up to date.
+    #[test]
+    fn push_nomad_refs_count() {
+        let origin = GitRemote::init(Some(Verbosity::default()));
+        let host0 = origin.clone("user0", "host0");
+
+        let push = |dry_run| {
+            host0
+                .git
+                .push_nomad_refs(
+                    &mut NoRenderer,
+                    &host0.user,
+                    &host0.host,
+                    &host0.remote,
+                    None,
+                    dry_run,
+                )
+                .unwrap()
+        };
+
+        let expected = |updated| PushCount {
+            refspecs: 1,
+            updated,
+        };
+        assert_eq!(push(true), expected(0));
+        assert_eq!(push(false), expected(1));
+        assert_eq!(push(false), expected(0));
+    }
+
+    /// Unrelated histories should count every commit towards one side, rather than failing.
+    #[test]
+    fn ahead_behind_unrelated() {
+        let origin = GitRemote::init(None);
+        let host0 = origin.clone("user0", "host0");
+        let branch = format!("refs/heads/{}", INITIAL_BRANCH);
+        assert_eq!(
+            host0
+                .git
+                .ahead_behind(&mut NoRenderer, &branch, &branch)
+                .unwrap(),
+            (0, 0)
+        );
+
+        for args in [
+            &["checkout", "--orphan", "unrelated"][..],
+            &["commit", "--allow-empty", "-m", "unrelated0"],
+            &["commit", "--allow-empty", "-m", "unrelated1"],
+        ] {
+            run_notable(
+                &mut NoRenderer,
+                None,
+                "",
+                git_command("git")
+                    .current_dir(host0.working_directory())
+                    .args(args),
+            )
+            .unwrap();
+        }
+
+        assert_eq!(
+            host0
+                .git
+                .ahead_behind(&mut NoRenderer, &branch, "refs/heads/unrelated")
+                .unwrap(),
+            (2, 1)
+        );
+    }
+
+    /// Fetching with `write_summary` should record which nomad refs changed.
+    #[test]
+    fn fetch_write_summary() {
+        let origin = GitRemote::init(None);
+        let host0 = origin.clone("user0", "host0");
+        host0.push();
+        let old_commit = host0.current_commit();
+
+        let host1 = origin.clone("user0", "host1");
+        let options = FetchOptions {
+            write_summary: true,
+            ..FetchOptions::default()
+        };
+        host1
+            .git
+            .fetch_nomad_refs(&mut NoRenderer, &host1.user, &host1.remote, options)
+            .unwrap();
+
+        let summary = fs::read_to_string(host1.git.fetch_summary_path()).unwrap();
+        assert_eq!(
+            summary,
+            format!(
+                "{{\"host\":\"host0\",\"branch\":\"{}\",\"old\":null,\"new\":\"{}\"}}\n",
+                INITIAL_BRANCH, old_commit.0
+            ),
+        );
+
+        git_command("git")
+            .current_dir(host0.working_directory())
+            .args(["commit", "--allow-empty", "-m", "changed"])
+            .output()
+            .unwrap();
+        host0.push();
+        let new_commit = host0.current_commit();
+
+        host1
+            .git
+            .fetch_nomad_refs(&mut NoRenderer, &host1.user, &host1.remote, options)
+            .unwrap();
+
+        let summary = fs::read_to_string(host1.git.fetch_summary_path()).unwrap();
+        assert_eq!(
+            summary,
+            format!(
+                "{{\"host\":\"host0\",\"branch\":\"{}\",\"old\":\"{}\",\"new\":\"{}\"}}\n",
+                INITIAL_BRANCH, old_commit.0, new_commit.0
+            ),
+        );
+    }
+
+    /// Partial fetches should pass a blob filter to git, which marks the remote as a promisor.
+    #[test]
+    fn fetch_partial() {
+        let verbosity = Some(Verbosity::max());
+        let origin = GitRemote::init(verbosity);
+        run_notable(
+            &mut NoRenderer,
+            verbosity,
+            "Allow partial clones",
+            origin
+                .git
+                .command()
+                .args(["config", "uploadpack.allowFilter", "true"]),
+        )
+        .unwrap();
+
+        let host0 = origin.clone("user0", "host0");
+        host0.push();
+
+        let host1 = origin.clone("user0", "host1");
+        let mut renderer = MemoryRenderer::new();
+        host1
+            .git
+            .fetch_nomad_refs(
+                &mut renderer,
+                &host1.user,
+                &host1.remote,
+                FetchOptions {
+                    partial: true,
+                    ..FetchOptions::default()
+                },
+            )
+            .unwrap();
+        assert!(renderer.as_str().contains("--filter=blob:none"));
+
+        let promisor = run_notable(
+            &mut NoRenderer,
+            verbosity,
+            "Check for promisor remote",
+            host1
+                .git
+                .command()
+                .args(["config", "--get", "remote.origin.promisor"]),
+        )
+        .and_then(output_stdout)
+        .map(LineArity::from)
+        .and_then(LineArity::one)
+        .unwrap();
+        assert_eq!(promisor, "true");
+
+        assert_eq!(
+            host1.nomad_refs(),
             HashSet::from_iter([host0.get_nomad_ref(INITIAL_BRANCH).unwrap()])
         );
     }
 
+    /// A git too old for partial fetches should fall back to fetching everything with a
+    /// warning, while a git too old to estimate disk usage should say which version it needs.
+    #[test]
+    fn old_git_version() {
+        let origin = GitRemote::init(Some(Verbosity::default()));
+        let host0 = origin.clone("user0", "host0");
+        host0.push();
+
+        let mut host1 = origin.clone("user0", "host1");
+        host1.git.version = OnceLock::from(Some(GitVersion(2, 18, 0)));
+
+        let mut renderer = MemoryRenderer::new();
+        host1
+            .git
+            .fetch_nomad_refs(
+                &mut renderer,
+                &host1.user,
+                &host1.remote,
+                FetchOptions {
+                    partial: true,
+                    ..FetchOptions::default()
+                },
+            )
+            .unwrap();
+        assert!(renderer.as_str().contains(
+            "Warning: Fetching partially needs git >= 2.19.0, you have 2.18.0, fetching everything instead"
+        ));
+        assert_eq!(host1.list().count(), 1);
+
+        let refs = host1
+            .list()
+            .map(|nomad_ref| nomad_ref.ref_)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            host1
+                .git
+                .disk_usage(&mut NoRenderer, "", &host1.remote, &refs)
+                .unwrap_err()
+                .to_string(),
+            "Estimating disk usage needs git >= 2.31.0, you have 2.18.0",
+        );
+    }
+
+    /// Only refs that differ from the local nomad refs should be fetched, by name while there are
+    /// few of them.
+    #[test]
+    fn fetch_changed_nomad_refs() {
+        let origin = GitRemote::init(Some(Verbosity::max()));
+        let host0 = origin.clone("user0", "host0");
+        let host1 = origin.clone("user0", "host1");
+
+        let fetch_changed = || {
+            let listed = host1
+                .git
+                .list_nomad_refs(&mut NoRenderer, &host1.user, &host1.remote)
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            let mut renderer = MemoryRenderer::new();
+            host1
+                .git
+                .fetch_changed_nomad_refs(
+                    &mut renderer,
+                    &host1.user,
+                    &host1.remote,
+                    &listed,
+                    FetchOptions::default(),
+                )
+                .unwrap();
+            renderer.as_str().to_string()
+        };
+
+        host0.push();
+        let output = fetch_changed();
+        assert!(output.contains("+refs/nomad/user0/host0/master:refs/nomad/host0/master"));
+        assert_eq!(host1.nomad_refs(), origin.nomad_refs());
+
+        assert!(!fetch_changed().contains("\"fetch\""));
+
+        let head = host0.current_commit();
+        let create = (0..=super::SELECTIVE_FETCH_LIMIT)
+            .map(|i| format!("create refs/heads/branch{} {}\n", i, head.0))
+            .collect::<String>();
+        with_stdin(create.into_bytes(), || {
+            run_notable(
+                &mut NoRenderer,
+                None,
+                "",
+                host0.git.command().args(["update-ref", "--stdin"]),
+            )
+        })
+        .unwrap();
+        host0.push();
+
+        let output = fetch_changed();
+        assert!(output.contains("+refs/nomad/user0/*:refs/nomad/*"));
+        assert_eq!(host1.nomad_refs().len(), super::SELECTIVE_FETCH_LIMIT + 2);
+    }
+
+    /// Pruning more refs than fit in a single push should still delete all of them, in batches.
+    #[test]
+    fn prune_many() {
+        let origin = GitRemote::init(None);
+        let host0 = origin.clone("user0", "host0");
+
+        let head = host0.current_commit();
+        let branches = (0..250)
+            .map(|i| format!("branch{:03}", i))
+            .collect::<Vec<_>>();
+        let create = branches
+            .iter()
+            .map(|branch| format!("create refs/heads/{} {}\n", branch, head.0))
+            .collect::<String>();
+        with_stdin(create.into_bytes(), || {
+            run_notable(
+                &mut NoRenderer,
+                None,
+                "",
+                host0.git.command().args(["update-ref", "--stdin"]),
+            )
+        })
+        .unwrap();
+
+        host0.push();
+        host0.fetch();
+        assert_eq!(origin.nomad_refs().len(), branches.len() + 1);
+        assert_eq!(host0.nomad_refs().len(), branches.len() + 1);
+
+        host0.prune_local_and_remote(branches.iter().map(String::as_str));
+        let remaining = HashSet::from_iter([host0.get_nomad_ref(INITIAL_BRANCH).unwrap()]);
+        assert_eq!(origin.nomad_refs(), remaining);
+        assert_eq!(host0.nomad_refs(), remaining);
+    }
+
     /// Pushing should create nomad refs in the remote.
     /// Fetching should create nomad refs locally.
     /// Pruning should delete refs in the local and remote.
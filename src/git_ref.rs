@@ -75,9 +75,57 @@ impl GitRef {
     }
 }
 
+/// A ref as listed by `git for-each-ref`, which unlike `git show-ref` distinguishes symbolic refs.
+///
+/// Symbolic refs (like `HEAD` pointing to `refs/heads/master`) report the commit ID of whatever
+/// they point at, so treating them as a [`GitRef`] would be a lie. For example, deleting one via
+/// `git update-ref -d` would delete its target instead.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum ListedRef {
+    /// A normal ref that points directly at a commit.
+    Direct(GitRef),
+    /// A ref that points at another ref.
+    Symbolic {
+        /// The full ref name, like `HEAD`.
+        name: String,
+        /// The full ref name being pointed to, like `refs/heads/master`.
+        target: String,
+    },
+}
+
+impl ListedRef {
+    /// Parse a single line from `git for-each-ref` produced via [`Self::FOR_EACH_REF_FORMAT`].
+    pub fn parse_for_each_ref_line(line: &str) -> Result<ListedRef, GitRefParseError> {
+        let (direct, symref) = line
+            .rsplit_once(' ')
+            .ok_or_else(|| GitRefParseError::MissingName(line.to_string()))?;
+        let git_ref = GitRef::parse_show_ref_line(direct)?;
+
+        if symref.is_empty() {
+            Ok(ListedRef::Direct(git_ref))
+        } else {
+            Ok(ListedRef::Symbolic {
+                name: git_ref.name,
+                target: symref.to_string(),
+            })
+        }
+    }
+
+    /// The `--format` to pass to `git for-each-ref` for [`Self::parse_for_each_ref_line`].
+    pub const FOR_EACH_REF_FORMAT: &'static str = "%(objectname) %(refname) %(symref)";
+
+    /// Only the refs that point directly at commits.
+    pub fn direct(self) -> Option<GitRef> {
+        match self {
+            Self::Direct(git_ref) => Some(git_ref),
+            Self::Symbolic { .. } => None,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{GitRef, GitRefParseError};
+    use super::{GitRef, GitRefParseError, ListedRef};
 
     #[test]
     fn parse() {
@@ -123,6 +171,68 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parse_for_each_ref_direct() {
+        assert_eq!(
+            ListedRef::parse_for_each_ref_line("commit_id refs/heads/master "),
+            Ok(ListedRef::Direct(GitRef {
+                commit_id: "commit_id".to_string(),
+                name: "refs/heads/master".to_string(),
+            }))
+        );
+    }
+
+    #[test]
+    fn parse_for_each_ref_symbolic() {
+        assert_eq!(
+            ListedRef::parse_for_each_ref_line(
+                "commit_id refs/nomad/host0/alias refs/heads/master"
+            ),
+            Ok(ListedRef::Symbolic {
+                name: "refs/nomad/host0/alias".to_string(),
+                target: "refs/heads/master".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn parse_for_each_ref_errors() {
+        for (line, err) in [
+            (
+                "",
+                GitRefParseError::MissingName as fn(String) -> GitRefParseError,
+            ),
+            ("refs/heads/master ", GitRefParseError::MissingCommitId),
+            (
+                "extra commit_id refs/heads/master ",
+                GitRefParseError::TooManyParts,
+            ),
+        ] {
+            let direct = line.rsplit_once(' ').map_or(line, |(direct, _)| direct);
+            assert_eq!(
+                ListedRef::parse_for_each_ref_line(line),
+                Err(err(direct.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn direct() {
+        let git_ref = GitRef {
+            commit_id: "commit_id".to_string(),
+            name: "refs/heads/master".to_string(),
+        };
+        assert_eq!(ListedRef::Direct(git_ref.clone()).direct(), Some(git_ref));
+        assert_eq!(
+            ListedRef::Symbolic {
+                name: "HEAD".to_string(),
+                target: "refs/heads/master".to_string(),
+            }
+            .direct(),
+            None
+        );
+    }
+
     /// Checks that displaying any [`GitRefParseError`] always includes the string passed in.
     fn assert_display_contains_str(func: impl Fn(String) -> GitRefParseError) {
         let displayed = format!("{}", func("foo".to_string()));
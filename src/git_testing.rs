@@ -8,11 +8,11 @@ use std::{
 use tempfile::{tempdir, TempDir};
 
 use crate::{
-    git_binary::{git_command, GitBinary, LineArity},
-    git_ref::GitRef,
+    git_binary::{git_command, FetchOptions, GitBinary, LineArity},
+    git_ref::{GitRef, ListedRef},
     renderer::test::NoRenderer,
     snapshot::PruneFrom,
-    types::{Branch, Host, NomadRef, Remote, User},
+    types::{Branch, Host, Interner, NomadRef, Remote, User},
     verbosity::{output_stdout, run_notable, Verbosity},
 };
 
@@ -132,7 +132,7 @@ impl GitRemote {
 
         GitClone {
             git_remote: self,
-            _clone_dir: clone_dir,
+            clone_dir,
             remote: Remote::from(ORIGIN),
             user: User::from(user),
             host: Host::from(host),
@@ -140,16 +140,35 @@ impl GitRemote {
         }
     }
 
+    /// Delete a ref directly in the remote, as if some other client had pruned it.
+    pub fn delete_ref(&self, ref_name: &str) {
+        run_notable(
+            &mut NoRenderer,
+            self.verbosity(),
+            "",
+            git_command(GIT)
+                .current_dir(&self.remote_dir)
+                .args(["update-ref", "-d", ref_name]),
+        )
+        .unwrap();
+    }
+
     /// List all nomad managed refs in the remote.
-    pub fn nomad_refs(&self) -> HashSet<NomadRef<GitCommitId>> {
+    pub fn nomad_refs(&self) -> HashSet<NomadRef<'_, GitCommitId>> {
+        let mut interner = Interner::default();
         self.git
             .list_refs(&mut NoRenderer, "")
             .unwrap()
             .into_iter()
+            .filter_map(ListedRef::direct)
             .filter_map(|git_ref| {
-                NomadRef::<GitRef>::from_git_remote_ref(git_ref)
-                    .ok()
-                    .map(Into::into)
+                NomadRef::<GitRef>::from_git_remote_ref(
+                    &self.git.ref_prefix,
+                    &mut interner,
+                    git_ref,
+                )
+                .ok()
+                .map(Into::into)
             })
             .collect::<HashSet<_>>()
     }
@@ -158,7 +177,7 @@ impl GitRemote {
 /// Acts like a separate [`Host`] in a temporary directory.
 pub struct GitClone<'a> {
     git_remote: &'a GitRemote,
-    _clone_dir: PathBuf,
+    clone_dir: PathBuf,
     pub remote: Remote<'static>,
     pub user: User<'static>,
     pub host: Host<'static>,
@@ -166,6 +185,26 @@ pub struct GitClone<'a> {
 }
 
 impl<'a> GitClone<'a> {
+    pub fn working_directory(&self) -> &Path {
+        &self.clone_dir
+    }
+
+    /// Register another simulated remote under `name`, like a backup of the original remote.
+    pub fn add_remote(&self, name: &'static str, git_remote: &GitRemote) -> Remote<'static> {
+        run_notable(
+            &mut NoRenderer,
+            self.git_remote.verbosity(),
+            "",
+            git_command(GIT)
+                .current_dir(&self.clone_dir)
+                .args(["remote", "add", name])
+                .arg(&git_remote.remote_dir),
+        )
+        .unwrap();
+
+        Remote::from(name)
+    }
+
     /// Get the commit ID at HEAD.
     pub fn current_commit(&self) -> GitCommitId {
         let commit_id = run_notable(
@@ -185,22 +224,35 @@ impl<'a> GitClone<'a> {
     /// Push all nomad managed refs to the remote.
     pub fn push(&self) {
         self.git
-            .push_nomad_refs(&mut NoRenderer, &self.user, &self.host, &self.remote)
+            .push_nomad_refs(
+                &mut NoRenderer,
+                &self.user,
+                &self.host,
+                &self.remote,
+                None,
+                false,
+            )
             .unwrap();
     }
 
     /// Fetch all nomad managed refs from the remote.
     pub fn fetch(&self) {
         self.git
-            .fetch_nomad_refs(&mut NoRenderer, &self.user, &self.remote)
+            .fetch_nomad_refs(
+                &mut NoRenderer,
+                &self.user,
+                &self.remote,
+                FetchOptions::default(),
+            )
             .unwrap()
     }
 
     /// List all nomad managed refs in the current clone.
-    pub fn list(&self) -> impl Iterator<Item = NomadRef<GitRef>> {
+    pub fn list(&self) -> impl Iterator<Item = NomadRef<'static, GitRef>> {
         self.git
             .list_nomad_refs(&mut NoRenderer, &self.user, &self.remote)
             .unwrap()
+            .map(Result::unwrap)
     }
 
     /// Delete the nomad managed refs backed by `branch_names` from both the local and remote.
@@ -213,7 +265,7 @@ impl<'a> GitClone<'a> {
                 ref_: (),
             };
 
-            let ref_name = nomad_ref.to_git_local_ref();
+            let ref_name = nomad_ref.to_git_local_ref(&self.git.ref_prefix);
 
             let nomad_ref = NomadRef {
                 user: nomad_ref.user,
@@ -226,7 +278,7 @@ impl<'a> GitClone<'a> {
         });
 
         self.git
-            .prune_nomad_refs(&mut NoRenderer, &self.remote, prune_from)
+            .prune_nomad_refs(&mut NoRenderer, &self.remote, prune_from, false, false)
             .unwrap();
     }
 
@@ -244,15 +296,22 @@ impl<'a> GitClone<'a> {
     }
 
     /// Get all nomad managed refs in the local clone.
-    pub fn nomad_refs(&self) -> HashSet<NomadRef<GitCommitId>> {
+    pub fn nomad_refs(&self) -> HashSet<NomadRef<'_, GitCommitId>> {
+        let mut interner = Interner::default();
         self.git
             .list_refs(&mut NoRenderer, &self.host.0)
             .unwrap()
             .into_iter()
+            .filter_map(ListedRef::direct)
             .filter_map(|git_ref| {
-                NomadRef::<GitRef>::from_git_local_ref(&self.user, git_ref)
-                    .ok()
-                    .map(Into::into)
+                NomadRef::<GitRef>::from_git_local_ref(
+                    &self.git.ref_prefix,
+                    &self.user,
+                    &mut interner,
+                    git_ref,
+                )
+                .ok()
+                .map(Into::into)
             })
             .collect::<HashSet<_>>()
     }
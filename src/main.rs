@@ -1,18 +1,41 @@
-use std::{borrow::Cow, collections::HashSet, env, ffi::OsString, path::Path};
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    env,
+    ffi::OsString,
+    fs, io,
+    os::fd::FromRawFd,
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::Duration,
+};
+
+use anyhow::Context;
 
 use clap::{
-    builder::PossibleValue, crate_authors, crate_description, crate_name, crate_version,
-    parser::ValueSource, value_parser, Arg, ArgAction, ArgMatches, Command, ValueHint,
+    builder::{NonEmptyStringValueParser, PossibleValue},
+    crate_authors, crate_description, crate_name, crate_version,
+    parser::ValueSource,
+    value_parser, Arg, ArgAction, ArgMatches, Command, ValueHint,
 };
 use git_version::git_version;
-use renderer::Renderer;
+use renderer::{ColorChoice, FileRenderer, ProgressRenderer, Renderer};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use types::Branch;
-use verbosity::Verbosity;
+use verbosity::{is_output_allowed, summarize_command_failure, Retries, Verbosity};
 
 use crate::{
-    git_binary::GitBinary,
-    types::{Host, Remote, User},
-    workflow::{Filter, LsPrinter, Workflow},
+    git_binary::{
+        check_ref_prefix, git_version, ConfigScope, FetchOptions, GitBinary, GitVersion,
+        SshMultiplex, DEFAULT_REF_PREFIX,
+    },
+    types::{check_ref_component, Host, Remote, User},
+    workflow::{
+        Age, CompletionShell, Filter, Glob, LsOptions, LsPrinter, LsSort, OnRateLimit, Outcome,
+        Pattern, PruneConfirm, PushRateLimit, SyncDirection, SyncForce, SyncOptions, SyncPrune,
+        Workflow, DEFAULT_PRUNE_CONFIRM_THRESHOLD,
+    },
 };
 
 mod git_binary;
@@ -29,9 +52,18 @@ mod git_testing;
 const DEFAULT_REMOTE: Remote<'static> = Remote(Cow::Borrowed("origin"));
 const ENV_USER: &str = "GIT_NOMAD_USER";
 const ENV_HOST: &str = "GIT_NOMAD_HOST";
+const ENV_HOST_SOURCE: &str = "GIT_NOMAD_HOST_SOURCE";
 const ENV_REMOTE: &str = "GIT_NOMAD_REMOTE";
+const ENV_REF_PREFIX: &str = "GIT_NOMAD_REF_PREFIX";
+const ENV_GIT_DIR: &str = "GIT_NOMAD_GIT_DIR";
+const ENV_ASSUME_YES: &str = "GIT_NOMAD_ASSUME_YES";
 const CONFIG_USER: &str = "user";
 const CONFIG_HOST: &str = "host";
+const CONFIG_VERSION: &str = "version";
+const CONFIG_REF_PREFIX: &str = "prefix";
+
+/// How long to wait before the first retry of a network command, see [`Retries::backoff`].
+const RETRY_BACKOFF: Duration = Duration::from_secs(1);
 
 const BUILD_VERSION: Option<&str> = option_env!("GIT_NOMAD_BUILD_VERSION");
 
@@ -47,16 +79,52 @@ fn version() -> &'static str {
     BUILD_VERSION.unwrap_or(GIT_VERSION)
 }
 
+/// What `version --json` prints, for tooling that needs more than [`version`] alone.
+#[derive(Serialize)]
+struct VersionInfo {
+    version: &'static str,
+    git_version: &'static str,
+    build_version: Option<&'static str>,
+}
+
+impl VersionInfo {
+    fn current() -> Self {
+        Self {
+            version: version(),
+            git_version: GIT_VERSION,
+            build_version: BUILD_VERSION,
+        }
+    }
+}
+
+/// Print the version, without needing a git repository to do so.
+fn print_version(renderer: &mut impl Renderer, json: bool) -> anyhow::Result<()> {
+    renderer.writer(|w| {
+        if json {
+            serde_json::to_writer(&mut *w, &VersionInfo::current()).context("printing version")?;
+            writeln!(w)?;
+        } else {
+            writeln!(w, "{}", version())?;
+        }
+        Ok(())
+    })
+}
+
 // This cfg skips gathering coverage for this function, since the entrypoint can't be effectively
 // tested.
 #[cfg(not(test))]
 fn main() -> anyhow::Result<()> {
-    nomad(
+    verbosity::install_interrupt_handler()?;
+    let outcome = nomad(
         &mut renderer::TerminalRenderer::stdout(),
         std::env::args_os(),
         std::env::current_dir()?.as_path(),
         env::var_os("SHELL"),
-    )
+    )?;
+    if outcome == Outcome::Unchanged {
+        std::process::exit(EXIT_UNCHANGED);
+    }
+    Ok(())
 }
 
 fn nomad(
@@ -64,14 +132,72 @@ fn nomad(
     args: impl IntoIterator<Item = impl Into<OsString> + Clone>,
     cwd: &Path,
     current_shell_path: Option<OsString>,
-) -> anyhow::Result<()> {
-    let default_user = whoami::fallible::username().ok().map(User::from);
-    let default_host = whoami::fallible::hostname().ok().map(Host::from);
-
-    let mut matches = cli(default_user, default_host, args).unwrap_or_else(|e| e.exit());
+) -> anyhow::Result<Outcome> {
+    let default_host = default_host(env::var_os(ENV_HOST_SOURCE), machine_id)?;
+    let mut matches = cli(User::os_default(), default_host, args).unwrap_or_else(|e| e.exit());
     let verbosity = specified_verbosity(&mut matches);
+    let quiet_errors = specified_quiet_errors(&mut matches, verbosity);
+    let exit_code = specified_exit_code(&matches);
+    let color = specified_color(&mut matches);
+    renderer.set_color(color);
+
+    let result = specified_output(&mut matches, cwd).and_then(|output| match output {
+        Some(output) => {
+            let mut renderer = FileRenderer::new(renderer, output);
+            renderer.set_color(color);
+            execute_with_progress(
+                &mut renderer,
+                &mut matches,
+                verbosity,
+                cwd,
+                current_shell_path,
+            )
+        }
+        None => execute_with_progress(renderer, &mut matches, verbosity, cwd, current_shell_path),
+    });
+
+    result
+        // Without `--exit-code`, nothing changing is just as successful as something changing.
+        .map(|outcome| if exit_code { outcome } else { Outcome::Changed })
+        .map_err(|e| {
+            if quiet_errors {
+                summarize_command_failure(e)
+            } else {
+                e
+            }
+        })
+}
+
+/// Like [`execute`], but also writes progress to a side channel if the user wants it, see
+/// [`specified_progress_fd`].
+fn execute_with_progress(
+    renderer: &mut impl Renderer,
+    matches: &mut ArgMatches,
+    verbosity: Option<Verbosity>,
+    cwd: &Path,
+    current_shell_path: Option<OsString>,
+) -> anyhow::Result<Outcome> {
+    match specified_progress_fd(matches)? {
+        Some(progress) => execute(
+            &mut ProgressRenderer::new(renderer, progress),
+            matches,
+            verbosity,
+            cwd,
+            current_shell_path,
+        ),
+        None => execute(renderer, matches, verbosity, cwd, current_shell_path),
+    }
+}
 
-    if verbosity.map_or(false, |v| v.display_version) {
+/// Execute the workflow the user intends via the CLI, after the [`Verbosity`] has been determined.
+fn execute(
+    renderer: &mut impl Renderer,
+    matches: &mut ArgMatches,
+    verbosity: Option<Verbosity>,
+    cwd: &Path,
+    current_shell_path: Option<OsString>,
+) -> anyhow::Result<Outcome> {
+    if verbosity.is_some_and(|v| v.display_version) {
         renderer.writer(|w| {
             writeln!(w)?;
             writeln!(w, "Version: {}", version())?;
@@ -79,15 +205,55 @@ fn nomad(
         })?;
     }
 
-    let git = GitBinary::new(
-        renderer,
-        verbosity,
-        Cow::from(specified_git(&mut matches)),
-        cwd,
-    )?;
-    let workflow = specified_workflow(renderer, &mut matches, &git, current_shell_path)?;
+    if let Some(("version", matches)) = matches.subcommand() {
+        return print_version(renderer, matches.get_flag("json")).map(|()| Outcome::Changed);
+    }
+
+    if let Some(("doctor", _)) = matches.subcommand() {
+        return doctor(renderer, matches, verbosity, cwd).map(|()| Outcome::Changed);
+    }
+
+    if let Some(("sync-all", sync_all_matches)) = matches.subcommand() {
+        let config_path = cwd.join(
+            sync_all_matches
+                .get_one::<PathBuf>("file")
+                .expect("<file> is a required argument"),
+        );
+        return sync_all(renderer, matches, verbosity, &config_path);
+    }
+
+    execute_in_repo(renderer, matches, verbosity, cwd, current_shell_path)
+}
+
+/// Execute the workflow the user intends via the CLI against the git repository at `cwd`.
+fn execute_in_repo(
+    renderer: &mut impl Renderer,
+    matches: &mut ArgMatches,
+    verbosity: Option<Verbosity>,
+    cwd: &Path,
+    current_shell_path: Option<OsString>,
+) -> anyhow::Result<Outcome> {
+    let name = Cow::from(specified_git(matches));
+    let mut git = match specified_git_dir(matches) {
+        Some(git_dir) => GitBinary::with_git_dir(verbosity, name, &cwd.join(git_dir))?,
+        None => GitBinary::new(renderer, verbosity, name, cwd)?,
+    };
+    git.allow_prompt = specified_allow_prompt(matches);
+    git.retries = specified_retries(matches);
+    if specified_ssh_multiplex(matches) {
+        git.ssh_multiplex = Some(Rc::new(SshMultiplex::new(&git.ssh_command(renderer)?)?));
+    }
+    git.git_namespace = specified_git_namespace(matches);
+    git.verify_objects = specified_verify_objects(matches);
+    let check_remote = specified_check_remote(matches);
+    git.ref_prefix = specified_ref_prefix(renderer, matches, &git)?;
+    if is_output_allowed(verbosity) {
+        check_config_version(renderer, &git)?;
+    }
+
+    let workflow = specified_workflow(renderer, matches, &git, current_shell_path)?;
 
-    if verbosity.map_or(false, |v| v.display_workflow) {
+    if verbosity.is_some_and(|v| v.display_workflow) {
         renderer.writer(|w| {
             writeln!(w)?;
             writeln!(w, "Workflow: {:?}", workflow)?;
@@ -95,9 +261,360 @@ fn nomad(
         })?;
     }
 
+    if check_remote {
+        for remote in workflow.remotes() {
+            git.check_remote(renderer, remote)?;
+        }
+    }
+
     workflow.execute(renderer, &git)
 }
 
+/// The [`Host`] to use when none is given, chosen by `host_source` from [`ENV_HOST_SOURCE`].
+///
+/// The host name is the default, but it can change with the network, like `Foo-MacBook` becoming
+/// `foo-mbp.local`, which scatters one machine's refs across several hosts. `machine-id` opts into
+/// a stable ID instead, falling back to the host name when `machine_id` cannot provide one.
+///
+/// The machine ID is confidential and ends up in ref names that get pushed, so the host is only
+/// derived from it via [`machine_id_host`].
+fn default_host(
+    host_source: Option<OsString>,
+    machine_id: impl FnOnce() -> Option<String>,
+) -> anyhow::Result<Option<Host<'static>>> {
+    let Some(host_source) = host_source else {
+        return Ok(Host::os_default());
+    };
+
+    match host_source.to_str() {
+        Some("hostname") => Ok(Host::os_default()),
+        Some("machine-id") => Ok(machine_id()
+            .map(|id| id.trim().to_lowercase())
+            .filter(|id| !id.is_empty())
+            .map(|id| machine_id_host(&id))
+            .or_else(Host::os_default)),
+        _ => anyhow::bail!(
+            "Invalid {} {:?}, expected hostname or machine-id",
+            ENV_HOST_SOURCE,
+            host_source,
+        ),
+    }
+}
+
+/// The first 12 hex digits of a SHA-256 of the machine ID, keyed so that the host cannot be
+/// matched against plain hashes of the ID used elsewhere.
+fn machine_id_host(machine_id: &str) -> Host<'static> {
+    let digest = Sha256::new()
+        .chain_update("git-nomad")
+        .chain_update(machine_id)
+        .finalize();
+    Host::from(
+        digest[..6]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>(),
+    )
+}
+
+/// A stable identifier for the current machine, from `/etc/machine-id` on Linux and the
+/// `IOPlatformUUID` on macOS, if there is one.
+fn machine_id() -> Option<String> {
+    if cfg!(target_os = "macos") {
+        let output = std::process::Command::new("ioreg")
+            .args(["-rd1", "-c", "IOPlatformExpertDevice"])
+            .output()
+            .ok()?;
+        parse_platform_uuid(&String::from_utf8_lossy(&output.stdout))
+    } else {
+        fs::read_to_string("/etc/machine-id").ok()
+    }
+}
+
+/// Find the `"IOPlatformUUID" = "..."` line in the output of `ioreg`.
+fn parse_platform_uuid(ioreg: &str) -> Option<String> {
+    ioreg.lines().find_map(|line| {
+        let (key, value) = line.split_once('=')?;
+        (key.trim() == "\"IOPlatformUUID\"").then(|| value.trim().trim_matches('"').to_string())
+    })
+}
+
+/// The oldest git that nomad works with, since finding the repository relies on
+/// `git rev-parse --absolute-git-dir`.
+const MIN_GIT_VERSION: GitVersion = GitVersion(2, 13, 0);
+
+/// Check everything nomad needs from its environment, printing a checklist along the way.
+///
+/// Every check runs even if an earlier one failed, except for the remotes which need a repository
+/// to look in. The error lists every failed check.
+fn doctor(
+    renderer: &mut impl Renderer,
+    matches: &mut ArgMatches,
+    verbosity: Option<Verbosity>,
+    cwd: &Path,
+) -> anyhow::Result<()> {
+    fn describe(error: anyhow::Error) -> String {
+        format!("{:#}", summarize_command_failure(error))
+    }
+
+    let name = specified_git(matches);
+    let mut checks = Vec::<(String, Result<String, String>)>::new();
+
+    let version = git_version(renderer, verbosity, &name)
+        .and_then(|version| check_git_version(&version).map(|()| version));
+    checks.push((name.clone(), version.map_err(describe)));
+
+    let git = match specified_git_dir(matches) {
+        Some(git_dir) => GitBinary::with_git_dir(verbosity, Cow::from(name), &cwd.join(git_dir)),
+        None => GitBinary::new(renderer, verbosity, Cow::from(name), cwd),
+    };
+    let git = match git {
+        Ok(git) => {
+            checks.push((
+                "repository".to_string(),
+                Ok(git.git_dir().display().to_string()),
+            ));
+            Some(git)
+        }
+        Err(error) => {
+            checks.push(("repository".to_string(), Err(describe(error))));
+            None
+        }
+    };
+
+    if let Some(git) = &git {
+        for remote in matches
+            .remove_many::<String>("remote")
+            .expect("default value")
+            .map(Remote::from)
+        {
+            let url = git.remote_url(renderer, &remote);
+            checks.push((format!("remote {}", remote.0), url.map_err(describe)));
+        }
+    }
+
+    // A broken file is its own failure, the user and host checks then carry on without it.
+    let nomad_file = match git.as_ref().map(NomadFile::load).transpose() {
+        Ok(nomad_file) => nomad_file.unwrap_or_default(),
+        Err(error) => {
+            checks.push((NOMAD_FILE.to_string(), Err(describe(error))));
+            NomadFile::default()
+        }
+    };
+
+    let user: anyhow::Result<User> = resolve(
+        matches,
+        "user",
+        ENV_USER,
+        check_ref_component,
+        &nomad_file,
+        || {
+            git.as_ref()
+                .map_or(Ok(None), |git| git.get_config(renderer, CONFIG_USER))
+        },
+    );
+    checks.push((
+        "user".to_string(),
+        user.map(|user| user.0.into_owned()).map_err(describe),
+    ));
+
+    let host: anyhow::Result<Host> = resolve(
+        matches,
+        "host",
+        ENV_HOST,
+        check_ref_component,
+        &nomad_file,
+        || {
+            git.as_ref()
+                .map_or(Ok(None), |git| git.get_config(renderer, CONFIG_HOST))
+        },
+    );
+    checks.push((
+        "host".to_string(),
+        host.map(|host| host.0.into_owned()).map_err(describe),
+    ));
+
+    if is_output_allowed(verbosity) {
+        renderer.writer(|w| {
+            for (check, result) in &checks {
+                match result {
+                    Ok(found) => writeln!(w, "[ok]   {}: {}", check, found)?,
+                    Err(problem) => writeln!(w, "[fail] {}: {}", check, problem)?,
+                }
+            }
+            Ok(())
+        })?;
+    }
+
+    let failed = checks
+        .iter()
+        .filter_map(|(check, result)| {
+            result
+                .as_ref()
+                .err()
+                .map(|problem| format!("  {}: {}", check, problem))
+        })
+        .collect::<Vec<_>>();
+    if !failed.is_empty() {
+        anyhow::bail!(
+            "{} of {} checks failed:\n{}",
+            failed.len(),
+            checks.len(),
+            failed.join("\n")
+        );
+    }
+
+    Ok(())
+}
+
+/// Error out if `version`, as reported by [`git_version`], is older than [`MIN_GIT_VERSION`].
+fn check_git_version(version: &str) -> anyhow::Result<()> {
+    match GitVersion::parse(version) {
+        Some(parsed) if parsed >= MIN_GIT_VERSION => Ok(()),
+        Some(_) => anyhow::bail!(
+            "git {} is too old, git-nomad needs at least {}",
+            version,
+            MIN_GIT_VERSION
+        ),
+        None => anyhow::bail!("Could not understand git version {:?}", version),
+    }
+}
+
+/// The repositories that `sync-all` should sync, read from a TOML file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SyncAllConfig {
+    /// Relative paths are resolved against the directory containing the file.
+    repos: Vec<PathBuf>,
+}
+
+/// Run `sync` in every repository listed in the file at `config_path`.
+///
+/// Each repository gets its own [`GitBinary`] and resolves user, host, and ref prefix from its own
+/// git config. A failing repository is reported and skipped, the overall result only fails once
+/// every repository has had a chance to sync.
+fn sync_all(
+    renderer: &mut impl Renderer,
+    matches: &ArgMatches,
+    verbosity: Option<Verbosity>,
+    config_path: &Path,
+) -> anyhow::Result<Outcome> {
+    if matches.contains_id("git_dir") {
+        anyhow::bail!("--git-dir cannot be used with sync-all, since every repository has its own");
+    }
+
+    let config: SyncAllConfig = fs::read_to_string(config_path)
+        .map_err(anyhow::Error::from)
+        .and_then(|toml| toml::from_str(&toml).map_err(anyhow::Error::from))
+        .with_context(|| format!("reading repositories from {}", config_path.display()))?;
+    let base_dir = config_path.parent().unwrap_or(Path::new(""));
+
+    let mut failed = Vec::new();
+    let mut outcome = Outcome::Unchanged;
+    for repo in &config.repos {
+        let repo = base_dir.join(repo);
+        if is_output_allowed(verbosity) {
+            renderer.writer(|w| {
+                writeln!(w, "Syncing {}", repo.display())?;
+                Ok(())
+            })?;
+        }
+
+        match execute_in_repo(renderer, &mut matches.clone(), verbosity, &repo, None) {
+            Ok(Outcome::Changed) => outcome = Outcome::Changed,
+            Ok(Outcome::Unchanged) => {}
+            Err(e) => {
+                if is_output_allowed(verbosity) {
+                    renderer.writer(|w| {
+                        writeln!(w, "Failed to sync {}: {:#}", repo.display(), e)?;
+                        Ok(())
+                    })?;
+                }
+                failed.push(repo);
+            }
+        }
+    }
+
+    let total = config.repos.len();
+    if is_output_allowed(verbosity) {
+        renderer.writer(|w| {
+            writeln!(
+                w,
+                "Synced {} of {} repositories",
+                total - failed.len(),
+                total
+            )?;
+            Ok(())
+        })?;
+    }
+
+    if !failed.is_empty() {
+        anyhow::bail!(
+            "Failed to sync {} of {} repositories: {}",
+            failed.len(),
+            total,
+            failed
+                .iter()
+                .map(|repo| repo.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+        );
+    }
+
+    Ok(outcome)
+}
+
+/// Warn when the nomad config was written by a newer `git-nomad`, since it may be in a format
+/// this version does not understand.
+fn check_config_version(renderer: &mut impl Renderer, git: &GitBinary) -> anyhow::Result<()> {
+    let Some(stored) = git.get_config(renderer, CONFIG_VERSION)? else {
+        return Ok(());
+    };
+
+    if let (Some(stored_triple), Some(running_triple)) = (parse_version(&stored), running_version())
+    {
+        if stored_triple > running_triple {
+            renderer.writer(|w| {
+                writeln!(
+                    w,
+                    "Warning: nomad config was last written by git-nomad {}, which is newer than this version ({})",
+                    stored,
+                    version(),
+                )?;
+                Ok(())
+            })?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The `(major, minor, patch)` of this binary, falling back to the crate version when [`version`]
+/// is just a commit hash.
+fn running_version() -> Option<(u64, u64, u64)> {
+    parse_version(version()).or_else(|| parse_version(_CARGO_VERSION))
+}
+
+/// Extract the `(major, minor, patch)` triple out of a [`version`] string like `git:v0.8.0-3-gabcdef`
+/// or `0.8.0`.
+fn parse_version(version: &str) -> Option<(u64, u64, u64)> {
+    let version = version.strip_prefix("git:").unwrap_or(version);
+    let version = version.strip_prefix('v').unwrap_or(version);
+    let mut parts = version.splitn(3, '.');
+    let mut next = |last: bool| -> Option<u64> {
+        let part = parts.next()?;
+        let digits = if last {
+            let end = part
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(part.len());
+            &part[..end]
+        } else {
+            part
+        };
+        digits.parse().ok()
+    };
+    Some((next(false)?, next(false)?, next(true)?))
+}
+
 fn maybe_apply_default(arg: Arg, optional_default: Option<String>) -> Arg {
     if let Some(default) = optional_default {
         arg.default_value(default)
@@ -106,6 +623,145 @@ fn maybe_apply_default(arg: Arg, optional_default: Option<String>) -> Arg {
     }
 }
 
+#[cfg(test)]
+mod test_config_version {
+    use crate::{
+        check_config_version, git_testing::GitRemote, parse_version,
+        renderer::test::MemoryRenderer, version, CONFIG_VERSION,
+    };
+
+    #[test]
+    fn parse() {
+        assert_eq!(parse_version("0.8.0"), Some((0, 8, 0)));
+        assert_eq!(parse_version("git:v0.8.0-3-gabcdef"), Some((0, 8, 0)));
+        assert_eq!(parse_version("git:v1.12.3-modified"), Some((1, 12, 3)));
+        assert_eq!(parse_version("git:abcdef"), None);
+    }
+
+    /// Config written by this version should not produce a warning.
+    #[test]
+    fn same_version() {
+        let remote = GitRemote::init(None);
+        remote
+            .git
+            .set_config(&mut MemoryRenderer::new(), "user", "user0")
+            .unwrap();
+        assert_eq!(
+            remote
+                .git
+                .get_config(&mut MemoryRenderer::new(), CONFIG_VERSION)
+                .unwrap()
+                .as_deref(),
+            Some(version()),
+        );
+
+        let mut renderer = MemoryRenderer::new();
+        check_config_version(&mut renderer, &remote.git).unwrap();
+        assert_eq!(renderer.as_str(), "");
+    }
+
+    /// Config written by a newer version should produce a warning.
+    #[test]
+    fn newer_version() {
+        let remote = GitRemote::init(None);
+        remote
+            .git
+            .command()
+            .args(["config", "--local", "nomad.version", "v999.0.0"])
+            .status()
+            .unwrap();
+
+        let mut renderer = MemoryRenderer::new();
+        check_config_version(&mut renderer, &remote.git).unwrap();
+        assert!(renderer.as_str().contains("v999.0.0"));
+    }
+}
+
+#[cfg(test)]
+mod test_doctor {
+    use tempfile::tempdir;
+
+    use crate::{check_git_version, git_testing::GitRemote, nomad, renderer::test::MemoryRenderer};
+
+    #[test]
+    fn git_version() {
+        for version in [
+            "2.13.0",
+            "2.39.2",
+            "2.39.2.windows.1",
+            "2.37.1 (Apple Git-137.1)",
+        ] {
+            assert!(check_git_version(version).is_ok(), "{}", version);
+        }
+
+        assert_eq!(
+            check_git_version("2.12.5").unwrap_err().to_string(),
+            "git 2.12.5 is too old, git-nomad needs at least 2.13.0",
+        );
+        assert!(check_git_version("unknown").is_err());
+    }
+
+    /// A freshly cloned repository should pass every check.
+    #[test]
+    fn healthy() {
+        let remote = GitRemote::init(None);
+        let host0 = remote.clone("user0", "host0");
+
+        let mut renderer = MemoryRenderer::new();
+        nomad(
+            &mut renderer,
+            ["git-nomad", "--user", "user0", "--host", "host0", "doctor"],
+            host0.working_directory(),
+            None,
+        )
+        .unwrap();
+
+        let output = renderer.as_str();
+        assert!(!output.contains("[fail]"), "{}", output);
+        assert!(output.contains(&format!(
+            "[ok]   remote origin: {}\n",
+            remote.working_directory().display()
+        )));
+        assert!(output.ends_with("[ok]   user: user0\n[ok]   host: host0\n"));
+    }
+
+    /// Every failed check should be reported, not just the first.
+    #[test]
+    fn unhealthy() {
+        let remote = GitRemote::init(None);
+        let error = nomad(
+            &mut MemoryRenderer::new(),
+            [
+                "git-nomad",
+                "--remote",
+                "does-not-exist",
+                "--host",
+                "",
+                "doctor",
+            ],
+            remote.working_directory(),
+            None,
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(error.starts_with("2 of 5 checks failed:\n  remote does-not-exist: "));
+        assert!(error.ends_with("\n  host: Invalid host \"\" from --host, it must not be empty"));
+
+        let not_a_repo = tempdir().unwrap();
+        let mut renderer = MemoryRenderer::new();
+        let error = nomad(
+            &mut renderer,
+            ["git-nomad", "--user", "user0", "--host", "host0", "doctor"],
+            not_a_repo.path(),
+            None,
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(error.starts_with("1 of 4 checks failed:\n  repository: "));
+        assert!(renderer.as_str().contains("[fail] repository: "));
+    }
+}
+
 #[cfg(test)]
 mod test_maybe_apply_default {
     use clap::{builder::OsStr, Arg};
@@ -143,6 +799,16 @@ fn build_cli(default_user: Option<User>, default_host: Option<Host>) -> Command
                 .value_hint(ValueHint::CommandName)
                 .default_value("git"),
         )
+        .arg(
+            Arg::new("git_dir")
+                .global(true)
+                .long("git-dir")
+                .value_name("DIR")
+                .help("Use DIR as the .git directory instead of asking git to find it")
+                .value_parser(value_parser!(PathBuf))
+                .value_hint(ValueHint::DirPath)
+                .env(ENV_GIT_DIR),
+        )
         .arg(
             Arg::new("quiet")
                 .global(true)
@@ -153,63 +819,228 @@ fn build_cli(default_user: Option<User>, default_host: Option<Host>) -> Command
                 .action(ArgAction::SetTrue),
         )
         .arg(
-            Arg::new("verbose")
+            Arg::new("no_progress")
                 .global(true)
-                .short('v')
-                .long("verbose")
-                .help("Verbose output, repeat up to 2 times for increasing verbosity")
-                .value_parser(value_parser!(u8))
-                .action(ArgAction::Count),
+                .long("no-progress")
+                .help("Suppress spinners while still printing results and errors")
+                .value_parser(value_parser!(bool))
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["quiet", "verbose"]),
         )
         .arg(
-            maybe_apply_default(
-                Arg::new("user")
-                    .global(true)
-                    .short('U')
-                    .long("user")
-                    .help("User name, shared by multiple clones, unique per remote")
-                    .value_parser(value_parser!(String))
-                    .value_hint(ValueHint::Username)
-                    .env(ENV_USER),
-                default_user.map(|u| u.0.into_owned()),
-            )
+            Arg::new("quiet_errors")
+                .global(true)
+                .long("quiet-errors")
+                .help("Only print a one line summary when a git command fails, unless verbose")
+                .value_parser(value_parser!(bool))
+                .action(ArgAction::SetTrue),
         )
         .arg(
-            maybe_apply_default(
-                Arg::new("host")
-                    .global(true)
-                    .short('H')
-                    .long("host")
-                    .value_parser(value_parser!(String))
-                    .value_hint(ValueHint::Hostname)
-                    .env(ENV_HOST)
-                    .help("Host name, unique per clone"),
-                default_host.map(|h| h.0.into_owned()),
-            )
+            Arg::new("allow_prompt")
+                .global(true)
+                .long("allow-prompt")
+                .help("Let git prompt for credentials when talking to the remote, instead of failing")
+                .value_parser(value_parser!(bool))
+                .action(ArgAction::SetTrue),
         )
         .arg(
-            Arg::new("remote")
+            Arg::new("retries")
                 .global(true)
-                .short('R')
-                .long("remote")
-                .help("Git remote to operate against")
-                .value_parser(value_parser!(String))
-                .value_hint(ValueHint::Other)
-                .env(ENV_REMOTE)
-                .default_value(DEFAULT_REMOTE.0.as_ref())
+                .long("retries")
+                .value_name("N")
+                .help("Retry fetches, pushes, and listing remote refs up to N times, with exponential backoff, when they fail for transient network reasons")
+                .value_parser(value_parser!(u32))
+                .default_value("2"),
         )
-        .subcommand(Command::new("sync").about("Sync local branches to remote"))
-        .subcommand(
-            Command::new("ls")
+        .arg(
+            Arg::new("ssh_multiplex")
+                .global(true)
+                .long("ssh-multiplex")
+                .help("Reuse one SSH connection per host across git commands via ControlMaster")
+                .value_parser(value_parser!(bool))
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("git_namespace")
+                .global(true)
+                .long("git-namespace")
+                .value_name("NAMESPACE")
+                .help("Keep refs on the remote in a separate git namespace, the server must honor GIT_NAMESPACE")
+                .value_parser(NonEmptyStringValueParser::new()),
+        )
+        .arg(
+            Arg::new("progress_fd")
+                .global(true)
+                .long("progress-fd")
+                .value_name("FD")
+                .help("Also write progress as JSON lines (phase, current, total, message) to the open file descriptor FD")
+                .value_parser(value_parser!(i32).range(0..)),
+        )
+        .arg(
+            Arg::new("output")
+                .global(true)
+                .long("output")
+                .value_name("FILE")
+                .help("Write output to FILE instead of stdout, errors still go to stderr")
+                .value_parser(value_parser!(PathBuf))
+                .value_hint(ValueHint::FilePath),
+        )
+        .arg(
+            Arg::new("color")
+                .global(true)
+                .long("color")
+                .value_name("WHEN")
+                .help("When to color output")
+                .value_parser([
+                    PossibleValue::new("auto")
+                        .help("Only when writing to a terminal and NO_COLOR is not set"),
+                    PossibleValue::new("always"),
+                    PossibleValue::new("never"),
+                ])
+                .default_value("auto"),
+        )
+        .arg(
+            Arg::new("no_color")
+                .global(true)
+                .long("no-color")
+                .help("Same as --color=never")
+                .value_parser(value_parser!(bool))
+                .action(ArgAction::SetTrue)
+                .overrides_with("color"),
+        )
+        .arg(
+            Arg::new("verify_objects")
+                .global(true)
+                .long("verify-objects")
+                .help("Warn about nomad refs whose commit is missing locally, to diagnose corruption or partial fetches")
+                .value_parser(value_parser!(bool))
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("ref_prefix")
+                .global(true)
+                .long("ref-prefix")
+                .value_parser(value_parser!(String))
+                .env(ENV_REF_PREFIX)
+                .default_value(DEFAULT_REF_PREFIX)
+                .help("Keep nomad refs under refs/<prefix>, to avoid colliding with other teams"),
+        )
+        .arg(
+            Arg::new("check_remote")
+                .global(true)
+                .long("check-remote")
+                .help("Check that the remote is reachable before doing anything else")
+                .value_parser(value_parser!(bool))
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("dry_run")
+                .global(true)
+                .long("dry-run")
+                .help("Print the pushes and deletions that sync or purge would make, without making them")
+                .value_parser(value_parser!(bool))
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("verbose")
+                .global(true)
+                .short('v')
+                .long("verbose")
+                .help("Verbose output, repeat up to 2 times for increasing verbosity")
+                .value_parser(value_parser!(u8))
+                .action(ArgAction::Count),
+        )
+        .arg(
+            maybe_apply_default(
+                Arg::new("user")
+                    .global(true)
+                    .short('U')
+                    .long("user")
+                    .help("User name, shared by multiple clones, unique per remote")
+                    .value_parser(value_parser!(String))
+                    .value_hint(ValueHint::Username)
+                    .env(ENV_USER),
+                default_user.map(|u| u.0.into_owned()),
+            )
+        )
+        .arg(
+            maybe_apply_default(
+                Arg::new("host")
+                    .global(true)
+                    .short('H')
+                    .long("host")
+                    .value_parser(value_parser!(String))
+                    .value_hint(ValueHint::Hostname)
+                    .env(ENV_HOST)
+                    .help("Host name, unique per clone"),
+                default_host.map(|h| h.0.into_owned()),
+            )
+        )
+        .arg(
+            Arg::new("remote")
+                .global(true)
+                .short('R')
+                .long("remote")
+                .help("Git remote to operate against, sync and ls accept it multiple times")
+                .action(ArgAction::Append)
+                .value_parser(value_parser!(String))
+                .value_hint(ValueHint::Other)
+                .env(ENV_REMOTE)
+                .default_value(DEFAULT_REMOTE.0.as_ref())
+        )
+        .arg(
+            Arg::new("local_remote")
+                .global(true)
+                .long("local-remote")
+                .value_name("PATH")
+                .help("Use the bare repository at PATH as the remote instead of --remote, creating it if missing, to try nomad out offline")
+                .value_parser(value_parser!(PathBuf))
+                .value_hint(ValueHint::DirPath),
+        )
+        .arg(
+            Arg::new("remote_url")
+                .global(true)
+                .long("remote-url")
+                .value_name("URL")
+                .help("Git URL to operate against instead of --remote, for when there is no named remote yet, sync accepts it multiple times")
+                .action(ArgAction::Append)
+                .value_parser(value_parser!(String))
+                .value_hint(ValueHint::Url)
+                .conflicts_with("local_remote"),
+        )
+        .subcommand(
+            Command::new("sync")
+                .about("Sync local branches to remote")
+                .args(sync_args()),
+        )
+        .subcommand(
+            Command::new("sync-all")
+                .about("Sync every repository listed in a TOML file, continuing past failures")
+                .arg(
+                    Arg::new("file")
+                        .help("Path to a TOML file with `repos = [...]`, relative paths are resolved against its directory")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf))
+                        .value_hint(ValueHint::FilePath),
+                )
+                .args(sync_args()),
+        )
+        .subcommand(
+            Command::new("fetch")
+                .about("Fetch nomad managed refs from other hosts without pushing or listing them"),
+        )
+        .subcommand(
+            Command::new("ls")
                 .about("List nomad managed refs")
                 .arg(
                     Arg::new("fetch")
                         .short('F')
                         .long("fetch")
-                        .help("Fetch refs from remote before listing")
+                        .help("Fetch refs from remote before listing, warning when multiple remotes disagree")
                         .value_parser(value_parser!(bool))
                         .action(ArgAction::SetTrue),
                 )
+                .args(fetch_options_args().map(|arg| arg.requires("fetch")))
                 .arg(
                     Arg::new("print")
                         .long("print")
@@ -219,9 +1050,60 @@ fn build_cli(default_user: Option<User>, default_host: Option<Host>) -> Command
                                 .help("Print ref name and commit ID grouped by host"),
                             PossibleValue::new("ref").help("Print only the ref name"),
                             PossibleValue::new("commit").help("Print only the commit ID"),
+                            PossibleValue::new("json")
+                                .help("Print host, branch, ref, and commit as a JSON array"),
+                            PossibleValue::new("tree")
+                                .help("Print branches and commit IDs as a tree under each host"),
                         ])
                         .default_value("grouped"),
                 )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_name("TEMPLATE")
+                        .help("Print each ref with %h (host), %b (branch), %r (ref), %c (commit) and %% substituted, overrides --print")
+                        .value_parser(LsPrinter::validate_template),
+                )
+                .arg(
+                    Arg::new("porcelain")
+                        .long("porcelain")
+                        .help("Print NUL-terminated user, host, branch, commit, and ref records separated by tabs, stable across versions, overrides --print")
+                        .value_parser(value_parser!(bool))
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("format"),
+                )
+                .arg(
+                    Arg::new("color_by_host")
+                        .long("color-by-host")
+                        .help("Print each host in its own color, only affects --print=grouped")
+                        .value_parser(value_parser!(bool))
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("format_host")
+                        .long("format-host")
+                        .value_name("TEMPLATE")
+                        .help("Print each host header with {host}, {user}, {count} (refs listed) and {{ }} substituted, only affects --print=grouped")
+                        .value_parser(LsPrinter::validate_host_format),
+                )
+                .arg(
+                    Arg::new("sort")
+                        .long("sort")
+                        .help("Order of branches within each host")
+                        .value_parser([
+                            PossibleValue::new("name"),
+                            PossibleValue::new("commit-date")
+                                .help("Most recently committed first"),
+                        ])
+                        .default_value("name"),
+                )
+                .arg(
+                    Arg::new("unicode")
+                        .long("unicode")
+                        .help("Draw the tree with Unicode box-drawing characters, only affects --print=tree")
+                        .value_parser(value_parser!(bool))
+                        .action(ArgAction::SetTrue),
+                )
                 .arg(
                     Arg::new("head")
                     .long("head")
@@ -233,8 +1115,25 @@ fn build_cli(default_user: Option<User>, default_host: Option<Host>) -> Command
                     Arg::new("branch")
                     .short('b')
                     .long("branch")
-                    .help("Only display refs for the named branch (can be specified multiple times)")
-                    .value_parser(value_parser!(String))
+                    .value_name("GLOB")
+                    .help("Only display refs for branches matching GLOB, like 'feature/*' (can be specified multiple times)")
+                    .value_parser(value_parser!(Glob))
+                    .action(ArgAction::Append)
+                )
+                .arg(
+                    Arg::new("branch_regex")
+                    .long("branch-regex")
+                    .value_name("REGEX")
+                    .help("Only display refs for branches matching REGEX anywhere, use ^ and $ to anchor")
+                    .value_parser(value_parser!(Pattern))
+                    .conflicts_with_all(["branch", "head"])
+                )
+                .arg(
+                    Arg::new("other_user")
+                    .long("other-user")
+                    .value_name("USER")
+                    .help("Also list refs that USER pushed, straight from the remote, needs --fetch (can be specified multiple times)")
+                    .value_parser(|user: &str| check_ref_component(user).map(|()| user.to_owned()))
                     .action(ArgAction::Append)
                 )
                 .arg(
@@ -243,6 +1142,74 @@ fn build_cli(default_user: Option<User>, default_host: Option<Host>) -> Command
                     .help("Print refs for the current host")
                     .value_parser(value_parser!(bool))
                     .action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("host_regex")
+                    .long("host-regex")
+                    .value_name("REGEX")
+                    .help("Only display refs for hosts matching REGEX anywhere (including the current host), use ^ and $ to anchor")
+                    .value_parser(value_parser!(Pattern))
+                    .conflicts_with("print_self")
+                )
+                .arg(
+                    Arg::new("tags")
+                    .long("tags")
+                    .help("List the tags synced with --include-tags instead of branches")
+                    .value_parser(value_parser!(bool))
+                    .action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("show_author")
+                    .long("show-author")
+                    .help("Show who committed each ref, only affects --print=grouped and --print=json")
+                    .value_parser(value_parser!(bool))
+                    .action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("show_age")
+                    .long("show-age")
+                    .help("Show how long ago each ref was committed, like `3 days ago`, only affects --print=grouped")
+                    .value_parser(value_parser!(bool))
+                    .action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("since")
+                    .long("since")
+                    .value_name("COMMITISH")
+                    .help("Only display branches with commits that COMMITISH does not have, like `origin/main`")
+                )
+                .arg(
+                    Arg::new("diff_self")
+                    .long("diff-self")
+                    .help("Only display branches that do not exist locally")
+                    .value_parser(value_parser!(bool))
+                    .action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("abbrev")
+                    .long("abbrev")
+                    .value_name("N")
+                    .help("Shorten commit IDs to N hex digits, or to core.abbrev if N is omitted")
+                    .value_parser(value_parser!(String))
+                    .num_args(0..=1)
+                    .require_equals(true)
+                    .default_missing_value("")
+                )
+                .arg(
+                    Arg::new("watch")
+                    .long("watch")
+                    .help("Fetch and list again every --interval seconds until interrupted, implies --fetch")
+                    .value_parser(value_parser!(bool))
+                    .action(ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("interval")
+                    .long("interval")
+                    .value_name("SECONDS")
+                    .help("How long --watch waits between listings")
+                    .value_parser(value_parser!(u64).range(1..))
+                    .default_value("30")
+                    .requires("watch")
                 ),
         )
         .subcommand(
@@ -254,6 +1221,225 @@ fn build_cli(default_user: Option<User>, default_host: Option<Host>) -> Command
                         .help("Delete refs for all hosts")
                         .value_parser(value_parser!(bool))
                         .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("branch")
+                        .short('b')
+                        .long("branch")
+                        .help("Only delete refs for the named branch (can be specified multiple times)")
+                        .value_parser(value_parser!(String))
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    Arg::new("branch_regex")
+                        .long("branch-regex")
+                        .value_name("REGEX")
+                        .help("Only delete refs for branches matching REGEX anywhere, use ^ and $ to anchor")
+                        .value_parser(value_parser!(Pattern))
+                        .conflicts_with("branch"),
+                )
+                .arg(
+                    Arg::new("host_regex")
+                        .long("host-regex")
+                        .value_name("REGEX")
+                        .help("Delete refs for hosts matching REGEX anywhere instead of the current host, use ^ and $ to anchor")
+                        .value_parser(value_parser!(Pattern))
+                        .conflicts_with("all"),
+                )
+                .arg(
+                    Arg::new("older_than")
+                        .long("older-than")
+                        .value_name("AGE")
+                        .help("Only delete refs whose commit is older than AGE, like 30d, 2w, or 6h")
+                        .value_parser(value_parser!(Age)),
+                )
+                .arg(
+                    Arg::new("local_only")
+                        .long("local-only")
+                        .help("Only delete the local copies of refs, without fetching or touching the remote")
+                        .value_parser(value_parser!(bool))
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("ref")
+                        .long("ref")
+                        .value_name("REF")
+                        .help("Delete exactly this nomad ref, like refs/nomad/<user>/<host>/<branch>, regardless of host or branch (can be specified multiple times)")
+                        .value_parser(value_parser!(String))
+                        .action(ArgAction::Append)
+                        .conflicts_with_all(["all", "host_regex", "branch", "branch_regex"]),
+                )
+                .arg(
+                    Arg::new("confirm")
+                        .long("confirm")
+                        .help("List the refs and ask before deleting any of them, like --prune-confirm-threshold 0")
+                        .value_parser(value_parser!(bool))
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("prune_confirm_threshold"),
+                )
+                .args(prune_confirm_args())
+                .arg(exit_code_arg()),
+        )
+        .subcommand(
+            Command::new("checkout")
+                .about("Fetch, then check out another host's branch as <host>/<branch>")
+                .arg(
+                    Arg::new("from_host")
+                        .help("Host that pushed the branch")
+                        .required(true)
+                        .value_parser(|host: &str| check_ref_component(host).map(|()| host.to_owned()))
+                        .value_hint(ValueHint::Hostname),
+                )
+                .arg(
+                    Arg::new("branch")
+                        .help("Branch to check out")
+                        .required(true)
+                        .value_parser(value_parser!(String)),
+                ),
+        )
+        .subcommand(
+            Command::new("adopt")
+                .about("Bring existing refs on the remote under nomad management")
+                .arg(
+                    Arg::new("pattern")
+                        .long("pattern")
+                        .help("Refs to adopt, as understood by `git ls-remote`, like refs/custom/*")
+                        .required(true)
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    Arg::new("as_host")
+                        .long("as-host")
+                        .help("Host to adopt the refs as")
+                        .required(true)
+                        .value_parser(value_parser!(String))
+                        .value_hint(ValueHint::Hostname),
+                )
+                .arg(
+                    Arg::new("delete_originals")
+                        .long("delete-originals")
+                        .help("Delete the original refs once adopted")
+                        .value_parser(value_parser!(bool))
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("fix")
+                        .long("fix")
+                        .help("Adopt the refs instead of only printing what would be adopted")
+                        .value_parser(value_parser!(bool))
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("du")
+                .about("Estimate how much storage nomad refs use on top of the remote's branches")
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .help("Print the estimate as JSON")
+                        .value_parser(value_parser!(bool))
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("detect-prefix")
+                .about("Suggest a --ref-prefix based on the nomad refs already at the remote")
+                .long_about(
+                    "Suggest a --ref-prefix based on the nomad refs already at the remote. \
+                    Counts refs shaped like refs/<prefix>/<user>/<host>/<branch> under each \
+                    prefix, most common first, for joining a remote that a team already shares.",
+                ),
+        )
+        .subcommand(
+            Command::new("config")
+                .about("Manage nomad configuration stored in git config")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("user")
+                        .about("Set the user in the repository's git config")
+                        .arg(
+                            Arg::new("value")
+                                .help("User name, shared by all your clones")
+                                .required(true)
+                                .value_parser(value_parser!(String)),
+                        ),
+                )
+                .subcommand(
+                    Command::new("host")
+                        .about("Set the host in the repository's git config")
+                        .arg(
+                            Arg::new("value")
+                                .help("Host name, unique per clone")
+                                .required(true)
+                                .value_parser(value_parser!(String))
+                                .value_hint(ValueHint::Hostname),
+                        ),
+                )
+                .subcommand(
+                    Command::new("show")
+                        .about("Print the user and host in effect, and where each came from"),
+                )
+                .subcommand(
+                    Command::new("unset")
+                        .about("Remove a config value so that the default applies again")
+                        .arg(
+                            Arg::new("key")
+                                .help("Config key to remove")
+                                .required(true)
+                                .value_parser([CONFIG_USER, CONFIG_HOST]),
+                        )
+                        .arg(
+                            Arg::new("config_global")
+                                .long("global")
+                                .help("Remove from the global git config instead of the repository")
+                                .value_parser(value_parser!(bool))
+                                .action(ArgAction::SetTrue),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("doctor")
+                .about("Check that git, the repository, the remote, and the user and host are usable"),
+        )
+        .subcommand(
+            Command::new("prompt")
+                .about("Print `=` if the current branch is synced or `↑` if not, for shell prompts")
+                .long_about(
+                    "Print `=` if the current branch is synced or `↑` if not, for shell prompts. \
+                    Only reads local refs, so it is fast but only as fresh as the last sync. \
+                    Prints nothing on a detached HEAD.",
+                ),
+        )
+        .subcommand(
+            Command::new("explain")
+                .about("Print the refspecs and remote ref names nomad uses, for debugging")
+                .long_about(
+                    "Print the refspecs and remote ref names nomad uses, for debugging. Shows the \
+                    push, fetch, and list refspecs for the resolved user and host, followed by \
+                    the remote ref that each local branch is pushed to. Nothing is run against \
+                    the remote.",
+                ),
+        )
+        .subcommand(
+            Command::new("version")
+                .about("Print the version of git-nomad")
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .help("Print version, git_version, and build_version as a JSON object")
+                        .value_parser(value_parser!(bool))
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("run")
+                .about("Run a workflow described in a TOML file")
+                .arg(
+                    Arg::new("file")
+                        .help("Path to the workflow file")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf))
+                        .value_hint(ValueHint::FilePath),
                 ),
         )
         .subcommand(Command::new("completions")
@@ -262,11 +1448,198 @@ fn build_cli(default_user: Option<User>, default_host: Option<Host>) -> Command
                     Arg::new("shell")
                         .help("Shell dialect")
                         .action(ArgAction::Set)
-                        .value_parser(value_parser!(clap_complete::Shell))
+                        .value_parser(value_parser!(CompletionShell))
                 )
         )
 }
 
+/// Arguments shared by `sync` and `sync-all`, see [`specified_workflow`].
+fn sync_args() -> Vec<Arg> {
+    let mut args = vec![
+        Arg::new("branch")
+            .short('b')
+            .long("branch")
+            .help("Only push the named local branch (can be specified multiple times)")
+            .value_parser(value_parser!(String))
+            .action(ArgAction::Append),
+        Arg::new("branch_regex")
+            .long("branch-regex")
+            .value_name("REGEX")
+            .help("Only push local branches matching REGEX anywhere, use ^ and $ to anchor")
+            .value_parser(value_parser!(Pattern))
+            .conflicts_with("branch"),
+        Arg::new("fail_on_prune")
+            .long("fail-on-prune")
+            .help("Exit with an error if any refs were pruned")
+            .value_parser(value_parser!(bool))
+            .action(ArgAction::SetTrue),
+        Arg::new("all_remotes")
+            .long("all-remotes")
+            .help("Sync with every remote listed by `git remote` instead of --remote")
+            .value_parser(value_parser!(bool))
+            .action(ArgAction::SetTrue),
+        Arg::new("mirror_remote_branches")
+            .long("mirror-remote-branches")
+            .help("Also record the remote's own branches under the `remote-<remote>` host, which needs a named remote")
+            .value_parser(value_parser!(bool))
+            .action(ArgAction::SetTrue),
+        Arg::new("tracking_refs")
+            .long("tracking-refs")
+            .help("Also copy other hosts' branches to `refs/remotes/<prefix>/<host>/<branch>`, for `git log --all` and `gitk`")
+            .value_parser(value_parser!(bool))
+            .action(ArgAction::SetTrue),
+        Arg::new("include_tags")
+            .long("include-tags")
+            .help("Also sync local tags, kept apart from branches under `refs/<prefix>-tags`")
+            .value_parser(value_parser!(bool))
+            .action(ArgAction::SetTrue),
+        Arg::new("push_only")
+            .long("push-only")
+            .help("Only push local branches, skipping the fetch and prune")
+            .value_parser(value_parser!(bool))
+            .action(ArgAction::SetTrue)
+            .conflicts_with("fetch_only"),
+        Arg::new("fetch_only")
+            .long("fetch-only")
+            .help("Only fetch and prune, skipping the push of local branches")
+            .value_parser(value_parser!(bool))
+            .action(ArgAction::SetTrue),
+        Arg::new("min_push_interval")
+            .long("min-push-interval")
+            .value_name("SECS")
+            .help("Push to each remote at most once every SECS seconds, as remembered in the local git config")
+            .value_parser(value_parser!(u64)),
+        Arg::new("on_rate_limit")
+            .long("on-rate-limit")
+            .help("What to do when pushing again within --min-push-interval")
+            .value_parser([
+                PossibleValue::new("skip").help("Skip the push, but still fetch and prune"),
+                PossibleValue::new("wait").help("Wait until the interval has passed"),
+                PossibleValue::new("error").help("Fail without pushing"),
+            ])
+            .default_value("skip")
+            .requires("min_push_interval"),
+        Arg::new("show_refspecs")
+            .long("show-refspecs")
+            .help("Print the refspecs that sync would use and exit without running git")
+            .value_parser(value_parser!(bool))
+            .action(ArgAction::SetTrue),
+        Arg::new("selective_fetch")
+            .long("selective-fetch")
+            .help("List the remote first and only fetch the refs that changed, skipping the fetch if none did")
+            .value_parser(value_parser!(bool))
+            .action(ArgAction::SetTrue)
+            .conflicts_with("push_only"),
+    ];
+    args.push(
+        Arg::new("prune")
+            .long("prune")
+            .help("Whether to prune refs for branches deleted on other hosts after fetching")
+            .value_parser([
+                PossibleValue::new("always"),
+                PossibleValue::new("never")
+                    .help("Keep every ref, to confirm the remote state before pruning later"),
+            ])
+            .default_value("always"),
+    );
+    args.push(
+        Arg::new("force")
+            .long("force")
+            .help("How to overwrite this host's nomad refs at the remote")
+            .value_parser([
+                PossibleValue::new("always"),
+                PossibleValue::new("with-lease").help(
+                    "Fail instead of overwriting refs that changed since they were last fetched, \
+                     like another clone with the same host name syncing at once",
+                ),
+            ])
+            .default_value("always"),
+    );
+    args.extend(prune_confirm_args());
+    args.extend(fetch_options_args());
+    args.push(exit_code_arg());
+    args
+}
+
+/// Exit code for `--exit-code` when nothing was pushed or pruned, chosen to stay clear of the 1
+/// that errors exit with.
+const EXIT_UNCHANGED: i32 = 100;
+
+/// The `--exit-code` argument of subcommands that report an [`Outcome`], see
+/// [`specified_exit_code`].
+fn exit_code_arg() -> Arg {
+    Arg::new("exit_code")
+        .long("exit-code")
+        .help(format!(
+            "Exit with 0 if refs were pushed or pruned, {} if nothing changed, 1 on errors",
+            EXIT_UNCHANGED
+        ))
+        .value_parser(value_parser!(bool))
+        .action(ArgAction::SetTrue)
+}
+
+/// Whether the subcommand was asked to report an [`Outcome::Unchanged`] as [`EXIT_UNCHANGED`].
+fn specified_exit_code(matches: &ArgMatches) -> bool {
+    matches.subcommand().is_some_and(|(_, matches)| {
+        matches!(matches.try_get_one::<bool>("exit_code"), Ok(Some(true)))
+    })
+}
+
+/// Arguments shared by subcommands that can prune refs, see [`specified_prune_confirm`].
+fn prune_confirm_args() -> [Arg; 4] {
+    [
+        Arg::new("prune_confirm_threshold")
+            .long("prune-confirm-threshold")
+            .help("Ask for confirmation before pruning more than this many refs")
+            .value_parser(value_parser!(usize))
+            .default_value(DEFAULT_PRUNE_CONFIRM_THRESHOLD.to_string()),
+        Arg::new("yes")
+            .short('y')
+            .long("yes")
+            .help(format!(
+                "Prune without asking for confirmation, also enabled by {}=1",
+                ENV_ASSUME_YES
+            ))
+            .value_parser(value_parser!(bool))
+            .action(ArgAction::SetTrue),
+        Arg::new("no_assume_yes")
+            .long("no-assume-yes")
+            .help(format!(
+                "Ask for confirmation even if {} is set",
+                ENV_ASSUME_YES
+            ))
+            .value_parser(value_parser!(bool))
+            .action(ArgAction::SetTrue)
+            .overrides_with("yes"),
+        Arg::new("fast_prune")
+            .long("fast-prune")
+            .help("Delete pruned local refs in one all-or-nothing batch after the remote deletions succeed")
+            .value_parser(value_parser!(bool))
+            .action(ArgAction::SetTrue),
+    ]
+}
+
+/// Arguments shared by subcommands that can fetch refs, see [`specified_fetch_options`].
+fn fetch_options_args() -> [Arg; 3] {
+    [
+        Arg::new("partial")
+            .long("partial")
+            .help("Fetch without file contents (`--filter=blob:none`), requires partial clone support")
+            .value_parser(value_parser!(bool))
+            .action(ArgAction::SetTrue),
+        Arg::new("depth")
+            .long("depth")
+            .value_name("N")
+            .help("Only fetch the last N commits of each ref (`--depth`), only allowed in a shallow clone")
+            .value_parser(value_parser!(u32).range(1..)),
+        Arg::new("write_fetch_summary")
+            .long("write-fetch-summary")
+            .help("Write the nomad refs changed by the fetch to .git/nomad/last-fetch as JSON lines")
+            .value_parser(value_parser!(bool))
+            .action(ArgAction::SetTrue),
+    ]
+}
+
 /// Use [`clap`] to implement the intended command line interface.
 fn cli(
     default_user: Option<User>,
@@ -280,6 +1653,11 @@ fn cli(
 fn specified_verbosity(matches: &mut ArgMatches) -> Option<Verbosity> {
     if matches.remove_one::<bool>("quiet").expect("has default") {
         None
+    } else if matches
+        .remove_one::<bool>("no_progress")
+        .expect("has default")
+    {
+        Some(Verbosity::no_progress())
     } else {
         match matches.remove_one::<u8>("verbose").expect("has default") {
             0 => Some(Verbosity::default()),
@@ -289,115 +1667,657 @@ fn specified_verbosity(matches: &mut ArgMatches) -> Option<Verbosity> {
     }
 }
 
-/// The [`GitBinary`] intended by the user via the CLI.
-///
-/// # Panics
+/// Whether the user wants git command failures summarized via the CLI.
 ///
-/// If [`clap`] does not prevent certain assumed invalid states.
-fn specified_git(matches: &mut ArgMatches) -> String {
-    matches.remove_one("git").expect("default value")
+/// Verbose output always gets the full failure, since the user is evidently debugging.
+fn specified_quiet_errors(matches: &mut ArgMatches, verbosity: Option<Verbosity>) -> bool {
+    matches
+        .remove_one::<bool>("quiet_errors")
+        .expect("has default")
+        && verbosity.is_none_or(|v| v == Verbosity::default() || v == Verbosity::no_progress())
 }
 
-/// The nomad workflow the user intends to execute via the CLI.
-///
-/// # Panics
-///
-/// If [`clap`] does not prevent certain assumed invalid states.
+/// Whether the user wants colored output via `--color` or `--no-color`, see
+/// [`Renderer::set_color`].
+fn specified_color(matches: &mut ArgMatches) -> ColorChoice {
+    if matches.remove_one::<bool>("no_color").expect("has default") {
+        return ColorChoice::Never;
+    }
+
+    match matches
+        .remove_one::<String>("color")
+        .expect("has default")
+        .as_str()
+    {
+        "auto" => ColorChoice::Auto,
+        "always" => ColorChoice::Always,
+        "never" => ColorChoice::Never,
+        _ => unreachable!("has possible values"),
+    }
+}
+
+/// The file that the user wants output written to via the CLI, see [`FileRenderer`].
+///
+/// Relative paths are resolved against `cwd`, and an existing file is overwritten.
+fn specified_output(matches: &mut ArgMatches, cwd: &Path) -> anyhow::Result<Option<fs::File>> {
+    let Some(path) = matches.remove_one::<PathBuf>("output") else {
+        return Ok(None);
+    };
+
+    let path = cwd.join(path);
+    fs::File::create(&path)
+        .with_context(|| format!("creating --output {}", path.display()))
+        .map(Some)
+}
+
+/// The file descriptor that the user wants progress written to via the CLI, see
+/// [`ProgressRenderer`].
+///
+/// Progress is written to a duplicate of the descriptor, the original is left open.
+fn specified_progress_fd(matches: &mut ArgMatches) -> anyhow::Result<Option<fs::File>> {
+    let Some(fd) = matches.remove_one::<i32>("progress_fd") else {
+        return Ok(None);
+    };
+
+    // Write through a duplicate so that closing it leaves the caller's descriptor alone, which
+    // matters for `--progress-fd 1` or `2` since those are still needed for stdout and stderr. The
+    // duplicate is close-on-exec, so that spawned git and ssh processes (including a lingering
+    // `--ssh-multiplex` master) cannot hold the caller's progress pipe open.
+    //
+    // SAFETY: `F_DUPFD_CLOEXEC` only creates a new descriptor, and fails if `fd` is not open.
+    let dup = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0) };
+    if dup == -1 {
+        anyhow::bail!("--progress-fd {} is not an open file descriptor", fd);
+    }
+
+    // SAFETY: The duplicate was just created, so nothing else owns it.
+    Ok(Some(unsafe { fs::File::from_raw_fd(dup) }))
+}
+
+/// How often the user wants network commands retried via the CLI, see [`GitBinary::retries`].
+fn specified_retries(matches: &mut ArgMatches) -> Retries {
+    Retries {
+        count: matches.remove_one::<u32>("retries").expect("has default"),
+        backoff: RETRY_BACKOFF,
+    }
+}
+
+/// Whether the user wants git to be able to prompt for credentials via the CLI.
+fn specified_allow_prompt(matches: &mut ArgMatches) -> bool {
+    matches
+        .remove_one::<bool>("allow_prompt")
+        .expect("has default")
+}
+
+/// Whether the user wants SSH connections shared between git commands via the CLI.
+fn specified_ssh_multiplex(matches: &mut ArgMatches) -> bool {
+    matches
+        .remove_one::<bool>("ssh_multiplex")
+        .expect("has default")
+}
+
+/// The git namespace the user wants refs on the remote scoped to via the CLI, if any.
+fn specified_git_namespace(matches: &mut ArgMatches) -> Option<String> {
+    matches.remove_one("git_namespace")
+}
+
+/// Whether the user wants nomad refs checked for missing commits via the CLI.
+fn specified_verify_objects(matches: &mut ArgMatches) -> bool {
+    matches
+        .remove_one::<bool>("verify_objects")
+        .expect("has default")
+}
+
+/// Whether the user wants the remote probed before the workflow via the CLI.
+fn specified_check_remote(matches: &mut ArgMatches) -> bool {
+    matches
+        .remove_one::<bool>("check_remote")
+        .expect("has default")
+}
+
+/// The [`GitBinary::ref_prefix`] intended by the user via the CLI, environment, or git config.
+fn specified_ref_prefix(
+    renderer: &mut impl Renderer,
+    matches: &mut ArgMatches,
+    git: &GitBinary,
+) -> anyhow::Result<String> {
+    let prefix: String = resolve(
+        matches,
+        "ref_prefix",
+        ENV_REF_PREFIX,
+        |_| Ok(()),
+        &NomadFile::default(),
+        || git.get_config(renderer, CONFIG_REF_PREFIX),
+    )?;
+
+    if prefix.is_empty() || prefix.contains('/') {
+        anyhow::bail!(
+            "Invalid ref prefix {:?}, it must be a single non-empty ref name component",
+            prefix
+        );
+    }
+    check_ref_prefix(&prefix)
+        .map_err(|problem| anyhow::anyhow!("Invalid ref prefix {:?}, {}", prefix, problem))?;
+
+    Ok(prefix)
+}
+
+/// The [`GitBinary`] intended by the user via the CLI.
+///
+/// # Panics
+///
+/// If [`clap`] does not prevent certain assumed invalid states.
+fn specified_git(matches: &mut ArgMatches) -> String {
+    matches.remove_one("git").expect("default value")
+}
+
+/// The `.git` directory the user wants used as is via the CLI, if any.
+fn specified_git_dir(matches: &mut ArgMatches) -> Option<PathBuf> {
+    matches.remove_one("git_dir")
+}
+
+/// The [`PruneConfirm`] intended by the user via the CLI, where `env_assume_yes` is the value of
+/// the [`ENV_ASSUME_YES`] environment variable. `--no-assume-yes` takes precedence over the
+/// environment variable, while whichever of `--yes` and `--no-assume-yes` comes last wins.
+///
+/// # Panics
+///
+/// If `matches` is not for a subcommand that has [`prune_confirm_args`].
+fn specified_prune_confirm(
+    matches: &mut ArgMatches,
+    env_assume_yes: Option<OsString>,
+) -> anyhow::Result<PruneConfirm> {
+    let threshold = matches
+        .remove_one::<usize>("prune_confirm_threshold")
+        .expect("has default");
+    let yes = matches.remove_one::<bool>("yes").expect("has default");
+    let no_assume_yes = matches
+        .remove_one::<bool>("no_assume_yes")
+        .expect("has default");
+
+    let from_env = match env_assume_yes.as_ref().map(|value| value.to_str()) {
+        None | Some(Some("" | "0")) => false,
+        Some(Some("1")) => true,
+        Some(_) => anyhow::bail!(
+            "Invalid {}={:?}, expected 1 or 0",
+            ENV_ASSUME_YES,
+            env_assume_yes.unwrap_or_default()
+        ),
+    };
+
+    Ok(PruneConfirm {
+        threshold,
+        assume_yes: yes || (from_env && !no_assume_yes),
+    })
+}
+
+/// The [`FetchOptions`] intended by the user via the CLI.
+///
+/// # Panics
+///
+/// If `matches` is not for a subcommand that has [`fetch_options_args`].
+fn specified_fetch_options(matches: &mut ArgMatches) -> FetchOptions {
+    FetchOptions {
+        partial: matches.remove_one::<bool>("partial").expect("has default"),
+        depth: matches.remove_one::<u32>("depth"),
+        write_summary: matches
+            .remove_one::<bool>("write_fetch_summary")
+            .expect("has default"),
+    }
+}
+
+/// The nomad workflow the user intends to execute via the CLI.
+///
+/// # Panics
+///
+/// If [`clap`] does not prevent certain assumed invalid states.
 fn specified_workflow<'a>(
     renderer: &mut impl Renderer,
     matches: &'a mut ArgMatches,
     git: &GitBinary,
     current_shell_path: Option<OsString>,
 ) -> anyhow::Result<Workflow<'a>> {
-    let user = resolve(matches, "user", || {
-        git.get_config(renderer, CONFIG_USER)
-            .map(|opt| opt.map(User::from))
-    })?;
-
-    let host = resolve(matches, "host", || {
-        git.get_config(renderer, CONFIG_HOST)
-            .map(|opt| opt.map(Host::from))
-    })?;
-
-    let remote = Remote::from(
-        matches
-            .remove_one::<String>("remote")
-            .expect("default value"),
-    );
+    // Writing config must work even when the user or host cannot be resolved yet, like setting
+    // the host on a machine without a usable hostname.
+    if let Some(workflow) = specified_config_write(matches)? {
+        return Ok(workflow);
+    }
+
+    let nomad_file = NomadFile::load(git)?;
+    let (user, user_source) = resolve_with_source::<User>(
+        matches,
+        "user",
+        ENV_USER,
+        check_ref_component,
+        &nomad_file,
+        || git.get_config(renderer, CONFIG_USER),
+    )?;
+
+    let (host, host_source) = resolve_with_source::<Host>(
+        matches,
+        "host",
+        ENV_HOST,
+        check_ref_component,
+        &nomad_file,
+        || git.get_config(renderer, CONFIG_HOST),
+    )?;
+
+    let remote_given = matches.value_source("remote") == Some(ValueSource::CommandLine)
+        || matches.contains_id("local_remote")
+        || matches.contains_id("remote_url");
+
+    // Checked before `--local-remote` creates anything, and by hand since clap does not check
+    // conflicts between global arguments and subcommand arguments that come before it.
+    let mirror_remote_branches = matches.subcommand().is_some_and(|(_, matches)| {
+        matches!(
+            matches.try_get_one::<bool>("mirror_remote_branches"),
+            Ok(Some(true))
+        )
+    });
+    if mirror_remote_branches
+        && (matches.contains_id("local_remote") || matches.contains_id("remote_url"))
+    {
+        anyhow::bail!(
+            "--mirror-remote-branches names its host after the remote, \
+             which needs --remote rather than --remote-url or --local-remote"
+        );
+    }
+
+    // Git accepts a URL anywhere it expects a remote name, so URLs are used as is.
+    let remote_urls = matches.remove_many::<String>("remote_url");
+    let remotes = match matches.remove_one::<PathBuf>("local_remote") {
+        Some(path) => {
+            if matches.value_source("remote") == Some(ValueSource::CommandLine) {
+                anyhow::bail!("--local-remote replaces --remote, pass only one of them");
+            }
+            vec![git.local_remote(renderer, &path)?]
+        }
+        None => match remote_urls {
+            Some(urls) => {
+                if matches.value_source("remote") == Some(ValueSource::CommandLine) {
+                    anyhow::bail!("--remote-url replaces --remote, pass only one of them");
+                }
+                urls.map(Remote::from).collect::<Vec<_>>()
+            }
+            None => matches
+                .remove_many::<String>("remote")
+                .expect("default value")
+                .map(Remote::from)
+                .collect::<Vec<_>>(),
+        },
+    };
+
+    let dry_run = matches.remove_one::<bool>("dry_run").expect("has default");
 
     let (subcommand, matches) = matches
         .remove_subcommand()
         .expect("subcommand is mandatory");
 
     return match (subcommand.as_str(), matches) {
-        ("sync", _) => Ok(Workflow::Sync { user, host, remote }),
-
-        ("ls", mut matches) => Ok(Workflow::Ls {
-            printer: match matches
-                .remove_one::<String>("print")
-                .expect("has default")
-                .as_str()
-            {
-                "grouped" => LsPrinter::Grouped,
-                "ref" => LsPrinter::Ref,
-                "commit" => LsPrinter::Commit,
-                _ => unreachable!("has possible values"),
-            },
+        ("sync" | "sync-all", mut matches) => Ok(Workflow::Sync {
             user,
-            fetch_remote: if matches.remove_one::<bool>("fetch").expect("has default") {
-                Some(remote)
-            } else {
-                None
-            },
-            host_filter: if matches
-                .remove_one::<bool>("print_self")
+            host,
+            remotes: if matches
+                .remove_one::<bool>("all_remotes")
                 .expect("has default")
             {
-                Filter::All
+                if remote_given {
+                    anyhow::bail!("--all-remotes replaces --remote, pass only one of them");
+                }
+                let all = git.list_remotes(renderer)?;
+                if all.is_empty() {
+                    anyhow::bail!("--all-remotes found no remotes, add one with `git remote add`");
+                }
+                all
             } else {
-                Filter::Deny([host].into())
+                remotes
             },
-            branch_filter: {
-                let mut branch_set = HashSet::<Branch>::new();
-
-                if matches.remove_one::<bool>("head").expect("has default") {
-                    branch_set.insert(git.current_branch(renderer)?);
-                }
+            options: SyncOptions {
+                fail_on_prune: matches
+                    .remove_one::<bool>("fail_on_prune")
+                    .expect("has default"),
+                prune_confirm: specified_prune_confirm(&mut matches, env::var_os(ENV_ASSUME_YES))?,
+                fetch: specified_fetch_options(&mut matches),
+                mirror_remote_branches: matches
+                    .remove_one::<bool>("mirror_remote_branches")
+                    .expect("has default"),
+                dry_run,
+                show_refspecs: matches
+                    .remove_one::<bool>("show_refspecs")
+                    .expect("has default"),
+                direction: if matches
+                    .remove_one::<bool>("push_only")
+                    .expect("has default")
+                {
+                    SyncDirection::PushOnly
+                } else if matches
+                    .remove_one::<bool>("fetch_only")
+                    .expect("has default")
+                {
+                    SyncDirection::FetchOnly
+                } else {
+                    SyncDirection::Both
+                },
+                fast_prune: matches
+                    .remove_one::<bool>("fast_prune")
+                    .expect("has default"),
+                selective_fetch: matches
+                    .remove_one::<bool>("selective_fetch")
+                    .expect("has default"),
+                prune: match matches
+                    .remove_one::<String>("prune")
+                    .expect("has default")
+                    .as_str()
+                {
+                    "always" => SyncPrune::Always,
+                    "never" => SyncPrune::Never,
+                    _ => unreachable!("unknown prune mode"),
+                },
+                push_rate_limit: {
+                    let on_limit = match matches
+                        .remove_one::<String>("on_rate_limit")
+                        .expect("has default")
+                        .as_str()
+                    {
+                        "skip" => OnRateLimit::Skip,
+                        "wait" => OnRateLimit::Wait,
+                        "error" => OnRateLimit::Error,
+                        _ => unreachable!("has possible values"),
+                    };
+                    matches
+                        .remove_one::<u64>("min_push_interval")
+                        .map(|min_interval| PushRateLimit {
+                            min_interval,
+                            on_limit,
+                        })
+                },
+                include_tags: matches
+                    .remove_one::<bool>("include_tags")
+                    .expect("has default"),
+                tracking_refs: matches
+                    .remove_one::<bool>("tracking_refs")
+                    .expect("has default"),
+                force: match matches
+                    .remove_one::<String>("force")
+                    .expect("has default")
+                    .as_str()
+                {
+                    "always" => SyncForce::Always,
+                    "with-lease" => SyncForce::WithLease,
+                    _ => unreachable!("has possible values"),
+                },
+            },
+            branch_filter: match matches.remove_many::<String>("branch") {
+                Some(branches) => Filter::Allow(branches.map(Branch::from).collect()),
+                None => match matches.remove_one::<Pattern>("branch_regex") {
+                    Some(pattern) => Filter::Regex(pattern),
+                    None => Filter::All,
+                },
+            },
+        }),
 
-                if let Some(branches) = matches.remove_many::<String>("branch") {
-                    branch_set.extend(branches.map(Branch::from));
-                }
+        ("fetch", _) => Ok(Workflow::Fetch {
+            user,
+            remote: single_remote("fetch", remotes)?,
+        }),
 
-                if branch_set.is_empty() {
+        ("ls", mut matches) => {
+            let options = LsOptions {
+                user_filter: {
+                    let mut users = HashSet::from([user.clone()]);
+                    if let Some(others) = matches.remove_many::<String>("other_user") {
+                        if !matches.get_flag("fetch") && !matches.get_flag("watch") {
+                            anyhow::bail!(
+                                "--other-user lists refs from the remote, so it needs --fetch"
+                            );
+                        }
+                        users.extend(others.map(User::from));
+                    }
+                    Filter::Allow(users)
+                },
+                printer: match matches.remove_one::<String>("format") {
+                    Some(template) => LsPrinter::Template(template),
+                    None if matches
+                        .remove_one::<bool>("porcelain")
+                        .expect("has default") =>
+                    {
+                        LsPrinter::Porcelain
+                    }
+                    None => match matches
+                        .remove_one::<String>("print")
+                        .expect("has default")
+                        .as_str()
+                    {
+                        "grouped" => {
+                            if matches
+                                .remove_one::<bool>("color_by_host")
+                                .expect("has default")
+                            {
+                                LsPrinter::ColoredGrouped
+                            } else {
+                                LsPrinter::Grouped
+                            }
+                        }
+                        "tree" => {
+                            if matches.remove_one::<bool>("unicode").expect("has default") {
+                                LsPrinter::UnicodeTree
+                            } else {
+                                LsPrinter::Tree
+                            }
+                        }
+                        "ref" => LsPrinter::Ref,
+                        "commit" => LsPrinter::Commit,
+                        "json" => LsPrinter::Json,
+                        _ => unreachable!("has possible values"),
+                    },
+                },
+                host_filter: if let Some(pattern) = matches.remove_one::<Pattern>("host_regex") {
+                    Filter::Regex(pattern)
+                } else if matches
+                    .remove_one::<bool>("print_self")
+                    .expect("has default")
+                {
                     Filter::All
                 } else {
-                    Filter::Allow(branch_set)
-                }
-            },
-        }),
+                    Filter::Deny([host].into())
+                },
+                branch_filter: if let Some(pattern) = matches.remove_one::<Pattern>("branch_regex")
+                {
+                    Filter::Regex(pattern)
+                } else {
+                    let mut globs = Vec::new();
+
+                    // A detached HEAD has no branch to list, which is worth a warning but not failing
+                    // the whole listing over.
+                    let head = matches.remove_one::<bool>("head").expect("has default");
+                    if head {
+                        match git.current_branch(renderer) {
+                            Ok(branch) => globs.push(Glob::literal(&branch.0)),
+                            Err(_) => renderer.writer(|w| {
+                                writeln!(
+                                    w,
+                                    "Warning: HEAD is detached, so --head matches no branch"
+                                )?;
+                                Ok(())
+                            })?,
+                        }
+                    }
+
+                    if let Some(branches) = matches.remove_many::<Glob>("branch") {
+                        globs.extend(branches);
+                    }
+
+                    // Even with no globs, `--head` should narrow the listing rather than widen it.
+                    if globs.is_empty() && !head {
+                        Filter::All
+                    } else {
+                        Filter::Glob(globs)
+                    }
+                },
+                diff_self: matches
+                    .remove_one::<bool>("diff_self")
+                    .expect("has default"),
+                abbrev: match matches.remove_one::<String>("abbrev").as_deref() {
+                    None => None,
+                    Some("") => git.abbrev_length(renderer)?,
+                    Some(length) => Some(
+                        length
+                            .parse()
+                            .with_context(|| format!("parsing --abbrev={}", length))?,
+                    ),
+                },
+                format_host: matches.remove_one::<String>("format_host"),
+                show_author: matches
+                    .remove_one::<bool>("show_author")
+                    .expect("has default"),
+                show_age: matches.remove_one::<bool>("show_age").expect("has default"),
+                since: matches.remove_one::<String>("since"),
+                sort: match matches
+                    .remove_one::<String>("sort")
+                    .expect("has default")
+                    .as_str()
+                {
+                    "name" => LsSort::Name,
+                    "commit-date" => LsSort::CommitDate,
+                    _ => unreachable!("has possible values"),
+                },
+            };
+
+            Ok(Workflow::Ls {
+                user,
+                fetch_remotes: if matches.remove_one::<bool>("fetch").expect("has default")
+                    || matches.get_flag("watch")
+                {
+                    remotes
+                } else {
+                    Vec::new()
+                },
+                fetch_options: specified_fetch_options(&mut matches),
+                options,
+                tags: matches.remove_one::<bool>("tags").expect("has default"),
+                watch_interval: if matches.remove_one::<bool>("watch").expect("has default") {
+                    matches.remove_one::<u64>("interval")
+                } else {
+                    None
+                },
+            })
+        }
 
         ("purge", mut matches) => {
-            let remote = Remote::from(
-                matches
-                    .remove_one::<String>("remote")
-                    .expect("<remote> is a required argument"),
-            );
-            let host_filter = if matches.remove_one::<bool>("all").expect("default value") {
+            let remote = single_remote("purge", remotes)?;
+            let host_filter = if let Some(pattern) = matches.remove_one::<Pattern>("host_regex") {
+                Filter::Regex(pattern)
+            } else if matches.remove_one::<bool>("all").expect("default value") {
                 Filter::All
             } else {
                 Filter::Allow(HashSet::from_iter([host]))
             };
+            let branch_filter = match matches.remove_many::<String>("branch") {
+                Some(branches) => Filter::Allow(branches.map(Branch::from).collect()),
+                None => match matches.remove_one::<Pattern>("branch_regex") {
+                    Some(pattern) => Filter::Regex(pattern),
+                    None => Filter::All,
+                },
+            };
+            let refs: Vec<String> = matches
+                .remove_many::<String>("ref")
+                .map(Iterator::collect)
+                .unwrap_or_default();
+            // Checked again when purging, but a typo should fail before anything is fetched.
+            for name in &refs {
+                git.parse_nomad_ref_name(&user, name)?;
+            }
+
+            let mut prune_confirm =
+                specified_prune_confirm(&mut matches, env::var_os(ENV_ASSUME_YES))?;
+            if matches.remove_one::<bool>("confirm").expect("has default") {
+                prune_confirm.threshold = 0;
+            }
 
             return Ok(Workflow::Purge {
                 user,
                 remote,
                 host_filter,
+                branch_filter,
+                prune_confirm,
+                older_than: matches.remove_one::<Age>("older_than"),
+                dry_run,
+                fast_prune: matches
+                    .remove_one::<bool>("fast_prune")
+                    .expect("has default"),
+                local_only: matches
+                    .remove_one::<bool>("local_only")
+                    .expect("has default"),
+                refs,
             });
         }
 
+        ("adopt", mut matches) => Ok(Workflow::Adopt {
+            user,
+            remote: single_remote("adopt", remotes)?,
+            pattern: matches
+                .remove_one::<String>("pattern")
+                .expect("--pattern is required"),
+            host: Host::from(
+                matches
+                    .remove_one::<String>("as_host")
+                    .expect("--as-host is required"),
+            ),
+            delete_originals: matches
+                .remove_one::<bool>("delete_originals")
+                .expect("has default"),
+            fix: matches.remove_one::<bool>("fix").expect("has default"),
+        }),
+
+        ("du", mut matches) => Ok(Workflow::Du {
+            user,
+            remote: single_remote("du", remotes)?,
+            json: matches.remove_one::<bool>("json").expect("has default"),
+        }),
+
+        ("checkout", mut matches) => Ok(Workflow::Checkout {
+            user,
+            remote: single_remote("checkout", remotes)?,
+            host: Host::from(
+                matches
+                    .remove_one::<String>("from_host")
+                    .expect("<from_host> is a required argument"),
+            ),
+            branch: Branch::from(
+                matches
+                    .remove_one::<String>("branch")
+                    .expect("<branch> is a required argument"),
+            ),
+        }),
+
+        ("detect-prefix", _) => Ok(Workflow::DetectPrefix {
+            remote: single_remote("detect-prefix", remotes)?,
+        }),
+
+        // Everything else was handled by `specified_config_write`.
+        ("config", _) => Ok(Workflow::ConfigShow {
+            user,
+            host,
+            user_source,
+            host_source,
+        }),
+
+        ("prompt", _) => Ok(Workflow::Prompt { user, host }),
+
+        ("explain", _) => Ok(Workflow::Explain { user, host }),
+
+        ("run", mut matches) => {
+            let path = matches
+                .remove_one::<PathBuf>("file")
+                .expect("<file> is a required argument");
+            let toml = fs::read_to_string(&path)
+                .with_context(|| format!("reading workflow from {}", path.display()))?;
+            Workflow::from_toml(&toml)
+        }
+
         ("completions", mut matches) => matches
-            .remove_one::<clap_complete::Shell>("shell")
-            .or_else(|| current_shell_path.and_then(clap_complete::Shell::from_shell_path))
+            .remove_one::<CompletionShell>("shell")
+            .or_else(|| current_shell_path.and_then(CompletionShell::from_shell_path))
             .map(Workflow::Completions)
             .ok_or_else(|| anyhow::anyhow!("Unsupported shell")),
 
@@ -405,50 +2325,207 @@ fn specified_workflow<'a>(
     };
 }
 
+/// Only `sync` knows how to work with multiple remotes, everything else wants exactly one.
+fn single_remote<'a>(subcommand: &str, mut remotes: Vec<Remote<'a>>) -> anyhow::Result<Remote<'a>> {
+    if remotes.len() > 1 {
+        anyhow::bail!("{} only supports a single --remote", subcommand);
+    }
+    Ok(remotes.pop().expect("default value"))
+}
+
+/// The file at the root of the working tree that can pin `user` and `host` for everyone who
+/// checks out the repository, see [`resolve`].
+const NOMAD_FILE: &str = ".git-nomad.toml";
+
+/// The contents of a [`NOMAD_FILE`], empty when there is no such file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NomadFile {
+    user: Option<String>,
+    host: Option<String>,
+}
+
+impl NomadFile {
+    /// Read the [`NOMAD_FILE`] in the working tree of `git`, if there is one.
+    fn load(git: &GitBinary) -> anyhow::Result<Self> {
+        let Some(path) = git.work_tree().map(|dir| dir.join(NOMAD_FILE)) else {
+            return Ok(Self::default());
+        };
+
+        let toml = match fs::read_to_string(&path) {
+            Ok(toml) => toml,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => {
+                return Err(e).with_context(|| format!("reading {}", path.display()));
+            }
+        };
+
+        toml::from_str(&toml).with_context(|| {
+            format!(
+                "Invalid {}, it may only set `user` and `host`, which are used unless given by --user/--host or the environment and take precedence over git config",
+                path.display()
+            )
+        })
+    }
+
+    /// The value for the `resolve` argument called `arg_name`.
+    fn get(&self, arg_name: &str) -> Option<&str> {
+        match arg_name {
+            "user" => self.user.as_deref(),
+            "host" => self.host.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+/// The `config` subcommands that only write config, which need neither the user nor the host.
+fn specified_config_write<'a>(matches: &ArgMatches) -> anyhow::Result<Option<Workflow<'a>>> {
+    let Some(("config", matches)) = matches.subcommand() else {
+        return Ok(None);
+    };
+
+    match matches.subcommand() {
+        Some(("unset", matches)) => Ok(Some(Workflow::ConfigUnset {
+            scope: if matches.get_flag("config_global") {
+                ConfigScope::Global
+            } else {
+                ConfigScope::Local
+            },
+            key: matches
+                .get_one::<String>("key")
+                .expect("<key> is a required argument")
+                .clone(),
+        })),
+        Some((key @ (CONFIG_USER | CONFIG_HOST), matches)) => {
+            let value = matches
+                .get_one::<String>("value")
+                .expect("<value> is a required argument")
+                .clone();
+            if let Err(problem) = check_ref_component(&value) {
+                anyhow::bail!("Invalid {} {:?}, {}", key, value, problem);
+            }
+            Ok(Some(Workflow::ConfigSet {
+                key: key.to_string(),
+                value,
+            }))
+        }
+        Some(("show", _)) => Ok(None),
+        _ => unreachable!("unknown config subcommand"),
+    }
+}
+
 /// Extract user arguments in order of preference:
 ///
 /// 1. Passed in as direct CLI options
 /// 2. Specified as an environment variable
-/// 3. Specified in `git config`
-/// 4. A default from querying the operating system
-fn resolve<T: Clone + From<String>>(
+/// 3. Specified in the [`NOMAD_FILE`] committed to the repository
+/// 4. Specified in `git config`
+/// 5. A default from querying the operating system
+///
+/// The operating system can fail to provide a default (like in minimal containers without a
+/// hostname), in which case this errors with instructions for providing the value explicitly.
+///
+/// Values that fail `validate` are rejected with an error naming where they came from, since a
+/// bad hostname from the operating system needs a different fix than a typo on the command line.
+fn resolve<T: From<String>>(
     matches: &mut ArgMatches,
     arg_name: &str,
-    from_git_config: impl FnOnce() -> anyhow::Result<Option<T>>,
+    env_name: &str,
+    validate: impl FnOnce(&str) -> Result<(), String>,
+    nomad_file: &NomadFile,
+    from_git_config: impl FnOnce() -> anyhow::Result<Option<String>>,
 ) -> anyhow::Result<T> {
-    match (
-        matches.value_source(arg_name).expect("default value"),
-        matches
-            .remove_one::<String>(arg_name)
-            .expect("default value"),
+    resolve_with_source(
+        matches,
+        arg_name,
+        env_name,
+        validate,
+        nomad_file,
+        from_git_config,
+    )
+    .map(|(value, _)| value)
+}
+
+/// Like [`resolve`], but also describes where the value came from, like `--user` or `the
+/// operating system default`.
+fn resolve_with_source<T: From<String>>(
+    matches: &mut ArgMatches,
+    arg_name: &str,
+    env_name: &str,
+    validate: impl FnOnce(&str) -> Result<(), String>,
+    nomad_file: &NomadFile,
+    from_git_config: impl FnOnce() -> anyhow::Result<Option<String>>,
+) -> anyhow::Result<(T, String)> {
+    let (value, source) = match (
+        matches.value_source(arg_name),
+        matches.remove_one::<String>(arg_name),
     ) {
-        (ValueSource::CommandLine | ValueSource::EnvVariable, value) => Ok(T::from(value)),
-        (_, value) => match from_git_config()? {
-            Some(git_value) => Ok(git_value),
-            None => Ok(T::from(value)),
+        (Some(ValueSource::CommandLine), Some(value)) => (value, format!("--{}", arg_name)),
+        (Some(ValueSource::EnvVariable), Some(value)) => {
+            (value, format!("the {} environment variable", env_name))
+        }
+        (_, default) => match nomad_file.get(arg_name) {
+            Some(file_value) => (
+                file_value.to_string(),
+                format!("{} in {}", arg_name, NOMAD_FILE),
+            ),
+            None => match from_git_config()? {
+                Some(git_value) => (git_value, format!("nomad.{} in git config", arg_name)),
+                None => match default {
+                    Some(default) => (default, "the operating system default".to_string()),
+                    None => anyhow::bail!(
+                        "Could not determine {name} automatically, \
+                         pass --{name} or set nomad.{name} with git config",
+                        name = arg_name,
+                    ),
+                },
+            },
         },
+    };
+
+    if let Err(problem) = validate(&value) {
+        anyhow::bail!(
+            "Invalid {} {:?} from {}, {}",
+            arg_name,
+            value,
+            source,
+            problem
+        );
     }
+
+    Ok((T::from(value), source))
 }
 
 /// End-to-end workflow tests.
 #[cfg(test)]
 mod test_e2e {
-    use std::{collections::HashSet, iter::FromIterator};
+    use std::{
+        collections::HashSet, ffi::OsString, fs, iter::FromIterator, os::unix::fs::PermissionsExt,
+    };
+
+    use tempfile::tempdir;
 
     use crate::{
+        cli,
+        git_binary::git_command,
         git_testing::{GitClone, GitRemote, INITIAL_BRANCH},
         nomad,
         renderer::test::{MemoryRenderer, NoRenderer},
-        types::Branch,
-        verbosity::Verbosity,
-        workflow::{Filter, Workflow},
+        specified_prune_confirm, specified_workflow,
+        types::{Branch, Host, NomadRef, Remote, User},
+        verbosity::{output_stdout, run_notable, Verbosity},
+        version,
+        workflow::{Filter, Outcome, PruneConfirm, SyncDirection, SyncOptions, Workflow},
+        BUILD_VERSION, GIT_VERSION,
     };
 
     fn sync_host(clone: &GitClone) {
         Workflow::Sync {
             user: clone.user.always_borrow(),
             host: clone.host.always_borrow(),
-            remote: clone.remote.always_borrow(),
+            remotes: vec![clone.remote.always_borrow()],
+            options: SyncOptions::default(),
+            branch_filter: Filter::All,
         }
         .execute(&mut NoRenderer, &clone.git)
         .unwrap();
@@ -497,7 +2574,9 @@ mod test_e2e {
         )
         .unwrap();
 
-        assert!(renderer.as_str().contains("complete -F _git-nomad -o"));
+        assert!(renderer
+            .as_str()
+            .contains("-o bashdefault -o default git-nomad"));
     }
 
     /// Invoking completions when there's no shell should error but not panic.
@@ -527,590 +2606,4403 @@ mod test_e2e {
             None,
         )
         .unwrap();
-        assert!(renderer.as_str().contains("complete -F _git-nomad -o"));
+        assert!(renderer
+            .as_str()
+            .contains("-o bashdefault -o default git-nomad"));
     }
 
-    /// Syncing should pick up nomad refs from other hosts.
-    ///
-    /// When the other host deletes their branch (and thus deletes their nomad ref on the remote),
-    /// the equivalent local nomad ref for that host should also be deleted.
-    ///
-    /// See https://github.com/rraval/git-nomad/issues/1
+    /// Nushell is not one of [`clap_complete::Shell`], but should still be supported, both
+    /// explicitly and implicitly from the shell path.
     #[test]
-    fn issue_1() {
+    fn nomad_completions_nushell() {
         let origin = GitRemote::init(None);
-        let feature = &Branch::from("feature");
 
-        let host0 = origin.clone("user0", "host0");
-        sync_host(&host0);
+        for (args, shell_path) in [
+            (&["git-nomad", "completions", "nushell"][..], None),
+            (
+                &["git-nomad", "completions"][..],
+                Some("/usr/bin/nu".into()),
+            ),
+        ] {
+            let mut renderer = MemoryRenderer::new();
+            nomad(&mut renderer, args, origin.working_directory(), shell_path).unwrap();
+            assert!(renderer.as_str().contains("export extern git-nomad"));
+        }
+    }
 
-        let host1 = origin.clone("user0", "host1");
-        host1
+    /// Elvish comes with [`clap_complete`], but check that it survived wrapping.
+    #[test]
+    fn nomad_completions_elvish() {
+        let origin = GitRemote::init(None);
+        let mut renderer = MemoryRenderer::new();
+        nomad(
+            &mut renderer,
+            ["git-nomad", "completions", "elvish"],
+            origin.working_directory(),
+            None,
+        )
+        .unwrap();
+        assert!(renderer
+            .as_str()
+            .contains("edit:completion:arg-completer[git-nomad]"));
+    }
+
+    /// `explain` should print the refspecs for the resolved user and host, and the remote ref for
+    /// every local branch.
+    #[test]
+    fn nomad_explain() {
+        let origin = GitRemote::init(None);
+        let host0 = origin.clone("user0", "host0");
+        host0
             .git
-            .create_branch(&mut NoRenderer, "Start feature branch", feature)
+            .create_branch(&mut NoRenderer, "", &Branch::from("feature"))
             .unwrap();
-        sync_host(&host1);
 
-        // both hosts have synced, the origin should have refs from both (including the one for the
-        // feature branch on host1)
+        let mut renderer = MemoryRenderer::new();
+        nomad(
+            &mut renderer,
+            ["git-nomad", "explain", "-U", "user0", "-H", "host0"],
+            host0.working_directory(),
+            None,
+        )
+        .unwrap();
+        // Spinners from reading local branches are followed by a blank line.
         assert_eq!(
-            origin.nomad_refs(),
-            HashSet::from_iter([
-                host0.get_nomad_ref(INITIAL_BRANCH).unwrap(),
-                host1.get_nomad_ref(INITIAL_BRANCH).unwrap(),
-                host1.get_nomad_ref("feature").unwrap(),
-            ])
+            renderer.as_str().trim_start(),
+            format!(
+                "push\t+refs/heads/*:refs/nomad/user0/host0/*\n\
+                 fetch\t+refs/nomad/user0/*:refs/nomad/*\n\
+                 list\trefs/nomad/user0/*\n\
+                 ref\trefs/nomad/user0/host0/feature\n\
+                 ref\trefs/nomad/user0/host0/{}\n",
+                INITIAL_BRANCH
+            ),
         );
+    }
 
-        // host0 hasn't observed host1 yet
-        assert_eq!(
-            host0.nomad_refs(),
-            HashSet::from_iter([host0.get_nomad_ref(INITIAL_BRANCH).unwrap(),])
-        );
-
-        // sync host0, which should observe host1 refs
-        sync_host(&host0);
-        assert_eq!(
-            host0.nomad_refs(),
-            HashSet::from_iter([
-                host0.get_nomad_ref(INITIAL_BRANCH).unwrap(),
-                host1.get_nomad_ref(INITIAL_BRANCH).unwrap(),
-                host1.get_nomad_ref("feature").unwrap(),
-            ])
-        );
+    /// `prompt` should reflect whether the current branch matches its nomad ref, using only local
+    /// state.
+    #[test]
+    fn nomad_prompt() {
+        let origin = GitRemote::init(None);
+        let host0 = origin.clone("user0", "host0");
 
-        // host1 deletes the branch and syncs, removing it from origin
-        host1
-            .git
-            .delete_branch(&mut NoRenderer, "Abandon feature branch", feature)
+        let prompt = || {
+            let mut renderer = MemoryRenderer::new();
+            nomad(
+                &mut renderer,
+                ["git-nomad", "prompt", "-U", "user0", "-H", "host0"],
+                host0.working_directory(),
+                None,
+            )
             .unwrap();
-        sync_host(&host1);
+            renderer.as_str().to_string()
+        };
+        let git = |args: &[&str]| {
+            run_notable(
+                &mut NoRenderer,
+                None,
+                "",
+                git_command("git")
+                    .current_dir(host0.working_directory())
+                    .args(args),
+            )
+            .unwrap();
+        };
 
-        assert_eq!(
-            origin.nomad_refs(),
-            HashSet::from_iter([
-                host0.get_nomad_ref(INITIAL_BRANCH).unwrap(),
-                host1.get_nomad_ref(INITIAL_BRANCH).unwrap(),
-            ])
-        );
+        assert_eq!(prompt(), "↑\n", "never synced");
 
-        // host0 syncs and removes the ref for the deleted feature branch
         sync_host(&host0);
-        assert_eq!(
-            host0.nomad_refs(),
-            HashSet::from_iter([
-                host0.get_nomad_ref(INITIAL_BRANCH).unwrap(),
-                host1.get_nomad_ref(INITIAL_BRANCH).unwrap(),
-            ])
-        );
+        assert_eq!(prompt(), "=\n", "synced");
+
+        git(&["commit", "--allow-empty", "-m", "commit1"]);
+        assert_eq!(prompt(), "↑\n", "committed since sync");
+
+        git(&["checkout", "--detach"]);
+        assert_eq!(prompt(), "", "detached HEAD");
+
+        git(&["checkout", "--orphan", "unborn"]);
+        assert_eq!(prompt(), "", "unborn branch");
     }
 
-    /// Explicitly pruning other hosts should delete both local and remote nomad refs for that
-    /// host.
-    ///
-    /// See https://github.com/rraval/git-nomad/issues/2
+    /// `fetch` should make other hosts' refs available locally without pushing anything, and say
+    /// nothing about it under `--quiet`.
     #[test]
-    fn issue_2_other_host() {
+    fn nomad_fetch() {
         let origin = GitRemote::init(None);
-
         let host0 = origin.clone("user0", "host0");
-        sync_host(&host0);
-
         let host1 = origin.clone("user0", "host1");
-        sync_host(&host1);
+        sync_host(&host0);
 
-        // both hosts have synced, the origin should have both refs
-        assert_eq!(
-            origin.nomad_refs(),
-            HashSet::from_iter([
-                host0.get_nomad_ref(INITIAL_BRANCH).unwrap(),
-                host1.get_nomad_ref(INITIAL_BRANCH).unwrap(),
-            ])
-        );
+        let remote_refs = origin.nomad_refs();
+        assert!(host1.nomad_refs().is_empty());
 
-        // pruning refs for host0 from host1
-        Workflow::Purge {
-            user: host1.user.always_borrow(),
-            remote: host1.remote.always_borrow(),
-            host_filter: Filter::Allow(HashSet::from_iter([host0.host.always_borrow()])),
-        }
-        .execute(&mut NoRenderer, &host1.git)
+        let mut renderer = MemoryRenderer::new();
+        nomad(
+            &mut renderer,
+            [
+                "git-nomad",
+                "fetch",
+                "--quiet",
+                "-U",
+                "user0",
+                "-H",
+                "host1",
+            ],
+            host1.working_directory(),
+            None,
+        )
         .unwrap();
 
-        // the origin should only have refs for host1
-        assert_eq!(
-            origin.nomad_refs(),
-            HashSet::from_iter([host1.get_nomad_ref(INITIAL_BRANCH).unwrap(),])
-        );
+        assert_eq!(renderer.as_str(), "");
+        assert_eq!(host1.nomad_refs(), host0.nomad_refs());
+        assert_eq!(origin.nomad_refs(), remote_refs);
     }
 
-    /// Explicitly pruning everything should delete both local and remote refs for both the current
-    /// and other host on the remote.
-    ///
-    /// See https://github.com/rraval/git-nomad/issues/2
+    /// `--git-dir` should be used as is, without asking git where the `.git` directory is.
     #[test]
-    fn issue_2_all() {
-        let origin = GitRemote::init(Some(Verbosity::max()));
-
+    fn nomad_git_dir() {
+        let origin = GitRemote::init(None);
         let host0 = origin.clone("user0", "host0");
-        sync_host(&host0);
 
-        let host1 = origin.clone("user0", "host1");
-        sync_host(&host1);
+        let wrapper_dir = tempdir().unwrap();
+        let wrapper = wrapper_dir.path().join("git");
+        fs::write(
+            &wrapper,
+            "#!/bin/sh\necho \"$*\" >> \"$0.calls\"\nexec git \"$@\"\n",
+        )
+        .unwrap();
+        fs::set_permissions(&wrapper, fs::Permissions::from_mode(0o755)).unwrap();
+        let calls_path = wrapper.with_extension("calls");
 
-        // both hosts have synced, the origin should have both refs
-        assert_eq!(
-            origin.nomad_refs(),
-            HashSet::from_iter([
-                host0.get_nomad_ref(INITIAL_BRANCH).unwrap(),
-                host1.get_nomad_ref(INITIAL_BRANCH).unwrap(),
-            ])
-        );
+        let ls = |git_dir: Option<&str>| {
+            let _ = fs::remove_file(&calls_path);
+            let mut args = vec!["git-nomad", "--git", wrapper.to_str().unwrap()];
+            if let Some(git_dir) = git_dir {
+                args.extend(["--git-dir", git_dir]);
+            }
+            args.push("ls");
+            let result = nomad(&mut NoRenderer, args, host0.working_directory(), None);
+            (result, fs::read_to_string(&calls_path).unwrap_or_default())
+        };
 
-        // pruning refs for all hosts from host1
-        Workflow::Purge {
-            user: host1.user.always_borrow(),
-            remote: host1.remote,
-            host_filter: Filter::All,
+        let (result, calls) = ls(None);
+        result.unwrap();
+        assert!(calls.contains("rev-parse --absolute-git-dir"), "{}", calls);
+
+        let absolute = host0.working_directory().join(".git");
+        for git_dir in [absolute.to_str().unwrap(), ".git"] {
+            let (result, calls) = ls(Some(git_dir));
+            result.unwrap();
+            assert!(!calls.is_empty());
+            assert!(!calls.contains("rev-parse"), "{}", calls);
         }
-        .execute(&mut NoRenderer, &host1.git)
-        .unwrap();
 
-        // the origin should have no refs
-        assert_eq!(origin.nomad_refs(), HashSet::new(),);
+        let (result, calls) = ls(Some("not-a-git-dir"));
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            format!(
+                "{} is not a .git directory",
+                host0.working_directory().join("not-a-git-dir").display()
+            ),
+        );
+        assert!(calls.is_empty());
     }
-}
-
-/// CLI invocation tests
-#[cfg(test)]
-mod test_cli {
-    use std::{collections::HashSet, iter::FromIterator};
-
-    use clap::{error::ErrorKind, ArgMatches};
 
-    use crate::{
-        cli,
-        git_testing::GitRemote,
-        renderer::test::NoRenderer,
-        specified_git, specified_verbosity, specified_workflow,
-        types::{Branch, Host, Remote, User},
-        verbosity::Verbosity,
-        workflow::{Filter, LsPrinter, Workflow},
-        CONFIG_HOST, CONFIG_USER, DEFAULT_REMOTE,
-    };
+    /// `version` should work outside of a git repository.
+    #[test]
+    fn nomad_version() {
+        let cwd = tempdir().unwrap();
+        let mut renderer = MemoryRenderer::new();
+        nomad(&mut renderer, ["git-nomad", "version"], cwd.path(), None).unwrap();
+        assert_eq!(renderer.as_str(), format!("{}\n", version()));
+    }
 
-    struct CliTest {
-        default_user: User<'static>,
-        default_host: Host<'static>,
+    /// `version --json` should print a stable JSON object for tooling.
+    #[test]
+    fn nomad_version_json() {
+        let cwd = tempdir().unwrap();
+        let mut renderer = MemoryRenderer::new();
+        nomad(
+            &mut renderer,
+            ["git-nomad", "version", "--json"],
+            cwd.path(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            renderer.as_str(),
+            format!(
+                "{{\"version\":{:?},\"git_version\":{:?},\"build_version\":{}}}\n",
+                version(),
+                GIT_VERSION,
+                BUILD_VERSION.map_or("null".to_string(), |v| format!("{:?}", v)),
+            ),
+        );
     }
 
-    impl CliTest {
-        fn default_host_filter(&self) -> Filter<Host> {
-            Filter::Deny([self.default_host.always_borrow()].into())
-        }
+    /// `run` should execute the workflow described in the given file.
+    #[test]
+    fn nomad_run() {
+        let origin = GitRemote::init(None);
+        let host0 = origin.clone("user0", "host0");
 
-        fn matches(&self, args: &[&str]) -> clap::error::Result<ArgMatches> {
-            let mut vec = vec!["git-nomad"];
-            vec.extend_from_slice(args);
-            cli(
-                Some(self.default_user.clone()),
-                Some(self.default_host.clone()),
-                &vec,
-            )
-        }
+        let workflow_dir = tempdir().unwrap();
+        let workflow_path = workflow_dir.path().join("workflow.toml");
+        fs::write(
+            &workflow_path,
+            "[sync]\nuser = \"user0\"\nhost = \"host0\"\nremote = \"origin\"\n",
+        )
+        .unwrap();
 
-        fn remote(&self, args: &[&str]) -> CliTestRemote {
-            CliTestRemote {
-                matches: self.matches(args).unwrap(),
-                remote: GitRemote::init(Some(Verbosity::max())),
-            }
-        }
-    }
+        nomad(
+            &mut NoRenderer,
+            [
+                OsString::from("git-nomad"),
+                "run".into(),
+                workflow_path.into_os_string(),
+            ],
+            host0.working_directory(),
+            None,
+        )
+        .unwrap();
 
-    struct CliTestRemote {
-        matches: ArgMatches,
-        remote: GitRemote,
+        assert_eq!(
+            origin.nomad_refs(),
+            HashSet::from_iter([host0.get_nomad_ref(INITIAL_BRANCH).unwrap()])
+        );
     }
 
-    impl CliTestRemote {
-        fn set_config(&mut self, key: &str, value: &str) -> &mut Self {
-            self.remote
-                .git
-                .set_config(&mut NoRenderer, key, value)
-                .unwrap();
-            self
-        }
+    /// `--quiet-errors` should summarize git command failures in a single line, unless verbose.
+    #[test]
+    fn nomad_quiet_errors() {
+        let origin = GitRemote::init(None);
+        // A path rather than a name, so that git itself fails instead of the unknown remote check.
+        let run = |extra_args: &[&str]| {
+            let mut args = vec!["git-nomad", "sync", "--remote", "/does-not-exist"];
+            args.extend_from_slice(extra_args);
+            nomad(&mut NoRenderer, args, origin.working_directory(), None)
+                .unwrap_err()
+                .to_string()
+        };
 
-        fn workflow(&mut self) -> Workflow<'_> {
-            specified_workflow(&mut NoRenderer, &mut self.matches, &self.remote.git, None).unwrap()
-        }
-    }
+        let summary = run(&["--quiet-errors"]);
+        assert!(summary.starts_with("Pushing local branches to /does-not-exist failed: "));
+        assert!(!summary.contains('\n'));
 
-    impl Default for CliTest {
-        fn default() -> Self {
-            Self {
-                default_user: User::from("default_user"),
-                default_host: Host::from("default_host"),
-            }
+        for args in [&[] as &[&str], &["--quiet-errors", "-v"]] {
+            let dump = run(args);
+            assert!(dump.contains("command failure"));
+            assert!(dump.contains("# ---- STDERR ----"));
         }
     }
 
-    /// Should print help and stop processing if no subcommand is specified.
+    /// A remote name that is not configured should be named in the error, along with the remotes
+    /// that are, before git gets a chance to fail.
     #[test]
-    fn subcommand_is_required() {
-        let cli_test = CliTest::default();
-        let matches = cli_test.matches(&[]);
-        assert!(matches.is_err());
-        assert_eq!(
-            match matches {
-                Ok(_) => unreachable!(),
-                Err(e) => e.kind(),
-            },
-            ErrorKind::DisplayHelpOnMissingArgumentOrSubcommand,
-        );
-    }
+    fn nomad_unknown_remote() {
+        let origin = GitRemote::init(None);
+        let backup = GitRemote::init(None);
+        let host0 = origin.clone("user0", "host0");
+        host0.add_remote("backup", &backup);
 
-    /// `--git` before/after the subcommand.
-    #[test]
-    fn git_option() {
-        for args in &[&["--git", "foo", "ls"], &["ls", "--git", "foo"]] {
-            println!("{:?}", args);
-            let cli_test = CliTest::default();
-            let mut matches = cli_test.matches(*args).unwrap();
-            assert_eq!(specified_git(&mut matches), "foo");
+        for args in [&["sync"] as &[&str], &["ls", "--fetch"], &["fetch"]] {
+            let mut full_args = vec!["git-nomad", "-U", "user0", "-H", "host0", "-R", "orgin"];
+            full_args.extend_from_slice(args);
+            let error =
+                nomad(&mut NoRenderer, full_args, host0.working_directory(), None).unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                "Unknown remote 'orgin'; known remotes: backup, origin",
+                "{:?}",
+                args
+            );
         }
+        assert!(origin.nomad_refs().is_empty());
     }
 
+    /// `--check-remote` should explain what is wrong with the remote before doing anything.
     #[test]
-    fn quiet_verbosity() {
-        for args in &[
-            &["--quiet", "ls"],
-            &["-q", "ls"],
-            &["ls", "--quiet"],
-            &["ls", "-q"],
-        ] {
-            println!("{:?}", args);
-            let cli_test = CliTest::default();
-            let mut matches = cli_test.matches(*args).unwrap();
-            assert_eq!(specified_verbosity(&mut matches), None);
-        }
+    fn nomad_check_remote() {
+        let origin = GitRemote::init(None);
+        let host0 = origin.clone("user0", "host0");
+        let error = nomad(
+            &mut NoRenderer,
+            [
+                "git-nomad",
+                "sync",
+                "--check-remote",
+                "--remote",
+                "does-not-exist",
+            ],
+            host0.working_directory(),
+            None,
+        )
+        .unwrap_err()
+        .to_string();
+        assert_eq!(error, "Remote does-not-exist has no git repository");
+
+        nomad(
+            &mut NoRenderer,
+            ["git-nomad", "sync", "--check-remote"],
+            host0.working_directory(),
+            None,
+        )
+        .unwrap();
     }
 
+    /// `sync-all` should sync every listed repository, keep going past ones that fail, and
+    /// summarize how it went.
     #[test]
-    fn default_verbosity() {
-        let cli_test = CliTest::default();
-        let mut matches = cli_test.matches(&["ls"]).unwrap();
+    fn nomad_sync_all() {
+        let origin0 = GitRemote::init(None);
+        let origin1 = GitRemote::init(None);
+        let host0 = origin0.clone("user0", "host0");
+        let host1 = origin1.clone("user0", "host0");
+
+        let config_dir = tempdir().unwrap();
+        let config_path = config_dir.path().join("repos.toml");
+        fs::write(
+            &config_path,
+            format!(
+                "repos = [{:?}, \"does-not-exist\", {:?}]\n",
+                host0.working_directory(),
+                host1.working_directory(),
+            ),
+        )
+        .unwrap();
+
+        let mut renderer = MemoryRenderer::new();
+        let error = nomad(
+            &mut renderer,
+            [
+                OsString::from("git-nomad"),
+                "--user".into(),
+                "user0".into(),
+                "--host".into(),
+                "host0".into(),
+                "sync-all".into(),
+                config_path.into_os_string(),
+            ],
+            config_dir.path(),
+            None,
+        )
+        .unwrap_err()
+        .to_string();
+
+        let missing = config_dir.path().join("does-not-exist");
         assert_eq!(
-            specified_verbosity(&mut matches),
-            Some(Verbosity::default())
+            error,
+            format!("Failed to sync 1 of 3 repositories: {}", missing.display()),
+        );
+        assert!(renderer
+            .as_str()
+            .contains(&format!("Failed to sync {}: ", missing.display())));
+        assert!(renderer.as_str().ends_with("Synced 2 of 3 repositories\n"));
+
+        assert_eq!(
+            origin0.nomad_refs(),
+            HashSet::from_iter([host0.get_nomad_ref(INITIAL_BRANCH).unwrap()])
+        );
+        assert_eq!(
+            origin1.nomad_refs(),
+            HashSet::from_iter([host1.get_nomad_ref(INITIAL_BRANCH).unwrap()])
         );
     }
 
+    /// `sync --prune never` should keep the refs for deleted branches until a regular sync.
     #[test]
-    fn verbose_verbosity() {
+    fn nomad_sync_prune_never() {
+        let origin = GitRemote::init(None);
+        let feature = &Branch::from("feature");
+        let host0 = origin.clone("user0", "host0");
+        host0
+            .git
+            .create_branch(&mut NoRenderer, "Start feature branch", feature)
+            .unwrap();
+        let feature_ref = host0.get_nomad_ref("feature").unwrap();
+
+        let sync = |extra_args: &[&str]| {
+            let mut args = vec!["git-nomad", "-U", "user0", "-H", "host0", "sync"];
+            args.extend_from_slice(extra_args);
+            nomad(&mut NoRenderer, args, host0.working_directory(), None).unwrap();
+        };
+
+        sync(&[]);
+        host0
+            .git
+            .delete_branch(&mut NoRenderer, "Abandon feature branch", feature)
+            .unwrap();
+
+        sync(&["--prune", "never"]);
+        assert!(origin.nomad_refs().contains(&feature_ref));
+        assert!(host0.nomad_refs().contains(&feature_ref));
+
+        sync(&[]);
+        assert_eq!(
+            origin.nomad_refs(),
+            HashSet::from_iter([host0.get_nomad_ref(INITIAL_BRANCH).unwrap()])
+        );
+        assert_eq!(
+            host0.nomad_refs(),
+            HashSet::from_iter([host0.get_nomad_ref(INITIAL_BRANCH).unwrap()])
+        );
+    }
+
+    /// `--progress-fd` should write JSON lines to the given descriptor, and refuse one that is not
+    /// open.
+    #[test]
+    fn nomad_progress_fd() {
+        use std::os::fd::IntoRawFd;
+
+        let origin = GitRemote::init(None);
+        let host0 = origin.clone("user0", "host0");
+        let progress_dir = tempfile::tempdir().unwrap();
+        let progress_path = progress_dir.path().join("progress");
+        let fd = fs::File::create(&progress_path).unwrap().into_raw_fd();
+
+        nomad(
+            &mut NoRenderer,
+            [
+                "git-nomad",
+                "-U",
+                "user0",
+                "-H",
+                "host0",
+                "sync",
+                "--progress-fd",
+                &fd.to_string(),
+            ],
+            host0.working_directory(),
+            None,
+        )
+        .unwrap();
+
+        let phases = fs::read_to_string(&progress_path)
+            .unwrap()
+            .lines()
+            .map(|line| {
+                let event = serde_json::from_str::<serde_json::Value>(line).unwrap();
+                event["phase"].as_str().unwrap().to_string()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(phases.first().map(String::as_str), Some("push"));
+        assert!(phases.contains(&"fetch".to_string()));
+        assert_eq!(phases.last().map(String::as_str), Some("complete"));
+
+        let error = nomad(
+            &mut NoRenderer,
+            ["git-nomad", "sync", "--progress-fd", "999999"],
+            host0.working_directory(),
+            None,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "--progress-fd 999999 is not an open file descriptor"
+        );
+
+        // SAFETY: Only the descriptor opened above is closed, and nothing else uses it.
+        assert_eq!(unsafe { libc::close(fd) }, 0, "fd should still be open");
+    }
+
+    /// `--progress-fd 2` should write to a duplicate of stderr instead of closing stderr itself
+    /// once done.
+    #[test]
+    fn nomad_progress_fd_stderr() {
+        let origin = GitRemote::init(None);
+        let host0 = origin.clone("user0", "host0");
+
+        nomad(
+            &mut NoRenderer,
+            [
+                "git-nomad",
+                "-U",
+                "user0",
+                "-H",
+                "host0",
+                "sync",
+                "--progress-fd",
+                "2",
+            ],
+            host0.working_directory(),
+            None,
+        )
+        .unwrap();
+
+        // SAFETY: `F_GETFD` only queries the descriptor.
+        assert_ne!(unsafe { libc::fcntl(2, libc::F_GETFD) }, -1);
+    }
+
+    /// `--no-progress` should drop the spinners from `sync` but keep the listing and summary.
+    #[test]
+    fn nomad_no_progress() {
+        let origin = GitRemote::init(None);
+        let host0 = origin.clone("user0", "host0");
+
+        let sync = |extra_args: &[&str]| {
+            let mut renderer = MemoryRenderer::new();
+            let mut args = vec!["git-nomad", "-U", "user0", "-H", "host0", "sync"];
+            args.extend_from_slice(extra_args);
+            nomad(&mut renderer, args, host0.working_directory(), None).unwrap();
+            renderer.as_str().to_string()
+        };
+
+        let output = sync(&[]);
+        assert!(output.contains("Pushing"), "{}", output);
+
+        let output = sync(&["--no-progress"]);
+        assert!(!output.contains("..."), "{}", output);
+        assert!(
+            output.contains(&format!("refs/nomad/host0/{}", INITIAL_BRANCH)),
+            "{}",
+            output
+        );
+        assert!(output.contains("Pushed "), "{}", output);
+    }
+
+    /// `--output` should write exactly what would otherwise be printed, relative to the working
+    /// directory, and refuse a path that cannot be created.
+    #[test]
+    fn nomad_output() {
+        let origin = GitRemote::init(None);
+        let host0 = origin.clone("user0", "host0");
+        sync_host(&host0);
+        let host1 = origin.clone("user0", "host1");
+        sync_host(&host1);
+
+        let ls = |renderer: &mut MemoryRenderer, extra_args: &[&str]| {
+            let mut args = vec!["git-nomad", "-U", "user0", "-H", "host1", "ls"];
+            args.extend_from_slice(extra_args);
+            nomad(renderer, args, host1.working_directory(), None).map(|_| ())
+        };
+
+        let mut expected = MemoryRenderer::new();
+        ls(&mut expected, &[]).unwrap();
+        assert!(expected.as_str().contains("host0"));
+
+        let mut renderer = MemoryRenderer::new();
+        ls(&mut renderer, &["--output", "nomad-ls.txt"]).unwrap();
+        assert_eq!(renderer.as_str(), "");
+        assert_eq!(
+            fs::read_to_string(host1.working_directory().join("nomad-ls.txt")).unwrap(),
+            expected.as_str(),
+        );
+
+        let error = ls(
+            &mut MemoryRenderer::new(),
+            &["--output", "does/not/exist.txt"],
+        )
+        .unwrap_err();
+        assert!(
+            error.to_string().starts_with("creating --output "),
+            "{}",
+            error
+        );
+    }
+
+    /// `--color always` should bold hosts and dim commits in grouped `ls`, even when not writing
+    /// to a terminal, while the default leaves output plain.
+    #[test]
+    fn nomad_color() {
+        let origin = GitRemote::init(None);
+        let host0 = origin.clone("user0", "host0");
+        host0.push();
+        let host1 = origin.clone("user0", "host1");
+        host1.fetch();
+
+        let ls = |extra_args: &[&str]| {
+            let mut args = vec!["git-nomad", "-U", "user0", "-H", "host1", "ls"];
+            args.extend(extra_args);
+            let mut renderer = MemoryRenderer::new();
+            nomad(&mut renderer, args, host1.working_directory(), None).unwrap();
+            renderer.as_str().to_string()
+        };
+
+        let commit_id = host0.current_commit().0;
+        assert_eq!(
+            ls(&["--color", "always"]),
+            format!(
+                "\u{1b}[1mhost0\u{1b}[0m\n  refs/nomad/host0/master -> \u{1b}[2m{}\u{1b}[0m (ahead 0, behind 0)\n",
+                commit_id
+            ),
+        );
+
+        let plain = format!(
+            "host0\n  refs/nomad/host0/master -> {} (ahead 0, behind 0)\n",
+            commit_id
+        );
+        assert_eq!(ls(&[]), plain);
+        assert_eq!(ls(&["--color", "always", "--no-color"]), plain);
+    }
+
+    /// `sync --include-tags` should round trip tags through their own namespace, separate from a
+    /// branch of the same name, and prune them once deleted.
+    #[test]
+    fn nomad_include_tags() {
+        let origin = GitRemote::init(None);
+        let host0 = origin.clone("user0", "host0");
+        let host1 = origin.clone("user0", "host1");
+
+        let git = |clone: &GitClone, args: &[&str]| {
+            run_notable(&mut NoRenderer, None, "", clone.git.command().args(args)).unwrap();
+        };
+        let nomad_in = |clone: &GitClone, host: &str, args: &[&str]| {
+            let mut argv = vec!["git-nomad", "-U", "user0", "-H", host];
+            argv.extend(args);
+            let mut renderer = MemoryRenderer::new();
+            nomad(&mut renderer, argv, clone.working_directory(), None).unwrap();
+            renderer.as_str().to_string()
+        };
+        let remote_tags = || {
+            host0
+                .git
+                .for_tags()
+                .list_nomad_refs(&mut NoRenderer, &host0.user, &host0.remote)
+                .unwrap()
+                .map(|nomad_ref| nomad_ref.unwrap().ref_.name)
+                .collect::<Vec<_>>()
+        };
+
+        git(&host0, &["tag", "v1"]);
+        git(&host0, &["branch", "v1"]);
+        nomad_in(&host0, "host0", &["sync"]);
+        assert_eq!(remote_tags(), Vec::<String>::new());
+
+        nomad_in(&host0, "host0", &["sync", "--include-tags"]);
+        assert_eq!(remote_tags(), vec!["refs/nomad-tags/user0/host0/v1"]);
+
+        nomad_in(&host1, "host1", &["sync", "--include-tags"]);
+        let commit_id = host0.current_commit().0;
+        assert_eq!(
+            nomad_in(&host1, "host1", &["ls", "--tags"]),
+            format!("host0\n  refs/nomad-tags/host0/v1 -> {}\n", commit_id),
+        );
+        assert_eq!(
+            nomad_in(&host1, "host1", &["ls", "--print=ref"]),
+            "refs/nomad/host0/master\nrefs/nomad/host0/v1\n",
+        );
+
+        git(&host0, &["tag", "-d", "v1"]);
+        nomad_in(&host0, "host0", &["sync", "--include-tags"]);
+        assert_eq!(remote_tags(), Vec::<String>::new());
+
+        nomad_in(&host1, "host1", &["sync", "--include-tags"]);
+        assert_eq!(nomad_in(&host1, "host1", &["ls", "--tags"]), "");
+        assert_eq!(
+            nomad_in(&host1, "host1", &["ls", "--print=ref"]),
+            "refs/nomad/host0/master\nrefs/nomad/host0/v1\n",
+        );
+    }
+
+    /// `sync --tracking-refs` should copy other hosts' branches to `refs/remotes/nomad`, and drop
+    /// the copies once the branches are pruned.
+    #[test]
+    fn nomad_tracking_refs() {
+        let origin = GitRemote::init(None);
+        let host0 = origin.clone("user0", "host0");
+        let host1 = origin.clone("user0", "host1");
+
+        let sync = |clone: &GitClone, host: &str| {
+            let args = vec![
+                "git-nomad",
+                "-U",
+                "user0",
+                "-H",
+                host,
+                "sync",
+                "--tracking-refs",
+            ];
+            nomad(&mut NoRenderer, args, clone.working_directory(), None).unwrap();
+        };
+        let tracking_refs = || {
+            run_notable(
+                &mut NoRenderer,
+                None,
+                "",
+                host1.git.command().args([
+                    "for-each-ref",
+                    "--format=%(refname)",
+                    "refs/remotes/nomad",
+                ]),
+            )
+            .and_then(output_stdout)
+            .unwrap()
+        };
+
+        host0
+            .git
+            .create_branch(&mut NoRenderer, "", &Branch::from("feature"))
+            .unwrap();
+        sync(&host0, "host0");
+        sync(&host1, "host1");
+        assert_eq!(
+            tracking_refs(),
+            "refs/remotes/nomad/host0/feature\nrefs/remotes/nomad/host0/master\n",
+        );
+
+        host0
+            .git
+            .delete_branch(&mut NoRenderer, "", &Branch::from("feature"))
+            .unwrap();
+        sync(&host0, "host0");
+        sync(&host1, "host1");
+        assert_eq!(tracking_refs(), "refs/remotes/nomad/host0/master\n");
+    }
+
+    /// `checkout` should fetch and switch to a branch pushed by another host, and explain when the
+    /// branch is not tracked.
+    #[test]
+    fn nomad_checkout() {
+        let origin = GitRemote::init(None);
+        let host0 = origin.clone("user0", "host0");
+        let host1 = origin.clone("user0", "host1");
+
+        let git = |clone: &GitClone, args: &[&str]| {
+            run_notable(&mut NoRenderer, None, "", clone.git.command().args(args))
+                .and_then(output_stdout)
+                .unwrap()
+        };
+        git(&host0, &["checkout", "-b", "feature"]);
+        git(&host0, &["commit", "--allow-empty", "-m", "feature work"]);
+        host0.push();
+        let feature_commit = host0.current_commit();
+
+        let checkout = |host: &str, branch: &str| {
+            let args = vec![
+                "git-nomad",
+                "-U",
+                "user0",
+                "-H",
+                "host1",
+                "checkout",
+                host,
+                branch,
+            ];
+            let mut renderer = MemoryRenderer::new();
+            nomad(&mut renderer, args, host1.working_directory(), None)
+                .map(|_| renderer.as_str().to_string())
+        };
+
+        let output = checkout("host0", "feature").unwrap();
+        assert!(
+            output.ends_with(&format!(
+                "\nChecked out {} as host0/feature\n",
+                feature_commit.0
+            )),
+            "{:?}",
+            output
+        );
+        assert_eq!(host1.current_commit(), feature_commit);
+        assert_eq!(
+            host1.git.current_branch(&mut NoRenderer).unwrap(),
+            Branch::from("host0/feature"),
+        );
+
+        let error = checkout("host0", "missing").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "host0 has no branch missing at origin, see `git nomad ls` for what is tracked",
+        );
+    }
+
+    /// `--exit-code` should tell a sync that pushed apart from one with nothing to do, while syncs
+    /// without it always report success.
+    #[test]
+    fn nomad_exit_code() {
+        let origin = GitRemote::init(None);
+        let host0 = origin.clone("user0", "host0");
+
+        let sync = |extra_args: &[&str]| {
+            let mut args = vec!["git-nomad", "-U", "user0", "-H", "host0", "sync"];
+            args.extend(extra_args);
+            nomad(&mut NoRenderer, args, host0.working_directory(), None).unwrap()
+        };
+
+        assert_eq!(sync(&["--exit-code"]), Outcome::Changed);
+        assert_eq!(sync(&["--exit-code"]), Outcome::Unchanged);
+        assert_eq!(sync(&[]), Outcome::Changed);
+
+        host0
+            .git
+            .create_branch(&mut NoRenderer, "", &Branch::from("feature"))
+            .unwrap();
+        assert_eq!(sync(&["--exit-code", "--dry-run"]), Outcome::Unchanged);
+        assert_eq!(sync(&["--exit-code"]), Outcome::Changed);
+
+        let purge = |extra_args: &[&str]| {
+            let mut args = vec!["git-nomad", "-U", "user0", "-H", "host0", "purge", "--yes"];
+            args.extend(extra_args);
+            nomad(&mut NoRenderer, args, host0.working_directory(), None).unwrap()
+        };
+        assert_eq!(purge(&["--exit-code"]), Outcome::Changed);
+        assert_eq!(purge(&["--exit-code"]), Outcome::Unchanged);
+    }
+
+    /// `--local-remote` should create the bare repository on first use, and then behave like any
+    /// other remote.
+    #[test]
+    fn nomad_local_remote() {
+        let origin = GitRemote::init(None);
+        let host0 = origin.clone("user0", "host0");
+        let host1 = origin.clone("user0", "host1");
+
+        let local_dir = tempdir().unwrap();
+        let local_remote = local_dir.path().join("nomad.git");
+        let sync = |clone: &GitClone, extra_args: &[&str]| {
+            let mut args = vec![
+                "git-nomad",
+                "-U",
+                "user0",
+                "-H",
+                clone.host.0.as_ref(),
+                "--local-remote",
+                local_remote.to_str().unwrap(),
+            ];
+            args.extend_from_slice(extra_args);
+            args.push("sync");
+            nomad(&mut NoRenderer, args, clone.working_directory(), None)
+        };
+
+        sync(&host0, &[]).unwrap();
+        assert!(local_remote.join("HEAD").is_file());
+        sync(&host1, &[]).unwrap();
+        sync(&host0, &[]).unwrap();
+
+        let expected = HashSet::from_iter([
+            host0.get_nomad_ref(INITIAL_BRANCH).unwrap(),
+            host1.get_nomad_ref(INITIAL_BRANCH).unwrap(),
+        ]);
+        assert_eq!(host0.nomad_refs(), expected);
+        assert_eq!(host1.nomad_refs(), expected);
+        assert_eq!(origin.nomad_refs(), HashSet::new());
+
+        let error = sync(&host0, &["--remote", "origin"]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "--local-remote replaces --remote, pass only one of them"
+        );
+
+        let not_a_repo = local_dir.path().join("not-a-repo");
+        fs::create_dir(&not_a_repo).unwrap();
+        let error = nomad(
+            &mut NoRenderer,
+            [
+                OsString::from("git-nomad"),
+                "--local-remote".into(),
+                not_a_repo.clone().into_os_string(),
+                "sync".into(),
+            ],
+            host0.working_directory(),
+            None,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "{} exists but is not a bare git repository",
+                not_a_repo.display()
+            ),
+        );
+    }
+
+    /// `config host` should land in the repository's own config, where `config show` and every
+    /// other command pick it up.
+    #[test]
+    fn nomad_config() {
+        let origin = GitRemote::init(None);
+        let host0 = origin.clone("user0", "host0");
+
+        let config = |args: &[&str]| {
+            let mut renderer = MemoryRenderer::new();
+            let mut full_args = vec!["git-nomad", "-U", "user0", "config"];
+            full_args.extend_from_slice(args);
+            nomad(&mut renderer, full_args, host0.working_directory(), None).unwrap();
+            renderer.as_str().to_string()
+        };
+
+        config(&["host", "laptop"]);
+        let local = run_notable(
+            &mut NoRenderer,
+            None,
+            "",
+            host0
+                .git
+                .command()
+                .args(["config", "--local", "nomad.host"]),
+        )
+        .and_then(output_stdout)
+        .unwrap();
+        assert_eq!(local, "laptop\n");
+
+        assert_eq!(
+            config(&["show"]),
+            "user: user0, from --user\nhost: laptop, from nomad.host in git config\nlast sync: never\n"
+        );
+    }
+
+    /// `sync` should list the branches from other hosts that it fetched for the first time, but
+    /// not the ones it already knew about or the ones it just pushed itself.
+    #[test]
+    fn nomad_sync_new_branches() {
+        let origin = GitRemote::init(None);
+        let host0 = origin.clone("user0", "host0");
+        let host1 = origin.clone("user0", "host1");
+
+        let sync = |clone: &GitClone, host: &str| {
+            let mut renderer = MemoryRenderer::new();
+            nomad(
+                &mut renderer,
+                ["git-nomad", "-U", "user0", "-H", host, "sync"],
+                clone.working_directory(),
+                None,
+            )
+            .unwrap();
+            renderer.as_str().to_string()
+        };
+
+        let output = sync(&host0, "host0");
+        assert!(!output.contains("New branches:"), "{}", output);
+
+        host1
+            .git
+            .create_branch(&mut NoRenderer, "", &Branch::from("feature"))
+            .unwrap();
+        sync(&host1, "host1");
+
+        let feature = host1.get_nomad_ref("feature").unwrap();
+        let output = sync(&host0, "host0");
+        assert!(
+            output.contains(&format!(
+                "New branches:\n  host1/feature -> {}\n  host1/{} -> ",
+                feature.ref_.0, INITIAL_BRANCH,
+            )),
+            "{}",
+            output
+        );
+
+        let output = sync(&host0, "host0");
+        assert!(!output.contains("New branches:"), "{}", output);
+    }
+
+    /// A successful `sync` should remember when it happened, which the next `sync` and `config
+    /// show` report, while a failed one leaves the time alone.
+    #[test]
+    fn nomad_last_sync() {
+        let origin = GitRemote::init(None);
+        let host0 = origin.clone("user0", "host0");
+
+        let run = |args: &[&str]| {
+            let mut renderer = MemoryRenderer::new();
+            let mut full_args = vec!["git-nomad", "-U", "user0", "-H", "host0"];
+            full_args.extend_from_slice(args);
+            nomad(&mut renderer, full_args, host0.working_directory(), None)
+                .map(|_| renderer.as_str().to_string())
+        };
+        let last_sync = || {
+            host0
+                .git
+                .get_config(&mut NoRenderer, "last-sync")
+                .unwrap()
+                .map(|value| value.parse::<u64>().unwrap())
+        };
+        let now = || {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+        };
+
+        assert_eq!(last_sync(), None);
+        let before = now();
+        let output = run(&["sync"]).unwrap();
+        assert!(!output.contains("Previously synced"), "{}", output);
+        let synced = last_sync().unwrap();
+        assert!((before..=now()).contains(&synced), "{}", synced);
+
+        host0
+            .git
+            .set_config(&mut NoRenderer, "last-sync", "1")
+            .unwrap();
+        assert!(run(&["sync", "--dry-run"])
+            .unwrap()
+            .contains("Previously synced "));
+        assert!(run(&["--remote", "nope", "sync"]).is_err());
+        assert_eq!(last_sync(), Some(1));
+
+        assert!(run(&["config", "show"]).unwrap().ends_with(" ago\n"));
+    }
+
+    /// A detached HEAD should not stop `sync` from pushing every branch, and `ls --head` should
+    /// warn and list nothing rather than fail.
+    #[test]
+    fn nomad_detached_head() {
+        let origin = GitRemote::init(None);
+        let host0 = origin.clone("user0", "host0");
+        let head = host0.current_commit().0;
+        run_notable(
+            &mut NoRenderer,
+            None,
+            "Switch to detached HEAD state",
+            host0.git.command().args(["checkout", &head]),
+        )
+        .unwrap();
+
+        nomad(
+            &mut NoRenderer,
+            ["git-nomad", "-U", "user0", "-H", "host0", "sync"],
+            host0.working_directory(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            origin.nomad_refs(),
+            HashSet::from_iter([host0.get_nomad_ref(INITIAL_BRANCH).unwrap()]),
+        );
+
+        let mut renderer = MemoryRenderer::new();
+        nomad(
+            &mut renderer,
+            [
+                "git-nomad",
+                "-U",
+                "user0",
+                "-H",
+                "host0",
+                "ls",
+                "--print-self",
+                "--head",
+            ],
+            host0.working_directory(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            renderer.as_str(),
+            "Warning: HEAD is detached, so --head matches no branch\nhost0\n"
+        );
+    }
+
+    /// `--remote-url` should work just like a named remote for `sync`, `ls`, and `purge`, even
+    /// without any remote configured.
+    #[test]
+    fn nomad_remote_url() {
+        let origin = GitRemote::init(None);
+        let host0 = origin.clone("user0", "host0");
+        let host1 = origin.clone("user0", "host1");
+        for clone in [&host0, &host1] {
+            run_notable(
+                &mut NoRenderer,
+                None,
+                "",
+                clone.git.command().args(["remote", "remove", "origin"]),
+            )
+            .unwrap();
+        }
+
+        let url = format!("file://{}", origin.working_directory().display());
+        let run = |clone: &GitClone, args: &[&str]| {
+            let mut renderer = MemoryRenderer::new();
+            let mut full_args = vec![
+                "git-nomad",
+                "-U",
+                "user0",
+                "-H",
+                clone.host.0.as_ref(),
+                "--remote-url",
+                &url,
+            ];
+            full_args.extend_from_slice(args);
+            nomad(&mut renderer, full_args, clone.working_directory(), None)
+                .map(|_| renderer.as_str().to_string())
+        };
+
+        run(&host0, &["sync"]).unwrap();
+        run(&host1, &["sync"]).unwrap();
+        assert_eq!(
+            origin.nomad_refs(),
+            HashSet::from_iter([
+                host0.get_nomad_ref(INITIAL_BRANCH).unwrap(),
+                host1.get_nomad_ref(INITIAL_BRANCH).unwrap(),
+            ]),
+        );
+        assert!(run(&host1, &["ls", "--fetch", "--print=ref"])
+            .unwrap()
+            .ends_with("refs/nomad/host0/master\n"));
+
+        run(&host0, &["purge", "--all"]).unwrap();
+        assert_eq!(origin.nomad_refs(), HashSet::new());
+
+        let error = run(&host0, &["--remote", "origin", "sync"]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "--remote-url replaces --remote, pass only one of them"
+        );
+    }
+
+    /// `sync --all-remotes` should push to every remote, and only prune refs that are gone from
+    /// all of them.
+    #[test]
+    fn nomad_sync_all_remotes() {
+        let origin = GitRemote::init(None);
+        let backup = GitRemote::init(None);
+        let host0 = origin.clone("user0", "host0");
+        let host1 = origin.clone("user0", "host1");
+        for clone in [&host0, &host1] {
+            clone.add_remote("backup", &backup);
+        }
+
+        let sync = |clone: &GitClone| {
+            nomad(
+                &mut NoRenderer,
+                [
+                    "git-nomad",
+                    "-U",
+                    "user0",
+                    "-H",
+                    clone.host.0.as_ref(),
+                    "sync",
+                    "--all-remotes",
+                ],
+                clone.working_directory(),
+                None,
+            )
+            .unwrap();
+        };
+        let has_host1 = |clone: &GitClone| {
+            clone
+                .nomad_refs()
+                .iter()
+                .any(|nomad_ref| nomad_ref.host == host1.host)
+        };
+
+        sync(&host1);
+        sync(&host0);
+        let expected = HashSet::from_iter([
+            host0.get_nomad_ref(INITIAL_BRANCH).unwrap(),
+            host1.get_nomad_ref(INITIAL_BRANCH).unwrap(),
+        ]);
+        assert_eq!(origin.nomad_refs(), expected);
+        assert_eq!(backup.nomad_refs(), expected);
+        assert!(has_host1(&host0));
+
+        let host1_ref = format!("refs/nomad/user0/host1/{}", INITIAL_BRANCH);
+        origin.delete_ref(&host1_ref);
+        sync(&host0);
+        assert!(has_host1(&host0));
+
+        backup.delete_ref(&host1_ref);
+        sync(&host0);
+        assert!(!has_host1(&host0));
+    }
+
+    /// `run` should report unreadable files as errors.
+    #[test]
+    fn nomad_run_missing_file() {
+        let origin = GitRemote::init(None);
+        let result = nomad(
+            &mut NoRenderer,
+            ["git-nomad", "run", "/does/not/exist.toml"],
+            origin.working_directory(),
+            None,
+        );
+
+        assert!(result.is_err());
+    }
+
+    /// Syncing should pick up nomad refs from other hosts.
+    ///
+    /// When the other host deletes their branch (and thus deletes their nomad ref on the remote),
+    /// the equivalent local nomad ref for that host should also be deleted.
+    ///
+    /// See https://github.com/rraval/git-nomad/issues/1
+    #[test]
+    fn issue_1() {
+        let origin = GitRemote::init(None);
+        let feature = &Branch::from("feature");
+
+        let host0 = origin.clone("user0", "host0");
+        sync_host(&host0);
+
+        let host1 = origin.clone("user0", "host1");
+        host1
+            .git
+            .create_branch(&mut NoRenderer, "Start feature branch", feature)
+            .unwrap();
+        sync_host(&host1);
+
+        // both hosts have synced, the origin should have refs from both (including the one for the
+        // feature branch on host1)
+        assert_eq!(
+            origin.nomad_refs(),
+            HashSet::from_iter([
+                host0.get_nomad_ref(INITIAL_BRANCH).unwrap(),
+                host1.get_nomad_ref(INITIAL_BRANCH).unwrap(),
+                host1.get_nomad_ref("feature").unwrap(),
+            ])
+        );
+
+        // host0 hasn't observed host1 yet
+        assert_eq!(
+            host0.nomad_refs(),
+            HashSet::from_iter([host0.get_nomad_ref(INITIAL_BRANCH).unwrap(),])
+        );
+
+        // sync host0, which should observe host1 refs
+        sync_host(&host0);
+        assert_eq!(
+            host0.nomad_refs(),
+            HashSet::from_iter([
+                host0.get_nomad_ref(INITIAL_BRANCH).unwrap(),
+                host1.get_nomad_ref(INITIAL_BRANCH).unwrap(),
+                host1.get_nomad_ref("feature").unwrap(),
+            ])
+        );
+
+        // host1 deletes the branch and syncs, removing it from origin
+        host1
+            .git
+            .delete_branch(&mut NoRenderer, "Abandon feature branch", feature)
+            .unwrap();
+        sync_host(&host1);
+
+        assert_eq!(
+            origin.nomad_refs(),
+            HashSet::from_iter([
+                host0.get_nomad_ref(INITIAL_BRANCH).unwrap(),
+                host1.get_nomad_ref(INITIAL_BRANCH).unwrap(),
+            ])
+        );
+
+        // host0 syncs and removes the ref for the deleted feature branch
+        sync_host(&host0);
+        assert_eq!(
+            host0.nomad_refs(),
+            HashSet::from_iter([
+                host0.get_nomad_ref(INITIAL_BRANCH).unwrap(),
+                host1.get_nomad_ref(INITIAL_BRANCH).unwrap(),
+            ])
+        );
+    }
+
+    /// Explicitly pruning other hosts should delete both local and remote nomad refs for that
+    /// host.
+    ///
+    /// See https://github.com/rraval/git-nomad/issues/2
+    #[test]
+    fn issue_2_other_host() {
+        let origin = GitRemote::init(None);
+
+        let host0 = origin.clone("user0", "host0");
+        sync_host(&host0);
+
+        let host1 = origin.clone("user0", "host1");
+        sync_host(&host1);
+
+        // both hosts have synced, the origin should have both refs
+        assert_eq!(
+            origin.nomad_refs(),
+            HashSet::from_iter([
+                host0.get_nomad_ref(INITIAL_BRANCH).unwrap(),
+                host1.get_nomad_ref(INITIAL_BRANCH).unwrap(),
+            ])
+        );
+
+        // pruning refs for host0 from host1
+        Workflow::Purge {
+            user: host1.user.always_borrow(),
+            remote: host1.remote.always_borrow(),
+            host_filter: Filter::Allow(HashSet::from_iter([host0.host.always_borrow()])),
+            branch_filter: Filter::All,
+            prune_confirm: PruneConfirm::default(),
+            older_than: None,
+            dry_run: false,
+            fast_prune: false,
+            local_only: false,
+            refs: Vec::new(),
+        }
+        .execute(&mut NoRenderer, &host1.git)
+        .unwrap();
+
+        // the origin should only have refs for host1
+        assert_eq!(
+            origin.nomad_refs(),
+            HashSet::from_iter([host1.get_nomad_ref(INITIAL_BRANCH).unwrap(),])
+        );
+    }
+
+    /// `purge --local-only` should only delete the local copies of refs matching the host filter,
+    /// leaving every ref on the remote in place.
+    #[test]
+    fn nomad_purge_local_only() {
+        let origin = GitRemote::init(None);
+        let host0 = origin.clone("user0", "host0");
+        sync_host(&host0);
+        let host1 = origin.clone("user0", "host1");
+        sync_host(&host1);
+
+        let everything = HashSet::from_iter([
+            host0.get_nomad_ref(INITIAL_BRANCH).unwrap(),
+            host1.get_nomad_ref(INITIAL_BRANCH).unwrap(),
+        ]);
+        let local_hosts = || {
+            let mut hosts = host1
+                .nomad_refs()
+                .into_iter()
+                .map(|nomad_ref| nomad_ref.host.0.into_owned())
+                .collect::<Vec<_>>();
+            hosts.sort();
+            hosts
+        };
+        let purge = |args: &[&str]| {
+            let mut full_args = vec!["git-nomad", "-U", "user0", "-H", "host1", "purge"];
+            full_args.extend_from_slice(args);
+            nomad(&mut NoRenderer, full_args, host1.working_directory(), None).unwrap();
+        };
+        assert_eq!(local_hosts(), ["host0", "host1"]);
+
+        purge(&["--local-only", "--host-regex", "^host0$"]);
+        assert_eq!(local_hosts(), ["host1"]);
+        assert_eq!(origin.nomad_refs(), everything);
+
+        purge(&["--local-only", "--all"]);
+        assert_eq!(local_hosts(), Vec::<String>::new());
+        assert_eq!(origin.nomad_refs(), everything);
+    }
+
+    /// `purge --ref` should delete exactly the named ref, from any host, both locally and on the
+    /// remote.
+    #[test]
+    fn nomad_purge_ref() {
+        let origin = GitRemote::init(None);
+        let host0 = origin.clone("user0", "host0");
+        sync_host(&host0);
+        let host1 = origin.clone("user0", "host1");
+        host1
+            .git
+            .create_branch(&mut NoRenderer, "", &Branch::from("feature"))
+            .unwrap();
+        sync_host(&host1);
+
+        let purge = |name: &str| {
+            nomad(
+                &mut NoRenderer,
+                [
+                    "git-nomad",
+                    "-U",
+                    "user0",
+                    "-H",
+                    "host0",
+                    "purge",
+                    "--ref",
+                    name,
+                ],
+                host0.working_directory(),
+                None,
+            )
+        };
+
+        purge("refs/nomad/user0/host1/feature").unwrap();
+        let remaining = HashSet::from_iter([
+            host0.get_nomad_ref(INITIAL_BRANCH).unwrap(),
+            host1.get_nomad_ref(INITIAL_BRANCH).unwrap(),
+        ]);
+        assert_eq!(origin.nomad_refs(), remaining);
+        assert_eq!(host0.nomad_refs(), remaining);
+
+        let error = purge("refs/nomad/host1/feature").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "No nomad ref refs/nomad/host1/feature for user user0"
+        );
+        assert_eq!(origin.nomad_refs(), remaining);
+    }
+
+    /// Explicitly pruning everything should delete both local and remote refs for both the current
+    /// and other host on the remote.
+    ///
+    /// See https://github.com/rraval/git-nomad/issues/2
+    #[test]
+    fn issue_2_all() {
+        let origin = GitRemote::init(Some(Verbosity::max()));
+
+        let host0 = origin.clone("user0", "host0");
+        sync_host(&host0);
+
+        let host1 = origin.clone("user0", "host1");
+        sync_host(&host1);
+
+        // both hosts have synced, the origin should have both refs
+        assert_eq!(
+            origin.nomad_refs(),
+            HashSet::from_iter([
+                host0.get_nomad_ref(INITIAL_BRANCH).unwrap(),
+                host1.get_nomad_ref(INITIAL_BRANCH).unwrap(),
+            ])
+        );
+
+        // pruning refs for all hosts from host1
+        Workflow::Purge {
+            user: host1.user.always_borrow(),
+            remote: host1.remote,
+            host_filter: Filter::All,
+            branch_filter: Filter::All,
+            prune_confirm: PruneConfirm::default(),
+            older_than: None,
+            dry_run: false,
+            fast_prune: false,
+            local_only: false,
+            refs: Vec::new(),
+        }
+        .execute(&mut NoRenderer, &host1.git)
+        .unwrap();
+
+        // the origin should have no refs
+        assert_eq!(origin.nomad_refs(), HashSet::new(),);
+    }
+
+    /// A dry run `sync` should neither push new refs nor prune deleted ones, but report both.
+    #[test]
+    fn sync_dry_run() {
+        let origin = GitRemote::init(Some(Verbosity::default()));
+        let feature = &Branch::from("feature");
+
+        let host0 = origin.clone("user0", "host0");
+        host0
+            .git
+            .create_branch(&mut NoRenderer, "Start feature branch", feature)
+            .unwrap();
+        sync_host(&host0);
+        let before = origin.nomad_refs();
+
+        host0
+            .git
+            .delete_branch(&mut NoRenderer, "Abandon feature branch", feature)
+            .unwrap();
+        host0
+            .git
+            .create_branch(
+                &mut NoRenderer,
+                "Start other branch",
+                &Branch::from("other"),
+            )
+            .unwrap();
+
+        let mut renderer = MemoryRenderer::new();
+        Workflow::Sync {
+            user: host0.user.always_borrow(),
+            host: host0.host.always_borrow(),
+            remotes: vec![host0.remote.always_borrow()],
+            options: SyncOptions {
+                dry_run: true,
+                ..SyncOptions::default()
+            },
+            branch_filter: Filter::All,
+        }
+        .execute(&mut renderer, &host0.git)
+        .unwrap();
+
+        assert_eq!(origin.nomad_refs(), before);
+        assert!(host0
+            .git
+            .get_ref(&mut NoRenderer, "", "refs/nomad/host0/feature")
+            .is_ok());
+        assert!(renderer
+            .as_str()
+            .contains("Pushing local branches to origin (dry run)"));
+        assert!(renderer
+            .as_str()
+            .contains(":refs/nomad/user0/host0/feature"));
+    }
+
+    /// Syncing against several remotes should push to all of them, and only prune refs from
+    /// other hosts once every remote has dropped them.
+    #[test]
+    fn sync_multiple_remotes() {
+        let origin = GitRemote::init(None);
+        let backup = GitRemote::init(None);
+
+        let host0 = origin.clone("user0", "host0");
+        let host0_backup = host0.add_remote("backup", &backup);
+        let host1 = origin.clone("user0", "host1");
+        let host1_backup = host1.add_remote("backup", &backup);
+
+        let sync_both = |clone: &GitClone, backup: &Remote| {
+            Workflow::Sync {
+                user: clone.user.always_borrow(),
+                host: clone.host.always_borrow(),
+                remotes: vec![clone.remote.always_borrow(), backup.always_borrow()],
+                options: SyncOptions::default(),
+                branch_filter: Filter::All,
+            }
+            .execute(&mut NoRenderer, &clone.git)
+            .unwrap();
+        };
+
+        sync_both(&host0, &host0_backup);
+        sync_both(&host1, &host1_backup);
+        sync_both(&host0, &host0_backup);
+
+        let all_refs = HashSet::from_iter([
+            host0.get_nomad_ref(INITIAL_BRANCH).unwrap(),
+            host1.get_nomad_ref(INITIAL_BRANCH).unwrap(),
+        ]);
+        assert_eq!(origin.nomad_refs(), all_refs);
+        assert_eq!(backup.nomad_refs(), all_refs);
+        assert_eq!(host0.nomad_refs(), all_refs);
+
+        // Still known by the backup, so it should survive
+        let host1_ref = format!("refs/nomad/user0/host1/{}", INITIAL_BRANCH);
+        origin.delete_ref(&host1_ref);
+        sync_both(&host0, &host0_backup);
+        assert_eq!(host0.nomad_refs(), all_refs);
+
+        backup.delete_ref(&host1_ref);
+        sync_both(&host0, &host0_backup);
+        assert_eq!(
+            host0.nomad_refs(),
+            HashSet::from_iter([host0.get_nomad_ref(INITIAL_BRANCH).unwrap()])
+        );
+    }
+
+    /// `--show-refspecs` should print the computed refspecs without pushing anything.
+    #[test]
+    fn sync_show_refspecs() {
+        let origin = GitRemote::init(None);
+        let host0 = origin.clone("user0", "host0");
+
+        let mut renderer = MemoryRenderer::new();
+        nomad(
+            &mut renderer,
+            [
+                "git-nomad",
+                "sync",
+                "--show-refspecs",
+                "--mirror-remote-branches",
+                "-U",
+                "user0",
+                "-H",
+                "host0",
+            ],
+            host0.working_directory(),
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(
+            renderer.as_str(),
+            "mirror fetch\t+refs/heads/*:refs/nomad/remote-origin/*\n\
+             mirror push\t+refs/nomad/remote-origin/*:refs/nomad/user0/remote-origin/*\n\
+             push\t+refs/heads/*:refs/nomad/user0/host0/*\n\
+             fetch\t+refs/nomad/user0/*:refs/nomad/*\n\
+             list\trefs/nomad/user0/*\n",
+        );
+        assert_eq!(origin.nomad_refs(), HashSet::new());
+    }
+
+    /// `--push-only` should push local branches without fetching refs pushed by other hosts.
+    #[test]
+    fn sync_push_only() {
+        let origin = GitRemote::init(None);
+
+        let host0 = origin.clone("user0", "host0");
+        sync_host(&host0);
+
+        let host1 = origin.clone("user0", "host1");
+        Workflow::Sync {
+            user: host1.user.always_borrow(),
+            host: host1.host.always_borrow(),
+            remotes: vec![host1.remote.always_borrow()],
+            options: SyncOptions {
+                direction: SyncDirection::PushOnly,
+                ..SyncOptions::default()
+            },
+            branch_filter: Filter::All,
+        }
+        .execute(&mut NoRenderer, &host1.git)
+        .unwrap();
+
+        assert_eq!(
+            origin.nomad_refs(),
+            HashSet::from_iter([
+                host0.get_nomad_ref(INITIAL_BRANCH).unwrap(),
+                host1.get_nomad_ref(INITIAL_BRANCH).unwrap(),
+            ])
+        );
+        assert_eq!(host1.nomad_refs(), HashSet::new());
+    }
+
+    /// `--fetch-only` should fetch refs pushed by other hosts without pushing local branches.
+    #[test]
+    fn sync_fetch_only() {
+        let origin = GitRemote::init(None);
+
+        let host0 = origin.clone("user0", "host0");
+        sync_host(&host0);
+
+        let host1 = origin.clone("user0", "host1");
+        Workflow::Sync {
+            user: host1.user.always_borrow(),
+            host: host1.host.always_borrow(),
+            remotes: vec![host1.remote.always_borrow()],
+            options: SyncOptions {
+                direction: SyncDirection::FetchOnly,
+                ..SyncOptions::default()
+            },
+            branch_filter: Filter::All,
+        }
+        .execute(&mut NoRenderer, &host1.git)
+        .unwrap();
+
+        assert_eq!(
+            origin.nomad_refs(),
+            HashSet::from_iter([host0.get_nomad_ref(INITIAL_BRANCH).unwrap()])
+        );
+        assert_eq!(
+            host1.nomad_refs(),
+            HashSet::from_iter([host0.get_nomad_ref(INITIAL_BRANCH).unwrap()])
+        );
+    }
+
+    /// `ls --abbrev` should shorten commits to the repository's `core.abbrev`.
+    #[test]
+    fn ls_abbrev_follows_core_abbrev() {
+        let origin = GitRemote::init(None);
+        let host0 = origin.clone("user0", "host0");
+        sync_host(&host0);
+        host0
+            .git
+            .command()
+            .args(["config", "core.abbrev", "12"])
+            .status()
+            .unwrap();
+
+        let mut renderer = MemoryRenderer::new();
+        nomad(
+            &mut renderer,
+            [
+                "git-nomad",
+                "ls",
+                "--print-self",
+                "--print=commit",
+                "--abbrev",
+            ],
+            host0.working_directory(),
+            None,
+        )
+        .unwrap();
+
+        let commit = host0.current_commit().0;
+        assert_eq!(renderer.as_str(), format!("{}\n", &commit[..12]));
+    }
+
+    /// `ls --show-author` should name the committer in both grouped and JSON output.
+    #[test]
+    fn ls_show_author() {
+        let origin = GitRemote::init(None);
+        let host0 = origin.clone("user0", "host0");
+        sync_host(&host0);
+        let commit = host0.current_commit().0;
+
+        let mut renderer = MemoryRenderer::new();
+        nomad(
+            &mut renderer,
+            ["git-nomad", "ls", "--print-self", "--show-author"],
+            host0.working_directory(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            renderer.as_str(),
+            format!(
+                "host0\n  refs/nomad/host0/master -> {} (ahead 0, behind 0) by git-nomad <git-nomad@invalid>\n",
+                commit
+            ),
+        );
+
+        let mut renderer = MemoryRenderer::new();
+        nomad(
+            &mut renderer,
+            [
+                "git-nomad",
+                "ls",
+                "--print-self",
+                "--print=json",
+                "--show-author",
+            ],
+            host0.working_directory(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            renderer.as_str(),
+            format!(
+                "[{{\"host\":\"host0\",\"branch\":\"master\",\"ref\":\"refs/nomad/host0/master\",\"commit\":\"{}\",\"author\":\"git-nomad <git-nomad@invalid>\"}}]\n",
+                commit
+            ),
+        );
+    }
+
+    /// `--show-age` should annotate each ref with how long ago it was committed, but only for
+    /// grouped output.
+    #[test]
+    fn ls_show_age() {
+        let origin = GitRemote::init(None);
+        let host0 = origin.clone("user0", "host0");
+        sync_host(&host0);
+        let commit = host0.current_commit().0;
+
+        let ls = |args: &[&str]| {
+            let mut renderer = MemoryRenderer::new();
+            let mut full_args = vec!["git-nomad", "ls", "--print-self", "--show-age"];
+            full_args.extend_from_slice(args);
+            nomad(&mut renderer, full_args, host0.working_directory(), None).unwrap();
+            renderer.as_str().to_string()
+        };
+
+        // The commit was just made, so the exact age depends on how fast the test runs.
+        let output = ls(&[]);
+        let prefix = format!(
+            "host0\n  refs/nomad/host0/master -> {} (ahead 0, behind 0) committed ",
+            commit
+        );
+        assert!(output.starts_with(&prefix), "{}", output);
+        assert!(output.ends_with(" ago\n"), "{}", output);
+
+        assert_eq!(ls(&["--print=commit"]), format!("{}\n", commit));
+    }
+
+    /// `ls --porcelain` emits exactly one tab-separated, NUL-terminated record per ref and no
+    /// host headers.
+    #[test]
+    fn ls_porcelain() {
+        let origin = GitRemote::init(None);
+        let host0 = origin.clone("user0", "host0");
+        sync_host(&host0);
+        let host1 = origin.clone("user0", "host1");
+        sync_host(&host1);
+        let commit = host0.current_commit().0;
+
+        let mut renderer = MemoryRenderer::new();
+        nomad(
+            &mut renderer,
+            [
+                "git-nomad",
+                "ls",
+                "--print-self",
+                "--porcelain",
+                "--user",
+                "user0",
+            ],
+            host1.working_directory(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            renderer.as_str(),
+            format!(
+                "user0\thost0\tmaster\t{commit}\trefs/nomad/host0/master\0\
+                 user0\thost1\tmaster\t{commit}\trefs/nomad/host1/master\0",
+            ),
+        );
+
+        let mut renderer = MemoryRenderer::new();
+        nomad(
+            &mut renderer,
+            [
+                "git-nomad",
+                "ls",
+                "--porcelain",
+                "--user",
+                "user0",
+                "--host",
+                "host1",
+            ],
+            host1.working_directory(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            renderer.as_str(),
+            format!("user0\thost0\tmaster\t{commit}\trefs/nomad/host0/master\0"),
+        );
+    }
+
+    /// A dry run `purge` should leave both the remote and local refs alone.
+    #[test]
+    fn purge_dry_run() {
+        let origin = GitRemote::init(None);
+
+        let host0 = origin.clone("user0", "host0");
+        sync_host(&host0);
+        let before = origin.nomad_refs();
+
+        nomad(
+            &mut NoRenderer,
+            ["git-nomad", "purge", "--all", "--dry-run"],
+            host0.working_directory(),
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(origin.nomad_refs(), before);
+        assert_eq!(host0.list().count(), 1);
+    }
+
+    /// Purging a branch should delete it across all hosts while leaving other branches alone.
+    #[test]
+    fn purge_branch() {
+        let origin = GitRemote::init(None);
+        let feature = &Branch::from("feature");
+
+        let host0 = origin.clone("user0", "host0");
+        host0
+            .git
+            .create_branch(&mut NoRenderer, "Start feature branch", feature)
+            .unwrap();
+        sync_host(&host0);
+
+        let host1 = origin.clone("user0", "host1");
+        host1
+            .git
+            .create_branch(&mut NoRenderer, "Start feature branch", feature)
+            .unwrap();
+        sync_host(&host1);
+
+        Workflow::Purge {
+            user: host1.user.always_borrow(),
+            remote: host1.remote.always_borrow(),
+            host_filter: Filter::All,
+            branch_filter: Filter::Allow([feature.always_borrow()].into()),
+            prune_confirm: PruneConfirm::default(),
+            older_than: None,
+            dry_run: false,
+            fast_prune: false,
+            local_only: false,
+            refs: Vec::new(),
+        }
+        .execute(&mut NoRenderer, &host1.git)
+        .unwrap();
+
+        assert_eq!(
+            origin.nomad_refs(),
+            HashSet::from_iter([
+                host0.get_nomad_ref(INITIAL_BRANCH).unwrap(),
+                host1.get_nomad_ref(INITIAL_BRANCH).unwrap(),
+            ])
+        );
+    }
+
+    /// `--fast-prune` should delete every pruned ref, both the ones this host pushed and the ones
+    /// fetched from other hosts.
+    #[test]
+    fn sync_fast_prune() {
+        let origin = GitRemote::init(None);
+        let features = (0..5)
+            .map(|i| Branch::from(format!("feature{}", i)))
+            .collect::<Vec<_>>();
+
+        let host0 = origin.clone("user0", "host0");
+        for feature in &features {
+            host0
+                .git
+                .create_branch(&mut NoRenderer, "Start feature branch", feature)
+                .unwrap();
+        }
+        sync_host(&host0);
+
+        let host1 = origin.clone("user0", "host1");
+        sync_host(&host1);
+        assert_eq!(host1.nomad_refs().len(), 7);
+
+        let sync_fast_prune = |clone: &GitClone| {
+            Workflow::Sync {
+                user: clone.user.always_borrow(),
+                host: clone.host.always_borrow(),
+                remotes: vec![clone.remote.always_borrow()],
+                options: SyncOptions {
+                    fast_prune: true,
+                    ..SyncOptions::default()
+                },
+                branch_filter: Filter::All,
+            }
+            .execute(&mut NoRenderer, &clone.git)
+            .unwrap();
+        };
+
+        for feature in &features {
+            host0
+                .git
+                .delete_branch(&mut NoRenderer, "Abandon feature branch", feature)
+                .unwrap();
+        }
+        sync_fast_prune(&host0);
+        sync_fast_prune(&host1);
+
+        let remaining = HashSet::from_iter([
+            host0.get_nomad_ref(INITIAL_BRANCH).unwrap(),
+            host1.get_nomad_ref(INITIAL_BRANCH).unwrap(),
+        ]);
+        assert_eq!(origin.nomad_refs(), remaining);
+        assert_eq!(host0.nomad_refs(), remaining);
+        assert_eq!(host1.nomad_refs(), remaining);
+    }
+
+    /// `--fail-on-prune` should still prune, but report an error naming the pruned ref.
+    #[test]
+    fn sync_fail_on_prune() {
+        let origin = GitRemote::init(None);
+        let feature = &Branch::from("feature");
+
+        let host0 = origin.clone("user0", "host0");
+        host0
+            .git
+            .create_branch(&mut NoRenderer, "Start feature branch", feature)
+            .unwrap();
+        sync_host(&host0);
+
+        let sync_fail_on_prune = || {
+            Workflow::Sync {
+                user: host0.user.always_borrow(),
+                host: host0.host.always_borrow(),
+                remotes: vec![host0.remote.always_borrow()],
+                options: SyncOptions {
+                    fail_on_prune: true,
+                    ..SyncOptions::default()
+                },
+                branch_filter: Filter::All,
+            }
+            .execute(&mut NoRenderer, &host0.git)
+        };
+
+        // nothing to prune, so this should succeed
+        sync_fail_on_prune().unwrap();
+
+        host0
+            .git
+            .delete_branch(&mut NoRenderer, "Abandon feature branch", feature)
+            .unwrap();
+
+        let err = sync_fail_on_prune().unwrap_err();
+        assert!(err.to_string().contains("refs/nomad/host0/feature"));
+
+        // the prune itself should have gone through
+        assert_eq!(
+            origin.nomad_refs(),
+            HashSet::from_iter([host0.get_nomad_ref(INITIAL_BRANCH).unwrap()])
+        );
+    }
+
+    /// `--mirror-remote-branches` should record the branches of the remote itself under the
+    /// synthetic `remote-origin` host, including pruning branches that the remote deleted.
+    #[test]
+    fn sync_mirror_remote_branches() {
+        let origin = GitRemote::init(None);
+        let shared = &Branch::from("shared");
+        origin
+            .git
+            .create_branch(&mut NoRenderer, "Create shared branch", shared)
+            .unwrap();
+
+        let host0 = origin.clone("user0", "host0");
+        let commit_id = host0.current_commit();
+        let sync_mirror = || {
+            Workflow::Sync {
+                user: host0.user.always_borrow(),
+                host: host0.host.always_borrow(),
+                remotes: vec![host0.remote.always_borrow()],
+                options: SyncOptions {
+                    mirror_remote_branches: true,
+                    ..SyncOptions::default()
+                },
+                branch_filter: Filter::All,
+            }
+            .execute(&mut NoRenderer, &host0.git)
+            .unwrap();
+        };
+        let mirror_ref = |branch: &'static str| NomadRef {
+            user: User::from("user0"),
+            host: Host::from("remote-origin"),
+            branch: Branch::from(branch),
+            ref_: commit_id.clone(),
+        };
+
+        sync_mirror();
+        assert_eq!(
+            origin.nomad_refs(),
+            HashSet::from_iter([
+                host0.get_nomad_ref(INITIAL_BRANCH).unwrap(),
+                mirror_ref(INITIAL_BRANCH),
+                mirror_ref("shared"),
+            ])
+        );
+
+        origin
+            .git
+            .delete_branch(&mut NoRenderer, "Delete shared branch", shared)
+            .unwrap();
+        sync_mirror();
+        assert_eq!(
+            origin.nomad_refs(),
+            HashSet::from_iter([
+                host0.get_nomad_ref(INITIAL_BRANCH).unwrap(),
+                mirror_ref(INITIAL_BRANCH),
+            ])
+        );
+    }
+
+    /// Purges two refs with the given confirmation behaviour, returning how many refs are left.
+    fn purge_two_refs(renderer: &mut MemoryRenderer, prune_confirm: PruneConfirm) -> (bool, usize) {
+        let origin = GitRemote::init(None);
+        let host0 = origin.clone("user0", "host0");
+        host0
+            .git
+            .create_branch(
+                &mut NoRenderer,
+                "Start feature branch",
+                &Branch::from("feature"),
+            )
+            .unwrap();
+        sync_host(&host0);
+        assert_eq!(origin.nomad_refs().len(), 2);
+
+        let result = Workflow::Purge {
+            user: host0.user.always_borrow(),
+            remote: host0.remote.always_borrow(),
+            host_filter: Filter::All,
+            branch_filter: Filter::All,
+            prune_confirm,
+            older_than: None,
+            dry_run: false,
+            fast_prune: false,
+            local_only: false,
+            refs: Vec::new(),
+        }
+        .execute(renderer, &host0.git);
+
+        (result.is_ok(), origin.nomad_refs().len())
+    }
+
+    /// Pruning no more than `--prune-confirm-threshold` refs should proceed without asking.
+    #[test]
+    fn prune_confirm_under_threshold() {
+        let mut renderer = MemoryRenderer::new();
+        let prune_confirm = PruneConfirm {
+            threshold: 2,
+            assume_yes: false,
+        };
+        assert_eq!(purge_two_refs(&mut renderer, prune_confirm), (true, 0));
+    }
+
+    /// Pruning more than `--prune-confirm-threshold` refs should only proceed when confirmed.
+    #[test]
+    fn prune_confirm_over_threshold() {
+        let prune_confirm = PruneConfirm {
+            threshold: 1,
+            assume_yes: false,
+        };
+
+        // non-interactive
+        let mut renderer = MemoryRenderer::new();
+        assert_eq!(purge_two_refs(&mut renderer, prune_confirm), (false, 2));
+
+        // declined
+        let mut renderer = MemoryRenderer::with_confirmation(false);
+        assert_eq!(purge_two_refs(&mut renderer, prune_confirm), (false, 2));
+        assert!(renderer.as_str().contains("refs/nomad/host0/feature"));
+
+        // confirmed
+        let mut renderer = MemoryRenderer::with_confirmation(true);
+        assert_eq!(purge_two_refs(&mut renderer, prune_confirm), (true, 0));
+
+        // `--yes` in a non-interactive session
+        let mut renderer = MemoryRenderer::new();
+        let prune_confirm = PruneConfirm {
+            assume_yes: true,
+            ..prune_confirm
+        };
+        assert_eq!(purge_two_refs(&mut renderer, prune_confirm), (true, 0));
+    }
+
+    /// `--confirm` should ask before deleting even a single ref, refusing without a terminal unless
+    /// `--yes` is passed.
+    #[test]
+    fn purge_confirm() {
+        let prune_confirm = |args: &[&str]| {
+            let mut argv = vec!["git-nomad", "purge", "--all", "--confirm"];
+            argv.extend_from_slice(args);
+            let mut matches =
+                cli(Some(User::from("user0")), Some(Host::from("host0")), &argv).unwrap();
+            let git = GitRemote::init(None).git;
+            match specified_workflow(&mut NoRenderer, &mut matches, &git, None).unwrap() {
+                Workflow::Purge { prune_confirm, .. } => prune_confirm,
+                workflow => panic!("{:?}", workflow),
+            }
+        };
+
+        let confirm = prune_confirm(&[]);
+        assert_eq!(
+            confirm,
+            PruneConfirm {
+                threshold: 0,
+                assume_yes: false,
+            }
+        );
+
+        // non-interactive
+        let mut renderer = MemoryRenderer::new();
+        assert_eq!(purge_two_refs(&mut renderer, confirm), (false, 2));
+
+        // the prompt lists exactly what would be deleted
+        let mut renderer = MemoryRenderer::with_confirmation(false);
+        assert_eq!(purge_two_refs(&mut renderer, confirm), (false, 2));
+        let output = renderer.as_str();
+        assert!(output.contains(&format!("  refs/nomad/host0/{}\n", INITIAL_BRANCH)));
+        assert!(output.contains("  refs/nomad/host0/feature\n"));
+        assert!(output.contains("Prune 2 refs? [y/N] n"));
+
+        // `-y` skips the prompt entirely
+        let yes = prune_confirm(&["-y"]);
+        assert!(yes.assume_yes);
+        let mut renderer = MemoryRenderer::new();
+        assert_eq!(purge_two_refs(&mut renderer, yes), (true, 0));
+        assert!(!renderer.as_str().contains("Prune 2 refs?"));
+    }
+
+    /// `GIT_NOMAD_ASSUME_YES=1` should act like `--yes`, unless `--no-assume-yes` comes after it.
+    #[test]
+    fn prune_confirm_assume_yes_env() {
+        let prune_confirm = |args: &[&str], env: Option<&str>| {
+            let mut argv = vec![
+                "git-nomad",
+                "purge",
+                "--all",
+                "--prune-confirm-threshold",
+                "1",
+            ];
+            argv.extend_from_slice(args);
+            let (_, mut matches) = cli(Some(User::from("user0")), Some(Host::from("host0")), &argv)
+                .unwrap()
+                .remove_subcommand()
+                .unwrap();
+            specified_prune_confirm(&mut matches, env.map(OsString::from))
+        };
+
+        // non-interactive, yet proceeds without prompting
+        let mut renderer = MemoryRenderer::new();
+        let from_env = prune_confirm(&[], Some("1")).unwrap();
+        assert!(from_env.assume_yes);
+        assert_eq!(purge_two_refs(&mut renderer, from_env), (true, 0));
+        assert!(!renderer.as_str().contains("Prune 2 refs?"));
+
+        for (args, env, expected) in [
+            (&[][..], None, false),
+            (&[][..], Some("0"), false),
+            (&["--no-assume-yes"][..], Some("1"), false),
+            (&["--yes", "--no-assume-yes"][..], Some("1"), false),
+            (&["--no-assume-yes", "--yes"][..], Some("1"), true),
+            (&["--yes"][..], Some("0"), true),
+        ] {
+            assert_eq!(
+                prune_confirm(args, env).unwrap().assume_yes,
+                expected,
+                "{:?} with {:?}",
+                args,
+                env
+            );
+        }
+
+        assert!(prune_confirm(&[], Some("maybe")).is_err());
+    }
+}
+
+/// CLI invocation tests
+#[cfg(test)]
+mod test_cli {
+    use std::{collections::HashSet, ffi::OsString, fs, iter::FromIterator};
+
+    use clap::{error::ErrorKind, ArgMatches};
+
+    use crate::{
+        cli, default_host,
+        git_binary::{ConfigScope, FetchOptions, DEFAULT_REF_PREFIX},
+        git_testing::GitRemote,
+        parse_platform_uuid,
+        renderer::test::NoRenderer,
+        specified_allow_prompt, specified_git, specified_git_namespace, specified_progress_fd,
+        specified_quiet_errors, specified_ref_prefix, specified_retries, specified_ssh_multiplex,
+        specified_verbosity, specified_verify_objects, specified_workflow,
+        types::{Branch, Host, Remote, User},
+        verbosity::{Retries, Verbosity},
+        workflow::{
+            Filter, Glob, LsOptions, LsPrinter, LsSort, OnRateLimit, PruneConfirm, PushRateLimit,
+            SyncDirection, SyncForce, SyncOptions, SyncPrune, Workflow,
+        },
+        CONFIG_HOST, CONFIG_REF_PREFIX, CONFIG_USER, DEFAULT_REMOTE, NOMAD_FILE, RETRY_BACKOFF,
+    };
+
+    struct CliTest {
+        default_user: User<'static>,
+        default_host: Host<'static>,
+    }
+
+    impl CliTest {
+        fn default_host_filter(&self) -> Filter<Host<'_>> {
+            Filter::Deny([self.default_host.always_borrow()].into())
+        }
+
+        fn default_user_filter(&self) -> Filter<User<'_>> {
+            Filter::Allow([self.default_user.always_borrow()].into())
+        }
+
+        fn matches(&self, args: &[&str]) -> clap::error::Result<ArgMatches> {
+            let mut vec = vec!["git-nomad"];
+            vec.extend_from_slice(args);
+            cli(
+                Some(self.default_user.clone()),
+                Some(self.default_host.clone()),
+                &vec,
+            )
+        }
+
+        fn remote(&self, args: &[&str]) -> CliTestRemote {
+            CliTestRemote {
+                matches: self.matches(args).unwrap(),
+                remote: GitRemote::init(Some(Verbosity::max())),
+            }
+        }
+    }
+
+    struct CliTestRemote {
+        matches: ArgMatches,
+        remote: GitRemote,
+    }
+
+    impl CliTestRemote {
+        fn set_config(&mut self, key: &str, value: &str) -> &mut Self {
+            self.remote
+                .git
+                .set_config(&mut NoRenderer, key, value)
+                .unwrap();
+            self
+        }
+
+        fn workflow(&mut self) -> Workflow<'_> {
+            self.try_workflow().unwrap()
+        }
+
+        fn try_workflow(&mut self) -> anyhow::Result<Workflow<'_>> {
+            specified_workflow(&mut NoRenderer, &mut self.matches, &self.remote.git, None)
+        }
+
+        fn ref_prefix(&mut self) -> anyhow::Result<String> {
+            specified_ref_prefix(&mut NoRenderer, &mut self.matches, &self.remote.git)
+        }
+    }
+
+    impl Default for CliTest {
+        fn default() -> Self {
+            Self {
+                default_user: User::from("default_user"),
+                default_host: Host::from("default_host"),
+            }
+        }
+    }
+
+    /// When the operating system cannot provide a user or host, not specifying one should error
+    /// with instructions instead of panicking.
+    #[test]
+    fn missing_os_defaults() {
+        let remote = GitRemote::init(Some(Verbosity::max()));
+
+        for (args, name) in [
+            (&["git-nomad", "sync"][..], "user"),
+            (&["git-nomad", "sync", "--user", "user0"][..], "host"),
+        ] {
+            let mut matches = cli(None, None, args).unwrap();
+            let error =
+                specified_workflow(&mut NoRenderer, &mut matches, &remote.git, None).unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                format!(
+                    "Could not determine {name} automatically, pass --{name} or set nomad.{name} with git config"
+                ),
+            );
+        }
+    }
+
+    /// Hosts that would corrupt refspecs should be rejected, pointing at where they came from.
+    #[test]
+    fn invalid_host() {
+        let error = |cli_test: CliTest, args: &[&str], config: Option<&str>| {
+            let mut remote = cli_test.remote(args);
+            if let Some(value) = config {
+                remote.set_config("host", value);
+            }
+            specified_workflow(
+                &mut NoRenderer,
+                &mut remote.matches,
+                &remote.remote.git,
+                None,
+            )
+            .unwrap_err()
+            .to_string()
+        };
+
+        assert_eq!(
+            error(CliTest::default(), &["sync", "--host", "desk/top"], None),
+            "Invalid host \"desk/top\" from --host, it must not contain '/'",
+        );
+        assert_eq!(
+            error(
+                CliTest {
+                    default_host: Host::from("Ronuks MacBook"),
+                    ..CliTest::default()
+                },
+                &["sync"],
+                None
+            ),
+            "Invalid host \"Ronuks MacBook\" from the operating system default, it must not contain ' '",
+        );
+        assert_eq!(
+            error(CliTest::default(), &["sync"], Some("host:0")),
+            "Invalid host \"host:0\" from nomad.host in git config, it must not contain ':'",
+        );
+    }
+
+    /// The host name is the default host unless `machine-id` is asked for, which falls back to the
+    /// host name when there is no usable machine ID.
+    #[test]
+    fn default_host_source() {
+        let default_host = |source: Option<&str>, machine_id: Option<&str>| {
+            default_host(source.map(OsString::from), || machine_id.map(String::from))
+        };
+
+        for (source, machine_id) in [
+            (None, Some("0123abcd")),
+            (Some("hostname"), Some("0123abcd")),
+            (Some("machine-id"), None),
+            (Some("machine-id"), Some(" \n")),
+        ] {
+            assert_eq!(
+                default_host(source, machine_id).unwrap(),
+                Host::os_default(),
+                "{:?} {:?}",
+                source,
+                machine_id
+            );
+        }
+
+        // `printf git-nomad0123abcd | sha256sum | cut -c1-12`
+        let host = Some(Host::from("494b4d097c8f"));
+        assert_eq!(
+            default_host(Some("machine-id"), Some("0123abcd")).unwrap(),
+            host
+        );
+        assert_eq!(
+            default_host(Some("machine-id"), Some("0123ABCD\n")).unwrap(),
+            host
+        );
+        assert_eq!(
+            default_host(Some("uuid"), None).unwrap_err().to_string(),
+            "Invalid GIT_NOMAD_HOST_SOURCE \"uuid\", expected hostname or machine-id",
+        );
+    }
+
+    #[test]
+    fn platform_uuid() {
+        let ioreg = r#"+-o J316sAP  <class IOPlatformExpertDevice, id 0x100000222, registered>
+    {
+      "IOPlatformSerialNumber" = "C02XXXXXXXXX"
+      "IOPlatformUUID" = "1A2B3C4D-0000-1111-2222-333344445555"
+      "IOPolledInterface" = "AppleARMWatchdogTimerHibernateHandler is not serializable"
+    }
+"#;
+        assert_eq!(
+            parse_platform_uuid(ioreg).as_deref(),
+            Some("1A2B3C4D-0000-1111-2222-333344445555"),
+        );
+        assert_eq!(parse_platform_uuid(""), None);
+    }
+
+    /// Config should still work when the operating system cannot provide a user or host.
+    #[test]
+    fn missing_os_defaults_with_config() {
+        let mut remote = CliTestRemote {
+            matches: cli(None, None, ["git-nomad", "purge", "--all"]).unwrap(),
+            remote: GitRemote::init(Some(Verbosity::max())),
+        };
+        remote
+            .set_config(CONFIG_USER, "user0")
+            .set_config(CONFIG_HOST, "host0");
+
+        assert_eq!(
+            remote.workflow(),
+            Workflow::Purge {
+                user: User::from("user0"),
+                remote: DEFAULT_REMOTE.clone(),
+                host_filter: Filter::All,
+                branch_filter: Filter::All,
+                prune_confirm: PruneConfirm::default(),
+                older_than: None,
+                dry_run: false,
+                fast_prune: false,
+                local_only: false,
+                refs: Vec::new(),
+            }
+        );
+    }
+
+    /// A `.git-nomad.toml` in the working tree should win over git config, but not over the command
+    /// line.
+    #[test]
+    fn nomad_file() {
+        let cli_test = CliTest::default();
+        for (args, config, expected_user, expected_host) in [
+            (&["prompt"] as &[&str], false, "file_user", "file_host"),
+            (&["prompt"], true, "file_user", "config_host"),
+            (
+                &["prompt", "--user", "cli_user"],
+                true,
+                "cli_user",
+                "config_host",
+            ),
+        ] {
+            let mut remote = cli_test.remote(args);
+            if config {
+                remote
+                    .set_config(CONFIG_USER, "config_user")
+                    .set_config(CONFIG_HOST, "config_host");
+            }
+            let file = if config {
+                "user = \"file_user\"\n"
+            } else {
+                "user = \"file_user\"\nhost = \"file_host\"\n"
+            };
+            fs::write(remote.remote.working_directory().join(NOMAD_FILE), file).unwrap();
+
+            assert_eq!(
+                remote.workflow(),
+                Workflow::Prompt {
+                    user: User::from(expected_user),
+                    host: Host::from(expected_host),
+                },
+                "{:?}",
+                args,
+            );
+        }
+    }
+
+    /// A malformed `.git-nomad.toml` should be reported along with how it fits in.
+    #[test]
+    fn nomad_file_malformed() {
+        let cli_test = CliTest::default();
+        for contents in ["user = ", "branch = \"master\"\n"] {
+            let mut remote = cli_test.remote(&["prompt"]);
+            let path = remote.remote.working_directory().join(NOMAD_FILE);
+            fs::write(&path, contents).unwrap();
+
+            let error = specified_workflow(
+                &mut NoRenderer,
+                &mut remote.matches,
+                &remote.remote.git,
+                None,
+            )
+            .unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                format!(
+                    "Invalid {}, it may only set `user` and `host`, which are used unless given by --user/--host or the environment and take precedence over git config",
+                    path.display()
+                ),
+            );
+        }
+    }
+
+    /// Should print help and stop processing if no subcommand is specified.
+    #[test]
+    fn subcommand_is_required() {
+        let cli_test = CliTest::default();
+        let matches = cli_test.matches(&[]);
+        assert!(matches.is_err());
+        assert_eq!(
+            match matches {
+                Ok(_) => unreachable!(),
+                Err(e) => e.kind(),
+            },
+            ErrorKind::DisplayHelpOnMissingArgumentOrSubcommand,
+        );
+    }
+
+    /// `--git` before/after the subcommand.
+    #[test]
+    fn git_option() {
+        for args in &[&["--git", "foo", "ls"], &["ls", "--git", "foo"]] {
+            println!("{:?}", args);
+            let cli_test = CliTest::default();
+            let mut matches = cli_test.matches(*args).unwrap();
+            assert_eq!(specified_git(&mut matches), "foo");
+        }
+    }
+
+    #[test]
+    fn quiet_verbosity() {
+        for args in &[
+            &["--quiet", "ls"],
+            &["-q", "ls"],
+            &["ls", "--quiet"],
+            &["ls", "-q"],
+        ] {
+            println!("{:?}", args);
+            let cli_test = CliTest::default();
+            let mut matches = cli_test.matches(*args).unwrap();
+            assert_eq!(specified_verbosity(&mut matches), None);
+        }
+    }
+
+    #[test]
+    fn no_progress_verbosity() {
+        for args in &[&["--no-progress", "ls"], &["ls", "--no-progress"]] {
+            println!("{:?}", args);
+            let cli_test = CliTest::default();
+            let mut matches = cli_test.matches(*args).unwrap();
+            assert_eq!(
+                specified_verbosity(&mut matches),
+                Some(Verbosity::no_progress())
+            );
+        }
+
+        for args in &[
+            &["--no-progress", "-q", "ls"],
+            &["ls", "-v", "--no-progress"],
+        ] {
+            println!("{:?}", args);
+            let error = CliTest::default().matches(*args).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::ArgumentConflict);
+        }
+    }
+
+    #[test]
+    fn quiet_errors() {
+        for (args, expected) in [
+            (&["ls"] as &[&str], false),
+            (&["--quiet-errors", "ls"], true),
+            (&["ls", "--quiet-errors", "--no-progress"], true),
+            (&["ls", "--quiet-errors", "-q"], true),
+            (&["ls", "--quiet-errors", "-v"], false),
+        ] {
+            println!("{:?}", args);
+            let cli_test = CliTest::default();
+            let mut matches = cli_test.matches(args).unwrap();
+            let verbosity = specified_verbosity(&mut matches);
+            assert_eq!(specified_quiet_errors(&mut matches, verbosity), expected);
+        }
+    }
+
+    #[test]
+    fn allow_prompt() {
+        for (args, expected) in [
+            (&["ls"] as &[&str], false),
+            (&["--allow-prompt", "ls"], true),
+            (&["sync", "--allow-prompt"], true),
+        ] {
+            println!("{:?}", args);
+            let cli_test = CliTest::default();
+            let mut matches = cli_test.matches(args).unwrap();
+            assert_eq!(specified_allow_prompt(&mut matches), expected);
+        }
+    }
+
+    #[test]
+    fn retries() {
+        for (args, expected) in [
+            (&["ls"] as &[&str], 2),
+            (&["--retries", "0", "ls"], 0),
+            (&["sync", "--retries", "5"], 5),
+        ] {
+            println!("{:?}", args);
+            let cli_test = CliTest::default();
+            let mut matches = cli_test.matches(args).unwrap();
+            assert_eq!(
+                specified_retries(&mut matches),
+                Retries {
+                    count: expected,
+                    backoff: RETRY_BACKOFF,
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn ssh_multiplex() {
+        for (args, expected) in [
+            (&["ls"] as &[&str], false),
+            (&["--ssh-multiplex", "ls"], true),
+            (&["sync", "--ssh-multiplex"], true),
+        ] {
+            println!("{:?}", args);
+            let cli_test = CliTest::default();
+            let mut matches = cli_test.matches(args).unwrap();
+            assert_eq!(specified_ssh_multiplex(&mut matches), expected);
+        }
+    }
+
+    /// The descriptor for `--progress-fd` should be a duplicate that is not inherited by the
+    /// commands nomad runs.
+    #[test]
+    fn progress_fd() {
+        use std::os::fd::AsRawFd;
+
+        let cli_test = CliTest::default();
+        let mut matches = cli_test.matches(&["ls"]).unwrap();
+        assert!(specified_progress_fd(&mut matches).unwrap().is_none());
+
+        let mut matches = cli_test.matches(&["ls", "--progress-fd", "2"]).unwrap();
+        let progress = specified_progress_fd(&mut matches).unwrap().unwrap();
+        assert_ne!(progress.as_raw_fd(), 2);
+        // SAFETY: `F_GETFD` only queries the descriptor.
+        let flags = unsafe { libc::fcntl(progress.as_raw_fd(), libc::F_GETFD) };
+        assert_eq!(flags & libc::FD_CLOEXEC, libc::FD_CLOEXEC);
+    }
+
+    #[test]
+    fn git_namespace() {
+        let cli_test = CliTest::default();
+        let mut matches = cli_test.matches(&["ls"]).unwrap();
+        assert_eq!(specified_git_namespace(&mut matches), None);
+
+        let mut matches = cli_test
+            .matches(&["sync", "--git-namespace", "nomad"])
+            .unwrap();
+        assert_eq!(
+            specified_git_namespace(&mut matches),
+            Some("nomad".to_string())
+        );
+
+        let error = cli_test
+            .matches(&["sync", "--git-namespace", ""])
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidValue);
+    }
+
+    #[test]
+    fn verify_objects() {
+        for (args, expected) in [
+            (&["ls"] as &[&str], false),
+            (&["--verify-objects", "ls"], true),
+            (&["sync", "--verify-objects"], true),
+        ] {
+            println!("{:?}", args);
+            let cli_test = CliTest::default();
+            let mut matches = cli_test.matches(args).unwrap();
+            assert_eq!(specified_verify_objects(&mut matches), expected);
+        }
+    }
+
+    #[test]
+    fn default_verbosity() {
+        let cli_test = CliTest::default();
+        let mut matches = cli_test.matches(&["ls"]).unwrap();
+        assert_eq!(
+            specified_verbosity(&mut matches),
+            Some(Verbosity::default())
+        );
+    }
+
+    #[test]
+    fn verbose_verbosity() {
+        for args in &[
+            &["--verbose", "ls"],
+            &["-v", "ls"],
+            &["ls", "--verbose"],
+            &["ls", "-v"],
+        ] {
+            println!("{:?}", args);
+            let cli_test = CliTest::default();
+            let mut matches = cli_test.matches(*args).unwrap();
+            assert_eq!(
+                specified_verbosity(&mut matches),
+                Some(Verbosity::verbose())
+            );
+        }
+    }
+
+    #[test]
+    fn max_verbosity() {
+        for args in &[
+            &["--verbose", "--verbose", "ls"] as &[&str],
+            &["ls", "-vv"],
+            &["ls", "-v", "--verbose"],
+            &["ls", "-vv", "-vv"],
+        ] {
+            println!("{:?}", args);
+            let cli_test = CliTest::default();
+            let mut matches = cli_test.matches(args).unwrap();
+            assert_eq!(specified_verbosity(&mut matches), Some(Verbosity::max()));
+        }
+    }
+
+    #[test]
+    fn ls() {
+        let cli_test = CliTest::default();
+        assert_eq!(
+            cli_test.remote(&["ls"]).workflow(),
+            Workflow::Ls {
+                user: cli_test.default_user.always_borrow(),
+                fetch_remotes: Vec::new(),
+                fetch_options: FetchOptions::default(),
+                options: LsOptions {
+                    host_filter: cli_test.default_host_filter(),
+                    user_filter: cli_test.default_user_filter(),
+                    ..LsOptions::default()
+                },
+                tags: false,
+                watch_interval: None,
+            },
+        );
+    }
+
+    #[test]
+    fn ls_fetch_remote_default() {
+        let cli_test = CliTest::default();
+        assert_eq!(
+            cli_test.remote(&["ls", "--fetch"]).workflow(),
+            Workflow::Ls {
+                user: cli_test.default_user.always_borrow(),
+                fetch_remotes: vec![DEFAULT_REMOTE],
+                fetch_options: FetchOptions::default(),
+                options: LsOptions {
+                    host_filter: cli_test.default_host_filter(),
+                    user_filter: cli_test.default_user_filter(),
+                    ..LsOptions::default()
+                },
+                tags: false,
+                watch_interval: None,
+            },
+        );
+    }
+
+    #[test]
+    fn ls_fetch_remote_global() {
+        let cli_test = CliTest::default();
+        assert_eq!(
+            cli_test
+                .remote(&["--remote", "foo", "ls", "--fetch"])
+                .workflow(),
+            Workflow::Ls {
+                user: cli_test.default_user.always_borrow(),
+                fetch_remotes: vec![Remote::from("foo")],
+                fetch_options: FetchOptions::default(),
+                options: LsOptions {
+                    host_filter: cli_test.default_host_filter(),
+                    user_filter: cli_test.default_user_filter(),
+                    ..LsOptions::default()
+                },
+                tags: false,
+                watch_interval: None,
+            },
+        );
+    }
+
+    #[test]
+    fn ls_fetch_remote_local() {
+        let cli_test = CliTest::default();
+        assert_eq!(
+            cli_test
+                .remote(&["ls", "--fetch", "--remote", "foo"])
+                .workflow(),
+            Workflow::Ls {
+                user: cli_test.default_user.always_borrow(),
+                fetch_remotes: vec![Remote::from("foo")],
+                fetch_options: FetchOptions::default(),
+                options: LsOptions {
+                    host_filter: cli_test.default_host_filter(),
+                    user_filter: cli_test.default_user_filter(),
+                    ..LsOptions::default()
+                },
+                tags: false,
+                watch_interval: None,
+            },
+        );
+    }
+
+    #[test]
+    fn ls_fetch_remote_mirrors() {
+        let cli_test = CliTest::default();
+        assert_eq!(
+            cli_test
+                .remote(&["ls", "--fetch", "--remote", "origin", "--remote", "backup"])
+                .workflow(),
+            Workflow::Ls {
+                user: cli_test.default_user.always_borrow(),
+                fetch_remotes: vec![Remote::from("origin"), Remote::from("backup")],
+                fetch_options: FetchOptions::default(),
+                options: LsOptions {
+                    host_filter: cli_test.default_host_filter(),
+                    user_filter: cli_test.default_user_filter(),
+                    ..LsOptions::default()
+                },
+                tags: false,
+                watch_interval: None,
+            },
+        );
+    }
+
+    #[test]
+    fn ls_fetch_partial() {
+        let cli_test = CliTest::default();
+        assert_eq!(
+            cli_test.remote(&["ls", "--fetch", "--partial"]).workflow(),
+            Workflow::Ls {
+                user: cli_test.default_user.always_borrow(),
+                fetch_remotes: vec![DEFAULT_REMOTE],
+                fetch_options: FetchOptions {
+                    partial: true,
+                    ..FetchOptions::default()
+                },
+                options: LsOptions {
+                    host_filter: cli_test.default_host_filter(),
+                    user_filter: cli_test.default_user_filter(),
+                    ..LsOptions::default()
+                },
+                tags: false,
+                watch_interval: None,
+            },
+        );
+    }
+
+    #[test]
+    fn ls_fetch_depth() {
+        let cli_test = CliTest::default();
+        assert_eq!(
+            cli_test
+                .remote(&["ls", "--fetch", "--depth", "1"])
+                .workflow(),
+            Workflow::Ls {
+                user: cli_test.default_user.always_borrow(),
+                fetch_remotes: vec![DEFAULT_REMOTE],
+                fetch_options: FetchOptions {
+                    depth: Some(1),
+                    ..FetchOptions::default()
+                },
+                options: LsOptions {
+                    host_filter: cli_test.default_host_filter(),
+                    user_filter: cli_test.default_user_filter(),
+                    ..LsOptions::default()
+                },
+                tags: false,
+                watch_interval: None,
+            },
+        );
+    }
+
+    /// `--partial` only makes sense when fetching.
+    #[test]
+    fn ls_partial_requires_fetch() {
+        let cli_test = CliTest::default();
+        assert!(cli_test.matches(&["ls", "--partial"]).is_err());
+    }
+
+    #[test]
+    fn ls_print_grouped() {
+        for args in &[
+            &["ls", "--print", "grouped"] as &[&str],
+            &["ls", "--print=grouped"],
+        ] {
+            println!("{:?}", args);
+
+            let cli_test = CliTest::default();
+            assert_eq!(
+                cli_test.remote(args).workflow(),
+                Workflow::Ls {
+                    user: cli_test.default_user.always_borrow(),
+                    fetch_remotes: Vec::new(),
+                    fetch_options: FetchOptions::default(),
+                    options: LsOptions {
+                        host_filter: cli_test.default_host_filter(),
+                        user_filter: cli_test.default_user_filter(),
+                        ..LsOptions::default()
+                    },
+                    tags: false,
+                    watch_interval: None,
+                },
+            );
+        }
+    }
+
+    #[test]
+    fn ls_print_ref() {
+        for args in &[&["ls", "--print", "ref"] as &[&str], &["ls", "--print=ref"]] {
+            println!("{:?}", args);
+
+            let cli_test = CliTest::default();
+            assert_eq!(
+                cli_test.remote(args).workflow(),
+                Workflow::Ls {
+                    user: cli_test.default_user.always_borrow(),
+                    fetch_remotes: Vec::new(),
+                    fetch_options: FetchOptions::default(),
+                    options: LsOptions {
+                        printer: LsPrinter::Ref,
+                        host_filter: cli_test.default_host_filter(),
+                        user_filter: cli_test.default_user_filter(),
+                        ..LsOptions::default()
+                    },
+                    tags: false,
+                    watch_interval: None,
+                },
+            );
+        }
+    }
+
+    #[test]
+    fn ls_color_by_host() {
+        for (args, printer) in [
+            (
+                &["ls", "--color-by-host"] as &[&str],
+                LsPrinter::ColoredGrouped,
+            ),
+            (&["ls", "--color-by-host", "--print=ref"], LsPrinter::Ref),
+            (&["ls", "--print=tree"], LsPrinter::Tree),
+            (&["ls", "--print=tree", "--unicode"], LsPrinter::UnicodeTree),
+            (&["ls", "--unicode"], LsPrinter::Grouped),
+        ] {
+            println!("{:?}", args);
+
+            let cli_test = CliTest::default();
+            assert_eq!(
+                cli_test.remote(args).workflow(),
+                Workflow::Ls {
+                    user: cli_test.default_user.always_borrow(),
+                    fetch_remotes: Vec::new(),
+                    fetch_options: FetchOptions::default(),
+                    options: LsOptions {
+                        printer,
+                        host_filter: cli_test.default_host_filter(),
+                        user_filter: cli_test.default_user_filter(),
+                        ..LsOptions::default()
+                    },
+                    tags: false,
+                    watch_interval: None,
+                },
+            );
+        }
+    }
+
+    /// `--format` should override `--print`, and reject placeholders it does not know about.
+    #[test]
+    fn ls_format() {
+        let cli_test = CliTest::default();
+        assert_eq!(
+            cli_test
+                .remote(&[
+                    "ls",
+                    "--print=json",
+                    "--format",
+                    "host=%h branch=%b commit=%c"
+                ])
+                .workflow(),
+            Workflow::Ls {
+                user: cli_test.default_user.always_borrow(),
+                fetch_remotes: Vec::new(),
+                fetch_options: FetchOptions::default(),
+                options: LsOptions {
+                    printer: LsPrinter::Template("host=%h branch=%b commit=%c".to_string()),
+                    host_filter: cli_test.default_host_filter(),
+                    user_filter: cli_test.default_user_filter(),
+                    ..LsOptions::default()
+                },
+                tags: false,
+                watch_interval: None,
+            },
+        );
+
+        let error = cli_test.matches(&["ls", "--format", "%h %x"]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ValueValidation);
+        assert!(error.to_string().contains("unknown placeholder %x"));
+    }
+
+    /// `--format-host` should be passed through, and reject placeholders it does not know about.
+    #[test]
+    fn ls_format_host() {
+        let cli_test = CliTest::default();
+        match cli_test
+            .remote(&["ls", "--format-host", "{host} ({count})"])
+            .workflow()
+        {
+            Workflow::Ls {
+                options: LsOptions { format_host, .. },
+                ..
+            } => {
+                assert_eq!(format_host.as_deref(), Some("{host} ({count})"))
+            }
+            other => panic!("Unexpected workflow: {:?}", other),
+        }
+
+        let error = cli_test
+            .matches(&["ls", "--format-host", "{branch}"])
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ValueValidation);
+        assert!(error.to_string().contains("unknown placeholder {branch}"));
+    }
+
+    /// `--other-user` should add to the user being listed, but only with a remote to list from.
+    #[test]
+    fn ls_other_user() {
+        let cli_test = CliTest::default();
+        match cli_test
+            .remote(&[
+                "ls",
+                "--fetch",
+                "--other-user",
+                "user1",
+                "--other-user",
+                "user2",
+            ])
+            .workflow()
+        {
+            Workflow::Ls {
+                options: LsOptions { user_filter, .. },
+                ..
+            } => assert_eq!(
+                user_filter,
+                Filter::Allow(
+                    [
+                        cli_test.default_user.always_borrow(),
+                        User::from("user1"),
+                        User::from("user2"),
+                    ]
+                    .into()
+                )
+            ),
+            other => panic!("Unexpected workflow: {:?}", other),
+        }
+
+        let mut remote = cli_test.remote(&["ls", "--other-user", "user1"]);
+        let error = specified_workflow(
+            &mut NoRenderer,
+            &mut remote.matches,
+            &remote.remote.git,
+            None,
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("needs --fetch"));
+
+        let error = cli_test
+            .matches(&["ls", "--fetch", "--other-user", "bad user"])
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ValueValidation);
+    }
+
+    #[test]
+    fn ls_sort() {
+        let cli_test = CliTest::default();
+        for (args, expected) in [
+            (&["ls"] as &[&str], LsSort::Name),
+            (&["ls", "--sort=commit-date"], LsSort::CommitDate),
+        ] {
+            match cli_test.remote(args).workflow() {
+                Workflow::Ls {
+                    options: LsOptions { sort, .. },
+                    ..
+                } => assert_eq!(sort, expected),
+                other => panic!("Unexpected workflow: {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn ls_abbrev() {
+        let cli_test = CliTest::default();
+        let workflow_abbrev = |args: &[&str], core_abbrev: Option<&str>| {
+            let mut remote = cli_test.remote(args);
+            if let Some(value) = core_abbrev {
+                remote
+                    .remote
+                    .git
+                    .command()
+                    .args(["config", "core.abbrev", value])
+                    .status()
+                    .unwrap();
+            }
+            match remote.workflow() {
+                Workflow::Ls {
+                    options: LsOptions { abbrev, .. },
+                    ..
+                } => abbrev,
+                other => panic!("Unexpected workflow: {:?}", other),
+            }
+        };
+
+        assert_eq!(workflow_abbrev(&["ls"], Some("12")), None);
+        assert_eq!(workflow_abbrev(&["ls", "--abbrev"], None), Some(7));
+        assert_eq!(workflow_abbrev(&["ls", "--abbrev"], Some("12")), Some(12));
+        assert_eq!(workflow_abbrev(&["ls", "--abbrev=9"], Some("12")), Some(9));
+    }
+
+    #[test]
+    fn ls_watch() {
+        let cli_test = CliTest::default();
+        let workflow_watch = |args: &[&str]| match cli_test.remote(args).workflow() {
+            Workflow::Ls {
+                fetch_remotes,
+                watch_interval,
+                ..
+            } => (
+                fetch_remotes
+                    .into_iter()
+                    .map(|remote| remote.0.into_owned())
+                    .collect::<Vec<_>>(),
+                watch_interval,
+            ),
+            other => panic!("Unexpected workflow: {:?}", other),
+        };
+
+        assert_eq!(workflow_watch(&["ls"]), (Vec::new(), None));
+        assert_eq!(
+            workflow_watch(&["ls", "--watch"]),
+            (vec![DEFAULT_REMOTE.0.to_string()], Some(30))
+        );
+        assert_eq!(
+            workflow_watch(&["ls", "--watch", "--interval", "5"]),
+            (vec![DEFAULT_REMOTE.0.to_string()], Some(5))
+        );
+
+        for args in [
+            &["ls", "--interval", "5"][..],
+            &["ls", "--watch", "--interval", "0"],
+        ] {
+            assert!(cli_test.matches(args).is_err(), "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn ls_diff_self() {
+        let cli_test = CliTest::default();
+        assert_eq!(
+            cli_test.remote(&["ls", "--diff-self"]).workflow(),
+            Workflow::Ls {
+                user: cli_test.default_user.always_borrow(),
+                fetch_remotes: Vec::new(),
+                fetch_options: FetchOptions::default(),
+                options: LsOptions {
+                    host_filter: cli_test.default_host_filter(),
+                    user_filter: cli_test.default_user_filter(),
+                    diff_self: true,
+                    ..LsOptions::default()
+                },
+                tags: false,
+                watch_interval: None,
+            },
+        );
+    }
+
+    #[test]
+    fn ls_since() {
+        let cli_test = CliTest::default();
+        assert_eq!(
+            cli_test
+                .remote(&["ls", "--since", "origin/main"])
+                .workflow(),
+            Workflow::Ls {
+                user: cli_test.default_user.always_borrow(),
+                fetch_remotes: Vec::new(),
+                fetch_options: FetchOptions::default(),
+                options: LsOptions {
+                    host_filter: cli_test.default_host_filter(),
+                    user_filter: cli_test.default_user_filter(),
+                    since: Some("origin/main".to_string()),
+                    ..LsOptions::default()
+                },
+                tags: false,
+                watch_interval: None,
+            },
+        );
+    }
+
+    #[test]
+    fn ls_print_json() {
+        let cli_test = CliTest::default();
+        assert_eq!(
+            cli_test.remote(&["ls", "--print=json"]).workflow(),
+            Workflow::Ls {
+                user: cli_test.default_user.always_borrow(),
+                fetch_remotes: Vec::new(),
+                fetch_options: FetchOptions::default(),
+                options: LsOptions {
+                    printer: LsPrinter::Json,
+                    host_filter: cli_test.default_host_filter(),
+                    user_filter: cli_test.default_user_filter(),
+                    ..LsOptions::default()
+                },
+                tags: false,
+                watch_interval: None,
+            },
+        );
+    }
+
+    #[test]
+    fn ls_porcelain() {
+        for args in &[
+            &["ls", "--porcelain"] as &[&str],
+            &["ls", "--print=ref", "--porcelain"],
+        ] {
+            println!("{:?}", args);
+
+            let cli_test = CliTest::default();
+            assert_eq!(
+                cli_test.remote(args).workflow(),
+                Workflow::Ls {
+                    user: cli_test.default_user.always_borrow(),
+                    fetch_remotes: Vec::new(),
+                    fetch_options: FetchOptions::default(),
+                    options: LsOptions {
+                        printer: LsPrinter::Porcelain,
+                        host_filter: cli_test.default_host_filter(),
+                        user_filter: cli_test.default_user_filter(),
+                        ..LsOptions::default()
+                    },
+                    tags: false,
+                    watch_interval: None,
+                },
+            );
+        }
+    }
+
+    #[test]
+    fn ls_porcelain_conflicts_with_format() {
+        let cli_test = CliTest::default();
+        assert!(cli_test
+            .matches(&["ls", "--porcelain", "--format", "%(refname)"])
+            .is_err());
+    }
+
+    #[test]
+    fn ls_print_commit() {
+        for args in &[
+            &["ls", "--print", "commit"] as &[&str],
+            &["ls", "--print=commit"],
+        ] {
+            println!("{:?}", args);
+
+            let cli_test = CliTest::default();
+            assert_eq!(
+                cli_test.remote(args).workflow(),
+                Workflow::Ls {
+                    user: cli_test.default_user.always_borrow(),
+                    fetch_remotes: Vec::new(),
+                    fetch_options: FetchOptions::default(),
+                    options: LsOptions {
+                        printer: LsPrinter::Commit,
+                        host_filter: cli_test.default_host_filter(),
+                        user_filter: cli_test.default_user_filter(),
+                        ..LsOptions::default()
+                    },
+                    tags: false,
+                    watch_interval: None,
+                },
+            );
+        }
+    }
+
+    #[test]
+    fn ls_explicit() {
+        let cli_test = CliTest::default();
+        assert_eq!(
+            cli_test.remote(&["ls", "-U", "explicit_user"]).workflow(),
+            Workflow::Ls {
+                user: User::from("explicit_user"),
+                fetch_remotes: Vec::new(),
+                fetch_options: FetchOptions::default(),
+                options: LsOptions {
+                    host_filter: cli_test.default_host_filter(),
+                    user_filter: Filter::Allow([User::from("explicit_user")].into()),
+                    ..LsOptions::default()
+                },
+                tags: false,
+                watch_interval: None,
+            },
+        );
+    }
+
+    #[test]
+    fn ref_prefix() {
+        let cli_test = CliTest::default();
+        assert_eq!(
+            cli_test.remote(&["ls"]).ref_prefix().unwrap(),
+            DEFAULT_REF_PREFIX
+        );
+        assert_eq!(
+            cli_test
+                .remote(&["ls"])
+                .set_config(CONFIG_REF_PREFIX, "config-prefix")
+                .ref_prefix()
+                .unwrap(),
+            "config-prefix"
+        );
+        assert_eq!(
+            cli_test
+                .remote(&["--ref-prefix", "team-a", "ls"])
+                .set_config(CONFIG_REF_PREFIX, "config-prefix")
+                .ref_prefix()
+                .unwrap(),
+            "team-a"
+        );
+    }
+
+    #[test]
+    fn ref_prefix_invalid() {
+        let cli_test = CliTest::default();
+        for prefix in ["", "team/a"] {
+            assert!(cli_test
+                .remote(&["--ref-prefix", prefix, "ls"])
+                .ref_prefix()
+                .is_err());
+        }
+    }
+
+    /// Prefixes naming git's own hierarchies would overwrite real refs, from any source.
+    #[test]
+    fn ref_prefix_reserved() {
+        let cli_test = CliTest::default();
+        for prefix in ["heads", "tags", "remotes", "notes"] {
+            let error = cli_test
+                .remote(&["--ref-prefix", prefix, "ls"])
+                .ref_prefix()
+                .unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                format!(
+                    "Invalid ref prefix {:?}, refs/{} is reserved for git itself, pushing nomad refs there would overwrite them",
+                    prefix, prefix
+                ),
+            );
+
+            assert!(cli_test
+                .remote(&["ls"])
+                .set_config(CONFIG_REF_PREFIX, prefix)
+                .ref_prefix()
+                .is_err());
+        }
+
+        for prefix in ["nomad", "heads-nomad", "team-a"] {
+            assert_eq!(
+                cli_test
+                    .remote(&["--ref-prefix", prefix, "ls"])
+                    .ref_prefix()
+                    .unwrap(),
+                prefix
+            );
+        }
+    }
+
+    #[test]
+    fn ls_config_beats_default() {
+        let cli_test = CliTest::default();
+        assert_eq!(
+            cli_test
+                .remote(&["ls"])
+                .set_config(CONFIG_USER, "config_user")
+                .workflow(),
+            Workflow::Ls {
+                user: User::from("config_user"),
+                fetch_remotes: Vec::new(),
+                fetch_options: FetchOptions::default(),
+                options: LsOptions {
+                    host_filter: cli_test.default_host_filter(),
+                    user_filter: Filter::Allow([User::from("config_user")].into()),
+                    ..LsOptions::default()
+                },
+                tags: false,
+                watch_interval: None,
+            },
+        );
+    }
+
+    #[test]
+    fn ls_head() {
+        let cli_test = CliTest::default();
+        assert_eq!(
+            cli_test.remote(&["ls", "--head"]).workflow(),
+            Workflow::Ls {
+                user: cli_test.default_user.always_borrow(),
+                fetch_remotes: Vec::new(),
+                fetch_options: FetchOptions::default(),
+                options: LsOptions {
+                    host_filter: cli_test.default_host_filter(),
+                    branch_filter: Filter::Glob(vec![Glob::literal("master")]),
+                    user_filter: cli_test.default_user_filter(),
+                    ..LsOptions::default()
+                },
+                tags: false,
+                watch_interval: None,
+            },
+        );
+    }
+
+    #[test]
+    fn ls_branches() {
+        let cli_test = CliTest::default();
+        assert_eq!(
+            cli_test
+                .remote(&["ls", "-b", "foo", "--branch", "bar/*", "--branch=*-wip"])
+                .workflow(),
+            Workflow::Ls {
+                user: cli_test.default_user.always_borrow(),
+                fetch_remotes: Vec::new(),
+                fetch_options: FetchOptions::default(),
+                options: LsOptions {
+                    host_filter: cli_test.default_host_filter(),
+                    branch_filter: Filter::Glob(
+                        ["foo", "bar/*", "*-wip"]
+                            .map(|glob| glob.parse().unwrap())
+                            .into()
+                    ),
+                    user_filter: cli_test.default_user_filter(),
+                    ..LsOptions::default()
+                },
+                tags: false,
+                watch_interval: None,
+            },
+        );
+    }
+
+    #[test]
+    fn ls_regex() {
+        let cli_test = CliTest::default();
+        assert_eq!(
+            cli_test
+                .remote(&["ls", "--branch-regex", "^feat", "--host-regex", "laptop"])
+                .workflow(),
+            Workflow::Ls {
+                user: cli_test.default_user.always_borrow(),
+                fetch_remotes: Vec::new(),
+                fetch_options: FetchOptions::default(),
+                options: LsOptions {
+                    host_filter: Filter::Regex("laptop".parse().unwrap()),
+                    branch_filter: Filter::Regex("^feat".parse().unwrap()),
+                    user_filter: cli_test.default_user_filter(),
+                    ..LsOptions::default()
+                },
+                tags: false,
+                watch_interval: None,
+            },
+        );
+
+        let error = cli_test
+            .matches(&["ls", "--branch-regex", "feat("])
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ValueValidation);
+        assert!(error.to_string().contains("Invalid regex \"feat(\""));
+
+        for args in [
+            &["ls", "--branch-regex", "feat", "--branch", "foo"][..],
+            &["ls", "--branch-regex", "feat", "--head"],
+            &["ls", "--host-regex", "laptop", "--print-self"],
+        ] {
+            let error = cli_test.matches(args).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::ArgumentConflict, "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn ls_print_self() {
+        let cli_test = CliTest::default();
+        assert_eq!(
+            cli_test.remote(&["ls", "--print-self"]).workflow(),
+            Workflow::Ls {
+                user: cli_test.default_user.always_borrow(),
+                fetch_remotes: Vec::new(),
+                fetch_options: FetchOptions::default(),
+                options: LsOptions {
+                    user_filter: cli_test.default_user_filter(),
+                    ..LsOptions::default()
+                },
+                tags: false,
+                watch_interval: None,
+            },
+        );
+    }
+
+    /// Invoke `sync` with explicit `user` and `host`
+    #[test]
+    fn sync_explicit() {
         for args in &[
-            &["--verbose", "ls"],
-            &["-v", "ls"],
-            &["ls", "--verbose"],
-            &["ls", "-v"],
+            &[
+                "--user", "user0", "sync", "--host", "host0", "--remote", "remote",
+            ] as &[&str],
+            &["sync", "-U", "user0", "-H", "host0", "-R", "remote"],
         ] {
             println!("{:?}", args);
             let cli_test = CliTest::default();
-            let mut matches = cli_test.matches(*args).unwrap();
             assert_eq!(
-                specified_verbosity(&mut matches),
-                Some(Verbosity::verbose())
+                cli_test.remote(args).workflow(),
+                Workflow::Sync {
+                    user: User::from("user0"),
+                    host: Host::from("host0"),
+                    remotes: vec![Remote::from("remote")],
+                    options: SyncOptions::default(),
+                    branch_filter: Filter::All,
+                },
+            );
+        }
+    }
+
+    /// Invoke `sync` with `user` and `host` coming from `git config`.
+    #[test]
+    fn sync_config() {
+        let cli_test = CliTest::default();
+        assert_eq!(
+            cli_test
+                .remote(&["sync"])
+                .set_config(CONFIG_USER, "user0")
+                .set_config(CONFIG_HOST, "host0")
+                .workflow(),
+            Workflow::Sync {
+                user: User::from("user0"),
+                host: Host::from("host0"),
+                remotes: vec![DEFAULT_REMOTE.clone()],
+                options: SyncOptions::default(),
+                branch_filter: Filter::All,
+            }
+        );
+    }
+
+    /// Invoke `sync` with defaults.
+    #[test]
+    fn sync_default() {
+        let cli_test = CliTest::default();
+        assert_eq!(
+            cli_test.remote(&["sync"]).workflow(),
+            Workflow::Sync {
+                user: cli_test.default_user.always_borrow(),
+                host: cli_test.default_host.always_borrow(),
+                remotes: vec![DEFAULT_REMOTE.clone()],
+                options: SyncOptions::default(),
+                branch_filter: Filter::All,
+            }
+        );
+    }
+
+    /// Invoke `sync` with `--mirror-remote-branches`.
+    #[test]
+    fn sync_mirror_remote_branches() {
+        let cli_test = CliTest::default();
+        assert_eq!(
+            cli_test
+                .remote(&["sync", "--mirror-remote-branches"])
+                .workflow(),
+            Workflow::Sync {
+                user: cli_test.default_user.always_borrow(),
+                host: cli_test.default_host.always_borrow(),
+                remotes: vec![DEFAULT_REMOTE.clone()],
+                options: SyncOptions {
+                    mirror_remote_branches: true,
+                    ..SyncOptions::default()
+                },
+                branch_filter: Filter::All,
+            }
+        );
+    }
+
+    /// The `remote-<remote>` host needs a remote name rather than a URL or path.
+    #[test]
+    fn sync_mirror_remote_branches_conflicts_with_unnamed_remotes() {
+        let cli_test = CliTest::default();
+        let local_dir = tempfile::tempdir().unwrap();
+        let local_remote = local_dir.path().join("nomad.git");
+        let local_remote = local_remote.to_str().unwrap();
+        let remote_url = format!("file://{}", local_remote);
+
+        for args in [
+            &[
+                "sync",
+                "--mirror-remote-branches",
+                "--remote-url",
+                &remote_url,
+            ] as &[&str],
+            &[
+                "--remote-url",
+                &remote_url,
+                "sync",
+                "--mirror-remote-branches",
+            ],
+            &[
+                "sync",
+                "--mirror-remote-branches",
+                "--local-remote",
+                local_remote,
+            ],
+            &[
+                "--local-remote",
+                local_remote,
+                "sync",
+                "--mirror-remote-branches",
+            ],
+        ] {
+            let error = cli_test.remote(args).try_workflow().unwrap_err();
+            assert!(
+                error.to_string().contains("--mirror-remote-branches"),
+                "{:?}: {}",
+                args,
+                error
+            );
+        }
+        assert!(!local_dir.path().join("nomad.git").exists());
+    }
+
+    /// Invoke `sync` with `--partial`.
+    #[test]
+    fn sync_partial() {
+        let cli_test = CliTest::default();
+        assert_eq!(
+            cli_test.remote(&["sync", "--partial"]).workflow(),
+            Workflow::Sync {
+                user: cli_test.default_user.always_borrow(),
+                host: cli_test.default_host.always_borrow(),
+                remotes: vec![DEFAULT_REMOTE.clone()],
+                options: SyncOptions {
+                    fetch: FetchOptions {
+                        partial: true,
+                        ..FetchOptions::default()
+                    },
+                    ..SyncOptions::default()
+                },
+                branch_filter: Filter::All,
+            }
+        );
+    }
+
+    /// Invoke `sync` with `--fail-on-prune`.
+    #[test]
+    fn sync_fail_on_prune() {
+        let cli_test = CliTest::default();
+        assert_eq!(
+            cli_test.remote(&["sync", "--fail-on-prune"]).workflow(),
+            Workflow::Sync {
+                user: cli_test.default_user.always_borrow(),
+                host: cli_test.default_host.always_borrow(),
+                remotes: vec![DEFAULT_REMOTE.clone()],
+                options: SyncOptions {
+                    fail_on_prune: true,
+                    ..SyncOptions::default()
+                },
+                branch_filter: Filter::All,
+            }
+        );
+    }
+
+    /// Invoke `sync` with `--dry-run`.
+    #[test]
+    fn sync_dry_run() {
+        let cli_test = CliTest::default();
+        assert_eq!(
+            cli_test.remote(&["--dry-run", "sync"]).workflow(),
+            Workflow::Sync {
+                user: cli_test.default_user.always_borrow(),
+                host: cli_test.default_host.always_borrow(),
+                remotes: vec![DEFAULT_REMOTE.clone()],
+                options: SyncOptions {
+                    dry_run: true,
+                    ..SyncOptions::default()
+                },
+                branch_filter: Filter::All,
+            }
+        );
+    }
+
+    /// Invoke `sync` with `--show-refspecs`.
+    #[test]
+    fn sync_show_refspecs() {
+        let cli_test = CliTest::default();
+        assert_eq!(
+            cli_test.remote(&["sync", "--show-refspecs"]).workflow(),
+            Workflow::Sync {
+                user: cli_test.default_user.always_borrow(),
+                host: cli_test.default_host.always_borrow(),
+                remotes: vec![DEFAULT_REMOTE.clone()],
+                options: SyncOptions {
+                    show_refspecs: true,
+                    ..SyncOptions::default()
+                },
+                branch_filter: Filter::All,
+            }
+        );
+    }
+
+    /// Invoke `sync` with `--push-only` or `--fetch-only`.
+    #[test]
+    fn sync_direction() {
+        let cli_test = CliTest::default();
+        for (arg, direction) in [
+            ("--push-only", SyncDirection::PushOnly),
+            ("--fetch-only", SyncDirection::FetchOnly),
+        ] {
+            assert_eq!(
+                cli_test.remote(&["sync", arg]).workflow(),
+                Workflow::Sync {
+                    user: cli_test.default_user.always_borrow(),
+                    host: cli_test.default_host.always_borrow(),
+                    remotes: vec![DEFAULT_REMOTE.clone()],
+                    options: SyncOptions {
+                        direction,
+                        ..SyncOptions::default()
+                    },
+                    branch_filter: Filter::All,
+                }
+            );
+        }
+    }
+
+    /// `sync` should accept `--remote` multiple times, keeping the order they were given in.
+    #[test]
+    fn sync_multiple_remotes() {
+        let cli_test = CliTest::default();
+        assert_eq!(
+            cli_test
+                .remote(&["sync", "-R", "origin", "--remote", "backup"])
+                .workflow(),
+            Workflow::Sync {
+                user: cli_test.default_user.always_borrow(),
+                host: cli_test.default_host.always_borrow(),
+                remotes: vec![Remote::from("origin"), Remote::from("backup")],
+                options: SyncOptions::default(),
+                branch_filter: Filter::All,
+            }
+        );
+    }
+
+    /// Everything other than `sync` only works with a single remote.
+    #[test]
+    fn multiple_remotes_unsupported() {
+        let cli_test = CliTest::default();
+        let mut remote = cli_test.remote(&["du", "-R", "origin", "-R", "backup"]);
+        let error = specified_workflow(
+            &mut NoRenderer,
+            &mut remote.matches,
+            &remote.remote.git,
+            None,
+        )
+        .unwrap_err();
+        assert_eq!(error.to_string(), "du only supports a single --remote");
+    }
+
+    /// `--push-only` and `--fetch-only` are contradictory.
+    #[test]
+    fn sync_direction_conflict() {
+        let cli_test = CliTest::default();
+        let error = cli_test
+            .matches(&["sync", "--push-only", "--fetch-only"])
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn selective_fetch() {
+        let cli_test = CliTest::default();
+        assert_eq!(
+            cli_test.remote(&["sync", "--selective-fetch"]).workflow(),
+            Workflow::Sync {
+                user: cli_test.default_user.always_borrow(),
+                host: cli_test.default_host.always_borrow(),
+                remotes: vec![DEFAULT_REMOTE.clone()],
+                options: SyncOptions {
+                    selective_fetch: true,
+                    ..SyncOptions::default()
+                },
+                branch_filter: Filter::All,
+            }
+        );
+
+        let error = cli_test
+            .matches(&["sync", "--selective-fetch", "--push-only"])
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn sync_branch_filter() {
+        let cli_test = CliTest::default();
+        let sync = |branch_filter| Workflow::Sync {
+            user: cli_test.default_user.always_borrow(),
+            host: cli_test.default_host.always_borrow(),
+            remotes: vec![DEFAULT_REMOTE.clone()],
+            options: SyncOptions::default(),
+            branch_filter,
+        };
+
+        assert_eq!(
+            cli_test
+                .remote(&["sync", "-b", "foo", "--branch", "bar"])
+                .workflow(),
+            sync(Filter::Allow(
+                [Branch::from("foo"), Branch::from("bar")].into()
+            )),
+        );
+        assert_eq!(
+            cli_test
+                .remote(&["sync", "--branch-regex", "^wip/"])
+                .workflow(),
+            sync(Filter::Regex("^wip/".parse().unwrap())),
+        );
+
+        let error = cli_test
+            .matches(&["sync", "-b", "foo", "--branch-regex", "^wip/"])
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn sync_prune() {
+        let cli_test = CliTest::default();
+        for (args, prune) in [
+            (&["sync"] as &[&str], SyncPrune::Always),
+            (&["sync", "--prune", "always"], SyncPrune::Always),
+            (&["sync", "--prune", "never"], SyncPrune::Never),
+        ] {
+            assert_eq!(
+                cli_test.remote(args).workflow(),
+                Workflow::Sync {
+                    user: cli_test.default_user.always_borrow(),
+                    host: cli_test.default_host.always_borrow(),
+                    remotes: vec![DEFAULT_REMOTE.clone()],
+                    options: SyncOptions {
+                        prune,
+                        ..SyncOptions::default()
+                    },
+                    branch_filter: Filter::All,
+                },
+            );
+        }
+
+        let error = cli_test.matches(&["sync", "--prune", "no"]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidValue);
+    }
+
+    #[test]
+    fn remote_url() {
+        let cli_test = CliTest::default();
+        let url = "file:///tmp/remote.git";
+        match cli_test.remote(&["--remote-url", url, "sync"]).workflow() {
+            Workflow::Sync { remotes, .. } => assert_eq!(remotes, vec![Remote::from(url)]),
+            other => panic!("Unexpected workflow: {:?}", other),
+        }
+
+        let error = cli_test
+            .matches(&[
+                "--remote-url",
+                url,
+                "--local-remote",
+                "/tmp/nomad.git",
+                "sync",
+            ])
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn sync_all_remotes() {
+        let cli_test = CliTest::default();
+        for (args, message) in [
+            (
+                &["--remote", "backup", "sync", "--all-remotes"] as &[&str],
+                "--all-remotes replaces --remote, pass only one of them",
+            ),
+            (
+                &["sync", "--all-remotes"],
+                "--all-remotes found no remotes, add one with `git remote add`",
+            ),
+        ] {
+            let error = cli_test.remote(args).try_workflow().unwrap_err();
+            assert_eq!(error.to_string(), message, "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn sync_force() {
+        let cli_test = CliTest::default();
+        for (args, force) in [
+            (&["sync"] as &[&str], SyncForce::Always),
+            (&["sync", "--force", "always"], SyncForce::Always),
+            (&["sync", "--force", "with-lease"], SyncForce::WithLease),
+        ] {
+            assert_eq!(
+                cli_test.remote(args).workflow(),
+                Workflow::Sync {
+                    user: cli_test.default_user.always_borrow(),
+                    host: cli_test.default_host.always_borrow(),
+                    remotes: vec![DEFAULT_REMOTE.clone()],
+                    options: SyncOptions {
+                        force,
+                        ..SyncOptions::default()
+                    },
+                    branch_filter: Filter::All,
+                },
+            );
+        }
+
+        let error = cli_test.matches(&["sync", "--force", "lease"]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidValue);
+    }
+
+    #[test]
+    fn sync_push_rate_limit() {
+        let cli_test = CliTest::default();
+        for (args, push_rate_limit) in [
+            (&["sync"] as &[&str], None),
+            (
+                &["sync", "--min-push-interval", "60"],
+                Some(PushRateLimit {
+                    min_interval: 60,
+                    on_limit: OnRateLimit::Skip,
+                }),
+            ),
+            (
+                &[
+                    "sync",
+                    "--min-push-interval",
+                    "60",
+                    "--on-rate-limit",
+                    "wait",
+                ],
+                Some(PushRateLimit {
+                    min_interval: 60,
+                    on_limit: OnRateLimit::Wait,
+                }),
+            ),
+        ] {
+            assert_eq!(
+                cli_test.remote(args).workflow(),
+                Workflow::Sync {
+                    user: cli_test.default_user.always_borrow(),
+                    host: cli_test.default_host.always_borrow(),
+                    remotes: vec![DEFAULT_REMOTE.clone()],
+                    options: SyncOptions {
+                        push_rate_limit,
+                        ..SyncOptions::default()
+                    },
+                    branch_filter: Filter::All,
+                },
             );
         }
+
+        let error = cli_test
+            .matches(&["sync", "--on-rate-limit", "error"])
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::MissingRequiredArgument);
     }
 
     #[test]
-    fn max_verbosity() {
-        for args in &[
-            &["--verbose", "--verbose", "ls"] as &[&str],
-            &["ls", "-vv"],
-            &["ls", "-v", "--verbose"],
-            &["ls", "-vv", "-vv"],
-        ] {
-            println!("{:?}", args);
-            let cli_test = CliTest::default();
-            let mut matches = cli_test.matches(args).unwrap();
-            assert_eq!(specified_verbosity(&mut matches), Some(Verbosity::max()));
-        }
+    fn fast_prune() {
+        let cli_test = CliTest::default();
+        assert_eq!(
+            cli_test.remote(&["sync", "--fast-prune"]).workflow(),
+            Workflow::Sync {
+                user: cli_test.default_user.always_borrow(),
+                host: cli_test.default_host.always_borrow(),
+                remotes: vec![DEFAULT_REMOTE.clone()],
+                options: SyncOptions {
+                    fast_prune: true,
+                    ..SyncOptions::default()
+                },
+                branch_filter: Filter::All,
+            }
+        );
+        assert_eq!(
+            cli_test
+                .remote(&["purge", "--all", "--fast-prune"])
+                .workflow(),
+            Workflow::Purge {
+                user: cli_test.default_user.always_borrow(),
+                remote: DEFAULT_REMOTE.clone(),
+                host_filter: Filter::All,
+                branch_filter: Filter::All,
+                prune_confirm: PruneConfirm::default(),
+                older_than: None,
+                dry_run: false,
+                fast_prune: true,
+                local_only: false,
+                refs: Vec::new(),
+            }
+        );
     }
 
     #[test]
-    fn ls() {
+    fn purge_dry_run() {
         let cli_test = CliTest::default();
         assert_eq!(
-            cli_test.remote(&["ls"]).workflow(),
-            Workflow::Ls {
-                printer: LsPrinter::Grouped,
+            cli_test.remote(&["purge", "--all", "--dry-run"]).workflow(),
+            Workflow::Purge {
                 user: cli_test.default_user.always_borrow(),
-                fetch_remote: None,
-                host_filter: cli_test.default_host_filter(),
+                remote: DEFAULT_REMOTE.clone(),
+                host_filter: Filter::All,
                 branch_filter: Filter::All,
-            },
+                prune_confirm: PruneConfirm::default(),
+                older_than: None,
+                dry_run: true,
+                fast_prune: false,
+                local_only: false,
+                refs: Vec::new(),
+            }
         );
     }
 
     #[test]
-    fn ls_fetch_remote_default() {
+    fn purge_all() {
         let cli_test = CliTest::default();
         assert_eq!(
-            cli_test.remote(&["ls", "--fetch"]).workflow(),
-            Workflow::Ls {
-                printer: LsPrinter::Grouped,
+            cli_test.remote(&["purge", "--all"]).workflow(),
+            Workflow::Purge {
                 user: cli_test.default_user.always_borrow(),
-                fetch_remote: Some(DEFAULT_REMOTE),
-                host_filter: cli_test.default_host_filter(),
+                remote: DEFAULT_REMOTE.clone(),
+                host_filter: Filter::All,
                 branch_filter: Filter::All,
-            },
+                prune_confirm: PruneConfirm::default(),
+                older_than: None,
+                dry_run: false,
+                fast_prune: false,
+                local_only: false,
+                refs: Vec::new(),
+            }
         );
     }
 
     #[test]
-    fn ls_fetch_remote_global() {
+    fn purge_regex() {
         let cli_test = CliTest::default();
         assert_eq!(
             cli_test
-                .remote(&["--remote", "foo", "ls", "--fetch"])
+                .remote(&["purge", "--host-regex", "^old-", "--branch-regex", "wip"])
                 .workflow(),
-            Workflow::Ls {
-                printer: LsPrinter::Grouped,
+            Workflow::Purge {
                 user: cli_test.default_user.always_borrow(),
-                fetch_remote: Some(Remote::from("foo")),
-                host_filter: cli_test.default_host_filter(),
-                branch_filter: Filter::All,
-            },
+                remote: DEFAULT_REMOTE.clone(),
+                host_filter: Filter::Regex("^old-".parse().unwrap()),
+                branch_filter: Filter::Regex("wip".parse().unwrap()),
+                prune_confirm: PruneConfirm::default(),
+                older_than: None,
+                dry_run: false,
+                fast_prune: false,
+                local_only: false,
+                refs: Vec::new(),
+            }
         );
+
+        for args in [
+            &["purge", "--host-regex", "^old-", "--all"][..],
+            &["purge", "--branch-regex", "wip", "--branch", "foo"],
+        ] {
+            let error = cli_test.matches(args).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::ArgumentConflict, "{:?}", args);
+        }
     }
 
     #[test]
-    fn ls_fetch_remote_local() {
+    fn purge_branch() {
         let cli_test = CliTest::default();
         assert_eq!(
             cli_test
-                .remote(&["ls", "--fetch", "--remote", "foo"])
+                .remote(&["purge", "--all", "--branch", "foo", "-b", "bar"])
                 .workflow(),
-            Workflow::Ls {
-                printer: LsPrinter::Grouped,
+            Workflow::Purge {
                 user: cli_test.default_user.always_borrow(),
-                fetch_remote: Some(Remote::from("foo")),
-                host_filter: cli_test.default_host_filter(),
-                branch_filter: Filter::All,
-            },
+                remote: DEFAULT_REMOTE.clone(),
+                host_filter: Filter::All,
+                branch_filter: Filter::Allow(HashSet::from_iter([
+                    Branch::from("foo"),
+                    Branch::from("bar"),
+                ])),
+                prune_confirm: PruneConfirm::default(),
+                older_than: None,
+                dry_run: false,
+                fast_prune: false,
+                local_only: false,
+                refs: Vec::new(),
+            }
         );
     }
 
     #[test]
-    fn ls_print_grouped() {
-        for args in &[
-            &["ls", "--print", "grouped"] as &[&str],
-            &["ls", "--print=grouped"],
-        ] {
-            println!("{:?}", args);
+    fn purge_older_than() {
+        let cli_test = CliTest::default();
+        assert_eq!(
+            cli_test
+                .remote(&["purge", "--all", "--older-than", "2w"])
+                .workflow(),
+            Workflow::Purge {
+                user: cli_test.default_user.always_borrow(),
+                remote: DEFAULT_REMOTE.clone(),
+                host_filter: Filter::All,
+                branch_filter: Filter::All,
+                prune_confirm: PruneConfirm::default(),
+                older_than: Some("14d".parse().unwrap()),
+                dry_run: false,
+                fast_prune: false,
+                local_only: false,
+                refs: Vec::new(),
+            }
+        );
 
-            let cli_test = CliTest::default();
-            assert_eq!(
-                cli_test.remote(args).workflow(),
-                Workflow::Ls {
-                    printer: LsPrinter::Grouped,
-                    user: cli_test.default_user.always_borrow(),
-                    fetch_remote: None,
-                    host_filter: cli_test.default_host_filter(),
-                    branch_filter: Filter::All,
-                },
-            );
-        }
+        let error = cli_test
+            .matches(&["purge", "--older-than", "soon"])
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ValueValidation);
     }
 
+    /// `--ref` accepts nomad refs of the current user in either their remote or local form.
     #[test]
-    fn ls_print_ref() {
-        for args in &[&["ls", "--print", "ref"] as &[&str], &["ls", "--print=ref"]] {
-            println!("{:?}", args);
+    fn purge_ref() {
+        let cli_test = CliTest::default();
+        assert_eq!(
+            cli_test
+                .remote(&[
+                    "purge",
+                    "--ref",
+                    "refs/nomad/default_user/host0/feature",
+                    "--ref",
+                    "refs/nomad/host1/feature/nested",
+                ])
+                .workflow(),
+            Workflow::Purge {
+                user: cli_test.default_user.always_borrow(),
+                remote: DEFAULT_REMOTE.clone(),
+                host_filter: Filter::Allow(HashSet::from_iter([cli_test
+                    .default_host
+                    .always_borrow()])),
+                branch_filter: Filter::All,
+                prune_confirm: PruneConfirm::default(),
+                older_than: None,
+                dry_run: false,
+                fast_prune: false,
+                local_only: false,
+                refs: vec![
+                    "refs/nomad/default_user/host0/feature".to_string(),
+                    "refs/nomad/host1/feature/nested".to_string(),
+                ],
+            }
+        );
 
-            let cli_test = CliTest::default();
+        for name in [
+            "refs/heads/master",
+            "refs/nomad/host0",
+            "refs/nomad/host0/",
+            "feature",
+            "refs/other/default_user/host0/feature",
+        ] {
+            let error = cli_test
+                .remote(&["purge", "--ref", name])
+                .try_workflow()
+                .unwrap_err();
             assert_eq!(
-                cli_test.remote(args).workflow(),
-                Workflow::Ls {
-                    printer: LsPrinter::Ref,
-                    user: cli_test.default_user.always_borrow(),
-                    fetch_remote: None,
-                    host_filter: cli_test.default_host_filter(),
-                    branch_filter: Filter::All,
-                },
+                error.to_string(),
+                format!(
+                    "{} is not a nomad ref, expected refs/nomad/default_user/<host>/<branch> or refs/nomad/<host>/<branch>",
+                    name
+                ),
             );
         }
+
+        let error = cli_test
+            .matches(&["purge", "--all", "--ref", "refs/nomad/host0/feature"])
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ArgumentConflict);
     }
 
     #[test]
-    fn ls_print_commit() {
-        for args in &[
-            &["ls", "--print", "commit"] as &[&str],
-            &["ls", "--print=commit"],
-        ] {
-            println!("{:?}", args);
-
-            let cli_test = CliTest::default();
-            assert_eq!(
-                cli_test.remote(args).workflow(),
-                Workflow::Ls {
-                    printer: LsPrinter::Commit,
-                    user: cli_test.default_user.always_borrow(),
-                    fetch_remote: None,
-                    host_filter: cli_test.default_host_filter(),
-                    branch_filter: Filter::All,
+    fn purge_prune_confirm() {
+        let cli_test = CliTest::default();
+        assert_eq!(
+            cli_test
+                .remote(&["purge", "--all", "--prune-confirm-threshold", "5", "--yes"])
+                .workflow(),
+            Workflow::Purge {
+                user: cli_test.default_user.always_borrow(),
+                remote: DEFAULT_REMOTE.clone(),
+                host_filter: Filter::All,
+                branch_filter: Filter::All,
+                prune_confirm: PruneConfirm {
+                    threshold: 5,
+                    assume_yes: true,
                 },
-            );
-        }
+                older_than: None,
+                dry_run: false,
+                fast_prune: false,
+                local_only: false,
+                refs: Vec::new(),
+            }
+        );
     }
 
     #[test]
-    fn ls_explicit() {
+    fn adopt() {
         let cli_test = CliTest::default();
         assert_eq!(
-            cli_test.remote(&["ls", "-U", "explicit_user"]).workflow(),
-            Workflow::Ls {
-                printer: LsPrinter::Grouped,
-                user: User::from("explicit_user"),
-                fetch_remote: None,
-                host_filter: cli_test.default_host_filter(),
-                branch_filter: Filter::All,
-            },
+            cli_test
+                .remote(&[
+                    "adopt",
+                    "--pattern",
+                    "refs/custom/*",
+                    "--as-host",
+                    "laptop",
+                    "--fix"
+                ])
+                .workflow(),
+            Workflow::Adopt {
+                user: cli_test.default_user.always_borrow(),
+                remote: DEFAULT_REMOTE.clone(),
+                pattern: "refs/custom/*".to_string(),
+                host: Host::from("laptop"),
+                delete_originals: false,
+                fix: true,
+            }
         );
     }
 
     #[test]
-    fn ls_config_beats_default() {
+    fn fetch() {
         let cli_test = CliTest::default();
+        assert_eq!(
+            cli_test.remote(&["fetch"]).workflow(),
+            Workflow::Fetch {
+                user: cli_test.default_user.always_borrow(),
+                remote: DEFAULT_REMOTE,
+            }
+        );
         assert_eq!(
             cli_test
-                .remote(&["ls"])
-                .set_config(CONFIG_USER, "config_user")
+                .remote(&["fetch", "-R", "remote", "-U", "user0"])
                 .workflow(),
-            Workflow::Ls {
-                printer: LsPrinter::Grouped,
-                user: User::from("config_user"),
-                fetch_remote: None,
-                host_filter: cli_test.default_host_filter(),
-                branch_filter: Filter::All,
-            },
+            Workflow::Fetch {
+                user: User::from("user0"),
+                remote: Remote::from("remote"),
+            }
         );
     }
 
     #[test]
-    fn ls_head() {
+    fn du() {
         let cli_test = CliTest::default();
         assert_eq!(
-            cli_test.remote(&["ls", "--head"]).workflow(),
-            Workflow::Ls {
-                printer: LsPrinter::Grouped,
+            cli_test
+                .remote(&["du", "--json", "-R", "remote"])
+                .workflow(),
+            Workflow::Du {
                 user: cli_test.default_user.always_borrow(),
-                fetch_remote: None,
-                host_filter: cli_test.default_host_filter(),
-                branch_filter: Filter::Allow(["master"].map(Branch::from).into()),
-            },
+                remote: Remote::from("remote"),
+                json: true,
+            }
         );
     }
 
     #[test]
-    fn ls_branches() {
+    fn detect_prefix() {
         let cli_test = CliTest::default();
         assert_eq!(
             cli_test
-                .remote(&["ls", "-b", "foo", "--branch", "bar", "--branch=baz"])
+                .remote(&["detect-prefix", "-R", "remote"])
                 .workflow(),
-            Workflow::Ls {
-                printer: LsPrinter::Grouped,
-                user: cli_test.default_user.always_borrow(),
-                fetch_remote: None,
-                host_filter: cli_test.default_host_filter(),
-                branch_filter: Filter::Allow(["foo", "bar", "baz"].map(Branch::from).into()),
-            },
+            Workflow::DetectPrefix {
+                remote: Remote::from("remote"),
+            }
         );
     }
 
     #[test]
-    fn ls_print_self() {
+    fn checkout() {
         let cli_test = CliTest::default();
         assert_eq!(
-            cli_test.remote(&["ls", "--print-self"]).workflow(),
-            Workflow::Ls {
-                printer: LsPrinter::Grouped,
+            cli_test
+                .remote(&["checkout", "host0", "feature/login"])
+                .workflow(),
+            Workflow::Checkout {
                 user: cli_test.default_user.always_borrow(),
-                fetch_remote: None,
-                host_filter: Filter::All,
-                branch_filter: Filter::All,
-            },
+                remote: DEFAULT_REMOTE.clone(),
+                host: Host::from("host0"),
+                branch: Branch::from("feature/login"),
+            }
         );
+
+        let error = cli_test.matches(&["checkout", "host0"]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::MissingRequiredArgument);
     }
 
-    /// Invoke `sync` with explicit `user` and `host`
     #[test]
-    fn sync_explicit() {
-        for args in &[
-            &[
-                "--user", "user0", "sync", "--host", "host0", "--remote", "remote",
-            ] as &[&str],
-            &["sync", "-U", "user0", "-H", "host0", "-R", "remote"],
+    fn config_unset() {
+        for (args, scope) in [
+            (&["config", "unset", "host"] as &[&str], ConfigScope::Local),
+            (
+                &["config", "unset", "--global", "host"],
+                ConfigScope::Global,
+            ),
         ] {
             println!("{:?}", args);
             let cli_test = CliTest::default();
             assert_eq!(
                 cli_test.remote(args).workflow(),
-                Workflow::Sync {
-                    user: User::from("user0"),
-                    host: Host::from("host0"),
-                    remote: Remote::from("remote"),
-                },
+                Workflow::ConfigUnset {
+                    scope,
+                    key: CONFIG_HOST.to_string(),
+                }
             );
         }
     }
 
-    /// Invoke `sync` with `user` and `host` coming from `git config`.
     #[test]
-    fn sync_config() {
+    fn config_set() {
+        for (args, key) in [
+            (&["config", "user", "user0"] as &[&str], CONFIG_USER),
+            (&["config", "host", "user0"], CONFIG_HOST),
+        ] {
+            let cli_test = CliTest::default();
+            assert_eq!(
+                cli_test.remote(args).workflow(),
+                Workflow::ConfigSet {
+                    key: key.to_string(),
+                    value: "user0".to_string(),
+                }
+            );
+        }
+
         let cli_test = CliTest::default();
-        assert_eq!(
-            cli_test
-                .remote(&["sync"])
-                .set_config(CONFIG_USER, "user0")
-                .set_config(CONFIG_HOST, "host0")
-                .workflow(),
-            Workflow::Sync {
-                user: User::from("user0"),
-                host: Host::from("host0"),
-                remote: DEFAULT_REMOTE.clone(),
-            }
-        );
+        assert!(cli_test.matches(&["config", "host"]).is_err());
+        let error = cli_test
+            .remote(&["config", "host", "bad host"])
+            .try_workflow()
+            .unwrap_err();
+        assert!(error.to_string().starts_with("Invalid host \"bad host\""));
     }
 
-    /// Invoke `sync` with defaults.
     #[test]
-    fn sync_default() {
+    fn config_show() {
         let cli_test = CliTest::default();
         assert_eq!(
-            cli_test.remote(&["sync"]).workflow(),
-            Workflow::Sync {
-                user: cli_test.default_user.always_borrow(),
+            cli_test
+                .remote(&["--user", "user0", "config", "show"])
+                .workflow(),
+            Workflow::ConfigShow {
+                user: User::from("user0"),
                 host: cli_test.default_host.always_borrow(),
-                remote: DEFAULT_REMOTE.clone(),
+                user_source: "--user".to_string(),
+                host_source: "the operating system default".to_string(),
             }
         );
+
+        let mut remote = cli_test.remote(&["config", "show"]);
+        match remote.set_config(CONFIG_HOST, "host0").workflow() {
+            Workflow::ConfigShow {
+                host, host_source, ..
+            } => {
+                assert_eq!(host, Host::from("host0"));
+                assert_eq!(host_source, "nomad.host in git config");
+            }
+            other => panic!("Unexpected workflow: {:?}", other),
+        }
     }
 
     #[test]
-    fn purge_all() {
+    fn config_unset_unknown_key() {
         let cli_test = CliTest::default();
-        assert_eq!(
-            cli_test.remote(&["purge", "--all"]).workflow(),
-            Workflow::Purge {
-                user: cli_test.default_user.always_borrow(),
-                remote: DEFAULT_REMOTE.clone(),
-                host_filter: Filter::All,
-            }
-        );
+        assert!(cli_test.matches(&["config", "unset", "bogus"]).is_err());
     }
 
     #[test]
@@ -1124,6 +7016,13 @@ mod test_cli {
                 user: cli_test.default_user.always_borrow(),
                 remote: Remote::from("remote"),
                 host_filter: Filter::Allow(HashSet::from_iter(["host0"].map(Host::from))),
+                branch_filter: Filter::All,
+                prune_confirm: PruneConfirm::default(),
+                older_than: None,
+                dry_run: false,
+                fast_prune: false,
+                local_only: false,
+                refs: Vec::new(),
             }
         );
     }
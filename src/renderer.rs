@@ -1,7 +1,12 @@
 use anyhow::Result;
-use console::Term;
+use console::{Style, StyledObject, Term};
 use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
-use std::{borrow::Cow, io::Write, time::Duration};
+use std::{
+    borrow::Cow,
+    fs,
+    io::{self, IsTerminal, Write},
+    time::Duration,
+};
 
 pub trait Renderer {
     fn writer<T>(&mut self, func: impl FnOnce(&mut dyn Write) -> Result<T>) -> Result<T>;
@@ -13,13 +18,287 @@ pub trait Renderer {
         description: impl Into<Cow<'static, str>>,
         func: impl FnOnce() -> Result<T>,
     ) -> Result<T>;
+
+    /// Can the user be asked questions via [`Self::confirm`]?
+    fn is_interactive(&self) -> bool;
+
+    /// Ask the user a yes/no question, defaulting to no.
+    fn confirm(&mut self, prompt: &str) -> Result<bool>;
+
+    /// Clear previous output before redrawing it, if that is possible.
+    fn clear_screen(&mut self) -> Result<()>;
+
+    /// Observe a workflow milestone as it happens, for renderers embedded in other tools that want
+    /// typed progress rather than formatted output.
+    ///
+    /// Ignored unless a renderer opts in.
+    fn event(&mut self, _event: ProgressEvent) {}
+
+    /// Whether output written via [`Self::writer`] should be colored, like `--color`.
+    ///
+    /// Ignored by renderers that cannot show colors.
+    fn set_color(&mut self, _color: ColorChoice) {}
+
+    /// Styles output written via [`Self::writer`] according to [`Self::set_color`].
+    fn styler(&self) -> Styler {
+        Styler::default()
+    }
+}
+
+/// When to color output, see [`Renderer::set_color`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Only when writing to a terminal and `NO_COLOR` is not set.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// Applies [`Style`]s only when the [`Renderer`] wants colors, see [`Renderer::styler`].
+///
+/// The default styler leaves everything plain.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Styler {
+    colors: bool,
+}
+
+impl Styler {
+    pub fn new(colors: bool) -> Self {
+        Self { colors }
+    }
+
+    pub fn apply<D>(&self, style: &Style, value: D) -> StyledObject<D> {
+        style.clone().force_styling(self.colors).apply_to(value)
+    }
+}
+
+/// Milestones of the `sync` and `purge` workflows, see [`Renderer::event`].
+///
+/// Where there are several of a kind, `current` counts from 1 up to `total`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ProgressEvent {
+    /// Local branches are about to be pushed to `remote`.
+    PushStarted {
+        remote: String,
+        current: usize,
+        total: usize,
+    },
+    /// Nomad refs are about to be fetched from `remote`.
+    FetchStarted {
+        remote: String,
+        current: usize,
+        total: usize,
+    },
+    /// The local nomad ref `name` was deleted, along with its remote counterpart if it had one.
+    ///
+    /// Never sent for dry runs, since nothing actually gets deleted.
+    RefPruned {
+        name: String,
+        current: usize,
+        total: usize,
+    },
+    /// `sync` finished after pushing to `pushed` remotes and pruning `pruned` refs.
+    SyncComplete { pushed: usize, pruned: usize },
+    /// `purge` finished after pruning `pruned` refs.
+    PurgeComplete { pruned: usize },
+}
+
+impl ProgressEvent {
+    /// A single line of JSON for `--progress-fd`, with the `phase` of the workflow, `current` and
+    /// `total` (`null` when they do not apply), and a human readable `message`.
+    fn to_json_line(&self) -> String {
+        let (phase, progress, message) = match self {
+            Self::PushStarted {
+                remote,
+                current,
+                total,
+            } => (
+                "push",
+                Some((*current, *total)),
+                format!("Pushing to {}", remote),
+            ),
+            Self::FetchStarted {
+                remote,
+                current,
+                total,
+            } => (
+                "fetch",
+                Some((*current, *total)),
+                format!("Fetching from {}", remote),
+            ),
+            Self::RefPruned {
+                name,
+                current,
+                total,
+            } => (
+                "prune",
+                Some((*current, *total)),
+                format!("Pruned {}", name),
+            ),
+            Self::SyncComplete { pushed, pruned } => (
+                "complete",
+                None,
+                format!("Pushed to {} remote(s), pruned {} ref(s)", pushed, pruned),
+            ),
+            Self::PurgeComplete { pruned } => {
+                ("complete", None, format!("Pruned {} ref(s)", pruned))
+            }
+        };
+        progress_json_line(phase, progress, &message)
+    }
+}
+
+fn progress_json_line(phase: &str, progress: Option<(usize, usize)>, message: &str) -> String {
+    serde_json::json!({
+        "phase": phase,
+        "current": progress.map(|(current, _)| current),
+        "total": progress.map(|(_, total)| total),
+        "message": message,
+    })
+    .to_string()
+}
+
+/// Wraps another [`Renderer`] to also write machine readable progress as JSON lines to a side
+/// channel, like `--progress-fd`.
+///
+/// Every [`ProgressEvent`] becomes a line as described by [`ProgressEvent::to_json_line`], and so
+/// does every spinner (which is how [`crate::verbosity::run_notable`] announces commands) with a
+/// `command` phase. Everything is also passed through to `inner`, so the human output is
+/// unchanged.
+///
+/// Failing to write progress is ignored, since a consumer going away should not stop the workflow.
+pub struct ProgressRenderer<'r, R, W> {
+    inner: &'r mut R,
+    progress: W,
+}
+
+impl<'r, R: Renderer, W: Write> ProgressRenderer<'r, R, W> {
+    pub fn new(inner: &'r mut R, progress: W) -> Self {
+        Self { inner, progress }
+    }
+
+    fn write_line(&mut self, line: String) {
+        let _ = writeln!(self.progress, "{}", line).and_then(|()| self.progress.flush());
+    }
+}
+
+impl<R: Renderer, W: Write> Renderer for ProgressRenderer<'_, R, W> {
+    fn writer<T>(&mut self, func: impl FnOnce(&mut dyn Write) -> Result<T>) -> Result<T> {
+        self.inner.writer(func)
+    }
+
+    fn are_spinners_visible(&self) -> bool {
+        self.inner.are_spinners_visible()
+    }
+
+    fn spinner<T>(
+        &mut self,
+        description: impl Into<Cow<'static, str>>,
+        func: impl FnOnce() -> Result<T>,
+    ) -> Result<T> {
+        let description = description.into();
+        self.write_line(progress_json_line("command", None, &description));
+        self.inner.spinner(description, func)
+    }
+
+    fn is_interactive(&self) -> bool {
+        self.inner.is_interactive()
+    }
+
+    fn confirm(&mut self, prompt: &str) -> Result<bool> {
+        self.inner.confirm(prompt)
+    }
+
+    fn clear_screen(&mut self) -> Result<()> {
+        self.inner.clear_screen()
+    }
+
+    fn set_color(&mut self, color: ColorChoice) {
+        self.inner.set_color(color);
+    }
+
+    fn styler(&self) -> Styler {
+        self.inner.styler()
+    }
+
+    fn event(&mut self, event: ProgressEvent) {
+        self.write_line(event.to_json_line());
+        self.inner.event(event);
+    }
+}
+
+/// Wraps another [`Renderer`] to write output to a file instead, like `--output`.
+///
+/// Only output written via [`Renderer::writer`] goes to `file`. Spinners and confirmations still
+/// go through `inner`, and so never end up in the file, nor do the blank lines that separate
+/// output from spinners. Since a file is not a terminal, output is only colored with
+/// [`ColorChoice::Always`].
+pub struct FileRenderer<'r, R> {
+    inner: &'r mut R,
+    file: fs::File,
+    color: ColorChoice,
+}
+
+impl<'r, R: Renderer> FileRenderer<'r, R> {
+    pub fn new(inner: &'r mut R, file: fs::File) -> Self {
+        Self {
+            inner,
+            file,
+            color: ColorChoice::default(),
+        }
+    }
 }
 
-pub struct TerminalRenderer(Term);
+impl<R: Renderer> Renderer for FileRenderer<'_, R> {
+    fn writer<T>(&mut self, func: impl FnOnce(&mut dyn Write) -> Result<T>) -> Result<T> {
+        let ret = func(&mut self.file)?;
+        self.file.flush()?;
+        Ok(ret)
+    }
+
+    fn are_spinners_visible(&self) -> bool {
+        false
+    }
+
+    fn spinner<T>(
+        &mut self,
+        description: impl Into<Cow<'static, str>>,
+        func: impl FnOnce() -> Result<T>,
+    ) -> Result<T> {
+        self.inner.spinner(description, func)
+    }
+
+    fn is_interactive(&self) -> bool {
+        self.inner.is_interactive()
+    }
+
+    fn confirm(&mut self, prompt: &str) -> Result<bool> {
+        self.inner.confirm(prompt)
+    }
+
+    fn clear_screen(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn set_color(&mut self, color: ColorChoice) {
+        self.color = color;
+    }
+
+    fn styler(&self) -> Styler {
+        Styler::new(self.color == ColorChoice::Always)
+    }
+
+    fn event(&mut self, event: ProgressEvent) {
+        self.inner.event(event);
+    }
+}
+
+pub struct TerminalRenderer(Term, ColorChoice);
 
 impl TerminalRenderer {
     pub fn stdout() -> Self {
-        Self(Term::buffered_stdout())
+        Self(Term::buffered_stdout(), ColorChoice::default())
     }
 }
 
@@ -59,6 +338,47 @@ impl Renderer for TerminalRenderer {
 
         ret
     }
+
+    fn is_interactive(&self) -> bool {
+        self.0.is_term() && io::stdin().is_terminal()
+    }
+
+    fn confirm(&mut self, prompt: &str) -> Result<bool> {
+        self.writer(|w| {
+            write!(w, "{} [y/N] ", prompt)?;
+            Ok(())
+        })?;
+
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer)?;
+        Ok(is_yes(&answer))
+    }
+
+    fn clear_screen(&mut self) -> Result<()> {
+        if self.0.is_term() {
+            self.0.clear_screen()?;
+            self.0.flush()?;
+        }
+        Ok(())
+    }
+
+    fn set_color(&mut self, color: ColorChoice) {
+        self.1 = color;
+    }
+
+    fn styler(&self) -> Styler {
+        Styler::new(match self.1 {
+            // `console` already checks for a terminal, `NO_COLOR`, and `CLICOLOR`.
+            ColorChoice::Auto => self.0.is_term() && console::colors_enabled(),
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        })
+    }
+}
+
+/// Interpret a line of user input as an answer to a yes/no question.
+fn is_yes(answer: &str) -> bool {
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
 }
 
 /// Adds a newline to separate output from spinners, but that's only necessary if spinners are even
@@ -93,6 +413,22 @@ pub mod test_terminal {
         TerminalRenderer::stdout().are_spinners_visible();
     }
 
+    #[test]
+    fn is_interactive() {
+        TerminalRenderer::stdout().is_interactive();
+    }
+
+    #[test]
+    fn is_yes() {
+        for answer in ["y", "Y", "yes", "YES\n", "  y  "] {
+            assert!(super::is_yes(answer), "{:?}", answer);
+        }
+
+        for answer in ["", "\n", "n", "no", "yeah", "nope"] {
+            assert!(!super::is_yes(answer), "{:?}", answer);
+        }
+    }
+
     #[test]
     fn spinner() {
         let mut renderer = TerminalRenderer::stdout();
@@ -114,18 +450,35 @@ pub mod test {
 
     use anyhow::{Context, Result};
 
-    use super::{add_newline_if_spinners_are_visible, Renderer};
+    use super::{
+        add_newline_if_spinners_are_visible, ColorChoice, FileRenderer, ProgressEvent,
+        ProgressRenderer, Renderer, Styler,
+    };
 
-    pub struct MemoryRenderer(Vec<u8>);
+    /// Captures all output and [`ProgressEvent`]s in memory.
+    ///
+    /// Interactive only when constructed via [`MemoryRenderer::with_confirmation`], in which case
+    /// every [`Renderer::confirm`] gets the same canned answer.
+    ///
+    /// Never a terminal, so output is only colored with [`ColorChoice::Always`].
+    pub struct MemoryRenderer(Vec<u8>, Option<bool>, Vec<ProgressEvent>, ColorChoice);
 
     impl MemoryRenderer {
         pub fn new() -> Self {
-            Self(Vec::new())
+            Self(Vec::new(), None, Vec::new(), ColorChoice::default())
+        }
+
+        pub fn with_confirmation(answer: bool) -> Self {
+            Self(Vec::new(), Some(answer), Vec::new(), ColorChoice::default())
         }
 
         pub fn as_str(&self) -> &str {
             std::str::from_utf8(self.0.as_slice()).expect("tests should have utf8 output")
         }
+
+        pub fn events(&self) -> &[ProgressEvent] {
+            &self.2
+        }
     }
 
     impl Renderer for MemoryRenderer {
@@ -145,6 +498,37 @@ pub mod test {
             writeln!(self.0, "{}...", description.into())?;
             func()
         }
+
+        fn is_interactive(&self) -> bool {
+            self.1.is_some()
+        }
+
+        fn confirm(&mut self, prompt: &str) -> Result<bool> {
+            let answer = self.1.context("MemoryRenderer is not interactive")?;
+            writeln!(
+                self.0,
+                "{} [y/N] {}",
+                prompt,
+                if answer { "y" } else { "n" }
+            )?;
+            Ok(answer)
+        }
+
+        fn clear_screen(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn event(&mut self, event: ProgressEvent) {
+            self.2.push(event);
+        }
+
+        fn set_color(&mut self, color: ColorChoice) {
+            self.3 = color;
+        }
+
+        fn styler(&self) -> Styler {
+            Styler::new(self.3 == ColorChoice::Always)
+        }
     }
 
     pub struct NoRenderer;
@@ -165,6 +549,18 @@ pub mod test {
         ) -> Result<T> {
             func()
         }
+
+        fn is_interactive(&self) -> bool {
+            false
+        }
+
+        fn confirm(&mut self, _prompt: &str) -> Result<bool> {
+            anyhow::bail!("NoRenderer is not interactive")
+        }
+
+        fn clear_screen(&mut self) -> Result<()> {
+            Ok(())
+        }
     }
 
     #[test]
@@ -191,6 +587,116 @@ pub mod test {
         assert!(func_called);
     }
 
+    #[test]
+    fn confirm() {
+        for answer in [true, false] {
+            let mut renderer = MemoryRenderer::with_confirmation(answer);
+            assert!(renderer.is_interactive());
+            assert_eq!(renderer.confirm("Proceed?").unwrap(), answer);
+        }
+
+        assert!(!MemoryRenderer::new().is_interactive());
+        assert!(MemoryRenderer::new().confirm("Proceed?").is_err());
+    }
+
+    #[test]
+    fn event() {
+        let mut renderer = MemoryRenderer::new();
+        let event = ProgressEvent::RefPruned {
+            name: "refs/nomad/host0/master".to_string(),
+            current: 1,
+            total: 1,
+        };
+        renderer.event(event.clone());
+        assert_eq!(renderer.events(), [event]);
+        assert_eq!(renderer.as_str(), "");
+
+        NoRenderer.event(ProgressEvent::PurgeComplete { pruned: 0 });
+    }
+
+    /// Output should go to the file, while spinners and events still reach the wrapped renderer.
+    #[test]
+    fn file_renderer() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("output");
+        let mut inner = MemoryRenderer::new();
+
+        {
+            let mut renderer = FileRenderer::new(&mut inner, std::fs::File::create(&path).unwrap());
+            renderer.spinner("Listing", || Ok(())).unwrap();
+            add_newline_if_spinners_are_visible(&mut renderer).unwrap();
+            renderer
+                .writer(|w| writeln!(w, "done").context("write in test"))
+                .unwrap();
+            renderer.event(ProgressEvent::PurgeComplete { pruned: 0 });
+            assert!(!renderer.styler().colors);
+            renderer.set_color(ColorChoice::Always);
+            assert!(renderer.styler().colors);
+        }
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "done\n");
+        assert_eq!(inner.as_str(), "Listing...\n");
+        assert_eq!(inner.events(), [ProgressEvent::PurgeComplete { pruned: 0 }]);
+    }
+
+    /// Progress should be written as JSON lines on the side, without changing what the wrapped
+    /// renderer sees.
+    #[test]
+    fn progress_renderer() {
+        let mut inner = MemoryRenderer::new();
+        let mut progress = Vec::new();
+        let pruned = ProgressEvent::RefPruned {
+            name: "refs/nomad/host0/feature".to_string(),
+            current: 2,
+            total: 3,
+        };
+
+        {
+            let mut renderer = ProgressRenderer::new(&mut inner, &mut progress);
+            renderer.spinner("Pushing", || Ok(())).unwrap();
+            renderer.event(pruned.clone());
+            renderer.event(ProgressEvent::SyncComplete {
+                pushed: 1,
+                pruned: 3,
+            });
+            renderer
+                .writer(|w| writeln!(w, "done").context("write in test"))
+                .unwrap();
+        }
+
+        assert_eq!(inner.as_str(), "Pushing...\ndone\n");
+        assert_eq!(inner.events()[0], pruned);
+
+        let lines = std::str::from_utf8(&progress)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            [
+                serde_json::json!({
+                    "phase": "command",
+                    "current": null,
+                    "total": null,
+                    "message": "Pushing",
+                }),
+                serde_json::json!({
+                    "phase": "prune",
+                    "current": 2,
+                    "total": 3,
+                    "message": "Pruned refs/nomad/host0/feature",
+                }),
+                serde_json::json!({
+                    "phase": "complete",
+                    "current": null,
+                    "total": null,
+                    "message": "Pushed to 1 remote(s), pruned 3 ref(s)",
+                }),
+            ]
+        );
+    }
+
     #[test]
     fn add_newline() {
         let mut renderer = MemoryRenderer::new();
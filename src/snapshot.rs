@@ -1,4 +1,7 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    cmp::Ordering,
+    collections::{hash_map::Entry, HashMap, HashSet},
+};
 
 use crate::types::{Branch, Host, NomadRef, RemoteNomadRefSet, User};
 
@@ -14,6 +17,27 @@ pub struct Snapshot<'a, Ref> {
     _private: (),
 }
 
+/// The difference between two [`Snapshot`]s of nomad refs, see [`Snapshot::diff`].
+///
+/// Nomad refs are identified by their host and branch, since a [`Snapshot`] is already scoped to
+/// a single [`User`].
+#[derive(Debug, PartialEq, Eq)]
+pub struct SnapshotDiff<'s, 'a, Ref> {
+    /// Only present in the newer snapshot.
+    pub added: Vec<&'s NomadRef<'a, Ref>>,
+    /// Only present in the older snapshot.
+    pub removed: Vec<&'s NomadRef<'a, Ref>>,
+    /// Present in both snapshots but pointing to different refs, as `(older, newer)`.
+    pub changed: Vec<(&'s NomadRef<'a, Ref>, &'s NomadRef<'a, Ref>)>,
+}
+
+impl<Ref> SnapshotDiff<'_, '_, Ref> {
+    /// Whether the two snapshots had identical nomad refs.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
 /// Describes where a ref should be removed from.
 #[derive(Debug, PartialEq, Eq)]
 pub enum PruneFrom<'a, Ref> {
@@ -21,6 +45,24 @@ pub enum PruneFrom<'a, Ref> {
     LocalAndRemote(NomadRef<'a, Ref>),
 }
 
+impl<'a, Ref> PruneFrom<'a, Ref> {
+    /// The [`NomadRef`] to be removed, regardless of where it is removed from.
+    pub fn nomad_ref(&self) -> &NomadRef<'a, Ref> {
+        match self {
+            Self::LocalOnly(nomad_ref) | Self::LocalAndRemote(nomad_ref) => nomad_ref,
+        }
+    }
+
+    /// Leave the remote alone, regardless of where the ref would have been removed from.
+    pub fn local_only(self) -> Self {
+        match self {
+            Self::LocalOnly(nomad_ref) | Self::LocalAndRemote(nomad_ref) => {
+                Self::LocalOnly(nomad_ref)
+            }
+        }
+    }
+}
+
 impl<Ref> Snapshot<'_, Ref> {
     /// Smart constructor that enforces the "scoped under a specific [`User`]" invariant.
     ///
@@ -76,12 +118,23 @@ impl<'a, Ref> Snapshot<'a, Ref> {
     }
 
     /// Return all nomad branches for specific hosts.
+    #[cfg(test)]
     pub fn prune_by_hosts(self, host_filter: impl Fn(&Host) -> bool) -> Vec<PruneFrom<'a, Ref>> {
+        self.prune_by_hosts_and_branches(host_filter, |_| true)
+    }
+
+    /// Return all nomad branches that match both the `host_filter` and the `branch_filter`, like
+    /// an abandoned branch across every host.
+    pub fn prune_by_hosts_and_branches(
+        self,
+        host_filter: impl Fn(&Host) -> bool,
+        branch_filter: impl Fn(&Branch) -> bool,
+    ) -> Vec<PruneFrom<'a, Ref>> {
         let Self { nomad_refs, .. } = self;
         nomad_refs
             .into_iter()
             .filter_map(|nomad_ref| {
-                if !host_filter(&nomad_ref.host) {
+                if !host_filter(&nomad_ref.host) || !branch_filter(&nomad_ref.branch) {
                     return None;
                 }
 
@@ -90,38 +143,190 @@ impl<'a, Ref> Snapshot<'a, Ref> {
             .collect()
     }
 
+    /// Ensure there is only a single nomad ref per host and branch, returning the ones that were
+    /// dropped.
+    ///
+    /// The nomad ref for which `is_canonical` returns `true` is kept, otherwise the first one
+    /// wins. Duplicates could only come about from refs written under a different naming scheme,
+    /// which would otherwise be double counted by listing and pruning.
+    pub fn dedup_hosts_and_branches(
+        &mut self,
+        is_canonical: impl Fn(&NomadRef<'a, Ref>) -> bool,
+    ) -> Vec<NomadRef<'a, Ref>> {
+        // Pick the winning index for each host and branch first, so that keys can borrow from the
+        // refs instead of copying their names.
+        let mut positions = HashMap::<(&Host, &Branch), usize>::new();
+        let mut winners = Vec::<usize>::new();
+        for (index, nomad_ref) in self.nomad_refs.iter().enumerate() {
+            match positions.entry((&nomad_ref.host, &nomad_ref.branch)) {
+                Entry::Vacant(entry) => {
+                    entry.insert(winners.len());
+                    winners.push(index);
+                }
+                Entry::Occupied(entry) => {
+                    let winner = &mut winners[*entry.get()];
+                    if !is_canonical(&self.nomad_refs[*winner]) && is_canonical(nomad_ref) {
+                        *winner = index;
+                    }
+                }
+            }
+        }
+
+        let mut slots = self.nomad_refs.drain(..).map(Some).collect::<Vec<_>>();
+        self.nomad_refs = winners
+            .into_iter()
+            .filter_map(|index| slots[index].take())
+            .collect();
+        slots.into_iter().flatten().collect()
+    }
+
+    /// Drop nomad refs for branches that also exist locally, leaving only what other hosts have
+    /// that this one does not.
+    pub fn missing_locally(self) -> Self {
+        let Self {
+            local_branches,
+            nomad_refs,
+            _private,
+        } = self;
+
+        let nomad_refs = nomad_refs
+            .into_iter()
+            .filter(|nomad_ref| !local_branches.contains(&nomad_ref.branch))
+            .collect();
+
+        Self {
+            local_branches,
+            nomad_refs,
+            _private,
+        }
+    }
+
+    /// Compare the nomad refs in this (older) snapshot against a `newer` one.
+    ///
+    /// All returned lists are sorted by host and then branch.
+    pub fn diff<'s>(&'s self, newer: &'s Snapshot<'a, Ref>) -> SnapshotDiff<'s, 'a, Ref>
+    where
+        Ref: PartialEq,
+    {
+        let by_identity = |snapshot: &'s Snapshot<'a, Ref>| {
+            snapshot
+                .nomad_refs
+                .iter()
+                .map(|nomad_ref| ((&nomad_ref.host, &nomad_ref.branch), nomad_ref))
+                .collect::<HashMap<_, _>>()
+        };
+
+        let older_refs = by_identity(self);
+        let newer_refs = by_identity(newer);
+
+        let mut diff = SnapshotDiff {
+            added: Vec::new(),
+            removed: Vec::new(),
+            changed: Vec::new(),
+        };
+
+        for (identity, older_ref) in &older_refs {
+            match newer_refs.get(identity) {
+                None => diff.removed.push(*older_ref),
+                Some(newer_ref) => {
+                    if older_ref.ref_ != newer_ref.ref_ {
+                        diff.changed.push((*older_ref, *newer_ref));
+                    }
+                }
+            }
+        }
+
+        for (identity, newer_ref) in &newer_refs {
+            if !older_refs.contains_key(identity) {
+                diff.added.push(*newer_ref);
+            }
+        }
+
+        fn identity_order<Ref>(a: &NomadRef<Ref>, b: &NomadRef<Ref>) -> Ordering {
+            a.host.cmp(&b.host).then_with(|| a.branch.cmp(&b.branch))
+        }
+
+        diff.added.sort_by(|a, b| identity_order(a, b));
+        diff.removed.sort_by(|a, b| identity_order(a, b));
+        diff.changed.sort_by(|(a, _), (b, _)| identity_order(a, b));
+
+        diff
+    }
+
     /// Return all [`NomadRef`]s grouped by host in sorted order.
+    ///
+    /// Snapshots usually only have refs for a single user, but when they do have several, hosts
+    /// of different users are kept apart and sorted by user first.
     pub fn sorted_hosts_and_branches(self) -> Vec<(Host<'a>, Vec<NomadRef<'a, Ref>>)> {
-        let mut by_host = HashMap::<Host, Vec<NomadRef<Ref>>>::new();
+        let mut by_user_and_host = HashMap::<(User, Host), Vec<NomadRef<Ref>>>::new();
         let Self { nomad_refs, .. } = self;
 
         for nomad_ref in nomad_refs {
-            by_host
-                .entry(nomad_ref.host.clone())
+            by_user_and_host
+                .entry((nomad_ref.user.clone(), nomad_ref.host.clone()))
                 .or_default()
                 .push(nomad_ref);
         }
 
-        let mut as_vec = by_host
+        let mut as_vec = by_user_and_host
             .into_iter()
-            .map(|(host, mut branches)| {
+            .map(|(user_and_host, mut branches)| {
                 branches.sort_by(|a, b| a.branch.cmp(&b.branch));
-                (host, branches)
+                (user_and_host, branches)
             })
             .collect::<Vec<_>>();
-        as_vec.sort_by(|(host_a, _), (host_b, _)| host_a.cmp(host_b));
+        as_vec.sort_by(|(key_a, _), (key_b, _)| key_a.cmp(key_b));
 
         as_vec
+            .into_iter()
+            .map(|((_, host), branches)| (host, branches))
+            .collect()
+    }
+}
+
+/// The nomad refs that several listings of the same refs (like mirrors of one remote) disagree
+/// on, like [`Snapshot::diff`] but across any number of listings.
+///
+/// Each disagreement carries the `(label, ref)` of every listing that has it, in the order of
+/// `listings`, and they are sorted by user, host, and then branch. A ref missing from some
+/// listings is not a disagreement, since a mirror may lag behind, see [`RemoteNomadRefSet::union`].
+pub fn disagreements<'l, 'a, Label, Ref>(
+    listings: &'l [(Label, Vec<NomadRef<'a, Ref>>)],
+) -> Vec<NomadRef<'static, Vec<(&'l Label, &'l Ref)>>>
+where
+    Ref: PartialEq,
+{
+    let mut by_identity = HashMap::<_, Vec<(&Label, &Ref)>>::new();
+    for (label, nomad_refs) in listings {
+        for nomad_ref in nomad_refs {
+            by_identity
+                .entry((&nomad_ref.user, &nomad_ref.host, &nomad_ref.branch))
+                .or_default()
+                .push((label, &nomad_ref.ref_));
+        }
     }
+
+    let mut disagreements = by_identity
+        .into_iter()
+        .filter(|(_, refs)| refs.iter().any(|(_, ref_)| *ref_ != refs[0].1))
+        .map(|((user, host, branch), refs)| NomadRef {
+            user: user.always_borrow().possibly_clone(),
+            host: host.always_borrow().possibly_clone(),
+            branch: branch.always_borrow().possibly_clone(),
+            ref_: refs,
+        })
+        .collect::<Vec<_>>();
+    disagreements.sort_by(|a, b| (&a.user, &a.host, &a.branch).cmp(&(&b.user, &b.host, &b.branch)));
+    disagreements
 }
 
 #[cfg(test)]
 mod tests {
-    use std::iter::FromIterator;
+    use std::{collections::HashSet, iter::FromIterator};
 
     use crate::types::{Host, RemoteNomadRefSet, User};
 
-    use super::{Branch, NomadRef, PruneFrom, Snapshot};
+    use super::{Branch, NomadRef, PruneFrom, Snapshot, SnapshotDiff};
 
     fn snapshot<'a>(
         user: &'a User,
@@ -207,6 +412,80 @@ mod tests {
         assert_eq!(prune, Vec::new());
     }
 
+    /// Duplicate nomad refs for the same host and branch should be dropped, keeping the canonical
+    /// one.
+    #[test]
+    fn snapshot_dedup_hosts_and_branches() {
+        let user = &User::from("user0");
+        let nomad_ref = |branch: &'static str, ref_: &'static str| NomadRef {
+            user: user.always_borrow(),
+            host: Host::from("host0"),
+            branch: Branch::from(branch),
+            ref_,
+        };
+
+        let mut snapshot = Snapshot::new(
+            user,
+            HashSet::new(),
+            vec![
+                nomad_ref("branch0", "old-layout"),
+                nomad_ref("branch1", "canonical"),
+                nomad_ref("branch0", "canonical"),
+                nomad_ref("branch1", "old-layout"),
+            ],
+        );
+
+        let dropped = snapshot.dedup_hosts_and_branches(|r| r.ref_ == "canonical");
+
+        assert_eq!(
+            dropped,
+            vec![
+                nomad_ref("branch0", "old-layout"),
+                nomad_ref("branch1", "old-layout")
+            ],
+        );
+        assert_eq!(
+            snapshot.nomad_refs,
+            vec![
+                nomad_ref("branch0", "canonical"),
+                nomad_ref("branch1", "canonical")
+            ],
+        );
+    }
+
+    /// Only nomad refs for branches that do not exist locally should remain.
+    #[test]
+    fn snapshot_missing_locally() {
+        let user = &User::from("user0");
+        let missing = snapshot(user, ["branch0"])
+            .missing_locally()
+            .sorted_hosts_and_branches();
+
+        assert_eq!(
+            missing,
+            vec![
+                (
+                    Host::from("host0"),
+                    vec![NomadRef {
+                        user: user.always_borrow(),
+                        host: Host::from("host0"),
+                        branch: Branch::from("branch1"),
+                        ref_: (),
+                    }],
+                ),
+                (
+                    Host::from("host1"),
+                    vec![NomadRef {
+                        user: user.always_borrow(),
+                        host: Host::from("host1"),
+                        branch: Branch::from("branch1"),
+                        ref_: (),
+                    }],
+                ),
+            ],
+        );
+    }
+
     /// Sets up the scenario where:
     ///
     ///     There are NO local branches
@@ -335,4 +614,265 @@ mod tests {
             ],
         );
     }
+
+    /// [`Snapshot::prune_by_hosts_and_branches`] should remove a branch across all hosts.
+    #[test]
+    fn snapshot_prune_branches() {
+        let user = &User::from("user0");
+        let prune = snapshot(user, ["branch0", "branch1"])
+            .prune_by_hosts_and_branches(|_| true, |b| *b == Branch::from("branch1"));
+        assert_eq!(
+            prune,
+            vec![
+                PruneFrom::LocalAndRemote(NomadRef {
+                    user: User::from("user0"),
+                    host: Host::from("host0"),
+                    branch: Branch::from("branch1"),
+                    ref_: (),
+                }),
+                PruneFrom::LocalAndRemote(NomadRef {
+                    user: User::from("user0"),
+                    host: Host::from("host1"),
+                    branch: Branch::from("branch1"),
+                    ref_: (),
+                }),
+            ],
+        );
+    }
+
+    /// [`Snapshot::prune_by_hosts_and_branches`] should only remove refs matching both filters.
+    #[test]
+    fn snapshot_prune_hosts_and_branches() {
+        let user = &User::from("user0");
+        let prune = snapshot(user, ["branch0", "branch1"]).prune_by_hosts_and_branches(
+            |h| *h == Host::from("host1"),
+            |b| *b == Branch::from("branch1"),
+        );
+        assert_eq!(
+            prune,
+            vec![PruneFrom::LocalAndRemote(NomadRef {
+                user: User::from("user0"),
+                host: Host::from("host1"),
+                branch: Branch::from("branch1"),
+                ref_: (),
+            })],
+        );
+
+        let prune = snapshot(user, ["branch0", "branch1"]).prune_by_hosts_and_branches(
+            |h| *h == Host::from("host1"),
+            |b| *b == Branch::from("branch0"),
+        );
+        assert_eq!(prune, Vec::new());
+    }
+
+    /// Builds a snapshot from `(host, branch, commit)` triples.
+    fn snapshot_with_commits<'a>(
+        user: &'a User,
+        nomad_refs: impl IntoIterator<Item = (&'static str, &'static str, &'static str)>,
+    ) -> Snapshot<'a, &'static str> {
+        Snapshot::new(
+            user,
+            Default::default(),
+            nomad_refs
+                .into_iter()
+                .map(|(host, branch, commit)| NomadRef {
+                    user: user.always_borrow(),
+                    host: Host::from(host),
+                    branch: Branch::from(branch),
+                    ref_: commit,
+                })
+                .collect(),
+        )
+    }
+
+    fn nomad_ref(
+        host: &'static str,
+        branch: &'static str,
+        commit: &'static str,
+    ) -> NomadRef<'static, &'static str> {
+        NomadRef {
+            user: User::from("user0"),
+            host: Host::from(host),
+            branch: Branch::from(branch),
+            ref_: commit,
+        }
+    }
+
+    /// Identical snapshots should have an empty diff.
+    #[test]
+    fn diff_identical() {
+        let user = &User::from("user0");
+        let refs = [("host0", "branch0", "c0"), ("host1", "branch0", "c1")];
+        let older = snapshot_with_commits(user, refs);
+        let newer = snapshot_with_commits(user, refs);
+
+        let diff = older.diff(&newer);
+        assert!(diff.is_empty());
+        assert_eq!(
+            diff,
+            SnapshotDiff {
+                added: Vec::new(),
+                removed: Vec::new(),
+                changed: Vec::new(),
+            }
+        );
+    }
+
+    /// Refs only in the newer snapshot should be added, sorted by host and branch.
+    #[test]
+    fn diff_added() {
+        let user = &User::from("user0");
+        let older = snapshot_with_commits(user, [("host0", "branch0", "c0")]);
+        let newer = snapshot_with_commits(
+            user,
+            [
+                ("host1", "branch0", "c2"),
+                ("host0", "branch1", "c1"),
+                ("host0", "branch0", "c0"),
+            ],
+        );
+
+        let diff = older.diff(&newer);
+        assert!(!diff.is_empty());
+        assert_eq!(
+            diff.added,
+            vec![
+                &nomad_ref("host0", "branch1", "c1"),
+                &nomad_ref("host1", "branch0", "c2"),
+            ]
+        );
+        assert!(diff.removed.is_empty());
+        assert!(diff.changed.is_empty());
+    }
+
+    /// Refs only in the older snapshot should be removed, sorted by host and branch.
+    #[test]
+    fn diff_removed() {
+        let user = &User::from("user0");
+        let older = snapshot_with_commits(
+            user,
+            [
+                ("host1", "branch0", "c2"),
+                ("host0", "branch1", "c1"),
+                ("host0", "branch0", "c0"),
+            ],
+        );
+        let newer = snapshot_with_commits(user, [("host0", "branch0", "c0")]);
+
+        let diff = older.diff(&newer);
+        assert!(diff.added.is_empty());
+        assert_eq!(
+            diff.removed,
+            vec![
+                &nomad_ref("host0", "branch1", "c1"),
+                &nomad_ref("host1", "branch0", "c2"),
+            ]
+        );
+        assert!(diff.changed.is_empty());
+    }
+
+    /// The same host and branch pointing to a different commit should be changed.
+    #[test]
+    fn diff_changed() {
+        let user = &User::from("user0");
+        let older = snapshot_with_commits(
+            user,
+            [("host0", "branch0", "c0"), ("host1", "branch0", "c1")],
+        );
+        let newer = snapshot_with_commits(
+            user,
+            [("host0", "branch0", "c0"), ("host1", "branch0", "c2")],
+        );
+
+        let diff = older.diff(&newer);
+        assert!(diff.added.is_empty());
+        assert!(diff.removed.is_empty());
+        assert_eq!(
+            diff.changed,
+            vec![(
+                &nomad_ref("host1", "branch0", "c1"),
+                &nomad_ref("host1", "branch0", "c2"),
+            )]
+        );
+    }
+
+    /// The same branch name on different hosts are different refs.
+    #[test]
+    fn diff_same_branch_different_host() {
+        let user = &User::from("user0");
+        let older = snapshot_with_commits(user, [("host0", "branch0", "c0")]);
+        let newer = snapshot_with_commits(user, [("host1", "branch0", "c0")]);
+
+        let diff = older.diff(&newer);
+        assert_eq!(diff.added, vec![&nomad_ref("host1", "branch0", "c0")]);
+        assert_eq!(diff.removed, vec![&nomad_ref("host0", "branch0", "c0")]);
+        assert!(diff.changed.is_empty());
+    }
+
+    /// Only refs that listings have with different commits disagree, not refs missing from some.
+    #[test]
+    fn disagreements() {
+        let listings = [
+            (
+                "origin",
+                vec![
+                    nomad_ref("host0", "branch0", "c0"),
+                    nomad_ref("host0", "branch1", "c1"),
+                    nomad_ref("host1", "branch0", "c2"),
+                ],
+            ),
+            (
+                "backup",
+                vec![
+                    nomad_ref("host0", "branch0", "c0"),
+                    nomad_ref("host1", "branch0", "c3"),
+                ],
+            ),
+        ];
+
+        assert_eq!(
+            super::disagreements(&listings),
+            vec![NomadRef {
+                user: User::from("user0"),
+                host: Host::from("host1"),
+                branch: Branch::from("branch0"),
+                ref_: vec![(&"origin", &"c2"), (&"backup", &"c3")],
+            }],
+        );
+        assert!(super::disagreements(&listings[..1]).is_empty());
+    }
+
+    /// All categories at once.
+    #[test]
+    fn diff_everything() {
+        let user = &User::from("user0");
+        let older = snapshot_with_commits(
+            user,
+            [
+                ("host0", "kept", "c0"),
+                ("host0", "removed", "c1"),
+                ("host1", "changed", "c2"),
+            ],
+        );
+        let newer = snapshot_with_commits(
+            user,
+            [
+                ("host0", "kept", "c0"),
+                ("host1", "changed", "c3"),
+                ("host1", "added", "c4"),
+            ],
+        );
+
+        assert_eq!(
+            older.diff(&newer),
+            SnapshotDiff {
+                added: vec![&nomad_ref("host1", "added", "c4")],
+                removed: vec![&nomad_ref("host0", "removed", "c1")],
+                changed: vec![(
+                    &nomad_ref("host1", "changed", "c2"),
+                    &nomad_ref("host1", "changed", "c3"),
+                )],
+            }
+        );
+    }
 }
@@ -1,21 +1,39 @@
 use std::{
     borrow::{Borrow, Cow},
+    cmp::Ordering,
     collections::HashSet,
+    fmt,
+    hash::{Hash, Hasher},
     iter::FromIterator,
+    ops::Deref,
+    rc::Rc,
 };
 
-/// Convenient [`From`] implementations for `Cow<'_, str>` based newtypes.
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
+
+/// Convenient [`From`] implementations for `Cow<'_, str>` and [`Name`] based newtypes.
 macro_rules! impl_str_from {
     ($typename:ident) => {
         impl<'a> From<&'a str> for $typename<'a> {
             fn from(s: &'a str) -> Self {
-                Self(Cow::from(s))
+                Self(s.into())
             }
         }
 
         impl<'a> From<String> for $typename<'a> {
             fn from(s: String) -> Self {
-                Self(Cow::from(s))
+                Self(s.into())
+            }
+        }
+    };
+}
+
+/// Exposes the underlying string, for matching against patterns.
+macro_rules! impl_str_as_ref {
+    ($typename:ident) => {
+        impl AsRef<str> for $typename<'_> {
+            fn as_ref(&self) -> &str {
+                &self.0
             }
         }
     };
@@ -30,8 +48,7 @@ macro_rules! impl_str_possibly_clone {
         /// exposing a generic lifetime of their own.
         impl $typename<'_> {
             pub fn possibly_clone(self) -> $typename<'static> {
-                let owned = self.0.into_owned();
-                $typename(Cow::Owned(owned))
+                $typename(IntoStatic::into_static(self.0))
             }
         }
     };
@@ -45,15 +62,192 @@ macro_rules! impl_str_always_borrow {
         /// permit zero allocation lookups while still owning the underlying data.
         impl<'a> $typename<'a> {
             pub fn always_borrow(&'a self) -> Self {
-                let y: &str = self.0.borrow();
+                let y: &str = &self.0;
                 Self::from(y)
             }
         }
     };
 }
 
+/// Deserializes through [`check_ref_component`], so that values from workflow files are held to the
+/// same rules as values from the CLI.
+macro_rules! impl_str_deserialize_checked {
+    ($typename:ident, $noun:literal) => {
+        impl<'de> Deserialize<'de> for $typename<'_> {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let s = String::deserialize(deserializer)?;
+                check_ref_component(&s).map_err(|problem| {
+                    D::Error::custom(format!("Invalid {} {:?}, {}", $noun, s, problem))
+                })?;
+                Ok(Self::from(s))
+            }
+        }
+    };
+}
+
+/// Detach string storage from whatever it borrows, see `impl_str_possibly_clone`.
+trait IntoStatic {
+    type Static;
+
+    fn into_static(self) -> Self::Static;
+}
+
+impl IntoStatic for Cow<'_, str> {
+    type Static = Cow<'static, str>;
+
+    fn into_static(self) -> Self::Static {
+        Cow::Owned(self.into_owned())
+    }
+}
+
+/// The storage behind [`User`] and [`Host`], which repeat heavily across large ref sets.
+///
+/// Like `Cow<'a, str>`, except that owned names are reference counted, so that every ref parsed
+/// with the same [`Interner`] shares a single allocation per distinct name.
+#[derive(Clone)]
+pub enum Name<'a> {
+    /// Borrowed from somewhere else, like the CLI arguments.
+    Borrowed(&'a str),
+    /// Owned, and possibly shared with other names through an [`Interner`].
+    Shared(Rc<str>),
+}
+
+impl Name<'_> {
+    /// Copy out the underlying string, like [`Cow::into_owned`].
+    pub fn into_owned(self) -> String {
+        self.to_string()
+    }
+}
+
+impl IntoStatic for Name<'_> {
+    type Static = Name<'static>;
+
+    /// Shared names are kept as is, so this only allocates for borrowed ones.
+    fn into_static(self) -> Self::Static {
+        match self {
+            Name::Borrowed(s) => Name::Shared(Rc::from(s)),
+            Name::Shared(s) => Name::Shared(s),
+        }
+    }
+}
+
+impl<'a> From<&'a str> for Name<'a> {
+    fn from(s: &'a str) -> Self {
+        Self::Borrowed(s)
+    }
+}
+
+impl From<String> for Name<'_> {
+    fn from(s: String) -> Self {
+        Self::Shared(Rc::from(s))
+    }
+}
+
+impl Deref for Name<'_> {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        match self {
+            Self::Borrowed(s) => s,
+            Self::Shared(s) => s,
+        }
+    }
+}
+
+impl AsRef<str> for Name<'_> {
+    fn as_ref(&self) -> &str {
+        self
+    }
+}
+
+impl Borrow<str> for Name<'_> {
+    fn borrow(&self) -> &str {
+        self
+    }
+}
+
+impl PartialEq for Name<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl Eq for Name<'_> {}
+
+impl PartialEq<str> for Name<'_> {
+    fn eq(&self, other: &str) -> bool {
+        &**self == other
+    }
+}
+
+impl PartialOrd for Name<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Name<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (**self).cmp(&**other)
+    }
+}
+
+impl Hash for Name<'_> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (**self).hash(state)
+    }
+}
+
+impl fmt::Debug for Name<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl fmt::Display for Name<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+impl Serialize for Name<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self)
+    }
+}
+
+/// Hands out one shared copy of each distinct name, see [`Name`].
+///
+/// Meant to live for a single operation, like parsing every ref in a
+/// [`crate::git_binary::GitBinary::snapshot`], so that nothing outlives the refs using it.
+#[derive(Debug, Default)]
+pub struct Interner(HashSet<Rc<str>>);
+
+impl Interner {
+    fn intern(&mut self, s: &str) -> Name<'static> {
+        if let Some(existing) = self.0.get(s) {
+            return Name::Shared(Rc::clone(existing));
+        }
+
+        let shared = Rc::<str>::from(s);
+        self.0.insert(Rc::clone(&shared));
+        Name::Shared(shared)
+    }
+}
+
+macro_rules! impl_str_interned {
+    ($typename:ident) => {
+        impl $typename<'static> {
+            /// Construct from a string that is likely to repeat, see [`Interner`].
+            pub fn interned(interner: &mut Interner, s: &str) -> Self {
+                Self(interner.intern(s))
+            }
+        }
+    };
+}
+
 /// A remote git repository identified by name, like `origin`.
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Remote<'a>(pub Cow<'a, str>);
 impl_str_from!(Remote);
 
@@ -61,9 +255,10 @@ impl_str_from!(Remote);
 impl_str_always_borrow!(Remote);
 
 /// The branch name part of a ref. `refs/head/master` would be `Branch::from("master")`.
-#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct Branch<'a>(pub Cow<'a, str>);
 impl_str_from!(Branch);
+impl_str_as_ref!(Branch);
 impl_str_possibly_clone!(Branch);
 impl_str_always_borrow!(Branch);
 
@@ -72,11 +267,24 @@ impl_str_always_borrow!(Branch);
 ///
 /// This string is used when pushing branches to the remote so that multiple users can use
 /// nomad on that remote without overwriting each others refs.
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
-pub struct User<'a>(pub Cow<'a, str>);
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+pub struct User<'a>(pub Name<'a>);
 impl_str_from!(User);
+impl_str_deserialize_checked!(User, "user");
+impl_str_as_ref!(User);
 impl_str_possibly_clone!(User);
 impl_str_always_borrow!(User);
+impl_str_interned!(User);
+
+impl User<'static> {
+    /// The user name of the current operating system user, if there is one.
+    pub fn os_default() -> Option<Self> {
+        whoami::fallible::username()
+            .ok()
+            .filter(|name| !name.is_empty())
+            .map(Self::from)
+    }
+}
 
 /// Represents "where" a given branch comes from. This value should be unique for every git
 /// clone belonging to a specific user.
@@ -86,11 +294,50 @@ impl_str_always_borrow!(User);
 ///
 /// This string is also used when pulling branches for all hosts of the current user
 /// and for detecting when branches have been deleted.
-#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
-pub struct Host<'a>(pub Cow<'a, str>);
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+pub struct Host<'a>(pub Name<'a>);
 impl_str_from!(Host);
+impl_str_deserialize_checked!(Host, "host");
+impl_str_as_ref!(Host);
 impl_str_possibly_clone!(Host);
 impl_str_always_borrow!(Host);
+impl_str_interned!(Host);
+
+impl Host<'static> {
+    /// The host name of the current machine, if the operating system provides one, see
+    /// [`Self::normalize_os_default`].
+    pub fn os_default() -> Option<Self> {
+        whoami::fallible::hostname()
+            .ok()
+            .and_then(|name| Self::normalize_os_default(&name))
+    }
+
+    /// Host names are case insensitive and may be written fully qualified with a trailing dot, so
+    /// `Desktop.local.` and `desktop.local` are the same machine and should be the same host.
+    fn normalize_os_default(name: &str) -> Option<Self> {
+        let name = name.trim_end_matches('.').to_lowercase();
+        (!name.is_empty()).then(|| Self::from(name))
+    }
+}
+
+/// Check that `name` can be used as a single path component of a ref, as [`User`] and [`Host`]
+/// are in refspecs like `refs/nomad/{user}/{host}/*`.
+///
+/// This is stricter than `git check-ref-format` about the characters that would silently split
+/// into extra components (`/`) or be read as refspec syntax (`:`, `*`, and friends).
+pub fn check_ref_component(name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        return Err("it must not be empty".to_string());
+    }
+
+    match name
+        .chars()
+        .find(|&c| c.is_whitespace() || c.is_control() || "/~^:*?[\\".contains(c))
+    {
+        Some(c) => Err(format!("it must not contain {:?}", c)),
+        None => Ok(()),
+    }
+}
 
 /// A ref representing a branch managed by nomad.
 #[derive(Debug, PartialEq, Eq, Hash)]
@@ -127,6 +374,22 @@ impl RemoteNomadRefSet {
             nomad_ref.branch.always_borrow(),
         ))
     }
+
+    /// Combine the nomad refs known by several remotes, as in a ref is known if *any* of the
+    /// remotes knows about it.
+    pub fn union<'a>(sets: impl IntoIterator<Item = &'a RemoteNomadRefSet>) -> Self {
+        Self::from_iter(
+            sets.into_iter()
+                .flat_map(|s| s.set.iter())
+                .map(|(user, host, branch)| {
+                    (
+                        user.always_borrow(),
+                        host.always_borrow(),
+                        branch.always_borrow(),
+                    )
+                }),
+        )
+    }
 }
 
 impl<'a> FromIterator<(User<'a>, Host<'a>, Branch<'a>)> for RemoteNomadRefSet {
@@ -152,3 +415,37 @@ impl<'a, Ref> FromIterator<NomadRef<'a, Ref>> for RemoteNomadRefSet {
         }))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::Host;
+
+    #[test]
+    fn host_normalize_os_default() {
+        for (name, expected) in [
+            ("desktop", Some("desktop")),
+            ("Desktop", Some("desktop")),
+            ("LAPTOP.local", Some("laptop.local")),
+            ("desktop.example.com.", Some("desktop.example.com")),
+            ("desktop..", Some("desktop")),
+            ("Ronuks MacBook", Some("ronuks macbook")),
+            ("", None),
+            (".", None),
+        ] {
+            assert_eq!(
+                Host::normalize_os_default(name),
+                expected.map(Host::from),
+                "{:?}",
+                name
+            );
+        }
+    }
+
+    /// Whatever the operating system says, the result should already be normalized.
+    #[test]
+    fn host_os_default() {
+        if let Some(host) = Host::os_default() {
+            assert_eq!(Host::normalize_os_default(&host.0).as_ref(), Some(&host));
+        }
+    }
+}
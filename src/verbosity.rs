@@ -1,6 +1,18 @@
 //! Helpers for executing [`Command`]s and parsing their [`Output`].
 
-use std::process::{Command, Output};
+use std::{
+    cell::{Cell, RefCell},
+    fmt,
+    io::{self, Read, Write},
+    os::unix::process::ExitStatusExt,
+    process::{ChildStderr, ChildStdout, Command, ExitStatus, Output, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
 
 use anyhow::{bail, Context, Result};
 
@@ -18,6 +30,8 @@ pub enum SignificanceVerbosity {
 /// How much output to display about invoked commands.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum CommandVerbosity {
+    /// Show nothing at all, as if the command were trivial.
+    Silent,
     /// Show a pretty spinner with a description.
     Spinner,
     /// Only print out the command line invocation (binary and arguments).
@@ -34,6 +48,7 @@ impl CommandVerbosity {
         command: &mut Command,
     ) -> Result<Output> {
         match self {
+            Self::Silent => run_silent(description, command),
             Self::Spinner => run_spinner(renderer, description, command),
             Self::Invocation => run_with_invocation(renderer, description, command),
             Self::InvocationAndOutput => {
@@ -72,6 +87,17 @@ impl Verbosity {
         }
     }
 
+    /// Like [`Self::standard`], but without any spinners, so that only the actual output of a
+    /// workflow is shown.
+    pub const fn no_progress() -> Self {
+        Self {
+            display_workflow: false,
+            display_version: false,
+            significance: SignificanceVerbosity::OnlyNotable,
+            command: CommandVerbosity::Silent,
+        }
+    }
+
     pub const fn verbose() -> Self {
         Self {
             display_workflow: true,
@@ -126,6 +152,101 @@ pub fn run_notable(
     }
 }
 
+/// Like [`run_notable`], but runs all of `commands` at once, each on its own thread, and returns
+/// their results in the same order.
+///
+/// Only the calling thread touches `renderer`: invocations are displayed before anything runs and
+/// output after everything has finished. Neither [`with_timeout`] nor [`with_stdin`] carry over to
+/// the spawned threads.
+pub fn run_notable_concurrently(
+    renderer: &mut impl Renderer,
+    verbosity: Option<Verbosity>,
+    description: impl AsRef<str>,
+    commands: Vec<(String, Command)>,
+) -> Result<Vec<Result<Output>>> {
+    let run_all = |commands: Vec<(String, Command)>| {
+        thread::scope(|scope| {
+            commands
+                .into_iter()
+                .map(|(description, mut command)| {
+                    scope.spawn(move || run_silent(description, &mut command))
+                })
+                .collect::<Vec<_>>()
+                .into_iter()
+                .map(|handle| handle.join().expect("running a command does not panic"))
+                .collect::<Vec<_>>()
+        })
+    };
+
+    let Some(verbosity) = verbosity else {
+        return Ok(run_all(commands));
+    };
+
+    match verbosity.command {
+        CommandVerbosity::Silent => Ok(run_all(commands)),
+        CommandVerbosity::Spinner => {
+            renderer.spinner(description.as_ref().to_owned(), || Ok(run_all(commands)))
+        }
+        CommandVerbosity::Invocation | CommandVerbosity::InvocationAndOutput => {
+            renderer.writer(|w| {
+                for (description, command) in &commands {
+                    writeln!(w)?;
+                    writeln!(w, "# {}", description)?;
+                    writeln!(w, "$ {:#?}", command)?;
+                }
+                Ok(())
+            })?;
+            let results = run_all(commands);
+
+            if verbosity.command == CommandVerbosity::InvocationAndOutput {
+                renderer.writer(|w| {
+                    for output in results.iter().flatten() {
+                        for (name, stream) in
+                            [("STDOUT", &output.stdout), ("STDERR", &output.stderr)]
+                        {
+                            if !stream.is_empty() {
+                                writeln!(w, "{}", String::from_utf8_lossy(stream))?;
+                                writeln!(w, "# ---- END {} ----", name)?;
+                            }
+                        }
+                    }
+                    Ok(())
+                })?;
+            }
+
+            Ok(results)
+        }
+    }
+}
+
+/// Describe `command` instead of running it, for commands that would modify refs during a dry
+/// run.
+///
+/// Only the program and its arguments are shown, since that is what the user would have to run to
+/// carry out the change themselves.
+pub fn report_dry_run(
+    renderer: &mut impl Renderer,
+    verbosity: Option<Verbosity>,
+    description: impl AsRef<str>,
+    command: &Command,
+) -> Result<()> {
+    if !is_output_allowed(verbosity) {
+        return Ok(());
+    }
+
+    let invocation = std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|arg| arg.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    renderer.writer(|w| {
+        writeln!(w, "{} (dry run)", description.as_ref())?;
+        writeln!(w, "    $ {}", invocation)?;
+        Ok(())
+    })
+}
+
 /// Extract the printed `stdout` from the [`Output`] of a [`Command`].
 ///
 /// Best used in an `and_then` chain.
@@ -133,40 +254,457 @@ pub fn output_stdout(output: Output) -> Result<String> {
     Ok(String::from_utf8(output.stdout)?)
 }
 
+/// Tracks the child processes that are currently running, so that they can be terminated when
+/// the user interrupts `git-nomad`.
+///
+/// Usually there is at most one, except for [`run_notable_concurrently`].
+struct InFlight {
+    interrupted: AtomicBool,
+    children: Mutex<Vec<u32>>,
+}
+
+/// The [`InFlight`] tracker used by every [`Command`] run through this module.
+static IN_FLIGHT: InFlight = InFlight::new();
+
+impl InFlight {
+    const fn new() -> Self {
+        Self {
+            interrupted: AtomicBool::new(false),
+            children: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Run `command` to completion, unless [`Self::interrupt`] is called first.
+    ///
+    /// Behaves like [`Command::output`], except that `stdin` is not passed along and is instead
+    /// only fed the input given to [`with_stdin`], if any.
+    fn output(&self, command: &mut Command) -> Result<Output> {
+        self.check()?;
+
+        let output = {
+            let input = STDIN.with(|cell| cell.borrow().clone());
+
+            let mut children = self.children.lock().unwrap();
+            let mut spawned = command
+                .stdin(if input.is_some() {
+                    Stdio::piped()
+                } else {
+                    Stdio::null()
+                })
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()?;
+            let pid = spawned.id();
+            children.push(pid);
+            drop(children);
+
+            if let (Some(input), Some(mut stdin)) = (input, spawned.stdin.take()) {
+                // Written from a separate thread so that a child filling up its output pipes
+                // before reading all of its input cannot deadlock us. Dropping `stdin` at the end
+                // closes it, signalling the end of the input.
+                thread::spawn(move || stdin.write_all(&input));
+            }
+
+            let watchdog = TIMEOUT
+                .with(Cell::get)
+                .map(|timeout| Watchdog::start(pid, timeout));
+
+            // Like `Child::wait_with_output`, except that the child is only reaped once nothing
+            // can signal it anymore, since its PID could be reused by an unrelated process after.
+            let (stdout, stderr) = read_pipes(spawned.stdout.take(), spawned.stderr.take());
+            wait_for_exit(pid)?;
+            let timed_out = watchdog.and_then(Watchdog::stop);
+            self.children.lock().unwrap().retain(|&child| child != pid);
+            let status = spawned.wait()?;
+
+            // The watchdog may fire just after the child exits on its own, which is not a timeout.
+            if let Some(timeout) = timed_out {
+                if status.signal() == Some(libc::SIGTERM) {
+                    return Err(TimedOut(timeout).into());
+                }
+            }
+            Output {
+                status,
+                stdout: stdout?,
+                stderr: stderr?,
+            }
+        };
+
+        self.check()?;
+        Ok(output)
+    }
+
+    /// Stop running commands and terminate the ones that are in flight, if any.
+    ///
+    /// `git` cleans up its own lock files on `SIGTERM`, so the repository is left as it would be
+    /// if that command had never started.
+    fn interrupt(&self) {
+        self.interrupted.store(true, Ordering::SeqCst);
+        for &pid in self.children.lock().unwrap().iter() {
+            // SAFETY: `kill` has no memory safety requirements. The PID still belongs to our child
+            // since children are only reaped after being removed under this lock, see
+            // `Self::output`.
+            unsafe {
+                libc::kill(pid as libc::pid_t, libc::SIGTERM);
+            }
+        }
+    }
+
+    fn check(&self) -> Result<()> {
+        if self.interrupted.load(Ordering::SeqCst) {
+            bail!("Interrupted");
+        }
+        Ok(())
+    }
+}
+
+/// Read both of a child's output pipes to the end, concurrently so that a child blocked on
+/// writing to one of them cannot deadlock us.
+fn read_pipes(
+    stdout: Option<ChildStdout>,
+    stderr: Option<ChildStderr>,
+) -> (io::Result<Vec<u8>>, io::Result<Vec<u8>>) {
+    fn read_to_end(pipe: Option<impl Read>) -> io::Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        if let Some(mut pipe) = pipe {
+            pipe.read_to_end(&mut buf)?;
+        }
+        Ok(buf)
+    }
+
+    thread::scope(|scope| {
+        let stdout = scope.spawn(|| read_to_end(stdout));
+        let stderr = read_to_end(stderr);
+        (stdout.join().expect("reading does not panic"), stderr)
+    })
+}
+
+/// Block until the child with `pid` has exited, but leave it to be reaped by [`Child::wait`].
+///
+/// [`Child::wait`]: std::process::Child::wait
+fn wait_for_exit(pid: u32) -> io::Result<()> {
+    loop {
+        // SAFETY: `siginfo_t` is plain data that `waitid` fills in, and `WNOWAIT` leaves the child
+        // waitable so that `Child` still owns it.
+        let result = unsafe {
+            let mut info = std::mem::zeroed::<libc::siginfo_t>();
+            libc::waitid(
+                libc::P_PID,
+                pid as libc::id_t,
+                &mut info,
+                libc::WEXITED | libc::WNOWAIT,
+            )
+        };
+        if result == 0 {
+            return Ok(());
+        }
+
+        let error = io::Error::last_os_error();
+        if error.kind() != io::ErrorKind::Interrupted {
+            return Err(error);
+        }
+    }
+}
+
+thread_local! {
+    /// How long commands may run on this thread before being terminated, see [`with_timeout`].
+    static TIMEOUT: Cell<Option<Duration>> = const { Cell::new(None) };
+
+    /// What commands run on this thread are fed on `stdin`, see [`with_stdin`].
+    static STDIN: RefCell<Option<Vec<u8>>> = const { RefCell::new(None) };
+}
+
+/// A command was terminated for running longer than allowed by [`with_timeout`].
+#[derive(Debug)]
+pub struct TimedOut(pub Duration);
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "timed out after {} seconds", self.0.as_secs_f64())
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// Terminates a child process unless it is stopped before the timeout.
+struct Watchdog {
+    timeout: Duration,
+    stop: mpsc::Sender<()>,
+    thread: thread::JoinHandle<bool>,
+}
+
+impl Watchdog {
+    fn start(pid: u32, timeout: Duration) -> Self {
+        let (stop, stopped) = mpsc::channel();
+        let thread = thread::spawn(move || match stopped.recv_timeout(timeout) {
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                // SAFETY: `kill` has no memory safety requirements. The PID still belongs to our
+                // child since it is only reaped after the watchdog is stopped, see
+                // `InFlight::output`.
+                unsafe {
+                    libc::kill(pid as libc::pid_t, libc::SIGTERM);
+                }
+                true
+            }
+            Ok(()) | Err(mpsc::RecvTimeoutError::Disconnected) => false,
+        });
+
+        Self {
+            timeout,
+            stop,
+            thread,
+        }
+    }
+
+    /// Returns the timeout if the child had to be terminated.
+    fn stop(self) -> Option<Duration> {
+        // The watchdog may have already fired, in which case nobody is listening.
+        let _ = self.stop.send(());
+        self.thread
+            .join()
+            .expect("watchdog does not panic")
+            .then_some(self.timeout)
+    }
+}
+
+/// Run `func` with every [`Command`] it invokes on this thread limited to `timeout`, failing with
+/// [`TimedOut`] otherwise.
+pub fn with_timeout<T>(timeout: Duration, func: impl FnOnce() -> Result<T>) -> Result<T> {
+    let previous = TIMEOUT.with(|cell| cell.replace(Some(timeout)));
+    let result = func();
+    TIMEOUT.with(|cell| cell.set(previous));
+    result
+}
+
+/// Run `func` with every [`Command`] it invokes on this thread receiving `input` on `stdin`, for
+/// commands like `git update-ref --stdin` that take their instructions that way.
+pub fn with_stdin<T>(input: Vec<u8>, func: impl FnOnce() -> Result<T>) -> Result<T> {
+    let previous = STDIN.with(|cell| cell.replace(Some(input)));
+    let result = func();
+    STDIN.with(|cell| cell.replace(previous));
+    result
+}
+
+/// Fragments of what git prints on `stderr` for network failures that tend to go away on their
+/// own, see [`retry_transient`].
+///
+/// Lowercase, since they are matched case insensitively: the capitalization varies between git
+/// versions and the underlying libraries.
+const TRANSIENT_FAILURES: [&str; 7] = [
+    "could not resolve host",
+    "temporary failure in name resolution",
+    "connection timed out",
+    "operation timed out",
+    "connection reset by peer",
+    "the remote end hung up unexpectedly",
+    "early eof",
+];
+
+/// How often to retry commands that fail for transient network reasons, see [`retry_transient`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Retries {
+    /// How many times to retry after the first attempt.
+    pub count: u32,
+    /// How long to wait before the first retry, each later retry waits twice as long as the one
+    /// before it.
+    pub backoff: Duration,
+}
+
+impl Retries {
+    /// Every failure is final.
+    pub const NONE: Self = Self {
+        count: 0,
+        backoff: Duration::ZERO,
+    };
+}
+
+/// Run `func`, running it again according to `retries` for as long as it fails with a
+/// [`CommandFailure`] that looks like a transient network problem, see [`TRANSIENT_FAILURES`].
+///
+/// Each retry is announced with a warning, unless output is not allowed.
+pub fn retry_transient<R: Renderer, T>(
+    renderer: &mut R,
+    verbosity: Option<Verbosity>,
+    retries: Retries,
+    mut func: impl FnMut(&mut R) -> Result<T>,
+) -> Result<T> {
+    let mut backoff = retries.backoff;
+    for attempt in 1..=retries.count {
+        let error = match func(renderer) {
+            Err(error) if is_transient(&error) => error,
+            result => return result,
+        };
+
+        if is_output_allowed(verbosity) {
+            let summary = match error.downcast_ref::<CommandFailure>() {
+                Some(failure) => failure.summary(),
+                None => error.to_string(),
+            };
+            renderer.writer(|w| {
+                writeln!(
+                    w,
+                    "Warning: {}, retrying in {}s ({} of {})",
+                    summary,
+                    backoff.as_secs_f64(),
+                    attempt,
+                    retries.count
+                )?;
+                Ok(())
+            })?;
+        }
+        sleep_unless_interrupted(backoff)?;
+        backoff = backoff.saturating_mul(2);
+    }
+    func(renderer)
+}
+
+/// Whether `error` is a [`CommandFailure`] that is worth retrying, see [`retry_transient`].
+fn is_transient(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<CommandFailure>()
+        .is_some_and(|failure| {
+            let stderr = failure.stderr().to_lowercase();
+            TRANSIENT_FAILURES
+                .iter()
+                .any(|fragment| stderr.contains(fragment))
+        })
+}
+
+/// Has the user interrupted `git-nomad`, see [`install_interrupt_handler`]?
+pub fn is_interrupted() -> bool {
+    IN_FLIGHT.interrupted.load(Ordering::SeqCst)
+}
+
+/// Like [`thread::sleep`], but fails with an "Interrupted" error as soon as the user interrupts
+/// `git-nomad`, rather than after the full `duration`.
+pub fn sleep_unless_interrupted(duration: Duration) -> Result<()> {
+    const POLL: Duration = Duration::from_millis(100);
+
+    let mut remaining = duration;
+    while !remaining.is_zero() {
+        IN_FLIGHT.check()?;
+        let step = remaining.min(POLL);
+        thread::sleep(step);
+        remaining -= step;
+    }
+    IN_FLIGHT.check()
+}
+
+/// Handle `SIGINT` (Ctrl-C) and `SIGTERM` by terminating the in-flight `git` command and
+/// refusing to start any new ones, so that the workflow stops with an "Interrupted" error.
+///
+/// A second signal exits immediately.
+#[cfg(not(test))]
+pub fn install_interrupt_handler() -> Result<()> {
+    ctrlc::set_handler(|| {
+        if IN_FLIGHT.interrupted.load(Ordering::SeqCst) {
+            std::process::exit(130);
+        }
+        IN_FLIGHT.interrupt();
+    })
+    .context("installing interrupt handler")
+}
+
 /// Invoke a [`Command`] and check its exit code for success.
 fn run_silent<S: AsRef<str>>(description: S, command: &mut Command) -> Result<Output> {
-    let output = command
-        .output()
+    let output = IN_FLIGHT
+        .output(command)
         .with_context(|| format!("{}: {:?}", description.as_ref(), command))?;
 
     if !output.status.success() {
-        return dump_command_failure(command, &output);
+        return dump_command_failure(description.as_ref(), command, &output);
     }
 
     Ok(output)
 }
 
-/// Make some effort to build a decent error message for commands that fail.
-fn dump_command_failure<T>(command: &Command, output: &Output) -> Result<T> {
-    let forward = |name: &str, stream: &[u8]| {
-        if stream.is_empty() {
-            String::new()
-        } else {
-            format!(
-                "\n# ---- {} ----\n{}",
-                name,
-                String::from_utf8_lossy(stream)
-            )
+/// A [`Command`] that exited unsuccessfully.
+///
+/// [`fmt::Display`] makes some effort to build a decent error message with everything the user
+/// might need for debugging, while [`CommandFailure::summary`] is a terse single line.
+#[derive(Debug)]
+pub struct CommandFailure {
+    description: String,
+    invocation: String,
+    status: ExitStatus,
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+}
+
+impl CommandFailure {
+    /// The exit code of the command, if it was not killed by a signal.
+    pub fn code(&self) -> Option<i32> {
+        self.status.code()
+    }
+
+    /// Whatever the command printed to `stdout`, lossily decoded.
+    pub fn stdout(&self) -> std::borrow::Cow<'_, str> {
+        String::from_utf8_lossy(&self.stdout)
+    }
+
+    /// Whatever the command printed to `stderr`, lossily decoded.
+    pub fn stderr(&self) -> std::borrow::Cow<'_, str> {
+        String::from_utf8_lossy(&self.stderr)
+    }
+
+    /// A single line describing the failure, like `Fetching branches from origin failed: fatal:
+    /// 'origin' does not appear to be a git repository`.
+    pub fn summary(&self) -> String {
+        let stderr = String::from_utf8_lossy(&self.stderr);
+        match stderr.lines().find(|line| !line.trim().is_empty()) {
+            Some(line) => format!("{} failed: {}", self.description, line.trim()),
+            None => format!("{} failed: {}", self.description, self.status),
         }
-    };
+    }
+}
+
+impl fmt::Display for CommandFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let forward = |name: &str, stream: &[u8]| {
+            if stream.is_empty() {
+                String::new()
+            } else {
+                format!(
+                    "\n# ---- {} ----\n{}",
+                    name,
+                    String::from_utf8_lossy(stream)
+                )
+            }
+        };
+
+        write!(
+            f,
+            "command failure\n$ {}\n# status: {}{}{}",
+            self.invocation,
+            self.status,
+            forward("STDOUT", &self.stdout),
+            forward("STDERR", &self.stderr)
+        )
+    }
+}
 
-    bail!(
-        "command failure\n$ {:?}\n# status: {}{}{}",
-        command,
-        output.status,
-        forward("STDOUT", &output.stdout),
-        forward("STDERR", &output.stderr)
-    );
+impl std::error::Error for CommandFailure {}
+
+/// Wrap the failure of a [`Command`] as a [`CommandFailure`] error.
+fn dump_command_failure<T>(description: &str, command: &Command, output: &Output) -> Result<T> {
+    Err(CommandFailure {
+        description: description.to_owned(),
+        invocation: format!("{:?}", command),
+        status: output.status,
+        stdout: output.stdout.clone(),
+        stderr: output.stderr.clone(),
+    }
+    .into())
+}
+
+/// Replace any [`CommandFailure`] in `error` with its single line [`CommandFailure::summary`].
+///
+/// Other errors are returned untouched.
+pub fn summarize_command_failure(error: anyhow::Error) -> anyhow::Error {
+    match error.downcast_ref::<CommandFailure>() {
+        Some(failure) => anyhow::anyhow!(failure.summary()),
+        None => error,
+    }
 }
 
 fn run_spinner(
@@ -230,16 +768,28 @@ mod test {
         process::{Command, ExitStatus, Output},
     };
 
+    use std::{
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use anyhow::Context;
+
     use crate::{
         renderer::test::NoRenderer,
         verbosity::{run_notable, run_silent},
     };
 
-    use super::{dump_command_failure, output_stdout, run_trivial, Verbosity};
+    use super::{
+        dump_command_failure, is_transient, output_stdout, retry_transient, run_trivial,
+        sleep_unless_interrupted, summarize_command_failure, with_stdin, with_timeout, InFlight,
+        Retries, TimedOut, Verbosity,
+    };
 
     const ALL_VERBOSITIES: &[Option<Verbosity>] = &[
         None,
         Some(Verbosity::standard()),
+        Some(Verbosity::no_progress()),
         Some(Verbosity::verbose()),
         Some(Verbosity::max()),
     ];
@@ -276,6 +826,63 @@ mod test {
         }
     }
 
+    /// Only failures that look transient should be retried, and only as often as allowed.
+    #[test]
+    fn test_retry_transient() {
+        let retries = Retries {
+            count: 2,
+            backoff: Duration::from_millis(1),
+        };
+        let run = |stderr: &'static str, failures: usize| {
+            let mut attempts = 0;
+            let result = retry_transient(&mut NoRenderer, None, retries, |_| {
+                attempts += 1;
+                let script = if attempts <= failures {
+                    format!("echo '{}' >&2; exit 128", stderr)
+                } else {
+                    "exit 0".to_string()
+                };
+                run_silent("flaky", Command::new("sh").args(["-c", &script]))
+            });
+            (result.is_ok(), attempts)
+        };
+
+        assert_eq!(
+            run("fatal: Could not resolve host: example.invalid", 0),
+            (true, 1)
+        );
+        assert_eq!(
+            run("fatal: Could not resolve host: example.invalid", 2),
+            (true, 3)
+        );
+        assert_eq!(run("fatal: Connection timed out", 3), (false, 3));
+        assert_eq!(run("fatal: repository not found", 1), (false, 1));
+    }
+
+    /// [`is_transient`] should match git's actual wording regardless of capitalization.
+    #[test]
+    fn test_is_transient() {
+        let failure = |stderr: &str| {
+            let output = Output {
+                status: ExitStatus::from_raw(128 << 8),
+                stdout: Vec::new(),
+                stderr: stderr.as_bytes().to_vec(),
+            };
+            dump_command_failure::<()>("Fetching", &Command::new("git"), &output).unwrap_err()
+        };
+
+        for stderr in [
+            "fatal: the remote end hung up unexpectedly\n",
+            "error: RPC failed; curl 56 Recv failure: Connection reset by peer\n",
+            "fatal: early EOF\nfatal: index-pack failed\n",
+            "ssh: Could not resolve hostname example.invalid: Temporary failure in name resolution\n",
+        ] {
+            assert!(is_transient(&failure(stderr)), "{}", stderr);
+        }
+        assert!(!is_transient(&failure("fatal: repository not found\n")));
+        assert!(!is_transient(&anyhow::anyhow!("connection timed out")));
+    }
+
     #[test]
     fn test_failure() {
         let output = run_silent("failure", &mut Command::new("false"));
@@ -299,7 +906,7 @@ mod test {
             stderr: "some stderr".as_bytes().to_vec(),
         };
 
-        let dump = dump_command_failure::<()>(&command, &output).unwrap_err();
+        let dump = dump_command_failure::<()>("Doing the thing", &command, &output).unwrap_err();
         let displayed_dump = format!("{}", dump);
 
         assert!(displayed_dump.contains("binary"));
@@ -322,7 +929,7 @@ mod test {
             stderr: Vec::new(),
         };
 
-        let dump = dump_command_failure::<()>(&command, &output).unwrap_err();
+        let dump = dump_command_failure::<()>("Doing the thing", &command, &output).unwrap_err();
         let displayed_dump = format!("{}", dump);
 
         assert!(displayed_dump.contains("binary"));
@@ -333,4 +940,121 @@ mod test {
         assert!(!displayed_dump.contains("STDERR"));
         assert!(!displayed_dump.contains("some stderr"));
     }
+
+    /// [`summarize_command_failure`] should produce a single line with the first line of stderr.
+    #[test]
+    fn test_summarize_command_failure() {
+        let mut command = Command::new("binary");
+        command.arg("arg");
+
+        let output = Output {
+            status: ExitStatus::from_raw(123),
+            stdout: "some stdout".as_bytes().to_vec(),
+            stderr: "\nfatal: first\nsecond\n".as_bytes().to_vec(),
+        };
+
+        let dump = dump_command_failure::<()>("Doing the thing", &command, &output)
+            .context("with context")
+            .unwrap_err();
+        let displayed_summary = format!("{}", summarize_command_failure(dump));
+
+        assert_eq!(displayed_summary, "Doing the thing failed: fatal: first");
+    }
+
+    /// [`summarize_command_failure`] should fall back to the exit status without any stderr.
+    #[test]
+    fn test_summarize_command_failure_without_stderr() {
+        let output = Output {
+            status: ExitStatus::from_raw(123),
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+        };
+
+        let dump = dump_command_failure::<()>("Doing the thing", &Command::new("binary"), &output)
+            .unwrap_err();
+        let displayed_summary = format!("{}", summarize_command_failure(dump));
+
+        assert!(displayed_summary.starts_with("Doing the thing failed: "));
+        assert!(!displayed_summary.contains('\n'));
+    }
+
+    /// [`summarize_command_failure`] should leave unrelated errors alone.
+    #[test]
+    fn test_summarize_other_error() {
+        let error = summarize_command_failure(anyhow::anyhow!("unrelated"));
+        assert_eq!(format!("{}", error), "unrelated");
+    }
+
+    /// [`with_timeout`] should terminate commands that run too long, and only while it is active.
+    #[test]
+    fn test_with_timeout() {
+        let error = with_timeout(Duration::from_millis(100), || {
+            run_silent("sleep", Command::new("sleep").arg("60"))
+        })
+        .unwrap_err();
+        assert!(error.downcast_ref::<TimedOut>().is_some());
+
+        with_timeout(Duration::from_secs(60), || {
+            run_silent("fast", &mut Command::new("true"))
+        })
+        .unwrap();
+        run_silent("no timeout", Command::new("sleep").arg("0.2")).unwrap();
+    }
+
+    /// A command that finishes on its own should not count as timed out, even if the watchdog
+    /// fired first, as with a command that ignores `SIGTERM`.
+    #[test]
+    fn test_with_timeout_finished() {
+        with_timeout(Duration::from_millis(100), || {
+            run_silent(
+                "ignore SIGTERM",
+                Command::new("sh").args(["-c", "trap '' TERM; sleep 0.3"]),
+            )
+        })
+        .unwrap();
+    }
+
+    /// [`with_stdin`] should feed its input to commands, and only while it is active.
+    #[test]
+    fn test_with_stdin() {
+        let echoed = with_stdin(b"line0\nline1\n".to_vec(), || {
+            run_silent("cat", &mut Command::new("cat")).and_then(output_stdout)
+        })
+        .unwrap();
+        assert_eq!(echoed, "line0\nline1\n");
+
+        let echoed = run_silent("cat", &mut Command::new("cat"))
+            .and_then(output_stdout)
+            .unwrap();
+        assert_eq!(echoed, "");
+    }
+
+    /// [`sleep_unless_interrupted`] should wait out the whole duration when nobody interrupts.
+    #[test]
+    fn test_sleep_unless_interrupted() {
+        let start = Instant::now();
+        sleep_unless_interrupted(Duration::from_millis(250)).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(250));
+        sleep_unless_interrupted(Duration::ZERO).unwrap();
+    }
+
+    /// [`InFlight::interrupt`] should terminate the running command and refuse to start new ones.
+    #[test]
+    fn test_interrupt() {
+        let in_flight = InFlight::new();
+
+        thread::scope(|scope| {
+            let handle = scope.spawn(|| in_flight.output(Command::new("sleep").arg("60")));
+            while in_flight.children.lock().unwrap().is_empty() {
+                thread::sleep(Duration::from_millis(10));
+            }
+            in_flight.interrupt();
+
+            let error = handle.join().unwrap().unwrap_err();
+            assert_eq!(format!("{}", error), "Interrupted");
+        });
+
+        let error = in_flight.output(&mut Command::new("true")).unwrap_err();
+        assert_eq!(format!("{}", error), "Interrupted");
+    }
 }
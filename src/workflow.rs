@@ -1,314 +1,4017 @@
 //! High level user invoked workflows for nomad.
 
-use std::{collections::HashSet, hash::Hash, io::Write};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    hash::Hash,
+    io::Write,
+    path::Path,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use clap::{builder::PossibleValue, ValueEnum};
+use console::{Color, Style};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    git_binary::GitBinary,
+    git_binary::{ConfigScope, FetchOptions, GitBinary, PushCount},
     git_ref::GitRef,
-    renderer::{add_newline_if_spinners_are_visible, Renderer},
-    types::{Branch, Host, NomadRef, Remote, User},
+    renderer::{add_newline_if_spinners_are_visible, ProgressEvent, Renderer, Styler},
+    snapshot::{disagreements, PruneFrom, Snapshot, SnapshotDiff},
+    types::{Branch, Host, NomadRef, Remote, RemoteNomadRefSet, User},
+    verbosity::{is_interrupted, sleep_unless_interrupted},
 };
 
+/// How many refs can be pruned before [`PruneConfirm`] asks the user for confirmation.
+pub const DEFAULT_PRUNE_CONFIRM_THRESHOLD: usize = 20;
+
 /// A boundary type that separates the CLI interface from high level nomad workflows.
-#[derive(Debug, PartialEq, Eq)]
+///
+/// Also (de)serializable so that workflows can be described in a file, see
+/// [`Workflow::from_toml`].
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Workflow<'a> {
     Sync {
         user: User<'a>,
         host: Host<'a>,
-        remote: Remote<'a>,
+        /// Synced in order, see [`sync`]. Written as `remote` so that a single remote can be
+        /// given as a plain string.
+        #[serde(rename = "remote", deserialize_with = "one_or_many")]
+        remotes: Vec<Remote<'a>>,
+        #[serde(flatten)]
+        options: SyncOptions,
+        /// Only push the local branches that match, see [`GitBinary::push_nomad_refs`].
+        #[serde(default)]
+        branch_filter: Filter<Branch<'a>>,
     },
     Ls {
-        printer: LsPrinter,
         user: User<'a>,
-        fetch_remote: Option<Remote<'a>>,
-        host_filter: Filter<Host<'a>>,
-        branch_filter: Filter<Branch<'a>>,
+        /// Mirrors of the same refs, see [`fetch_mirrors`]. Written as `fetch_remote` so that a
+        /// single remote can be given as a plain string.
+        #[serde(rename = "fetch_remote", default, deserialize_with = "one_or_many")]
+        fetch_remotes: Vec<Remote<'a>>,
+        #[serde(default)]
+        fetch_options: FetchOptions,
+        #[serde(flatten)]
+        options: LsOptions<'a>,
+        /// List tags synced with [`SyncOptions::include_tags`] instead of branches.
+        #[serde(default)]
+        tags: bool,
+        /// Fetch and list again every this many seconds until interrupted, see [`watch`].
+        #[serde(default)]
+        watch_interval: Option<u64>,
     },
+    /// Fetch other hosts' refs without pushing, pruning, or printing them.
+    Fetch { user: User<'a>, remote: Remote<'a> },
     Purge {
         user: User<'a>,
         remote: Remote<'a>,
+        #[serde(default)]
         host_filter: Filter<Host<'a>>,
+        #[serde(default)]
+        branch_filter: Filter<Branch<'a>>,
+        #[serde(default)]
+        prune_confirm: PruneConfirm,
+        /// Only delete refs whose commit is older than this.
+        #[serde(default)]
+        older_than: Option<Age>,
+        /// Only report the refs that would be deleted, see [`SyncOptions::dry_run`].
+        #[serde(default)]
+        dry_run: bool,
+        /// See [`SyncOptions::fast_prune`].
+        #[serde(default)]
+        fast_prune: bool,
+        /// Only delete the local copies, leaving the remote untouched, see [`purge`].
+        #[serde(default)]
+        local_only: bool,
+        /// Delete exactly these nomad refs instead of the ones matching the filters, see
+        /// [`GitBinary::parse_nomad_ref_name`].
+        #[serde(default)]
+        refs: Vec<String>,
+    },
+    /// Bring refs pushed outside of nomad under nomad management, see [`adopt`].
+    Adopt {
+        user: User<'a>,
+        remote: Remote<'a>,
+        /// Which refs to adopt, as understood by `git ls-remote`, like `refs/custom/*`.
+        pattern: String,
+        /// The host to adopt the refs as.
+        host: Host<'a>,
+        #[serde(default)]
+        delete_originals: bool,
+        /// Actually adopt the refs instead of reporting what would happen.
+        #[serde(default)]
+        fix: bool,
+    },
+    Du {
+        user: User<'a>,
+        remote: Remote<'a>,
+        #[serde(default)]
+        json: bool,
+    },
+    /// Fetch and check out another host's branch as a new local branch, see [`checkout`].
+    Checkout {
+        user: User<'a>,
+        remote: Remote<'a>,
+        /// The host whose branch to check out, not the current host.
+        host: Host<'a>,
+        branch: Branch<'a>,
+    },
+    /// Suggest a ref prefix based on the nomad refs already at a remote, see [`detect_prefix`].
+    DetectPrefix { remote: Remote<'a> },
+    /// Print a compact sync status for the current branch, see [`prompt`].
+    Prompt { user: User<'a>, host: Host<'a> },
+    /// Print the refspecs and remote ref names nomad computes, see [`explain`].
+    Explain { user: User<'a>, host: Host<'a> },
+    /// Remove a nomad config key so that it falls back to its default.
+    #[serde(skip)]
+    ConfigUnset { scope: ConfigScope, key: String },
+    /// Write a nomad config key to the repository's git config, see [`GitBinary::set_config`].
+    #[serde(skip)]
+    ConfigSet { key: String, value: String },
+    /// Print the resolved user and host, along with where each came from.
+    #[serde(skip)]
+    ConfigShow {
+        user: User<'a>,
+        host: Host<'a>,
+        user_source: String,
+        host_source: String,
     },
-    Completions(clap_complete::Shell),
+    #[serde(skip)]
+    Completions(CompletionShell),
+}
+
+/// Accept either a single value or a list of them, so that a field can grow from one value to many
+/// without breaking existing workflow files.
+fn one_or_many<'de, D, T>(deserializer: D) -> std::result::Result<Vec<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany<T> {
+        One(T),
+        Many(Vec<T>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => vec![value],
+        OneOrMany::Many(values) => values,
+    })
+}
+
+impl Workflow<'static> {
+    /// Parse a workflow described as TOML, like:
+    ///
+    /// ```toml
+    /// [sync]
+    /// user = "rraval"
+    /// host = "apollo"
+    /// remote = "origin"
+    /// ```
+    pub fn from_toml(toml: &str) -> Result<Self> {
+        toml::from_str(toml).context("parsing workflow")
+    }
 }
 
 impl Workflow<'_> {
+    /// The remotes that the workflow will talk to, if any.
+    pub fn remotes(&self) -> &[Remote<'_>] {
+        match self {
+            Self::Sync { remotes, .. } => remotes,
+            Self::Fetch { remote, .. }
+            | Self::Purge { remote, .. }
+            | Self::Adopt { remote, .. }
+            | Self::Checkout { remote, .. }
+            | Self::Du { remote, .. }
+            | Self::DetectPrefix { remote } => std::slice::from_ref(remote),
+            Self::Ls { fetch_remotes, .. } => fetch_remotes,
+            Self::Prompt { .. }
+            | Self::Explain { .. }
+            | Self::ConfigUnset { .. }
+            | Self::ConfigSet { .. }
+            | Self::ConfigShow { .. }
+            | Self::Completions(_) => &[],
+        }
+    }
+
     /// Imperatively execute the workflow.
-    pub fn execute(self, renderer: &mut impl Renderer, git: &GitBinary) -> Result<()> {
+    pub fn execute(self, renderer: &mut impl Renderer, git: &GitBinary) -> Result<Outcome> {
         match self {
-            Self::Sync { user, host, remote } => sync(renderer, git, &user, &host, &remote),
+            Self::Sync {
+                user,
+                host,
+                remotes,
+                options,
+                branch_filter,
+            } => {
+                return sync(
+                    renderer,
+                    git,
+                    &user,
+                    &host,
+                    &remotes,
+                    options,
+                    &branch_filter,
+                )
+            }
             Self::Ls {
-                printer,
                 user,
-                fetch_remote,
+                fetch_remotes,
+                fetch_options,
+                options,
+                tags,
+                watch_interval,
+            } => {
+                let tags_git;
+                let git = if tags {
+                    tags_git = git.for_tags();
+                    &tags_git
+                } else {
+                    git
+                };
+
+                match watch_interval {
+                    None => ls(
+                        renderer,
+                        git,
+                        &user,
+                        &fetch_remotes,
+                        Some(fetch_options),
+                        &options,
+                    ),
+                    Some(seconds) => watch(
+                        renderer,
+                        Duration::from_secs(seconds),
+                        sleep_unless_interrupted,
+                        |renderer| {
+                            renderer.clear_screen()?;
+                            // Fetched in reverse so that the first remote wins, like `fetch_mirrors`.
+                            for remote in fetch_remotes.iter().rev() {
+                                fetch_or_warn(renderer, git, &user, remote, fetch_options)?;
+                            }
+                            ls(renderer, git, &user, &fetch_remotes, None, &options)
+                        },
+                    ),
+                }
+            }
+            Self::Fetch { user, remote } => {
+                git.fetch_nomad_refs(renderer, &user, &remote, FetchOptions::default())
+            }
+            Self::Purge {
+                user,
+                remote,
                 host_filter,
                 branch_filter,
-            } => ls(
+                prune_confirm,
+                older_than,
+                dry_run,
+                fast_prune,
+                local_only,
+                refs,
+            } => {
+                return purge(
+                    renderer,
+                    git,
+                    &user,
+                    &remote,
+                    host_filter,
+                    branch_filter,
+                    prune_confirm,
+                    older_than,
+                    dry_run,
+                    fast_prune,
+                    local_only,
+                    &refs,
+                )
+            }
+            Self::Adopt {
+                user,
+                remote,
+                pattern,
+                host,
+                delete_originals,
+                fix,
+            } => adopt(
                 renderer,
                 git,
-                printer,
                 &user,
-                fetch_remote,
-                host_filter,
-                branch_filter,
+                &remote,
+                &pattern,
+                &host,
+                delete_originals,
+                fix,
             ),
-            Self::Purge {
+            Self::Du { user, remote, json } => du(renderer, git, &user, &remote, json),
+            Self::Checkout {
                 user,
                 remote,
-                host_filter,
-            } => purge(renderer, git, &user, &remote, host_filter),
+                host,
+                branch,
+            } => checkout(renderer, git, &user, &remote, &host, &branch).map(|_| ()),
+            Self::DetectPrefix { remote } => detect_prefix(renderer, git, &remote),
+            Self::Prompt { user, host } => prompt(renderer, git, user, host),
+            Self::Explain { user, host } => explain(renderer, git, &user, &host),
+            Self::ConfigUnset { scope, key } => git.unset_config(renderer, scope, &key),
+            Self::ConfigSet { key, value } => git.set_config(renderer, &key, &value),
+            Self::ConfigShow {
+                user,
+                host,
+                user_source,
+                host_source,
+            } => {
+                let last_sync = match git.last_sync(renderer)? {
+                    Some(timestamp) => format!("{} ago", Age::since(timestamp, unix_now()?)),
+                    None => "never".to_string(),
+                };
+                renderer.writer(|w| {
+                    writeln!(w, "user: {}, from {}", user.0, user_source)?;
+                    writeln!(w, "host: {}, from {}", host.0, host_source)?;
+                    writeln!(w, "last sync: {}", last_sync)?;
+                    Ok(())
+                })
+            }
             Self::Completions(shell) => print_completions(renderer, shell),
+        }?;
+        Ok(Outcome::Changed)
+    }
+}
+
+/// Whether a workflow changed any nomad refs, which `--exit-code` reports as the exit code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// Refs were pushed or pruned. Workflows that do not keep track always report this.
+    Changed,
+    /// Everything was already up to date.
+    Unchanged,
+}
+
+impl Outcome {
+    fn from_changed(changed: bool) -> Self {
+        if changed {
+            Self::Changed
+        } else {
+            Self::Unchanged
         }
     }
 }
 
 /// Declarative representation of a limited filter function.
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Filter<T: PartialEq + Eq + Hash> {
     /// Everything.
+    #[default]
     All,
     /// Only the specified values.
     Allow(HashSet<T>),
     /// Everything except the specified values.
     Deny(HashSet<T>),
+    /// Only values matching the pattern.
+    Regex(Pattern),
+    /// Only values matching any of the globs.
+    Glob(Vec<Glob>),
 }
 
-impl<T: PartialEq + Eq + Hash> Filter<T> {
+impl<T: PartialEq + Eq + Hash + AsRef<str>> Filter<T> {
     pub fn contains(&self, t: &T) -> bool {
         match self {
             Self::All => true,
             Self::Allow(hash_set) => hash_set.contains(t),
             Self::Deny(hash_set) => !hash_set.contains(t),
+            Self::Regex(pattern) => pattern.0.is_match(t.as_ref()),
+            Self::Glob(globs) => globs.iter().any(|glob| glob.0.matches(t.as_ref())),
         }
     }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
-pub enum LsPrinter {
-    Grouped,
-    Ref,
-    Commit,
+/// A regular expression for [`Filter::Regex`], compiled once up front.
+///
+/// Unanchored like `grep`, so `feat` matches `my-feature`. Use `^` and `$` to match whole names.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Pattern(Regex);
+
+impl FromStr for Pattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Regex::new(s)
+            .map(Self)
+            .map_err(|e| format!("Invalid regex {:?}: {}", s, e))
+    }
 }
 
-impl LsPrinter {
-    pub fn print_host(self, output: &mut dyn Write, host: &Host) -> Result<()> {
-        match self {
-            Self::Grouped => writeln!(output, "{}", host.0).context("printing grouped host"),
-            Self::Ref | Self::Commit => Ok(()),
-        }
+impl TryFrom<String> for Pattern {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
     }
+}
 
-    pub fn print_ref(self, output: &mut dyn Write, ref_: &GitRef) -> Result<()> {
-        match self {
-            Self::Grouped => writeln!(output, "  {} -> {}", ref_.name, ref_.commit_id)
-                .context("printing ref and commit"),
-            Self::Ref => writeln!(output, "{}", ref_.name).context("printing ref"),
-            Self::Commit => writeln!(output, "{}", ref_.commit_id).context("printing commit"),
-        }
+impl From<Pattern> for String {
+    fn from(pattern: Pattern) -> Self {
+        pattern.0.as_str().to_string()
     }
 }
 
-/// Synchronize current local branches with nomad managed refs in the given remote.
-fn sync(
-    renderer: &mut impl Renderer,
-    git: &GitBinary,
-    user: &User,
-    host: &Host,
-    remote: &Remote,
-) -> Result<()> {
-    git.push_nomad_refs(renderer, user, host, remote)?;
-    git.fetch_nomad_refs(renderer, user, remote)?;
-    let remote_nomad_refs = git.list_nomad_refs(renderer, user, remote)?.collect();
-    let snapshot = git.snapshot(renderer, user)?;
-    git.prune_nomad_refs(
-        renderer,
-        remote,
-        snapshot
-            .prune_deleted_branches(host, &remote_nomad_refs)
-            .into_iter(),
-    )?;
+/// Patterns are equal if they were written the same, which is all that workflow comparisons need.
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
 
-    if git.is_output_allowed() {
-        add_newline_if_spinners_are_visible(renderer)?;
+impl Eq for Pattern {}
 
-        ls(
-            renderer,
-            git,
-            LsPrinter::Grouped,
-            user,
-            None,
-            Filter::All,
-            Filter::All,
-        )?
+/// A shell style glob for [`Filter::Glob`], like `release/*` or `*-wip`.
+///
+/// Unlike [`Pattern`], globs match whole names. `*` matches `/` too, so `feature/*` also matches
+/// `feature/login/v2`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Glob(glob::Pattern);
+
+impl Glob {
+    /// A glob that only matches `name` itself, even if it contains characters like `*` or `[`.
+    pub fn literal(name: &str) -> Self {
+        Self(glob::Pattern::new(&glob::Pattern::escape(name)).expect("escaped globs are valid"))
     }
+}
 
-    Ok(())
+impl FromStr for Glob {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        glob::Pattern::new(s)
+            .map(Self)
+            .map_err(|e| format!("Invalid glob {:?}: {}", s, e))
+    }
 }
 
-/// List all nomad managed refs organized by host.
-///
-/// Does not respect [`GitBinary::is_output_allowed`] because output is the whole point of this
-/// command.
-fn ls(
-    renderer: &mut impl Renderer,
-    git: &GitBinary,
-    printer: LsPrinter,
-    user: &User,
-    fetch_remote: Option<Remote>,
-    host_filter: Filter<Host>,
-    branch_filter: Filter<Branch>,
-) -> Result<()> {
-    if let Some(remote) = fetch_remote {
-        git.fetch_nomad_refs(renderer, user, &remote)?;
+impl TryFrom<String> for Glob {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
     }
+}
 
-    let snapshot = git.snapshot(renderer, user)?;
+impl From<Glob> for String {
+    fn from(glob: Glob) -> Self {
+        glob.0.as_str().to_string()
+    }
+}
 
-    for (host, branches) in snapshot.sorted_hosts_and_branches() {
-        if !host_filter.contains(&host) {
-            continue;
-        }
+/// How old a commit has to be, written like `30d` with one of the units `s`, `m`, `h`, `d`, or
+/// `w`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Age(pub Duration);
 
-        renderer.writer(|w| {
-            printer.print_host(w, &host)?;
+impl Age {
+    const UNITS: [(char, u64); 5] = [
+        ('w', 7 * 24 * 60 * 60),
+        ('d', 24 * 60 * 60),
+        ('h', 60 * 60),
+        ('m', 60),
+        ('s', 1),
+    ];
 
-            for NomadRef { ref_, branch, .. } in branches {
-                if branch_filter.contains(&branch) {
-                    printer.print_ref(w, &ref_)?;
-                }
-            }
+    /// How long ago `timestamp` (seconds since the Unix epoch) was as of `now`, rounded down to a
+    /// whole number of the largest unit that fits, like `3h` for three and a bit hours.
+    pub fn since(timestamp: u64, now: u64) -> Self {
+        let total = now.saturating_sub(timestamp);
+        let (_, seconds) = Self::UNITS
+            .iter()
+            .find(|(_, seconds)| total >= *seconds)
+            .unwrap_or(&('s', 1));
+        Self(Duration::from_secs(total / seconds * seconds))
+    }
 
-            Ok(())
-        })?;
+    /// Whether a commit made at `timestamp` (seconds since the Unix epoch) is older than this as
+    /// of `now`.
+    pub fn is_exceeded_by(self, timestamp: u64, now: Duration) -> bool {
+        now.saturating_sub(Duration::from_secs(timestamp)) > self.0
     }
+}
 
-    Ok(())
+impl FromStr for Age {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid age {:?}, expected a number and a unit like 30d", s);
+
+        let unit = s.chars().last().ok_or_else(invalid)?;
+        let seconds = Self::UNITS
+            .iter()
+            .find(|(name, _)| *name == unit)
+            .map(|(_, seconds)| *seconds)
+            .ok_or_else(invalid)?;
+        let count = s[..s.len() - unit.len_utf8()]
+            .parse::<u64>()
+            .map_err(|_| invalid())?;
+
+        count
+            .checked_mul(seconds)
+            .map(|total| Self(Duration::from_secs(total)))
+            .ok_or_else(invalid)
+    }
 }
 
-/// Delete nomad managed refs returned by `to_prune`.
-fn purge(
-    renderer: &mut impl Renderer,
-    git: &GitBinary,
-    user: &User,
-    remote: &Remote,
-    host_filter: Filter<Host>,
-) -> Result<()> {
-    git.fetch_nomad_refs(renderer, user, remote)?;
-    let snapshot = git.snapshot(renderer, user)?;
-    let prune = snapshot.prune_by_hosts(|h| host_filter.contains(h));
-    git.prune_nomad_refs(renderer, remote, prune.into_iter())?;
-    Ok(())
+impl TryFrom<String> for Age {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
 }
 
-/// Use [`clap_complete`] to emit shell syntax for tab-completions
-fn print_completions(
-    renderer: &mut impl Renderer,
-    gen: impl clap_complete::Generator,
-) -> Result<()> {
-    let mut cmd = crate::build_cli(None, None);
-    let bin_name = cmd.get_name().to_string();
-    renderer.writer(|writer| {
-        clap_complete::generate(gen, &mut cmd, bin_name, writer);
-        Ok(())
-    })
+impl fmt::Display for Age {
+    /// Uses the largest unit that represents the age exactly, so that it parses back the same.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.0.as_secs();
+        // Zero is a multiple of every unit, but reads best in the smallest one.
+        let (unit, seconds) = Self::UNITS
+            .iter()
+            .find(|(_, seconds)| total > 0 && total.is_multiple_of(*seconds))
+            .unwrap_or(&('s', 1));
+        write!(f, "{}{}", total / seconds, unit)
+    }
 }
 
-#[cfg(test)]
-mod test {
-    use crate::{
-        git_testing::GitRemote,
-        renderer::test::{MemoryRenderer, NoRenderer},
-        workflow::sync,
-    };
+impl From<Age> for String {
+    fn from(age: Age) -> Self {
+        age.to_string()
+    }
+}
 
-    use super::{Filter, LsPrinter, Workflow};
+/// Knobs to tweak how [`Workflow::Sync`] behaves.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SyncOptions {
+    /// Error out after syncing if any nomad refs were pruned.
+    pub fail_on_prune: bool,
+    pub prune_confirm: PruneConfirm,
+    pub fetch: FetchOptions,
+    /// Also record the branches of the remote itself, see
+    /// [`GitBinary::mirror_remote_branches`].
+    pub mirror_remote_branches: bool,
+    /// Report the pushes and deletions that would modify refs instead of running them.
+    ///
+    /// Fetching and listing still happen, so that the report reflects the current remote.
+    pub dry_run: bool,
+    /// Print the refspecs the sync would use and stop, without running any git commands, see
+    /// [`GitBinary::sync_refspecs`].
+    pub show_refspecs: bool,
+    pub direction: SyncDirection,
+    /// Delete pruned local refs in a single batch once the remote deletions have succeeded, see
+    /// [`GitBinary::prune_local_nomad_refs`].
+    pub fast_prune: bool,
+    /// List the remote before fetching, and only fetch the refs that changed since the last
+    /// fetch, see [`GitBinary::fetch_changed_nomad_refs`].
+    pub selective_fetch: bool,
+    pub prune: SyncPrune,
+    pub push_rate_limit: Option<PushRateLimit>,
+    /// Also sync local tags, under their own ref prefix, see [`GitBinary::for_tags`].
+    pub include_tags: bool,
+    /// Copy other hosts' nomad refs to remote-tracking refs after fetching and pruning, see
+    /// [`GitBinary::update_tracking_refs`].
+    pub tracking_refs: bool,
+    pub force: SyncForce,
+}
 
-    #[test]
-    fn ls_one_host() {
-        let remote = GitRemote::init(None);
+impl SyncOptions {
+    /// Push nomad refs with `git` according to [`Self::force`] and [`Self::dry_run`], see
+    /// [`GitBinary::push_nomad_refs`].
+    fn push_nomad_refs(
+        &self,
+        renderer: &mut impl Renderer,
+        git: &GitBinary,
+        user: &User,
+        host: &Host,
+        remote: &Remote,
+        branches: Option<&[Branch]>,
+    ) -> Result<PushCount> {
+        match self.force {
+            SyncForce::Always => {
+                git.push_nomad_refs(renderer, user, host, remote, branches, self.dry_run)
+            }
+            SyncForce::WithLease => {
+                git.push_nomad_refs_with_lease(renderer, user, host, remote, branches, self.dry_run)
+            }
+        }
+    }
+}
 
-        let clone = remote.clone("user0", "host0");
-        let commit_id = clone.current_commit();
+/// Spaces out pushes to the same remote, so that frequent syncs do not trip the abuse protection
+/// of shared servers.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PushRateLimit {
+    /// Minimum seconds between pushes to the same remote, see [`GitBinary::last_push`].
+    pub min_interval: u64,
+    #[serde(default)]
+    pub on_limit: OnRateLimit,
+}
 
-        sync(
-            &mut NoRenderer,
-            &clone.git,
-            &clone.user,
-            &clone.host,
-            &clone.remote,
-        )
-        .unwrap();
+/// What [`PushRateLimit`] does with a push that comes too soon after the last one.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OnRateLimit {
+    /// Leave the push for a later sync, but still fetch and prune.
+    #[default]
+    Skip,
+    /// Sleep until the interval has passed, then push.
+    Wait,
+    /// Fail the sync.
+    Error,
+}
 
-        for (printer, expected) in [
-            (
-                LsPrinter::Grouped,
-                format!(
-                    "{}\n  refs/nomad/{}/master -> {}\n",
-                    clone.host.0, clone.host.0, commit_id.0
-                ),
-            ),
-            (
-                LsPrinter::Ref,
-                format!("refs/nomad/{}/master\n", clone.host.0),
-            ),
-            (LsPrinter::Commit, format!("{}\n", commit_id.0)),
-        ] {
-            let mut renderer = MemoryRenderer::new();
+impl PushRateLimit {
+    /// Whether to go ahead with pushing to `remote`, which may first wait out the rest of the
+    /// interval.
+    fn allows_push(
+        self,
+        renderer: &mut impl Renderer,
+        git: &GitBinary,
+        remote: &Remote,
+        now: u64,
+    ) -> Result<bool> {
+        let Some(last_push) = git.last_push(renderer, remote)? else {
+            return Ok(true);
+        };
 
-            Workflow::Ls {
-                printer,
-                user: clone.user.clone(),
-                fetch_remote: Some(clone.remote.clone()),
-                host_filter: Filter::All,
-                branch_filter: Filter::All,
-            }
-            .execute(&mut renderer, &clone.git)
-            .unwrap();
+        let elapsed = now.saturating_sub(last_push);
+        if elapsed >= self.min_interval {
+            return Ok(true);
+        }
+        let remaining = self.min_interval - elapsed;
 
-            assert_eq!(renderer.as_str(), expected);
+        match self.on_limit {
+            OnRateLimit::Skip => {
+                if git.is_output_allowed() {
+                    renderer.writer(|w| {
+                        writeln!(
+                            w,
+                            "Skipping push to {}, last pushed {}s ago which is within the minimum interval of {}s",
+                            remote.0, elapsed, self.min_interval,
+                        )?;
+                        Ok(())
+                    })?;
+                }
+                Ok(false)
+            }
+            OnRateLimit::Wait => {
+                sleep_unless_interrupted(Duration::from_secs(remaining))?;
+                Ok(true)
+            }
+            OnRateLimit::Error => bail!(
+                "Last pushed to {} {}s ago, which is within the minimum interval of {}s, try again in {}s",
+                remote.0,
+                elapsed,
+                self.min_interval,
+                remaining,
+            ),
         }
     }
+}
 
-    /// Exercise `LsPrinter::Grouped` with a bunch of `Filter::Deny`s.
-    #[test]
-    fn ls_two_hosts() {
-        let remote = GitRemote::init(None);
+/// Whether [`Workflow::Sync`] prunes nomad refs for deleted branches after fetching.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncPrune {
+    #[default]
+    Always,
+    /// Keep every ref around, for when the remote state is in doubt and should be confirmed first.
+    Never,
+}
 
-        let host0 = remote.clone("user0", "host0");
-        let host1 = remote.clone("user0", "host1");
+/// How [`Workflow::Sync`] overwrites the nomad refs of this host at the remote.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncForce {
+    /// Force push, since only this host is supposed to write its nomad refs.
+    #[default]
+    Always,
+    /// Only overwrite refs that still point where they were last fetched, see
+    /// [`GitBinary::push_nomad_refs_with_lease`].
+    WithLease,
+}
 
-        sync(
-            &mut NoRenderer,
-            &host0.git,
-            &host0.user,
-            &host0.host,
-            &host0.remote,
-        )
-        .unwrap();
+/// Which halves of [`Workflow::Sync`] to run.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncDirection {
+    /// Push local branches, then fetch and prune.
+    #[default]
+    Both,
+    /// Only push local branches, for when the remote may become unreachable right after.
+    PushOnly,
+    /// Only fetch and prune, leaving the pushed refs for this host as they were.
+    FetchOnly,
+}
 
-        sync(
-            &mut NoRenderer,
-            &host1.git,
-            &host1.user,
-            &host1.host,
-            &host1.remote,
-        )
-        .unwrap();
+impl SyncDirection {
+    fn pushes(self) -> bool {
+        matches!(self, Self::Both | Self::PushOnly)
+    }
 
-        let mut renderer = MemoryRenderer::new();
-        Workflow::Ls {
-            printer: LsPrinter::Grouped,
-            user: host1.user,
-            fetch_remote: Some(host1.remote),
-            host_filter: Filter::Deny([host0.host].into()),
-            branch_filter: Filter::Deny([host1.git.current_branch(&mut renderer).unwrap()].into()),
+    fn fetches(self) -> bool {
+        matches!(self, Self::Both | Self::FetchOnly)
+    }
+}
+
+/// Guards against surprise mass deletions by asking the user before pruning too many refs.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PruneConfirm {
+    /// Pruning more than this many refs requires confirmation.
+    pub threshold: usize,
+    /// Skip confirmation, as if the user had answered yes.
+    pub assume_yes: bool,
+}
+
+impl Default for PruneConfirm {
+    fn default() -> Self {
+        Self {
+            threshold: DEFAULT_PRUNE_CONFIRM_THRESHOLD,
+            assume_yes: false,
         }
-        .execute(&mut renderer, &host1.git)
-        .unwrap();
+    }
+}
 
-        assert_eq!(renderer.as_str(), "host1\n");
+impl PruneConfirm {
+    /// Errors if `prune` is too large and the user does not confirm it.
+    ///
+    /// Non-interactive sessions cannot confirm, so they error unless [`Self::assume_yes`] is set.
+    pub fn check(self, renderer: &mut impl Renderer, prune: &[PruneFrom<GitRef>]) -> Result<()> {
+        if self.assume_yes || prune.len() <= self.threshold {
+            return Ok(());
+        }
+
+        if !renderer.is_interactive() {
+            bail!(
+                "Refusing to prune {} refs (more than the threshold of {}) without confirmation, \
+                 pass --yes to proceed anyway",
+                prune.len(),
+                self.threshold,
+            );
+        }
+
+        add_newline_if_spinners_are_visible(renderer)?;
+        renderer.writer(|w| {
+            for prune_from in prune {
+                writeln!(w, "  {}", prune_from.nomad_ref().ref_.name)?;
+            }
+            Ok(())
+        })?;
+
+        if renderer.confirm(&format!("Prune {} refs?", prune.len()))? {
+            Ok(())
+        } else {
+            bail!("Aborted, no refs were pruned");
+        }
+    }
+}
+
+/// Details that [`LsPrinter::print_ref`] shows after a ref, each only when known.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RefAnnotations<'a> {
+    /// How many commits the ref is `(ahead, behind)` the local branch of the same name, see
+    /// [`GitBinary::ahead_behind`].
+    pub divergence: Option<(usize, usize)>,
+    /// Who committed the ref's commit, see [`GitBinary::ref_committers`].
+    pub author: Option<&'a str>,
+    /// When the ref's commit was committed relative to now, like `3 days ago`, see
+    /// [`GitBinary::commit_dates`].
+    pub age: Option<&'a str>,
+}
+
+/// Knobs to tweak what [`Workflow::Ls`] lists and how, see [`ls`].
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LsOptions<'a> {
+    pub printer: LsPrinter,
+    pub host_filter: Filter<Host<'a>>,
+    pub branch_filter: Filter<Branch<'a>>,
+    /// Which users to list branches for, see [`ls`].
+    pub user_filter: Filter<User<'a>>,
+    /// Only list branches that do not exist locally, see
+    /// [`crate::snapshot::Snapshot::missing_locally`].
+    pub diff_self: bool,
+    /// Only list refs with commits beyond this commit, see [`GitBinary::is_ancestor`].
+    pub since: Option<String>,
+    /// Shorten commit IDs to this many hex digits.
+    pub abbrev: Option<usize>,
+    /// Replaces the host header of grouped output, see [`LsPrinter::validate_host_format`].
+    pub format_host: Option<String>,
+    /// Show who committed each ref's commit, see [`GitBinary::ref_committers`].
+    pub show_author: bool,
+    /// Show how long ago each ref's commit was committed, see [`GitBinary::commit_dates`].
+    pub show_age: bool,
+    pub sort: LsSort,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LsPrinter {
+    #[default]
+    Grouped,
+    /// Like [`Self::Grouped`], but each host and its refs are printed in a color derived from the
+    /// host name, see [`host_color`].
+    ColoredGrouped,
+    Ref,
+    Commit,
+    /// A single JSON array of objects with `host`, `branch`, `ref`, and `commit` fields.
+    ///
+    /// Printed all at once by [`ls`] rather than through [`Self::print_host`] and
+    /// [`Self::print_ref`].
+    Json,
+    /// Each host with its branches as children, drawn with ASCII connectors like `tree
+    /// --charset=ascii`.
+    ///
+    /// Printed by [`print_tree`], since connectors depend on which branch is last.
+    Tree,
+    /// Like [`Self::Tree`], but with Unicode box-drawing connectors.
+    UnicodeTree,
+    /// One line per ref, with the placeholders described by [`Self::validate_template`]
+    /// substituted.
+    Template(String),
+    /// One NUL-terminated record per ref with tab-separated `user`, `host`, `branch`, `commit`,
+    /// and `refname` fields, and no host headers. The format is stable across versions.
+    ///
+    /// Printed by [`print_porcelain`].
+    Porcelain,
+}
+
+impl LsPrinter {
+    /// `format_host` replaces the bare host name in the header, with the placeholders described
+    /// by [`Self::validate_host_format`] substituted. `count` is how many refs will be printed
+    /// under the header.
+    pub fn print_host(
+        &self,
+        output: &mut dyn Write,
+        styler: &Styler,
+        format_host: Option<&str>,
+        user: &User,
+        host: &Host,
+        count: usize,
+    ) -> Result<()> {
+        let header = match format_host {
+            Some(format_host) => {
+                let count = count.to_string();
+                expand_host_format(format_host, |placeholder| match placeholder {
+                    "host" => Some(&host.0),
+                    "user" => Some(&user.0),
+                    "count" => Some(&count),
+                    _ => None,
+                })
+                .map_err(anyhow::Error::msg)?
+            }
+            None => host.0.to_string(),
+        };
+
+        match self {
+            Self::Grouped => writeln!(output, "{}", styler.apply(&Style::new().bold(), header))
+                .context("printing grouped host"),
+            Self::ColoredGrouped => writeln!(output, "{}", styler.apply(&host_style(host), header))
+                .context("printing colored host"),
+            Self::Ref | Self::Commit | Self::Json | Self::Tree | Self::UnicodeTree => Ok(()),
+            Self::Template(_) | Self::Porcelain => Ok(()),
+        }
+    }
+
+    /// Whether [`Self::print_ref`] shows the `divergence` from the local branch, which costs a
+    /// `git rev-list` per ref to compute.
+    pub fn shows_divergence(&self) -> bool {
+        matches!(self, Self::Grouped | Self::ColoredGrouped)
+    }
+
+    /// Whether [`Self::print_ref`] or [`print_json`] show the author of each ref, which costs a
+    /// `git for-each-ref` to look up.
+    pub fn shows_author(&self) -> bool {
+        matches!(self, Self::Grouped | Self::ColoredGrouped | Self::Json)
+    }
+
+    /// Whether [`Self::print_ref`] shows the `age` of each ref, which costs a `git log` to look
+    /// up.
+    pub fn shows_age(&self) -> bool {
+        matches!(self, Self::Grouped | Self::ColoredGrouped)
+    }
+
+    /// Grouped output follows each ref with whatever of `annotations` is known.
+    pub fn print_ref(
+        &self,
+        output: &mut dyn Write,
+        styler: &Styler,
+        host: &Host,
+        branch: &Branch,
+        ref_: &GitRef,
+        annotations: &RefAnnotations,
+    ) -> Result<()> {
+        let RefAnnotations {
+            divergence,
+            author,
+            age,
+        } = *annotations;
+        let mut annotation = match divergence {
+            Some((ahead, behind)) => format!(" (ahead {}, behind {})", ahead, behind),
+            None => String::new(),
+        };
+        if let Some(author) = author {
+            annotation.push_str(&format!(" by {}", author));
+        }
+        if let Some(age) = age {
+            annotation.push_str(&format!(" committed {}", age));
+        }
+
+        match self {
+            Self::Grouped => writeln!(
+                output,
+                "  {} -> {}{}",
+                ref_.name,
+                styler.apply(&Style::new().dim(), &ref_.commit_id),
+                annotation
+            )
+            .context("printing ref and commit"),
+            Self::ColoredGrouped => writeln!(
+                output,
+                "  {}",
+                styler.apply(
+                    &host_style(host),
+                    format!("{} -> {}{}", ref_.name, ref_.commit_id, annotation)
+                )
+            )
+            .context("printing colored ref and commit"),
+            Self::Ref => writeln!(output, "{}", ref_.name).context("printing ref"),
+            Self::Commit => writeln!(output, "{}", ref_.commit_id).context("printing commit"),
+            Self::Json | Self::Tree | Self::UnicodeTree | Self::Porcelain => Ok(()),
+            Self::Template(template) => {
+                let line = expand_template(template, |placeholder| match placeholder {
+                    'h' => Some(&host.0),
+                    'b' => Some(&branch.0),
+                    'r' => Some(&ref_.name),
+                    'c' => Some(&ref_.commit_id),
+                    _ => None,
+                })
+                .map_err(anyhow::Error::msg)?;
+                writeln!(output, "{}", line).context("printing template")
+            }
+        }
+    }
+
+    /// Check that `template` only uses the placeholders `%h` (host), `%b` (branch), `%r` (full
+    /// ref name), `%c` (commit ID), and `%%` (a literal `%`), for parsing
+    /// [`Self::Template`] from the CLI.
+    pub fn validate_template(template: &str) -> std::result::Result<String, String> {
+        expand_template(template, |placeholder| {
+            matches!(placeholder, 'h' | 'b' | 'r' | 'c').then_some("")
+        })?;
+        Ok(template.to_owned())
+    }
+
+    /// Check that `format_host` only uses the placeholders `{host}`, `{user}`, `{count}` (the
+    /// number of refs listed for that host), `{{` and `}}` (literal braces), for parsing the host
+    /// header of [`Self::Grouped`] and [`Self::ColoredGrouped`] from the CLI.
+    pub fn validate_host_format(format_host: &str) -> std::result::Result<String, String> {
+        expand_host_format(format_host, |placeholder| {
+            matches!(placeholder, "host" | "user" | "count").then_some("")
+        })?;
+        Ok(format_host.to_owned())
+    }
+}
+
+/// The order of branches within each host for [`ls`], hosts themselves are always sorted by name.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LsSort {
+    #[default]
+    Name,
+    /// Most recently committed first, see [`GitBinary::commit_timestamps`]. Branches with the
+    /// same date (or a missing commit) stay in name order, after the rest.
+    CommitDate,
+}
+
+impl LsSort {
+    /// Reorder the branches of `hosts_and_branches`, which must already be sorted by name, see
+    /// [`crate::snapshot::Snapshot::sorted_hosts_and_branches`].
+    fn apply(
+        self,
+        renderer: &mut impl Renderer,
+        git: &GitBinary,
+        hosts_and_branches: &mut [(Host, Vec<NomadRef<GitRef>>)],
+    ) -> Result<()> {
+        match self {
+            Self::Name => Ok(()),
+            Self::CommitDate => {
+                let timestamps = git.commit_timestamps(
+                    renderer,
+                    hosts_and_branches
+                        .iter()
+                        .flat_map(|(_, branches)| branches)
+                        .map(|nomad_ref| &nomad_ref.ref_.commit_id),
+                )?;
+
+                for (_, branches) in hosts_and_branches {
+                    branches.sort_by_key(|nomad_ref| {
+                        Reverse(timestamps.get(&nomad_ref.ref_.commit_id))
+                    });
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Replace each `%x` in `template` with `value('x')`, erroring on placeholders that `value` does
+/// not know about. `%%` is always a literal `%`.
+fn expand_template<'a>(
+    template: &str,
+    value: impl Fn(char) -> Option<&'a str>,
+) -> std::result::Result<String, String> {
+    let mut expanded = String::with_capacity(template.len());
+    let mut chars = template.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            expanded.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('%') => expanded.push('%'),
+            Some(placeholder) => match value(placeholder) {
+                Some(value) => expanded.push_str(value),
+                None => {
+                    return Err(format!(
+                        "unknown placeholder %{} in {:?}, expected one of %h, %b, %r, %c, or %%",
+                        placeholder, template
+                    ))
+                }
+            },
+            None => return Err(format!("{:?} ends with a lone %", template)),
+        }
+    }
+
+    Ok(expanded)
+}
+
+/// Replace each `{name}` in `format_host` with `value("name")`, erroring on placeholders that
+/// `value` does not know about. `{{` and `}}` are always literal braces, like [`format!`].
+fn expand_host_format<'a>(
+    format_host: &str,
+    value: impl Fn(&str) -> Option<&'a str>,
+) -> std::result::Result<String, String> {
+    let mut expanded = String::with_capacity(format_host.len());
+    let mut rest = format_host;
+
+    while let Some(index) = rest.find(['{', '}']) {
+        expanded.push_str(&rest[..index]);
+        let brace = &rest[index..];
+
+        if let Some(after) = brace.strip_prefix("{{") {
+            expanded.push('{');
+            rest = after;
+        } else if let Some(after) = brace.strip_prefix("}}") {
+            expanded.push('}');
+            rest = after;
+        } else if let Some((placeholder, after)) = brace
+            .strip_prefix('{')
+            .and_then(|brace| brace.split_once('}'))
+        {
+            match value(placeholder) {
+                Some(value) => expanded.push_str(value),
+                None => {
+                    return Err(format!(
+                        "unknown placeholder {{{}}} in {:?}, expected one of {{host}}, {{user}}, {{count}}, {{{{, or }}}}",
+                        placeholder, format_host
+                    ))
+                }
+            }
+            rest = after;
+        } else {
+            return Err(format!(
+                "{:?} has an unmatched brace, use {{{{ or }}}} for literal braces",
+                format_host
+            ));
+        }
+    }
+
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Colors that are distinguishable on both light and dark terminals.
+const HOST_PALETTE: [Color; 6] = [
+    Color::Red,
+    Color::Green,
+    Color::Yellow,
+    Color::Blue,
+    Color::Magenta,
+    Color::Cyan,
+];
+
+/// Pick a color for `host` that stays the same across runs (and across builds, which is why this
+/// uses FNV-1a instead of [`std::hash::DefaultHasher`]).
+fn host_color(host: &Host) -> Color {
+    let hash = host.0.bytes().fold(0xcbf29ce484222325_u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    });
+    HOST_PALETTE[(hash % HOST_PALETTE.len() as u64) as usize]
+}
+
+/// Only takes effect through a [`Styler`] that wants colors, so output degrades to plain text
+/// when piped, with `NO_COLOR`, or with `--color never`.
+fn host_style(host: &Host) -> Style {
+    Style::new().fg(host_color(host))
+}
+
+/// Synchronize current local branches with nomad managed refs in the given remotes.
+///
+/// Every remote is pushed to and fetched from in order. A ref from another host is only pruned
+/// locally once it is missing from *all* of the remotes, so that a remote lagging behind (like an
+/// occasionally synced backup) cannot cause deletions on its own.
+///
+/// With [`SyncOptions::fail_on_prune`], the sync is carried out in full but an error listing the
+/// pruned refs is returned at the end, so that automated environments can flag deletions for
+/// human review.
+fn sync(
+    renderer: &mut impl Renderer,
+    git: &GitBinary,
+    user: &User,
+    host: &Host,
+    remotes: &[Remote],
+    options: SyncOptions,
+    branch_filter: &Filter<Branch>,
+) -> Result<Outcome> {
+    // Excluded branches are left out of the push entirely, rather than pruned, so that refs pushed
+    // before the filter was in place stay around until the local branch is actually deleted.
+    let branches = match branch_filter {
+        Filter::All => None,
+        _ => {
+            let mut branches = git
+                .snapshot(renderer, user)?
+                .local_branches
+                .into_iter()
+                .filter(|branch| branch_filter.contains(branch))
+                .collect::<Vec<_>>();
+            branches.sort();
+            Some(branches)
+        }
+    };
+
+    // Tags are synced in full, the branch filter and remote mirroring only apply to branches.
+    let tags = options.include_tags.then(|| git.for_tags());
+
+    if options.show_refspecs {
+        renderer.writer(|w| {
+            for remote in remotes {
+                let mut refspecs = git.sync_refspecs(
+                    user,
+                    host,
+                    remote,
+                    branches.as_deref(),
+                    options.mirror_remote_branches,
+                );
+                if let Some(tags) = &tags {
+                    refspecs.extend(tags.sync_refspecs(user, host, remote, None, false));
+                }
+                for (kind, refspec) in refspecs {
+                    writeln!(w, "{}\t{}", kind, refspec)?;
+                }
+            }
+            Ok(())
+        })?;
+        return Ok(Outcome::Unchanged);
+    }
+
+    let mut pushed = 0;
+    let mut pushed_refspecs = 0;
+    let mut updated = 0;
+    if options.direction.pushes() {
+        for (index, remote) in remotes.iter().enumerate() {
+            // Push times are only remembered while rate limiting, plain syncs just remember when
+            // they last succeeded.
+            let push_time = match options.push_rate_limit {
+                Some(limit) => {
+                    let now = unix_now()?;
+                    if !limit.allows_push(renderer, git, remote, now)? {
+                        continue;
+                    }
+                    Some(unix_now()?)
+                }
+                None => None,
+            };
+
+            renderer.event(ProgressEvent::PushStarted {
+                remote: remote.0.to_string(),
+                current: index + 1,
+                total: remotes.len(),
+            });
+            if options.mirror_remote_branches {
+                git.mirror_remote_branches(renderer, user, remote, options.dry_run)?;
+            }
+
+            let push_count =
+                options.push_nomad_refs(renderer, git, user, host, remote, branches.as_deref())?;
+            pushed_refspecs += push_count.refspecs;
+            updated += push_count.updated;
+            pushed += 1;
+
+            if let Some(tags) = &tags {
+                let push_count =
+                    options.push_nomad_refs(renderer, tags, user, host, remote, None)?;
+                pushed_refspecs += push_count.refspecs;
+                updated += push_count.updated;
+            }
+
+            if let Some(push_time) = push_time.filter(|_| !options.dry_run) {
+                git.record_push(renderer, remote, push_time)?;
+            }
+        }
+    }
+
+    // Only needed to report new branches, which is skipped entirely when output is suppressed.
+    let before_fetch = if options.direction.fetches() && git.is_output_allowed() {
+        Some(git.snapshot(renderer, user)?)
+    } else {
+        None
+    };
+
+    let mut pruned = Vec::new();
+    if options.direction.fetches() {
+        pruned.extend(fetch_and_prune(
+            renderer, git, user, host, remotes, &options,
+        )?);
+        if let Some(tags) = &tags {
+            pruned.extend(fetch_and_prune(
+                renderer, tags, user, host, remotes, &options,
+            )?);
+        }
+
+        // Like pruning, the copies only change for real runs.
+        if options.tracking_refs && !options.dry_run {
+            git.update_tracking_refs(renderer, user, host)?;
+        }
+    }
+    let pruned_count = report_pruned(renderer, &pruned, options.dry_run);
+    renderer.event(ProgressEvent::SyncComplete {
+        pushed,
+        pruned: pruned_count,
+    });
+
+    if git.is_output_allowed() {
+        add_newline_if_spinners_are_visible(renderer)?;
+
+        ls(renderer, git, user, &[], None, &LsOptions::default())?;
+
+        if let Some(tags) = &tags {
+            ls(
+                renderer,
+                tags,
+                user,
+                &[],
+                None,
+                &LsOptions {
+                    format_host: Some("{host} (tags)".to_string()),
+                    ..LsOptions::default()
+                },
+            )?;
+        }
+
+        let after_fetch = git.snapshot(renderer, user)?;
+        if let Some(before_fetch) = &before_fetch {
+            write_new_branches(renderer, host, &before_fetch.diff(&after_fetch))?;
+        }
+
+        let hosts = after_fetch.sorted_hosts_and_branches().len();
+        let summary = sync_summary(pushed_refspecs, pruned.len(), hosts, options.dry_run);
+        let previous_sync = git.last_sync(renderer)?;
+        let now = unix_now()?;
+        renderer.writer(|w| {
+            if let Some(previous_sync) = previous_sync {
+                writeln!(
+                    w,
+                    "Previously synced {} ago",
+                    Age::since(previous_sync, now)
+                )?;
+            }
+            writeln!(w, "{}", summary).context("printing sync summary")
+        })?;
+    }
+
+    if options.fail_on_prune && !pruned.is_empty() {
+        let names = pruned
+            .iter()
+            .map(|r| format!("  {} (was {})", r.name, r.commit_id))
+            .collect::<Vec<_>>();
+        bail!("Sync pruned {} ref(s):\n{}", pruned.len(), names.join("\n"));
+    }
+
+    // Only now has the sync succeeded, and dry runs never really synced.
+    if !options.dry_run {
+        git.record_sync(renderer, unix_now()?)?;
+    }
+
+    Ok(Outcome::from_changed(updated > 0 || pruned_count > 0))
+}
+
+/// The fetch and prune half of [`sync`] for the refs `git` tracks, returning the pruned refs.
+fn fetch_and_prune(
+    renderer: &mut impl Renderer,
+    git: &GitBinary,
+    user: &User,
+    host: &Host,
+    remotes: &[Remote],
+    options: &SyncOptions,
+) -> Result<Vec<GitRef>> {
+    let mut remote_nomad_refs = Vec::<RemoteNomadRefSet>::new();
+    for (index, remote) in remotes.iter().enumerate() {
+        renderer.event(ProgressEvent::FetchStarted {
+            remote: remote.0.to_string(),
+            current: index + 1,
+            total: remotes.len(),
+        });
+        let listed = if options.selective_fetch {
+            let listed = git
+                .list_nomad_refs(renderer, user, remote)?
+                .collect::<Result<Vec<_>>>()?;
+            git.fetch_changed_nomad_refs(renderer, user, remote, &listed, options.fetch)?;
+            listed
+        } else {
+            git.fetch_nomad_refs(renderer, user, remote, options.fetch)?;
+            git.list_nomad_refs(renderer, user, remote)?
+                .collect::<Result<Vec<_>>>()?
+        };
+        remote_nomad_refs.push(listed.into_iter().collect());
+    }
+
+    if options.prune == SyncPrune::Never {
+        return Ok(Vec::new());
+    }
+
+    let snapshot = git.snapshot(renderer, user)?;
+    let prune =
+        snapshot.prune_deleted_branches(host, &RemoteNomadRefSet::union(remote_nomad_refs.iter()));
+    if !options.dry_run {
+        options.prune_confirm.check(renderer, &prune)?;
+    }
+
+    // Deleting a ref that a remote never had is an error, so only ask each remote to delete
+    // what it actually knows about.
+    for (remote, known) in remotes.iter().zip(&remote_nomad_refs) {
+        let on_remote = prune.iter().filter_map(|prune_from| match prune_from {
+            PruneFrom::LocalAndRemote(nomad_ref) if known.contains(nomad_ref) => Some(nomad_ref),
+            _ => None,
+        });
+        git.prune_remote_nomad_refs(renderer, remote, on_remote, options.dry_run)?;
+    }
+    git.prune_local_nomad_refs(
+        renderer,
+        prune.into_iter(),
+        options.fast_prune,
+        options.dry_run,
+    )
+}
+
+/// The line printed after `sync`, like `Pushed 3, pruned 1, tracking 5 hosts`.
+fn sync_summary(pushed: usize, pruned: usize, hosts: usize, dry_run: bool) -> String {
+    let (push, prune) = if dry_run {
+        ("Would push", "would prune")
+    } else {
+        ("Pushed", "pruned")
+    };
+    let hosts_noun = if hosts == 1 { "host" } else { "hosts" };
+    format!("{push} {pushed}, {prune} {pruned}, tracking {hosts} {hosts_noun}")
+}
+
+/// Print the nomad refs from other hosts that only appeared during this sync, as
+/// `host/branch -> commit`.
+///
+/// Refs for the current `host` are left out since they were just pushed from here.
+fn write_new_branches(
+    renderer: &mut impl Renderer,
+    host: &Host,
+    diff: &SnapshotDiff<GitRef>,
+) -> Result<()> {
+    // The common case of a sync where nothing changed.
+    if diff.is_empty() {
+        return Ok(());
+    }
+
+    let new_branches = diff
+        .added
+        .iter()
+        .filter(|nomad_ref| nomad_ref.host != *host)
+        .collect::<Vec<_>>();
+    if new_branches.is_empty() {
+        return Ok(());
+    }
+
+    renderer.writer(|w| {
+        writeln!(w, "New branches:")?;
+        for nomad_ref in new_branches {
+            writeln!(
+                w,
+                "  {}/{} -> {}",
+                nomad_ref.host.0, nomad_ref.branch.0, nomad_ref.ref_.commit_id
+            )?;
+        }
+        Ok(())
+    })
+}
+
+/// Send a [`ProgressEvent::RefPruned`] for each of `pruned`, returning how many were sent.
+fn report_pruned(renderer: &mut impl Renderer, pruned: &[GitRef], dry_run: bool) -> usize {
+    if dry_run {
+        return 0;
+    }
+
+    for (index, git_ref) in pruned.iter().enumerate() {
+        renderer.event(ProgressEvent::RefPruned {
+            name: git_ref.name.clone(),
+            current: index + 1,
+            total: pruned.len(),
+        });
+    }
+    pruned.len()
+}
+
+/// Seconds since the epoch, which is how [`GitBinary::record_push`] and
+/// [`GitBinary::record_sync`] remember times.
+fn unix_now() -> Result<u64> {
+    Ok(SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .context("reading the current time")?
+        .as_secs())
+}
+
+/// Run `iteration` every `interval` until the user interrupts `git-nomad`, which is not treated as
+/// an error.
+///
+/// `wait` is called between iterations, [`sleep_unless_interrupted`] outside of tests.
+fn watch<R: Renderer>(
+    renderer: &mut R,
+    interval: Duration,
+    mut wait: impl FnMut(Duration) -> Result<()>,
+    mut iteration: impl FnMut(&mut R) -> Result<()>,
+) -> Result<()> {
+    loop {
+        let result = iteration(renderer).and_then(|()| wait(interval));
+        if is_interrupted() {
+            return Ok(());
+        }
+        result?;
+    }
+}
+
+/// Fetch nomad refs like `ls --fetch` does, except that failures only print a warning.
+///
+/// Meant for [`watch`], where a flaky network should not stop the refs that were already fetched
+/// from being listed, and the next iteration will try again anyway.
+fn fetch_or_warn(
+    renderer: &mut impl Renderer,
+    git: &GitBinary,
+    user: &User,
+    remote: &Remote,
+    fetch_options: FetchOptions,
+) -> Result<()> {
+    match git.fetch_nomad_refs(renderer, user, remote, fetch_options) {
+        Ok(()) => Ok(()),
+        Err(_) if is_interrupted() => bail!("Interrupted"),
+        Err(error) => renderer.writer(|w| {
+            writeln!(
+                w,
+                "Warning: listing refs from the last successful fetch, fetching from {} failed: {:#}",
+                remote.0, error,
+            )?;
+            Ok(())
+        }),
+    }
+}
+
+/// Fetch `user`'s nomad refs from several `remotes` that mirror each other, warning about the
+/// refs that they disagree on, see [`disagreements`].
+///
+/// All remotes are listed at once, see [`GitBinary::list_nomad_refs_many`], then only the refs
+/// that differ from the local clone are fetched, see [`GitBinary::fetch_changed_nomad_refs`].
+/// Remotes are fetched in reverse so that the first remote that has a ref wins any disagreement.
+fn fetch_mirrors(
+    renderer: &mut impl Renderer,
+    git: &GitBinary,
+    user: &User,
+    remotes: &[Remote],
+    fetch_options: FetchOptions,
+) -> Result<()> {
+    let listings = remotes
+        .iter()
+        .zip(git.list_nomad_refs_many(renderer, user, remotes)?)
+        .collect::<Vec<_>>();
+
+    for (remote, listed) in listings.iter().rev() {
+        git.fetch_changed_nomad_refs(renderer, user, remote, listed, fetch_options)?;
+    }
+
+    renderer.writer(|w| {
+        for nomad_ref in disagreements(&listings) {
+            let commits = nomad_ref
+                .ref_
+                .iter()
+                .map(|(remote, ref_)| format!("{} has {}", remote.0, ref_.commit_id))
+                .collect::<Vec<_>>();
+            let (first_remote, first_ref) = nomad_ref.ref_[0];
+            writeln!(
+                w,
+                "Warning: remotes disagree on {}, {}, listing the commit from {}",
+                first_ref.name,
+                commits.join(", "),
+                first_remote.0,
+            )?;
+        }
+        Ok(())
+    })
+}
+
+/// List all nomad managed refs organized by host.
+///
+/// `user`'s refs are fetched from `remotes` (only when `fetch` is given) and listed from the local
+/// clone, with a warning for any refs that several remotes disagree on, see [`fetch_mirrors`].
+/// Refs of any other users that `user_filter` allows are never fetched, so they are listed
+/// straight from the first remote instead, and left out without one. Hosts are then shown as
+/// `{user}/{host}` to tell them apart.
+///
+/// Does not respect [`GitBinary::is_output_allowed`] because output is the whole point of this
+/// command.
+fn ls(
+    renderer: &mut impl Renderer,
+    git: &GitBinary,
+    user: &User,
+    remotes: &[Remote],
+    fetch: Option<FetchOptions>,
+    options: &LsOptions,
+) -> Result<()> {
+    let LsOptions {
+        printer,
+        host_filter,
+        branch_filter,
+        user_filter,
+        diff_self,
+        since,
+        abbrev,
+        format_host,
+        show_author,
+        show_age,
+        sort,
+    } = options;
+
+    if let Some(fetch_options) = fetch {
+        match remotes {
+            [] => {}
+            [remote] => git.fetch_nomad_refs(renderer, user, remote, fetch_options)?,
+            _ => fetch_mirrors(renderer, git, user, remotes, fetch_options)?,
+        }
+    }
+
+    let mut snapshot = git.snapshot(renderer, user)?;
+    if !user_filter.contains(user) {
+        snapshot.nomad_refs.clear();
+    }
+
+    if let Some(remote) = remotes.first() {
+        let other_users = match user_filter {
+            Filter::Allow(users) => Some(
+                users
+                    .iter()
+                    .filter(|other| *other != user)
+                    .cloned()
+                    .collect::<Vec<_>>(),
+            ),
+            Filter::All | Filter::Deny(_) | Filter::Regex(_) | Filter::Glob(_) => None,
+        };
+
+        // Nothing is listed when there are no other users to list.
+        let other_refs = git.list_users_nomad_refs(renderer, remote, other_users.as_deref())?;
+        snapshot.nomad_refs.extend(
+            other_refs.into_iter().filter(|nomad_ref| {
+                nomad_ref.user != *user && user_filter.contains(&nomad_ref.user)
+            }),
+        );
+    }
+
+    if *diff_self {
+        snapshot = snapshot.missing_locally();
+    }
+
+    if let Some(since) = since {
+        let mut ahead = Vec::with_capacity(snapshot.nomad_refs.len());
+        for nomad_ref in std::mem::take(&mut snapshot.nomad_refs) {
+            if !git.is_ancestor(renderer, &nomad_ref.ref_.commit_id, since)? {
+                ahead.push(nomad_ref);
+            }
+        }
+        snapshot.nomad_refs = ahead;
+    }
+
+    let show_user = snapshot
+        .nomad_refs
+        .iter()
+        .any(|nomad_ref| nomad_ref.user != *user);
+    let format_host = format_host
+        .as_deref()
+        .or(show_user.then_some("{user}/{host}"));
+
+    let local_branches = std::mem::take(&mut snapshot.local_branches);
+    let mut hosts_and_branches = snapshot.sorted_hosts_and_branches();
+    sort.apply(renderer, git, &mut hosts_and_branches)?;
+
+    // Refs only listed at the remote have no local commit to read, and failures (like missing
+    // objects) just mean no authors, since this is purely informational.
+    let authors = if *show_author && printer.shows_author() {
+        let ref_names = hosts_and_branches
+            .iter()
+            .flat_map(|(_, branches)| branches)
+            .map(|nomad_ref| &nomad_ref.ref_.name);
+        Some(git.ref_committers(renderer, ref_names).unwrap_or_default())
+    } else {
+        None
+    };
+
+    // Looked up by full commit ID, so this has to happen before `abbrev`. Commits missing
+    // locally just have no age, since this is purely informational.
+    let ages = if *show_age && printer.shows_age() {
+        let dates = git
+            .commit_dates(
+                renderer,
+                hosts_and_branches
+                    .iter()
+                    .flat_map(|(_, branches)| branches)
+                    .map(|nomad_ref| &nomad_ref.ref_.commit_id),
+            )
+            .unwrap_or_default();
+        Some(
+            hosts_and_branches
+                .iter()
+                .flat_map(|(_, branches)| branches)
+                .filter_map(|nomad_ref| {
+                    let date = dates.get(&nomad_ref.ref_.commit_id)?;
+                    Some((nomad_ref.ref_.name.clone(), date.clone()))
+                })
+                .collect::<HashMap<_, _>>(),
+        )
+    } else {
+        None
+    };
+
+    if let Some(length) = *abbrev {
+        for (_, branches) in &mut hosts_and_branches {
+            for nomad_ref in branches {
+                // Commit IDs are hex, so any length is a valid char boundary.
+                nomad_ref.ref_.commit_id.truncate(length);
+            }
+        }
+    }
+
+    match printer {
+        LsPrinter::Json => {
+            return print_json(
+                renderer,
+                hosts_and_branches,
+                host_filter,
+                branch_filter,
+                show_user,
+                authors.as_ref(),
+            )
+        }
+        LsPrinter::Tree => {
+            return print_tree(
+                renderer,
+                hosts_and_branches,
+                host_filter,
+                branch_filter,
+                show_user,
+                ASCII_CONNECTORS,
+            )
+        }
+        LsPrinter::UnicodeTree => {
+            return print_tree(
+                renderer,
+                hosts_and_branches,
+                host_filter,
+                branch_filter,
+                show_user,
+                UNICODE_CONNECTORS,
+            )
+        }
+        LsPrinter::Porcelain => {
+            return print_porcelain(renderer, hosts_and_branches, host_filter, branch_filter)
+        }
+        LsPrinter::Grouped
+        | LsPrinter::ColoredGrouped
+        | LsPrinter::Ref
+        | LsPrinter::Commit
+        | LsPrinter::Template(_) => {}
+    }
+
+    for (host, branches) in hosts_and_branches {
+        if !host_filter.contains(&host) {
+            continue;
+        }
+
+        // Every group has at least one ref, all of the same user.
+        let host_user = branches[0].user.clone();
+        let mut refs = Vec::with_capacity(branches.len());
+        for NomadRef { ref_, branch, .. } in branches {
+            if !branch_filter.contains(&branch) {
+                continue;
+            }
+
+            // Comparing by name rather than commit ID sidesteps `abbrev`. Failures (like missing
+            // objects) just mean no annotation, since this is purely informational.
+            let divergence = if printer.shows_divergence() && local_branches.contains(&branch) {
+                let local = format!("refs/{}/{}", git.tracked.hierarchy(), branch.0);
+                git.ahead_behind(renderer, &local, &ref_.name).ok()
+            } else {
+                None
+            };
+            let author = authors
+                .as_ref()
+                .and_then(|authors| authors.get(&ref_.name))
+                .cloned();
+            let age = ages.as_ref().and_then(|ages| ages.get(&ref_.name)).cloned();
+            refs.push((branch, ref_, divergence, author, age));
+        }
+
+        let styler = renderer.styler();
+        renderer.writer(|w| {
+            printer.print_host(w, &styler, format_host, &host_user, &host, refs.len())?;
+
+            for (branch, ref_, divergence, author, age) in &refs {
+                printer.print_ref(
+                    w,
+                    &styler,
+                    &host,
+                    branch,
+                    ref_,
+                    &RefAnnotations {
+                        divergence: *divergence,
+                        author: author.as_deref(),
+                        age: age.as_deref(),
+                    },
+                )?;
+            }
+
+            Ok(())
+        })?;
+    }
+
+    Ok(())
+}
+
+/// The connectors for a child that has more siblings after it, and for the last child.
+type TreeConnectors = (&'static str, &'static str);
+
+const ASCII_CONNECTORS: TreeConnectors = ("|-- ", "`-- ");
+const UNICODE_CONNECTORS: TreeConnectors = ("├── ", "└── ");
+
+/// Print the filtered nomad refs as a tree for [`LsPrinter::Tree`] and
+/// [`LsPrinter::UnicodeTree`], like:
+///
+/// ```text
+/// apollo
+/// |-- feature 1a101799507ba67d822b97105aafa0ac91ce5183
+/// `-- master 267719fb8448cc1cbef2c35a638610573779f2ac
+/// ```
+///
+/// Commit IDs are dimmed when colors are enabled, so that branch names stand out.
+fn print_tree(
+    renderer: &mut impl Renderer,
+    hosts_and_branches: Vec<(Host, Vec<NomadRef<GitRef>>)>,
+    host_filter: &Filter<Host>,
+    branch_filter: &Filter<Branch>,
+    show_user: bool,
+    (middle, last): TreeConnectors,
+) -> Result<()> {
+    let commit_style = Style::new().dim();
+    let styler = renderer.styler();
+
+    for (host, branches) in hosts_and_branches {
+        if !host_filter.contains(&host) {
+            continue;
+        }
+
+        let header = if show_user {
+            format!("{}/{}", branches[0].user.0, host.0)
+        } else {
+            host.0.to_string()
+        };
+        let branches = branches
+            .into_iter()
+            .filter(|nomad_ref| branch_filter.contains(&nomad_ref.branch))
+            .collect::<Vec<_>>();
+
+        renderer.writer(|w| {
+            writeln!(w, "{}", header)?;
+
+            for (index, NomadRef { branch, ref_, .. }) in branches.iter().enumerate() {
+                let connector = if index + 1 == branches.len() {
+                    last
+                } else {
+                    middle
+                };
+                writeln!(
+                    w,
+                    "{}{} {}",
+                    connector,
+                    branch.0,
+                    styler.apply(&commit_style, &ref_.commit_id)
+                )?;
+            }
+
+            Ok(())
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Print the filtered nomad refs as a single JSON array for [`LsPrinter::Json`].
+///
+/// Unlike the other printers this cannot stream host by host, since the output has to be one
+/// valid JSON document, even when it is just `[]`.
+///
+/// When `authors` is given, every entry gets an `author` field, which is `null` for refs without
+/// a known author.
+fn print_json(
+    renderer: &mut impl Renderer,
+    hosts_and_branches: Vec<(Host, Vec<NomadRef<GitRef>>)>,
+    host_filter: &Filter<Host>,
+    branch_filter: &Filter<Branch>,
+    show_user: bool,
+    authors: Option<&HashMap<String, String>>,
+) -> Result<()> {
+    #[derive(Serialize)]
+    struct Entry<'a> {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        user: Option<&'a str>,
+        host: &'a str,
+        branch: &'a str,
+        #[serde(rename = "ref")]
+        ref_: &'a str,
+        commit: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        author: Option<Option<&'a str>>,
+    }
+
+    let entries = hosts_and_branches
+        .iter()
+        .filter(|(host, _)| host_filter.contains(host))
+        .flat_map(|(_, branches)| branches)
+        .filter(|nomad_ref| branch_filter.contains(&nomad_ref.branch))
+        .map(|nomad_ref| Entry {
+            user: show_user.then_some(&*nomad_ref.user.0),
+            host: &nomad_ref.host.0,
+            branch: &nomad_ref.branch.0,
+            ref_: &nomad_ref.ref_.name,
+            commit: &nomad_ref.ref_.commit_id,
+            author: authors.map(|authors| authors.get(&nomad_ref.ref_.name).map(String::as_str)),
+        })
+        .collect::<Vec<_>>();
+
+    renderer.writer(|w| {
+        serde_json::to_writer(&mut *w, &entries).context("printing json")?;
+        writeln!(w)?;
+        Ok(())
+    })
+}
+
+/// Print the filtered nomad refs for [`LsPrinter::Porcelain`], like
+/// `rraval\tapollo\tmaster\t267719fb8448cc1cbef2c35a638610573779f2ac\trefs/nomad/apollo/master\0`.
+fn print_porcelain(
+    renderer: &mut impl Renderer,
+    hosts_and_branches: Vec<(Host, Vec<NomadRef<GitRef>>)>,
+    host_filter: &Filter<Host>,
+    branch_filter: &Filter<Branch>,
+) -> Result<()> {
+    renderer.writer(|w| {
+        for nomad_ref in hosts_and_branches
+            .iter()
+            .filter(|(host, _)| host_filter.contains(host))
+            .flat_map(|(_, branches)| branches)
+            .filter(|nomad_ref| branch_filter.contains(&nomad_ref.branch))
+        {
+            write!(
+                w,
+                "{}\t{}\t{}\t{}\t{}\0",
+                nomad_ref.user.0,
+                nomad_ref.host.0,
+                nomad_ref.branch.0,
+                nomad_ref.ref_.commit_id,
+                nomad_ref.ref_.name,
+            )
+            .context("printing porcelain")?;
+        }
+        Ok(())
+    })
+}
+
+/// Delete nomad managed refs for hosts and branches matching both filters.
+///
+/// With `local_only`, nothing is fetched and only the local copies are deleted, so the remote is
+/// never contacted.
+///
+/// Naming `refs` explicitly bypasses both filters and deletes exactly those refs, see
+/// [`prune_by_names`].
+#[allow(clippy::too_many_arguments)]
+fn purge(
+    renderer: &mut impl Renderer,
+    git: &GitBinary,
+    user: &User,
+    remote: &Remote,
+    host_filter: Filter<Host>,
+    branch_filter: Filter<Branch>,
+    prune_confirm: PruneConfirm,
+    older_than: Option<Age>,
+    dry_run: bool,
+    fast_prune: bool,
+    local_only: bool,
+    refs: &[String],
+) -> Result<Outcome> {
+    if !local_only {
+        renderer.event(ProgressEvent::FetchStarted {
+            remote: remote.0.to_string(),
+            current: 1,
+            total: 1,
+        });
+        git.fetch_nomad_refs(renderer, user, remote, FetchOptions::default())?;
+    }
+    let snapshot = git.snapshot(renderer, user)?;
+    let mut prune = if refs.is_empty() {
+        snapshot
+            .prune_by_hosts_and_branches(|h| host_filter.contains(h), |b| branch_filter.contains(b))
+    } else {
+        prune_by_names(git, user, snapshot, refs)?
+    };
+    if local_only {
+        prune = prune.into_iter().map(PruneFrom::local_only).collect();
+    }
+
+    if let Some(age) = older_than {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .context("reading the current time")?;
+
+        let timestamps = git.commit_timestamps(
+            renderer,
+            prune
+                .iter()
+                .map(|prune_from| &prune_from.nomad_ref().ref_.commit_id),
+        )?;
+        // Commits that are not present locally have no known age, so they are left alone.
+        prune.retain(|prune_from| {
+            timestamps
+                .get(&prune_from.nomad_ref().ref_.commit_id)
+                .is_some_and(|&timestamp| age.is_exceeded_by(timestamp, now))
+        });
+    }
+
+    if !dry_run {
+        prune_confirm.check(renderer, &prune)?;
+    }
+    let pruned = git.prune_nomad_refs(renderer, remote, prune.into_iter(), fast_prune, dry_run)?;
+    let pruned = report_pruned(renderer, &pruned, dry_run);
+    renderer.event(ProgressEvent::PurgeComplete { pruned });
+    Ok(Outcome::from_changed(pruned > 0))
+}
+
+/// Remove every nomad ref in `snapshot` named in `names` locally and from the remote, erroring if
+/// a name is not a nomad ref of `user` or does not exist.
+fn prune_by_names<'a>(
+    git: &GitBinary,
+    user: &User,
+    snapshot: Snapshot<'a, GitRef>,
+    names: &[String],
+) -> Result<Vec<PruneFrom<'a, GitRef>>> {
+    let mut nomad_refs = snapshot.nomad_refs;
+    let mut prune = Vec::<PruneFrom<GitRef>>::new();
+
+    for name in names {
+        let (host, branch) = git.parse_nomad_ref_name(user, name)?;
+        let is_named =
+            |nomad_ref: &NomadRef<GitRef>| nomad_ref.host == host && nomad_ref.branch == branch;
+
+        // The same ref can be named twice, like once in its local and once in its remote form.
+        if prune
+            .iter()
+            .any(|prune_from| is_named(prune_from.nomad_ref()))
+        {
+            continue;
+        }
+
+        let index = nomad_refs
+            .iter()
+            .position(is_named)
+            .with_context(|| format!("No nomad ref {} for user {}", name, user.0))?;
+        prune.push(PruneFrom::LocalAndRemote(nomad_refs.swap_remove(index)));
+    }
+
+    Ok(prune)
+}
+
+/// The branch name to adopt `ref_name` as, which is whatever the `*` in `pattern` matched, like
+/// `feature/login` for `refs/custom/feature/login` with `refs/custom/*`.
+///
+/// Patterns without a `*` only match whole ref names, so the last component is used instead.
+fn adopted_branch(pattern: &str, ref_name: &str) -> Branch<'static> {
+    let matched = pattern
+        .split_once('*')
+        .and_then(|(literal_prefix, _)| {
+            ref_name
+                .find(literal_prefix)
+                .map(|index| &ref_name[index + literal_prefix.len()..])
+        })
+        .filter(|branch| !branch.is_empty());
+    let branch = matched.unwrap_or_else(|| ref_name.rsplit('/').next().unwrap_or(ref_name));
+    Branch::from(branch.to_string())
+}
+
+/// Re-push refs in `remote` matching `pattern` as nomad managed refs for `host`, so that teams
+/// with their own ref conventions can switch to nomad without losing work.
+///
+/// Only reports what would be adopted unless `fix` is set.
+#[allow(clippy::too_many_arguments)]
+fn adopt(
+    renderer: &mut impl Renderer,
+    git: &GitBinary,
+    user: &User,
+    remote: &Remote,
+    pattern: &str,
+    host: &Host,
+    delete_originals: bool,
+    fix: bool,
+) -> Result<()> {
+    let adopt = git
+        .list_adoptable_refs(renderer, remote, pattern)?
+        .into_iter()
+        .map(|original| {
+            let nomad_ref = NomadRef {
+                user: user.always_borrow(),
+                host: host.always_borrow(),
+                branch: adopted_branch(pattern, &original.name),
+                ref_: (),
+            };
+            (original, nomad_ref)
+        })
+        .collect::<Vec<_>>();
+
+    if git.is_output_allowed() {
+        add_newline_if_spinners_are_visible(renderer)?;
+        renderer.writer(|w| {
+            if adopt.is_empty() {
+                writeln!(w, "No refs at {} match {}", remote.0, pattern)?;
+            }
+            for (original, nomad_ref) in &adopt {
+                writeln!(
+                    w,
+                    "{} -> {} ({})",
+                    original.name,
+                    nomad_ref.to_git_remote_ref(&git.ref_prefix),
+                    original.commit_id,
+                )?;
+            }
+            Ok(())
+        })?;
+    }
+
+    git.adopt_refs(renderer, remote, &adopt, delete_originals, !fix)?;
+
+    if !fix && !adopt.is_empty() && git.is_output_allowed() {
+        renderer.writer(|w| {
+            writeln!(w, "Pass --fix to adopt these refs")?;
+            Ok(())
+        })?;
+    }
+
+    Ok(())
+}
+
+/// How much storage nomad refs cost, as reported by [`du`].
+#[derive(Debug, PartialEq, Eq, Serialize)]
+struct DiskUsage {
+    /// Bytes for all hosts together, which can be less than the sum of `hosts` since hosts often
+    /// share commits.
+    total_bytes: u64,
+    hosts: BTreeMap<String, u64>,
+}
+
+/// Estimate the storage attributable to nomad refs, in total and per host, for deciding on
+/// retention policies.
+///
+/// Read-only aside from fetching nomad refs.
+fn du(
+    renderer: &mut impl Renderer,
+    git: &GitBinary,
+    user: &User,
+    remote: &Remote,
+    json: bool,
+) -> Result<()> {
+    git.fetch_nomad_refs(renderer, user, remote, FetchOptions::default())?;
+    let hosts_and_branches = git.snapshot(renderer, user)?.sorted_hosts_and_branches();
+
+    let total_bytes = git.disk_usage(
+        renderer,
+        "Measuring all nomad refs",
+        remote,
+        hosts_and_branches
+            .iter()
+            .flat_map(|(_, branches)| branches.iter().map(|nomad_ref| &nomad_ref.ref_)),
+    )?;
+
+    let mut hosts = BTreeMap::new();
+    for (host, branches) in &hosts_and_branches {
+        let bytes = git.disk_usage(
+            renderer,
+            format!("Measuring nomad refs for {}", host.0),
+            remote,
+            branches.iter().map(|nomad_ref| &nomad_ref.ref_),
+        )?;
+        hosts.insert(host.0.to_string(), bytes);
+    }
+
+    let usage = DiskUsage { total_bytes, hosts };
+
+    add_newline_if_spinners_are_visible(renderer)?;
+    renderer.writer(|w| {
+        if json {
+            serde_json::to_writer(&mut *w, &usage).context("printing disk usage")?;
+            writeln!(w)?;
+        } else {
+            for (host, bytes) in &usage.hosts {
+                writeln!(w, "{}\t{}", bytes, host)?;
+            }
+            writeln!(w, "{}\ttotal", usage.total_bytes)?;
+        }
+        Ok(())
+    })
+}
+
+/// Fetch from `remote` and switch to a new local branch at the nomad ref for `branch` on `host`.
+///
+/// Returns the nomad ref that was checked out.
+fn checkout(
+    renderer: &mut impl Renderer,
+    git: &GitBinary,
+    user: &User,
+    remote: &Remote,
+    host: &Host,
+    branch: &Branch,
+) -> Result<GitRef> {
+    git.fetch_nomad_refs(renderer, user, remote, FetchOptions::default())?;
+
+    let Some(nomad_ref) = git
+        .snapshot(renderer, user)?
+        .nomad_refs
+        .into_iter()
+        .find(|nomad_ref| nomad_ref.host == *host && nomad_ref.branch == *branch)
+    else {
+        bail!(
+            "{} has no branch {} at {}, see `git nomad ls` for what is tracked",
+            host.0,
+            branch.0,
+            remote.0
+        );
+    };
+
+    let local = git.checkout_nomad_ref(renderer, &nomad_ref)?;
+    if git.is_output_allowed() {
+        add_newline_if_spinners_are_visible(renderer)?;
+        renderer.writer(|w| {
+            writeln!(w, "Checked out {} as {}", nomad_ref.ref_.commit_id, local.0)?;
+            Ok(())
+        })?;
+    }
+
+    Ok(nomad_ref.ref_)
+}
+
+/// Print how many refs at `remote` are shaped like nomad refs under each prefix, and suggest
+/// the most common one for joining a remote that is already shared.
+fn detect_prefix(renderer: &mut impl Renderer, git: &GitBinary, remote: &Remote) -> Result<()> {
+    let prefixes = git.detect_ref_prefixes(renderer, remote)?;
+
+    add_newline_if_spinners_are_visible(renderer)?;
+    renderer.writer(|w| {
+        let Some((suggested, _)) = prefixes.first() else {
+            writeln!(w, "No nomad refs found at {}", remote.0)?;
+            return Ok(());
+        };
+
+        for (prefix, count) in &prefixes {
+            writeln!(w, "{}\t{}", count, prefix)?;
+        }
+        writeln!(w, "Suggested: --ref-prefix {}", suggested)?;
+        Ok(())
+    })
+}
+
+/// Every shell that [`print_completions`] can generate for, which is [`clap_complete::Shell`] plus
+/// shells supported by separate crates.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompletionShell {
+    Builtin(clap_complete::Shell),
+    Nushell,
+}
+
+impl CompletionShell {
+    /// Like [`clap_complete::Shell::from_shell_path`], also recognizing `nu`.
+    pub fn from_shell_path(path: impl AsRef<Path>) -> Option<Self> {
+        let path = path.as_ref();
+        if path.file_stem().is_some_and(|stem| stem == "nu") {
+            return Some(Self::Nushell);
+        }
+        clap_complete::Shell::from_shell_path(path).map(Self::Builtin)
+    }
+}
+
+impl ValueEnum for CompletionShell {
+    fn value_variants<'a>() -> &'a [Self] {
+        use clap_complete::Shell;
+        &[
+            Self::Builtin(Shell::Bash),
+            Self::Builtin(Shell::Elvish),
+            Self::Builtin(Shell::Fish),
+            Self::Builtin(Shell::PowerShell),
+            Self::Builtin(Shell::Zsh),
+            Self::Nushell,
+        ]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        match self {
+            Self::Builtin(shell) => shell.to_possible_value(),
+            Self::Nushell => Some(PossibleValue::new("nushell")),
+        }
+    }
+}
+
+impl clap_complete::Generator for CompletionShell {
+    fn file_name(&self, name: &str) -> String {
+        match self {
+            Self::Builtin(shell) => shell.file_name(name),
+            Self::Nushell => clap_complete_nushell::Nushell.file_name(name),
+        }
+    }
+
+    fn generate(&self, cmd: &clap::Command, buf: &mut dyn std::io::Write) {
+        match self {
+            Self::Builtin(shell) => shell.generate(cmd, buf),
+            Self::Nushell => clap_complete_nushell::Nushell.generate(cmd, buf),
+        }
+    }
+}
+
+/// Print `=` if the current branch matches what this host last synced, `↑` if it has changed
+/// since (or was never synced), and nothing at all on a detached HEAD or an unborn branch.
+///
+/// Meant to be called on every shell prompt, so this only reads local refs and never touches the
+/// network. The nomad ref is only as fresh as the last `sync`, since that is what fetches it back.
+fn prompt(renderer: &mut impl Renderer, git: &GitBinary, user: User, host: Host) -> Result<()> {
+    let Ok(branch) = git.current_branch_fast(renderer) else {
+        return Ok(());
+    };
+
+    // An unborn branch, like in a fresh `git init`, has nothing to compare yet.
+    let Ok(local) = git.get_ref(
+        renderer,
+        "Reading current branch commit",
+        format!("refs/heads/{}", branch.0),
+    ) else {
+        return Ok(());
+    };
+    let nomad_ref_name = NomadRef {
+        user,
+        host,
+        branch,
+        ref_: (),
+    }
+    .to_git_local_ref(&git.ref_prefix);
+    let synced = git
+        .get_ref(renderer, "Reading nomad ref", nomad_ref_name)
+        .ok();
+
+    let token = match synced {
+        Some(synced) if synced.commit_id == local.commit_id => "=",
+        _ => "↑",
+    };
+    renderer.writer(|w| {
+        writeln!(w, "{}", token)?;
+        Ok(())
+    })
+}
+
+/// Print the push, fetch, and list refspecs for `user` on `host`, followed by the remote ref that
+/// each local branch is pushed to, as tab separated `kind\tvalue` lines like `sync
+/// --show-refspecs`.
+///
+/// Nothing is run against a remote, this only explains the naming scheme for debugging.
+fn explain(renderer: &mut impl Renderer, git: &GitBinary, user: &User, host: &Host) -> Result<()> {
+    let mut branches = git
+        .snapshot(renderer, user)?
+        .local_branches
+        .into_iter()
+        .collect::<Vec<_>>();
+    branches.sort();
+
+    add_newline_if_spinners_are_visible(renderer)?;
+    renderer.writer(|w| {
+        for (kind, refspec) in git.nomad_refspecs(user, host, None) {
+            writeln!(w, "{}\t{}", kind, refspec)?;
+        }
+        for branch in branches {
+            let remote_ref = NomadRef {
+                user: user.always_borrow(),
+                host: host.always_borrow(),
+                branch,
+                ref_: (),
+            }
+            .to_git_remote_ref(&git.ref_prefix);
+            writeln!(w, "ref\t{}", remote_ref)?;
+        }
+        Ok(())
+    })
+}
+
+/// Use [`clap_complete`] to emit shell syntax for tab-completions
+fn print_completions(
+    renderer: &mut impl Renderer,
+    gen: impl clap_complete::Generator,
+) -> Result<()> {
+    let mut cmd = crate::build_cli(None, None);
+    let bin_name = cmd.get_name().to_string();
+    renderer.writer(|writer| {
+        clap_complete::generate(gen, &mut cmd, bin_name, writer);
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, time::Duration};
+
+    use anyhow::bail;
+
+    use crate::{
+        git_binary::{git_command, FetchOptions},
+        git_ref::{GitRef, ListedRef},
+        git_testing::{GitClone, GitCommitId, GitRemote, INITIAL_BRANCH},
+        renderer::{
+            test::{MemoryRenderer, NoRenderer},
+            ProgressEvent, Styler,
+        },
+        verbosity::{run_notable, Verbosity},
+        workflow::{sync, SyncDirection, SyncForce},
+    };
+
+    use crate::types::{Branch, Host, NomadRef, Remote, User};
+
+    use console::Color;
+
+    use super::{
+        adopted_branch, host_color, watch, Age, Filter, Glob, LsOptions, LsPrinter, LsSort,
+        OnRateLimit, Pattern, PruneConfirm, PushRateLimit, RefAnnotations, SyncOptions, Workflow,
+    };
+
+    #[test]
+    fn ls_one_host() {
+        let remote = GitRemote::init(None);
+
+        let clone = remote.clone("user0", "host0");
+        let commit_id = clone.current_commit();
+
+        sync(
+            &mut NoRenderer,
+            &clone.git,
+            &clone.user,
+            &clone.host,
+            std::slice::from_ref(&clone.remote),
+            SyncOptions::default(),
+            &Filter::All,
+        )
+        .unwrap();
+
+        for (printer, expected) in [
+            (
+                LsPrinter::Grouped,
+                format!(
+                    "{}\n  refs/nomad/{}/master -> {} (ahead 0, behind 0)\n",
+                    clone.host.0, clone.host.0, commit_id.0
+                ),
+            ),
+            (
+                LsPrinter::Ref,
+                format!("refs/nomad/{}/master\n", clone.host.0),
+            ),
+            (LsPrinter::Commit, format!("{}\n", commit_id.0)),
+            (
+                LsPrinter::Json,
+                format!(
+                    "[{{\"host\":\"{}\",\"branch\":\"master\",\"ref\":\"refs/nomad/{}/master\",\"commit\":\"{}\"}}]\n",
+                    clone.host.0, clone.host.0, commit_id.0
+                ),
+            ),
+        ] {
+            let mut renderer = MemoryRenderer::new();
+
+            Workflow::Ls {
+                user: clone.user.clone(),
+                fetch_remotes: vec![clone.remote.clone()],
+                fetch_options: FetchOptions::default(),
+                options: LsOptions {
+                    printer,
+                    ..LsOptions::default()
+                },
+                tags: false,
+                watch_interval: None,
+            }
+            .execute(&mut renderer, &clone.git)
+            .unwrap();
+
+            assert_eq!(renderer.as_str(), expected);
+        }
+    }
+
+    /// Grouped output should say how other hosts' branches relate to local branches of the same
+    /// name, and leave branches without a local counterpart alone.
+    #[test]
+    fn ls_ahead_behind() {
+        let remote = GitRemote::init(None);
+        let host0 = remote.clone("user0", "host0");
+        let host1 = remote.clone("user0", "host1");
+
+        let commit = |clone: &GitClone, message: &str| {
+            run_notable(
+                &mut NoRenderer,
+                None,
+                "",
+                git_command("git")
+                    .current_dir(clone.working_directory())
+                    .args(["commit", "--allow-empty", "-m", message]),
+            )
+            .unwrap();
+        };
+
+        commit(&host0, "host0 commit0");
+        commit(&host0, "host0 commit1");
+        host0
+            .git
+            .create_branch(&mut NoRenderer, "Start feature", &Branch::from("feature"))
+            .unwrap();
+        host0.push();
+        commit(&host1, "host1 commit0");
+
+        let mut renderer = MemoryRenderer::new();
+        Workflow::Ls {
+            user: host1.user.clone(),
+            fetch_remotes: vec![host1.remote.clone()],
+            fetch_options: FetchOptions::default(),
+            options: LsOptions::default(),
+            tags: false,
+            watch_interval: None,
+        }
+        .execute(&mut renderer, &host1.git)
+        .unwrap();
+
+        let commit_id = host0.current_commit().0;
+        assert_eq!(
+            renderer.as_str(),
+            format!(
+                "host0\n  refs/nomad/host0/feature -> {commit_id}\n  refs/nomad/host0/master -> {commit_id} (ahead 2, behind 1)\n"
+            ),
+        );
+    }
+
+    /// `since` should leave out refs that the baseline already contains, and keep the ones that
+    /// are strictly ahead of it.
+    #[test]
+    fn ls_since() {
+        let remote = GitRemote::init(None);
+        let host0 = remote.clone("user0", "host0");
+        let host1 = remote.clone("user0", "host1");
+
+        host0
+            .git
+            .create_branch(&mut NoRenderer, "Start stale", &Branch::from("stale"))
+            .unwrap();
+        run_notable(
+            &mut NoRenderer,
+            None,
+            "",
+            git_command("git")
+                .current_dir(host0.working_directory())
+                .args(["commit", "--allow-empty", "-m", "host0 ahead"]),
+        )
+        .unwrap();
+        host0.push();
+
+        let mut renderer = MemoryRenderer::new();
+        Workflow::Ls {
+            user: host1.user.clone(),
+            fetch_remotes: vec![host1.remote.clone()],
+            fetch_options: FetchOptions::default(),
+            options: LsOptions {
+                since: Some("master".to_string()),
+                ..LsOptions::default()
+            },
+            tags: false,
+            watch_interval: None,
+        }
+        .execute(&mut renderer, &host1.git)
+        .unwrap();
+
+        assert_eq!(
+            renderer.as_str(),
+            format!(
+                "host0\n  refs/nomad/host0/master -> {} (ahead 1, behind 0)\n",
+                host0.current_commit().0
+            ),
+        );
+    }
+
+    /// `{count}` in the host header should only count the refs listed under it, after filtering.
+    #[test]
+    fn ls_format_host() {
+        let remote = GitRemote::init(None);
+        let host0 = remote.clone("user0", "host0");
+        let host1 = remote.clone("user0", "host1");
+
+        host0
+            .git
+            .create_branch(&mut NoRenderer, "Start feature", &Branch::from("feature"))
+            .unwrap();
+        host0.push();
+
+        let workflow = |branch_filter| Workflow::Ls {
+            user: host1.user.clone(),
+            fetch_remotes: vec![host1.remote.clone()],
+            fetch_options: FetchOptions::default(),
+            options: LsOptions {
+                host_filter: Filter::Deny([host1.host.clone()].into()),
+                branch_filter,
+                format_host: Some("-- {user}@{host} ({count}) {{}} --".to_string()),
+                ..LsOptions::default()
+            },
+            tags: false,
+            watch_interval: None,
+        };
+
+        let commit_id = host0.current_commit().0;
+        for (branch_filter, expected) in [
+            (
+                Filter::All,
+                format!(
+                    "-- user0@host0 (2) {{}} --\n  refs/nomad/host0/feature -> {commit_id}\n  refs/nomad/host0/master -> {commit_id} (ahead 0, behind 0)\n"
+                ),
+            ),
+            (
+                Filter::Allow([Branch::from("feature")].into()),
+                format!("-- user0@host0 (1) {{}} --\n  refs/nomad/host0/feature -> {commit_id}\n"),
+            ),
+        ] {
+            let mut renderer = MemoryRenderer::new();
+            workflow(branch_filter)
+                .execute(&mut renderer, &host1.git)
+                .unwrap();
+            assert_eq!(renderer.as_str(), expected);
+        }
+    }
+
+    /// Other users' refs should be listed straight from the remote, with hosts qualified by user,
+    /// without fetching them into the local clone.
+    #[test]
+    fn ls_other_users() {
+        let remote = GitRemote::init(None);
+        let host0 = remote.clone("user0", "host0");
+        let teammate = remote.clone("user1", "laptop");
+        let stranger = remote.clone("user2", "desktop");
+        host0.push();
+        teammate.push();
+        stranger.push();
+
+        let commit_id = host0.current_commit().0;
+        for (user_filter, expected) in [
+            (
+                Filter::Allow([host0.user.clone()].into()),
+                format!("host0\n  refs/nomad/host0/master -> {commit_id} (ahead 0, behind 0)\n"),
+            ),
+            (
+                Filter::Allow([host0.user.clone(), teammate.user.clone()].into()),
+                format!(
+                    "user0/host0\n  refs/nomad/host0/master -> {commit_id} (ahead 0, behind 0)\nuser1/laptop\n  refs/nomad/user1/laptop/master -> {commit_id}\n"
+                ),
+            ),
+            (
+                Filter::Deny([host0.user.clone()].into()),
+                format!(
+                    "user1/laptop\n  refs/nomad/user1/laptop/master -> {commit_id}\nuser2/desktop\n  refs/nomad/user2/desktop/master -> {commit_id}\n"
+                ),
+            ),
+        ] {
+            let mut renderer = MemoryRenderer::new();
+            Workflow::Ls {
+                user: host0.user.clone(),
+                fetch_remotes: vec![host0.remote.clone()],
+                fetch_options: FetchOptions::default(),
+                options: LsOptions {
+                    user_filter,
+                    ..LsOptions::default()
+                },
+                tags: false,
+                watch_interval: None,
+            }
+            .execute(&mut renderer, &host0.git)
+            .unwrap();
+
+            assert_eq!(renderer.as_str(), expected);
+        }
+
+        assert_eq!(
+            host0.nomad_refs(),
+            [host0.get_nomad_ref(INITIAL_BRANCH).unwrap()].into()
+        );
+    }
+
+    /// `LsSort::CommitDate` should list the most recently committed branch of each host first.
+    #[test]
+    fn ls_sort_commit_date() {
+        let remote = GitRemote::init(None);
+        let host0 = remote.clone("user0", "host0");
+        let host1 = remote.clone("user0", "host1");
+
+        let git = |args: &[&str], committer_date: &str| {
+            run_notable(
+                &mut NoRenderer,
+                None,
+                "",
+                git_command("git")
+                    .current_dir(host0.working_directory())
+                    .env("GIT_COMMITTER_DATE", committer_date)
+                    .args(args),
+            )
+            .unwrap();
+        };
+
+        for (branch, committer_date) in [
+            ("old", "2000-01-01T00:00:00Z"),
+            ("new", "2099-01-01T00:00:00Z"),
+        ] {
+            git(&["checkout", "-b", branch, INITIAL_BRANCH], committer_date);
+            git(&["commit", "--allow-empty", "-m", branch], committer_date);
+        }
+        host0.push();
+
+        for (sort, expected) in [
+            (LsSort::Name, "master\nnew\nold\n"),
+            (LsSort::CommitDate, "new\nmaster\nold\n"),
+        ] {
+            let mut renderer = MemoryRenderer::new();
+            Workflow::Ls {
+                user: host1.user.clone(),
+                fetch_remotes: vec![host1.remote.clone()],
+                fetch_options: FetchOptions::default(),
+                options: LsOptions {
+                    printer: LsPrinter::Template("%b".to_string()),
+                    abbrev: Some(7),
+                    sort,
+                    ..LsOptions::default()
+                },
+                tags: false,
+                watch_interval: None,
+            }
+            .execute(&mut renderer, &host1.git)
+            .unwrap();
+
+            assert_eq!(renderer.as_str(), expected);
+        }
+    }
+
+    /// [`watch`] should keep iterating until something fails.
+    #[test]
+    fn watch_until_error() {
+        let mut iterations = 0;
+        let mut waits = Vec::new();
+        let error = watch(
+            &mut NoRenderer,
+            Duration::from_secs(5),
+            |interval| {
+                waits.push(interval);
+                if waits.len() == 3 {
+                    bail!("stop");
+                }
+                Ok(())
+            },
+            |_| {
+                iterations += 1;
+                Ok(())
+            },
+        )
+        .unwrap_err();
+
+        assert_eq!(error.to_string(), "stop");
+        assert_eq!(iterations, 3);
+        assert_eq!(waits, vec![Duration::from_secs(5); 3]);
+    }
+
+    /// A single iteration of `ls --watch` should list refs that were already fetched even if the
+    /// remote has gone away.
+    #[test]
+    fn fetch_or_warn() {
+        let remote = GitRemote::init(None);
+        let host0 = remote.clone("user0", "host0");
+        let host1 = remote.clone("user0", "host1");
+        host0.push();
+
+        let mut renderer = MemoryRenderer::new();
+        super::fetch_or_warn(
+            &mut renderer,
+            &host1.git,
+            &host1.user,
+            &host1.remote,
+            FetchOptions::default(),
+        )
+        .unwrap();
+        assert!(!renderer.as_str().contains("Warning"));
+        let fetched = host1.nomad_refs();
+        assert_eq!(fetched.len(), 1);
+
+        let mut renderer = MemoryRenderer::new();
+        super::fetch_or_warn(
+            &mut renderer,
+            &host1.git,
+            &host1.user,
+            &Remote::from("missing"),
+            FetchOptions::default(),
+        )
+        .unwrap();
+        assert!(renderer.as_str().contains(
+            "Warning: listing refs from the last successful fetch, fetching from missing failed"
+        ));
+        assert_eq!(host1.nomad_refs(), fetched);
+    }
+
+    /// `LsPrinter::Json` should always print a valid document, even with no refs.
+    #[test]
+    fn ls_json_empty() {
+        let remote = GitRemote::init(None);
+        let clone = remote.clone("user0", "host0");
+
+        let mut renderer = MemoryRenderer::new();
+        Workflow::Ls {
+            user: clone.user,
+            fetch_remotes: Vec::new(),
+            fetch_options: FetchOptions::default(),
+            options: LsOptions {
+                printer: LsPrinter::Json,
+                ..LsOptions::default()
+            },
+            tags: false,
+            watch_interval: None,
+        }
+        .execute(&mut renderer, &clone.git)
+        .unwrap();
+
+        assert_eq!(renderer.as_str(), "[]\n");
+    }
+
+    /// Exercise `LsPrinter::Grouped` with a bunch of `Filter::Deny`s.
+    #[test]
+    fn ls_two_hosts() {
+        let remote = GitRemote::init(None);
+
+        let host0 = remote.clone("user0", "host0");
+        let host1 = remote.clone("user0", "host1");
+
+        sync(
+            &mut NoRenderer,
+            &host0.git,
+            &host0.user,
+            &host0.host,
+            std::slice::from_ref(&host0.remote),
+            SyncOptions::default(),
+            &Filter::All,
+        )
+        .unwrap();
+
+        sync(
+            &mut NoRenderer,
+            &host1.git,
+            &host1.user,
+            &host1.host,
+            std::slice::from_ref(&host1.remote),
+            SyncOptions::default(),
+            &Filter::All,
+        )
+        .unwrap();
+
+        let mut renderer = MemoryRenderer::new();
+        Workflow::Ls {
+            user: host1.user,
+            fetch_remotes: vec![host1.remote],
+            fetch_options: FetchOptions::default(),
+            options: LsOptions {
+                host_filter: Filter::Deny([host0.host].into()),
+                branch_filter: Filter::Deny(
+                    [host1.git.current_branch(&mut renderer).unwrap()].into(),
+                ),
+                ..LsOptions::default()
+            },
+            tags: false,
+            watch_interval: None,
+        }
+        .execute(&mut renderer, &host1.git)
+        .unwrap();
+
+        assert_eq!(renderer.as_str(), "host1\n");
+    }
+
+    /// Tree output should nest branches under hosts, with the last branch getting a different
+    /// connector.
+    #[test]
+    fn ls_tree() {
+        let remote = GitRemote::init(None);
+
+        let host0 = remote.clone("user0", "host0");
+        host0
+            .git
+            .create_branch(&mut NoRenderer, "", &Branch::from("feature"))
+            .unwrap();
+        sync(
+            &mut NoRenderer,
+            &host0.git,
+            &host0.user,
+            &host0.host,
+            std::slice::from_ref(&host0.remote),
+            SyncOptions::default(),
+            &Filter::All,
+        )
+        .unwrap();
+
+        let host1 = remote.clone("user0", "host1");
+        sync(
+            &mut NoRenderer,
+            &host1.git,
+            &host1.user,
+            &host1.host,
+            std::slice::from_ref(&host1.remote),
+            SyncOptions::default(),
+            &Filter::All,
+        )
+        .unwrap();
+
+        let commit = host1.current_commit().0;
+        for (printer, expected) in [
+            (
+                LsPrinter::Tree,
+                format!("host0\n|-- feature {commit}\n`-- master {commit}\nhost1\n`-- master {commit}\n"),
+            ),
+            (
+                LsPrinter::UnicodeTree,
+                format!("host0\n├── feature {commit}\n└── master {commit}\nhost1\n└── master {commit}\n"),
+            ),
+        ] {
+            let mut renderer = MemoryRenderer::new();
+            Workflow::Ls {
+                user: host1.user.always_borrow(),
+                fetch_remotes: Vec::new(),
+                fetch_options: FetchOptions::default(),
+                options: LsOptions {
+                    printer,
+                    ..LsOptions::default()
+                },
+                tags: false,
+                watch_interval: None,
+            }
+            .execute(&mut renderer, &host1.git)
+            .unwrap();
+
+            assert_eq!(renderer.as_str(), expected);
+        }
+    }
+
+    /// `diff_self` should only list branches from other hosts that do not exist locally.
+    #[test]
+    fn ls_diff_self() {
+        let remote = GitRemote::init(None);
+
+        let host0 = remote.clone("user0", "host0");
+        host0
+            .git
+            .create_branch(&mut NoRenderer, "Create extra", &Branch::from("extra"))
+            .unwrap();
+        sync(
+            &mut NoRenderer,
+            &host0.git,
+            &host0.user,
+            &host0.host,
+            std::slice::from_ref(&host0.remote),
+            SyncOptions::default(),
+            &Filter::All,
+        )
+        .unwrap();
+
+        let host1 = remote.clone("user0", "host1");
+        sync(
+            &mut NoRenderer,
+            &host1.git,
+            &host1.user,
+            &host1.host,
+            std::slice::from_ref(&host1.remote),
+            SyncOptions::default(),
+            &Filter::All,
+        )
+        .unwrap();
+
+        let mut renderer = MemoryRenderer::new();
+        Workflow::Ls {
+            user: host1.user,
+            fetch_remotes: Vec::new(),
+            fetch_options: FetchOptions::default(),
+            options: LsOptions {
+                printer: LsPrinter::Ref,
+                diff_self: true,
+                ..LsOptions::default()
+            },
+            tags: false,
+            watch_interval: None,
+        }
+        .execute(&mut renderer, &host1.git)
+        .unwrap();
+
+        assert_eq!(renderer.as_str(), "refs/nomad/host0/extra\n");
+    }
+
+    /// With `selective_fetch`, a sync against a remote that has not changed since the last one
+    /// should not fetch at all, but should still pick up changes from other hosts.
+    #[test]
+    fn sync_selective_fetch() {
+        let remote = GitRemote::init(Some(Verbosity::default()));
+        let host0 = remote.clone("user0", "host0");
+        let host1 = remote.clone("user0", "host1");
+        let options = SyncOptions {
+            selective_fetch: true,
+            ..SyncOptions::default()
+        };
+
+        let sync_host = |clone: &GitClone| {
+            let mut renderer = MemoryRenderer::new();
+            sync(
+                &mut renderer,
+                &clone.git,
+                &clone.user,
+                &clone.host,
+                std::slice::from_ref(&clone.remote),
+                options,
+                &Filter::All,
+            )
+            .unwrap();
+            renderer.as_str().to_string()
+        };
+
+        assert!(sync_host(&host0).contains("Fetching branches from origin"));
+        assert!(!sync_host(&host0).contains("Fetching branches from origin"));
+
+        sync_host(&host1);
+        assert!(sync_host(&host0).contains("Fetching branches from origin"));
+        assert_eq!(host0.nomad_refs(), host1.nomad_refs());
+        assert!(!sync_host(&host0).contains("Fetching branches from origin"));
+    }
+
+    /// Excluded branches should neither be pushed nor pruned, until the local branch goes away.
+    #[test]
+    fn sync_branch_filter() {
+        let remote = GitRemote::init(None);
+        let host0 = remote.clone("user0", "host0");
+        let feature = Branch::from("feature");
+        host0
+            .git
+            .create_branch(&mut NoRenderer, "", &feature)
+            .unwrap();
+
+        let sync_host = |branch_filter: &Filter<Branch>| {
+            sync(
+                &mut NoRenderer,
+                &host0.git,
+                &host0.user,
+                &host0.host,
+                std::slice::from_ref(&host0.remote),
+                SyncOptions::default(),
+                branch_filter,
+            )
+            .unwrap();
+        };
+        let only_feature = Filter::Allow([feature.always_borrow()].into());
+
+        sync_host(&only_feature);
+        assert_eq!(
+            remote.nomad_refs(),
+            [host0.get_nomad_ref("feature").unwrap()].into()
+        );
+
+        sync_host(&Filter::All);
+        let all_refs = [
+            host0.get_nomad_ref(INITIAL_BRANCH).unwrap(),
+            host0.get_nomad_ref("feature").unwrap(),
+        ]
+        .into();
+        assert_eq!(remote.nomad_refs(), all_refs);
+
+        let only_initial = Filter::Allow([Branch::from(INITIAL_BRANCH)].into());
+        sync_host(&only_initial);
+        assert_eq!(remote.nomad_refs(), all_refs);
+        assert_eq!(host0.nomad_refs(), all_refs);
+
+        host0
+            .git
+            .delete_branch(&mut NoRenderer, "", &feature)
+            .unwrap();
+        sync_host(&only_initial);
+        assert_eq!(
+            remote.nomad_refs(),
+            [host0.get_nomad_ref(INITIAL_BRANCH).unwrap()].into()
+        );
+    }
+
+    /// `sync` should end with a summary of what it pushed and pruned, counting only the hosts
+    /// left after pruning.
+    #[test]
+    fn sync_summary_line() {
+        let remote = GitRemote::init(Some(Verbosity::default()));
+        let host0 = remote.clone("user0", "host0");
+        let host1 = remote.clone("user0", "host1");
+
+        host0
+            .git
+            .create_branch(&mut NoRenderer, "", &Branch::from("feature"))
+            .unwrap();
+        host0.push();
+
+        let sync_host = |renderer: &mut MemoryRenderer, host: &GitClone| {
+            sync(
+                renderer,
+                &host.git,
+                &host.user,
+                &host.host,
+                std::slice::from_ref(&host.remote),
+                SyncOptions::default(),
+                &Filter::All,
+            )
+            .unwrap();
+        };
+
+        let mut renderer = MemoryRenderer::new();
+        sync_host(&mut renderer, &host1);
+        assert!(renderer
+            .as_str()
+            .ends_with("Pushed 1, pruned 0, tracking 2 hosts\n"));
+
+        host0
+            .git
+            .delete_branch(&mut NoRenderer, "", &Branch::from("feature"))
+            .unwrap();
+        let mut renderer = MemoryRenderer::new();
+        sync_host(&mut renderer, &host0);
+        assert!(renderer
+            .as_str()
+            .ends_with("Pushed 1, pruned 1, tracking 2 hosts\n"));
+    }
+
+    /// A second sync within the minimum push interval should skip the push but still record it
+    /// as last pushed by the first sync.
+    #[test]
+    fn sync_push_rate_limit() {
+        let remote = GitRemote::init(Some(Verbosity::default()));
+        let host0 = remote.clone("user0", "host0");
+
+        let sync_host = |renderer: &mut MemoryRenderer, on_limit| {
+            sync(
+                renderer,
+                &host0.git,
+                &host0.user,
+                &host0.host,
+                std::slice::from_ref(&host0.remote),
+                SyncOptions {
+                    push_rate_limit: Some(PushRateLimit {
+                        min_interval: 3600,
+                        on_limit,
+                    }),
+                    ..SyncOptions::default()
+                },
+                &Filter::All,
+            )
+        };
+
+        sync_host(&mut MemoryRenderer::new(), OnRateLimit::Skip).unwrap();
+        let initial_refs = [host0.get_nomad_ref(INITIAL_BRANCH).unwrap()].into();
+        assert_eq!(remote.nomad_refs(), initial_refs);
+        let last_push = host0.git.last_push(&mut NoRenderer, &host0.remote).unwrap();
+        assert!(last_push.is_some());
+
+        host0
+            .git
+            .create_branch(&mut NoRenderer, "", &Branch::from("feature"))
+            .unwrap();
+
+        let mut renderer = MemoryRenderer::new();
+        sync_host(&mut renderer, OnRateLimit::Skip).unwrap();
+        assert!(renderer
+            .as_str()
+            .contains("Skipping push to origin, last pushed"));
+        assert_eq!(remote.nomad_refs(), initial_refs);
+        assert_eq!(
+            host0.git.last_push(&mut NoRenderer, &host0.remote).unwrap(),
+            last_push
+        );
+
+        let error = sync_host(&mut MemoryRenderer::new(), OnRateLimit::Error).unwrap_err();
+        assert!(error.to_string().starts_with("Last pushed to origin"));
+        assert_eq!(remote.nomad_refs(), initial_refs);
+    }
+
+    /// `sync` and `purge` should report their milestones as typed events.
+    #[test]
+    fn progress_events() {
+        let remote = GitRemote::init(None);
+        let host0 = remote.clone("user0", "host0");
+        let feature = Branch::from("feature");
+        host0
+            .git
+            .create_branch(&mut NoRenderer, "", &feature)
+            .unwrap();
+
+        let sync_host = |renderer: &mut MemoryRenderer| {
+            sync(
+                renderer,
+                &host0.git,
+                &host0.user,
+                &host0.host,
+                std::slice::from_ref(&host0.remote),
+                SyncOptions::default(),
+                &Filter::All,
+            )
+            .unwrap();
+        };
+        let origin = || "origin".to_string();
+
+        sync_host(&mut MemoryRenderer::new());
+        host0
+            .git
+            .delete_branch(&mut NoRenderer, "", &feature)
+            .unwrap();
+
+        let mut renderer = MemoryRenderer::new();
+        sync_host(&mut renderer);
+        assert_eq!(
+            renderer.events(),
+            [
+                ProgressEvent::PushStarted {
+                    remote: origin(),
+                    current: 1,
+                    total: 1,
+                },
+                ProgressEvent::FetchStarted {
+                    remote: origin(),
+                    current: 1,
+                    total: 1,
+                },
+                ProgressEvent::RefPruned {
+                    name: "refs/nomad/host0/feature".to_string(),
+                    current: 1,
+                    total: 1,
+                },
+                ProgressEvent::SyncComplete {
+                    pushed: 1,
+                    pruned: 1,
+                },
+            ]
+        );
+
+        let mut renderer = MemoryRenderer::new();
+        Workflow::Purge {
+            user: host0.user.always_borrow(),
+            remote: host0.remote.always_borrow(),
+            host_filter: Filter::All,
+            branch_filter: Filter::All,
+            prune_confirm: PruneConfirm::default(),
+            older_than: None,
+            dry_run: false,
+            fast_prune: false,
+            local_only: false,
+            refs: Vec::new(),
+        }
+        .execute(&mut renderer, &host0.git)
+        .unwrap();
+        assert_eq!(
+            renderer.events(),
+            [
+                ProgressEvent::FetchStarted {
+                    remote: origin(),
+                    current: 1,
+                    total: 1,
+                },
+                ProgressEvent::RefPruned {
+                    name: format!("refs/nomad/host0/{}", INITIAL_BRANCH),
+                    current: 1,
+                    total: 1,
+                },
+                ProgressEvent::PurgeComplete { pruned: 1 },
+            ]
+        );
+    }
+
+    /// `detect-prefix` should suggest the prefix used by most refs, or say that there are none.
+    #[test]
+    fn detect_prefix_suggests_most_common() {
+        let remote = GitRemote::init(None);
+        let mut host0 = remote.clone("user0", "host0");
+
+        let detect = |host: &GitClone| {
+            let mut renderer = MemoryRenderer::new();
+            Workflow::DetectPrefix {
+                remote: host.remote.clone(),
+            }
+            .execute(&mut renderer, &host.git)
+            .unwrap();
+            renderer.as_str().to_string()
+        };
+        assert_eq!(detect(&host0), "\nNo nomad refs found at origin\n");
+
+        host0.push();
+        host0.git.ref_prefix = "team-a".to_string();
+        host0
+            .git
+            .create_branch(&mut NoRenderer, "", &Branch::from("feature"))
+            .unwrap();
+        host0.push();
+
+        assert_eq!(
+            detect(&host0),
+            "\n2\tteam-a\n1\tnomad\nSuggested: --ref-prefix team-a\n"
+        );
+    }
+
+    /// Listing mirrors should show each ref once, warn about refs the mirrors disagree on, and
+    /// list the commit from the first remote.
+    #[test]
+    fn ls_fetch_mirrors() {
+        let origin = GitRemote::init(None);
+        let backup = GitRemote::init(None);
+        let host0 = origin.clone("user0", "host0");
+        let host0_backup = host0.add_remote("backup", &backup);
+        let host1 = origin.clone("user0", "host1");
+        let host1_backup = host1.add_remote("backup", &backup);
+
+        let sync_host0 = |remotes: Vec<Remote<'static>>| {
+            sync(
+                &mut NoRenderer,
+                &host0.git,
+                &host0.user,
+                &host0.host,
+                &remotes,
+                SyncOptions::default(),
+                &Filter::All,
+            )
+            .unwrap();
+        };
+
+        host0
+            .git
+            .create_branch(&mut NoRenderer, "Start feature", &Branch::from("feature"))
+            .unwrap();
+        sync_host0(vec![host0.remote.clone(), host0_backup.clone()]);
+        let feature_commit = host0.current_commit().0;
+
+        // Only origin gets the new commit on master.
+        run_notable(
+            &mut NoRenderer,
+            None,
+            "",
+            git_command("git")
+                .current_dir(host0.working_directory())
+                .args(["commit", "--allow-empty", "-m", "origin only"]),
+        )
+        .unwrap();
+        sync_host0(vec![host0.remote.clone()]);
+        let master_commit = host0.current_commit().0;
+
+        let ls = |fetch_remotes| {
+            let mut renderer = MemoryRenderer::new();
+            Workflow::Ls {
+                user: host1.user.clone(),
+                fetch_remotes,
+                fetch_options: FetchOptions::default(),
+                options: LsOptions {
+                    host_filter: Filter::Deny([host1.host.clone()].into()),
+                    ..LsOptions::default()
+                },
+                tags: false,
+                watch_interval: None,
+            }
+            .execute(&mut renderer, &host1.git)
+            .unwrap();
+            renderer.as_str().to_string()
+        };
+
+        assert_eq!(
+            ls(vec![host1.remote.clone(), host1_backup.clone()]),
+            format!(
+                "Warning: remotes disagree on refs/nomad/user0/host0/master, \
+                 origin has {master_commit}, backup has {feature_commit}, \
+                 listing the commit from origin\n\
+                 host0\n  \
+                 refs/nomad/host0/feature -> {feature_commit}\n  \
+                 refs/nomad/host0/master -> {master_commit} (ahead 1, behind 0)\n"
+            ),
+        );
+
+        // The first remote wins, even when the local clone last saw the other one.
+        assert!(
+            ls(vec![host1_backup, host1.remote.clone()]).ends_with(&format!(
+                "listing the commit from backup\n\
+             host0\n  \
+             refs/nomad/host0/feature -> {feature_commit}\n  \
+             refs/nomad/host0/master -> {feature_commit} (ahead 0, behind 0)\n"
+            ))
+        );
+    }
+
+    /// With [`SyncForce::WithLease`], a sync should refuse to overwrite a nomad ref that another
+    /// clone with the same host name pushed since it was last fetched, while [`SyncForce::Always`]
+    /// still overwrites it.
+    #[test]
+    fn sync_force_with_lease() {
+        let remote = GitRemote::init(None);
+        let host0 = remote.clone("user0", "host0");
+        // Clones are laid out by host name, so the concurrent clone borrows the name at sync time.
+        let concurrent = remote.clone("user0", "concurrent");
+
+        let commit = |clone: &GitClone, message: &str| {
+            run_notable(
+                &mut NoRenderer,
+                None,
+                "",
+                git_command("git")
+                    .current_dir(clone.working_directory())
+                    .args(["commit", "--allow-empty", "-m", message]),
+            )
+            .unwrap();
+        };
+        let sync_as_host0 = |clone: &GitClone, force| {
+            sync(
+                &mut NoRenderer,
+                &clone.git,
+                &clone.user,
+                &host0.host,
+                std::slice::from_ref(&clone.remote),
+                SyncOptions {
+                    force,
+                    ..SyncOptions::default()
+                },
+                &Filter::All,
+            )
+        };
+        let remote_commit = || {
+            remote
+                .git
+                .get_ref(&mut NoRenderer, "", "refs/nomad/user0/host0/master")
+                .unwrap()
+                .commit_id
+        };
+
+        sync_as_host0(&host0, SyncForce::WithLease).unwrap();
+        assert_eq!(remote_commit(), host0.current_commit().0);
+        commit(&host0, "host0 commit0");
+        sync_as_host0(&host0, SyncForce::WithLease).unwrap();
+        assert_eq!(remote_commit(), host0.current_commit().0);
+
+        // Never having fetched the ref means expecting it to not exist.
+        commit(&concurrent, "concurrent commit0");
+        let error = sync_as_host0(&concurrent, SyncForce::WithLease).unwrap_err();
+        assert!(format!("{:#}", error).contains("with lease"));
+        assert_eq!(remote_commit(), host0.current_commit().0);
+
+        sync_as_host0(&concurrent, SyncForce::Always).unwrap();
+        assert_eq!(remote_commit(), concurrent.current_commit().0);
+
+        commit(&host0, "host0 commit1");
+        sync_as_host0(&host0, SyncForce::WithLease).unwrap_err();
+        assert_eq!(remote_commit(), concurrent.current_commit().0);
+    }
+
+    /// A push-only sync never fetches, so a leased push has to refresh the local copy of the
+    /// nomad ref itself for the next leased push to expect the right commit.
+    #[test]
+    fn sync_push_only_force_with_lease_twice() {
+        let remote = GitRemote::init(None);
+        let host0 = remote.clone("user0", "host0");
+
+        let push_only = || {
+            sync(
+                &mut NoRenderer,
+                &host0.git,
+                &host0.user,
+                &host0.host,
+                std::slice::from_ref(&host0.remote),
+                SyncOptions {
+                    direction: SyncDirection::PushOnly,
+                    force: SyncForce::WithLease,
+                    ..SyncOptions::default()
+                },
+                &Filter::All,
+            )
+        };
+        let remote_commit = || {
+            remote
+                .git
+                .get_ref(&mut NoRenderer, "", "refs/nomad/user0/host0/master")
+                .unwrap()
+                .commit_id
+        };
+
+        push_only().unwrap();
+        assert_eq!(remote_commit(), host0.current_commit().0);
+
+        run_notable(
+            &mut NoRenderer,
+            None,
+            "",
+            git_command("git")
+                .current_dir(host0.working_directory())
+                .args(["commit", "--allow-empty", "-m", "host0 commit0"]),
+        )
+        .unwrap();
+        push_only().unwrap();
+        assert_eq!(remote_commit(), host0.current_commit().0);
+    }
+
+    /// Only a push rejected by its lease should suggest `--force always`, not one that failed for
+    /// an unrelated reason like a missing remote.
+    #[test]
+    fn sync_force_with_lease_unrelated_failure() {
+        let remote = GitRemote::init(None);
+        let host0 = remote.clone("user0", "host0");
+        run_notable(
+            &mut NoRenderer,
+            None,
+            "",
+            git_command("git")
+                .current_dir(host0.working_directory())
+                .args(["remote", "set-url", &host0.remote.0, "/nonexistent/remote"]),
+        )
+        .unwrap();
+
+        let error = sync(
+            &mut NoRenderer,
+            &host0.git,
+            &host0.user,
+            &host0.host,
+            std::slice::from_ref(&host0.remote),
+            SyncOptions {
+                direction: SyncDirection::PushOnly,
+                force: SyncForce::WithLease,
+                ..SyncOptions::default()
+            },
+            &Filter::All,
+        )
+        .unwrap_err();
+        assert!(!format!("{:#}", error).contains("--force always"));
+    }
+
+    /// Only commits that are not on the remote's branches should count towards disk usage.
+    #[test]
+    fn du_two_hosts() {
+        let remote = GitRemote::init(None);
+
+        let host0 = remote.clone("user0", "host0");
+        git_command("git")
+            .current_dir(host0.working_directory())
+            .args(["commit", "--allow-empty", "-m", "unique to host0"])
+            .output()
+            .unwrap();
+        sync(
+            &mut NoRenderer,
+            &host0.git,
+            &host0.user,
+            &host0.host,
+            std::slice::from_ref(&host0.remote),
+            SyncOptions::default(),
+            &Filter::All,
+        )
+        .unwrap();
+
+        let host1 = remote.clone("user0", "host1");
+        sync(
+            &mut NoRenderer,
+            &host1.git,
+            &host1.user,
+            &host1.host,
+            std::slice::from_ref(&host1.remote),
+            SyncOptions::default(),
+            &Filter::All,
+        )
+        .unwrap();
+
+        let mut renderer = MemoryRenderer::new();
+        Workflow::Du {
+            user: host1.user.clone(),
+            remote: host1.remote.clone(),
+            json: true,
+        }
+        .execute(&mut renderer, &host1.git)
+        .unwrap();
+
+        let usage: serde_json::Value = serde_json::from_str(renderer.as_str()).unwrap();
+        let host0_bytes = usage["hosts"]["host0"].as_u64().unwrap();
+        assert!(host0_bytes > 0);
+        assert_eq!(usage["hosts"]["host1"].as_u64(), Some(0));
+        assert_eq!(usage["total_bytes"].as_u64(), Some(host0_bytes));
+
+        let mut renderer = MemoryRenderer::new();
+        Workflow::Du {
+            user: host1.user,
+            remote: host1.remote,
+            json: false,
+        }
+        .execute(&mut renderer, &host1.git)
+        .unwrap();
+
+        assert_eq!(
+            renderer.as_str(),
+            format!(
+                "\n{}\thost0\n0\thost1\n{}\ttotal\n",
+                host0_bytes, host0_bytes
+            ),
+        );
+    }
+
+    #[test]
+    fn age_parse_and_display() {
+        for (input, seconds, display) in [
+            ("30d", 30 * 24 * 60 * 60, "30d"),
+            ("2w", 14 * 24 * 60 * 60, "2w"),
+            ("6h", 6 * 60 * 60, "6h"),
+            ("90m", 90 * 60, "90m"),
+            ("120s", 120, "2m"),
+        ] {
+            let age = input.parse::<Age>().unwrap();
+            assert_eq!(age, Age(Duration::from_secs(seconds)));
+            assert_eq!(age.to_string(), display);
+        }
+
+        for invalid in ["", "d", "30", "30y", "-1d", "1.5d"] {
+            assert!(invalid.parse::<Age>().is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn age_is_exceeded_by() {
+        let age = Age(Duration::from_secs(100));
+        let now = Duration::from_secs(1000);
+        assert!(age.is_exceeded_by(899, now));
+        assert!(!age.is_exceeded_by(900, now));
+        assert!(!age.is_exceeded_by(2000, now));
+    }
+
+    #[test]
+    fn age_since() {
+        for (elapsed, display) in [
+            (0, "0s"),
+            (59, "59s"),
+            (3 * 60 * 60 + 59, "3h"),
+            (13 * 24 * 60 * 60, "1w"),
+        ] {
+            assert_eq!(Age::since(1000, 1000 + elapsed).to_string(), display);
+        }
+
+        // Clocks can go backwards.
+        assert_eq!(Age::since(1000, 900).to_string(), "0s");
+    }
+
+    /// Purging with an age should only delete refs whose commit is older than that.
+    #[test]
+    fn purge_older_than() {
+        let remote = GitRemote::init(None);
+        let host0 = remote.clone("user0", "host0");
+
+        let git = |args: &[&str]| {
+            git_command("git")
+                .current_dir(host0.working_directory())
+                .env("GIT_COMMITTER_DATE", "@1000000000 +0000")
+                .args(args)
+                .output()
+                .unwrap()
+        };
+        git(&["checkout", "-b", "old"]);
+        git(&["commit", "--allow-empty", "-m", "old"]);
+        git(&["checkout", "master"]);
+        sync(
+            &mut NoRenderer,
+            &host0.git,
+            &host0.user,
+            &host0.host,
+            std::slice::from_ref(&host0.remote),
+            SyncOptions::default(),
+            &Filter::All,
+        )
+        .unwrap();
+
+        Workflow::Purge {
+            user: host0.user.always_borrow(),
+            remote: host0.remote.always_borrow(),
+            host_filter: Filter::All,
+            branch_filter: Filter::All,
+            prune_confirm: PruneConfirm::default(),
+            older_than: Some("30d".parse().unwrap()),
+            dry_run: false,
+            fast_prune: false,
+            local_only: false,
+            refs: Vec::new(),
+        }
+        .execute(&mut NoRenderer, &host0.git)
+        .unwrap();
+
+        let branches = remote
+            .nomad_refs()
+            .into_iter()
+            .map(|r| r.branch.0.into_owned())
+            .collect::<HashSet<_>>();
+        assert_eq!(branches, HashSet::from(["master".to_string()]));
+    }
+
+    #[test]
+    fn adopted_branch_names() {
+        for (pattern, ref_name, branch) in [
+            ("refs/custom/*", "refs/custom/feature", "feature"),
+            (
+                "refs/custom/*",
+                "refs/custom/feature/login",
+                "feature/login",
+            ),
+            ("custom/*", "refs/custom/feature", "feature"),
+            ("refs/custom/feature", "refs/custom/feature", "feature"),
+        ] {
+            assert_eq!(adopted_branch(pattern, ref_name), Branch::from(branch));
+        }
+    }
+
+    /// Refs pushed outside of nomad should only be adopted with `fix`, optionally deleting the
+    /// originals.
+    #[test]
+    fn adopt_custom_refs() {
+        let remote = GitRemote::init(None);
+        let host0 = remote.clone("user0", "host0");
+        git_command("git")
+            .current_dir(host0.working_directory())
+            .args(["push", "origin", "HEAD:refs/custom/feature/login"])
+            .output()
+            .unwrap();
+
+        let adopt = |fix| Workflow::Adopt {
+            user: host0.user.always_borrow(),
+            remote: host0.remote.always_borrow(),
+            pattern: "refs/custom/*".to_string(),
+            host: Host::from("laptop"),
+            delete_originals: true,
+            fix,
+        };
+        let custom_refs = || {
+            remote
+                .git
+                .list_refs(&mut NoRenderer, "")
+                .unwrap()
+                .into_iter()
+                .filter_map(ListedRef::direct)
+                .filter(|r| r.name.starts_with("refs/custom/"))
+                .count()
+        };
+
+        adopt(false).execute(&mut NoRenderer, &host0.git).unwrap();
+        assert_eq!(remote.nomad_refs(), HashSet::new());
+        assert_eq!(custom_refs(), 1);
+
+        adopt(true).execute(&mut NoRenderer, &host0.git).unwrap();
+        assert_eq!(
+            remote.nomad_refs(),
+            HashSet::from([NomadRef {
+                user: User::from("user0"),
+                host: Host::from("laptop"),
+                branch: Branch::from("feature/login"),
+                ref_: GitCommitId(host0.current_commit().0),
+            }]),
+        );
+        assert_eq!(custom_refs(), 0);
+    }
+
+    #[test]
+    fn print_ref_template() {
+        let printer = LsPrinter::Template("host=%h branch=%b ref=%r commit=%c 100%%".to_string());
+        let mut output = Vec::new();
+        printer
+            .print_ref(
+                &mut output,
+                &Styler::default(),
+                &Host::from("host0"),
+                &Branch::from("feature/login"),
+                &GitRef {
+                    commit_id: "abc123".to_string(),
+                    name: "refs/nomad/host0/feature/login".to_string(),
+                },
+                &RefAnnotations::default(),
+            )
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "host=host0 branch=feature/login ref=refs/nomad/host0/feature/login commit=abc123 100%\n"
+        );
+    }
+
+    #[test]
+    fn validate_template() {
+        assert_eq!(
+            LsPrinter::validate_template("%h\t%b\t%r\t%c %%"),
+            Ok("%h\t%b\t%r\t%c %%".to_string())
+        );
+        assert_eq!(
+            LsPrinter::validate_template("%h %x"),
+            Err(
+                "unknown placeholder %x in \"%h %x\", expected one of %h, %b, %r, %c, or %%"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            LsPrinter::validate_template("100%"),
+            Err("\"100%\" ends with a lone %".to_string())
+        );
+    }
+
+    #[test]
+    fn validate_host_format() {
+        assert_eq!(
+            LsPrinter::validate_host_format("{{{host}}} {user} {count}"),
+            Ok("{{{host}}} {user} {count}".to_string())
+        );
+        assert_eq!(
+            LsPrinter::validate_host_format("{host} {branch}"),
+            Err(
+                "unknown placeholder {branch} in \"{host} {branch}\", expected one of {host}, {user}, {count}, {{, or }}"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            LsPrinter::validate_host_format("{host"),
+            Err("\"{host\" has an unmatched brace, use {{ or }} for literal braces".to_string())
+        );
+    }
+
+    /// The same host should always get the same color, and output degrades to plain text when
+    /// colors are disabled.
+    #[test]
+    fn host_color_is_deterministic() {
+        let host = Host::from("host0");
+        assert_eq!(host_color(&host), host_color(&Host::from("host0")));
+        assert_eq!(host_color(&host), Color::Blue);
+
+        let mut output = Vec::new();
+        LsPrinter::ColoredGrouped
+            .print_host(
+                &mut output,
+                &Styler::default(),
+                None,
+                &User::from("user0"),
+                &host,
+                1,
+            )
+            .unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "host0\n");
+    }
+
+    #[test]
+    fn from_toml() {
+        for (toml, expected) in [
+            (
+                r#"
+                    [sync]
+                    user = "user0"
+                    host = "host0"
+                    remote = "origin"
+                    fail_on_prune = true
+                "#,
+                Workflow::Sync {
+                    user: User::from("user0"),
+                    host: Host::from("host0"),
+                    remotes: vec![Remote::from("origin")],
+                    options: SyncOptions {
+                        fail_on_prune: true,
+                        ..SyncOptions::default()
+                    },
+                    branch_filter: Filter::All,
+                },
+            ),
+            (
+                r#"
+                    [sync]
+                    user = "user0"
+                    host = "host0"
+                    remote = ["origin", "backup"]
+                "#,
+                Workflow::Sync {
+                    user: User::from("user0"),
+                    host: Host::from("host0"),
+                    remotes: vec![Remote::from("origin"), Remote::from("backup")],
+                    options: SyncOptions::default(),
+                    branch_filter: Filter::All,
+                },
+            ),
+            (
+                r#"
+                    [ls]
+                    printer = "commit"
+                    user = "user0"
+                    fetch_remote = "origin"
+                    host_filter = { deny = ["host0"] }
+                "#,
+                Workflow::Ls {
+                    user: User::from("user0"),
+                    fetch_remotes: vec![Remote::from("origin")],
+                    fetch_options: FetchOptions::default(),
+                    options: LsOptions {
+                        printer: LsPrinter::Commit,
+                        host_filter: Filter::Deny([Host::from("host0")].into()),
+                        ..LsOptions::default()
+                    },
+                    tags: false,
+                    watch_interval: None,
+                },
+            ),
+            (
+                r#"
+                    [purge]
+                    user = "user0"
+                    remote = "origin"
+                    prune_confirm = { threshold = 5 }
+                    branch_filter = { regex = "^feature/" }
+                "#,
+                Workflow::Purge {
+                    user: User::from("user0"),
+                    remote: Remote::from("origin"),
+                    host_filter: Filter::All,
+                    branch_filter: Filter::Regex("^feature/".parse().unwrap()),
+                    prune_confirm: PruneConfirm {
+                        threshold: 5,
+                        assume_yes: false,
+                    },
+                    older_than: None,
+                    dry_run: false,
+                    fast_prune: false,
+                    local_only: false,
+                    refs: Vec::new(),
+                },
+            ),
+        ] {
+            assert_eq!(Workflow::from_toml(toml).unwrap(), expected);
+        }
+    }
+
+    /// `Filter::Regex` should match anywhere in the name unless anchored.
+    #[test]
+    fn filter_regex() {
+        let filter = |pattern: &str| Filter::<Branch>::Regex(pattern.parse().unwrap());
+
+        for (pattern, name, expected) in [
+            ("feat", "feature", true),
+            ("feat", "my-feature", true),
+            ("feat", "master", false),
+            ("^feat", "feature", true),
+            ("^feat", "my-feature", false),
+            ("^host[0-9]$", "host1", true),
+            ("^host[0-9]$", "host10", false),
+            ("fix|feat", "bugfix", true),
+        ] {
+            assert_eq!(
+                filter(pattern).contains(&Branch::from(name)),
+                expected,
+                "{:?} on {:?}",
+                pattern,
+                name
+            );
+        }
+    }
+
+    /// `Filter::Glob` should match whole names against any of its globs.
+    #[test]
+    fn filter_glob() {
+        let filter = |globs: &[&str]| {
+            Filter::<Branch>::Glob(globs.iter().map(|glob| glob.parse().unwrap()).collect())
+        };
+
+        for (globs, name, expected) in [
+            (&["release/*"][..], "release/1.0", true),
+            (&["release/*"], "release/1.0/hotfix", true),
+            (&["release/*"], "release", false),
+            (&["release/*"], "pre-release/1.0", false),
+            (&["*-wip"], "login-wip", true),
+            (&["*-wip"], "login-wip-2", false),
+            (&["release/*", "*-wip"], "login-wip", true),
+            (&["master"], "master", true),
+            (&["master"], "master2", false),
+            (&[], "master", false),
+        ] {
+            assert_eq!(
+                filter(globs).contains(&Branch::from(name)),
+                expected,
+                "{:?} on {:?}",
+                globs,
+                name
+            );
+        }
+    }
+
+    /// Literal globs should only match the name itself, even with glob syntax in it.
+    #[test]
+    fn glob_literal() {
+        let filter = Filter::Glob(vec![Glob::literal("fix-[1]*")]);
+        assert!(filter.contains(&Branch::from("fix-[1]*")));
+        assert!(!filter.contains(&Branch::from("fix-1")));
+        assert!(!filter.contains(&Branch::from("fix-[1]-more")));
+    }
+
+    /// Invalid globs should be rejected with the reason, like patterns.
+    #[test]
+    fn glob_invalid() {
+        let error = "feature/[".parse::<Glob>().unwrap_err();
+        assert!(error.starts_with("Invalid glob \"feature/[\""), "{}", error);
+    }
+
+    /// Invalid patterns should be rejected with the reason, rather than panicking later.
+    #[test]
+    fn pattern_invalid() {
+        let error = "feat(".parse::<Pattern>().unwrap_err();
+        assert!(error.starts_with("Invalid regex \"feat(\""), "{}", error);
+    }
+
+    /// Serializing and deserializing should round trip.
+    #[test]
+    fn toml_round_trip() {
+        let workflow = Workflow::Purge {
+            user: User::from("user0"),
+            remote: Remote::from("origin"),
+            host_filter: Filter::Allow([Host::from("host0")].into()),
+            branch_filter: Filter::All,
+            prune_confirm: PruneConfirm::default(),
+            older_than: None,
+            dry_run: false,
+            fast_prune: false,
+            local_only: false,
+            refs: Vec::new(),
+        };
+
+        let toml = toml::to_string(&workflow).unwrap();
+        assert_eq!(Workflow::from_toml(&toml).unwrap(), workflow);
+    }
+
+    #[test]
+    fn from_toml_invalid() {
+        assert!(Workflow::from_toml("[bogus]").is_err());
+        assert!(Workflow::from_toml("[sync]\nuser = \"user0\"").is_err());
+    }
+
+    /// Users and hosts from workflow files should be checked like those from the CLI, since a `/`
+    /// would silently attribute branches to the wrong host.
+    #[test]
+    fn from_toml_invalid_user_or_host() {
+        for (user, host, problem) in [
+            (
+                "user0",
+                "evil/host",
+                "Invalid host \"evil/host\", it must not contain '/'",
+            ),
+            (
+                "us:er",
+                "host0",
+                "Invalid user \"us:er\", it must not contain ':'",
+            ),
+        ] {
+            let toml = format!(
+                "[sync]\nuser = \"{}\"\nhost = \"{}\"\nremote = \"origin\"\n",
+                user, host
+            );
+            let error = Workflow::from_toml(&toml).unwrap_err();
+            assert!(format!("{:#}", error).contains(problem), "{:#}", error);
+        }
     }
 
     #[test]